@@ -1,59 +1,182 @@
-use std::path::Path;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::{env, fs};
 
+#[path = "zeus_core/src/vfs/archive.rs"]
+#[allow(dead_code)]
+mod archive;
+
+use archive::ArchiveWriter;
+
+const DATA_DIR: &str = "data";
+const SHADERS_DIR: &str = "data/shaders";
+const ARCHIVE_NAME: &str = "data.zpk";
+//NOTE: These formats are already compressed, deflating them again only costs load time
+const STORED_EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "gif"];
+
 fn main() {
     let target_dir_path = env::var("OUT_DIR").unwrap();
+    let output_path = Path::new(&target_dir_path).join("../../..");
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={}", DATA_DIR);
+
+    let files = collect_files(Path::new(DATA_DIR));
+
+    for file in files.iter() {
+        println!("cargo:rerun-if-changed={}", file.display());
+        copy(&output_path, file);
+    }
+
+    compile_shaders(&output_path, SHADERS_DIR);
+
+    if env::var("CARGO_FEATURE_PACK_ASSETS").is_ok() {
+        let data_path = output_path.join(DATA_DIR);
+        pack_assets(&data_path, &output_path.join(ARCHIVE_NAME));
+    }
+}
+
+/// Walks the directory recursively and returns every file in it.
+fn collect_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
 
-    create_data_paths(&target_dir_path);
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+
+        if path.is_dir() {
+            files.append(&mut collect_files(&path));
+        } else {
+            files.push(path);
+        }
+    }
 
-    //shaders
-    copy(&target_dir_path, "data/shaders/quad.vert");
-    copy(&target_dir_path, "data/shaders/quad.frag");
+    files.sort();
+    files
+}
+
+/// Copies the file into the output directory, keeping its relative path.
+/// Files that haven't changed since the last build are skipped.
+fn copy(output_path: &Path, file_name: &Path) {
+    let target = output_path.join(file_name);
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
 
-    //textures
-    copy(&target_dir_path, "data/textures/logo.png");
-    copy(&target_dir_path, "data/textures/statue.jpg");
-    copy(&target_dir_path, "data/textures/error.png");
-    copy(&target_dir_path, "data/textures/viking_room.png");
+    if is_unchanged(file_name, &target) {
+        return;
+    }
 
-    //models
-    copy(&target_dir_path, "data/models/viking_room.mtl");
-    copy(&target_dir_path, "data/models/viking_room.obj");
+    fs::copy(file_name, target).unwrap();
 }
 
-fn create_data_paths(target_dir_path: &str) {
-    let data_path = Path::new(&target_dir_path).join("../../../data");
+fn is_unchanged(source: &Path, target: &Path) -> bool {
+    let (source_meta, target_meta) = match (fs::metadata(source), fs::metadata(target)) {
+        (Ok(source_meta), Ok(target_meta)) => (source_meta, target_meta),
+        _ => return false,
+    };
+
+    if source_meta.len() != target_meta.len() {
+        return false;
+    }
 
-    if !data_path.exists() {
-        fs::create_dir(data_path).unwrap();
+    if let (Ok(source_time), Ok(target_time)) = (source_meta.modified(), target_meta.modified()) {
+        if target_time >= source_time {
+            return true;
+        }
     }
 
-    let shaders_path = Path::new(&target_dir_path).join("../../../data/shaders");
+    //NOTE: Timestamps change on checkout, so compare the contents before copying again
+    hash_file(source) == hash_file(target)
+}
+
+fn hash_file(path: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&fs::read(path).unwrap());
+    hasher.finish()
+}
+
+/// Compiles every GLSL shader in the directory to SPIR-V, next to the copied sources.
+/// Any compilation error fails the build.
+fn compile_shaders(output_path: &Path, shaders_dir: &str) {
+    let mut errors = Vec::new();
+
+    for path in collect_files(Path::new(shaders_dir)) {
+        let shader_type = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("vert") => glsl_to_spirv::ShaderType::Vertex,
+            Some("frag") => glsl_to_spirv::ShaderType::Fragment,
+            Some("geom") => glsl_to_spirv::ShaderType::Geometry,
+            Some("tesc") => glsl_to_spirv::ShaderType::TessellationControl,
+            Some("tese") => glsl_to_spirv::ShaderType::TessellationEvaluation,
+            Some("comp") => glsl_to_spirv::ShaderType::Compute,
+            _ => continue,
+        };
+
+        let spirv_path = output_path.join(format!("{}.spv", path.display()));
 
-    if !shaders_path.exists() {
-        fs::create_dir(shaders_path).unwrap();
+        if is_up_to_date(&path, &spirv_path) {
+            continue;
+        }
+
+        let source = fs::read_to_string(&path).unwrap();
+
+        let mut spirv = Vec::new();
+        match glsl_to_spirv::compile(&source, shader_type) {
+            Ok(mut file) => {
+                file.read_to_end(&mut spirv).unwrap();
+            }
+            Err(err) => {
+                errors.push(format!("{}:\n{}", path.display(), err));
+                continue;
+            }
+        }
+
+        fs::write(spirv_path, spirv).unwrap();
     }
 
-    let textures_path = Path::new(&target_dir_path).join("../../../data/textures");
+    if !errors.is_empty() {
+        for error in errors.iter() {
+            eprintln!("Shader compilation failed: {}", error);
+        }
 
-    if !textures_path.exists() {
-        fs::create_dir(textures_path).unwrap();
+        panic!("{} shader(s) failed to compile", errors.len());
     }
+}
 
-    let models_path = Path::new(&target_dir_path).join("../../../data/models");
+fn is_up_to_date(source: &Path, target: &Path) -> bool {
+    let source_time = fs::metadata(source).and_then(|meta| meta.modified());
+    let target_time = fs::metadata(target).and_then(|meta| meta.modified());
 
-    if !models_path.exists() {
-        fs::create_dir(models_path).unwrap();
+    match (source_time, target_time) {
+        (Ok(source_time), Ok(target_time)) => target_time >= source_time,
+        _ => false,
     }
 }
 
-fn copy<S: AsRef<std::ffi::OsStr> + ?Sized, P: Copy + AsRef<Path>>(
-    target_dir_path: &S,
-    file_name: P,
-) {
-    fs::copy(
-        file_name,
-        Path::new(&target_dir_path).join("../../..").join(file_name),
-    )
-    .unwrap();
+/// Packs every file of the data directory into a single archive, see `zeus_core::vfs::Archive`.
+fn pack_assets(data_path: &Path, archive_path: &Path) {
+    let mut writer = ArchiveWriter::new();
+
+    for file in collect_files(data_path) {
+        let name = file
+            .strip_prefix(data_path)
+            .unwrap()
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let compress = match file.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => !STORED_EXTENSIONS.contains(&ext.to_lowercase().as_str()),
+            None => true,
+        };
+
+        writer
+            .add_file(&name, fs::read(&file).unwrap(), compress)
+            .unwrap();
+    }
+
+    writer.write_to_file(archive_path).unwrap();
 }
@@ -0,0 +1,33 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+//IN
+layout(location = 0) in vec2 v_uv;
+
+//UNIFORMS
+layout(set = 0, binding = 0) uniform texture2D u_source;
+layout(set = 0, binding = 1) uniform sampler u_sampler;
+
+//See PostState::constants in post.rs
+layout(push_constant) uniform PostData {
+    vec4 params;    //x: threshold, y: soft knee
+} post;
+
+//OUT
+layout(location = 0) out vec4 target0;
+
+void main() {
+    vec3 color = texture(sampler2D(u_source, u_sampler), v_uv).rgb;
+
+    //Keeps what is brighter than the threshold, with a soft knee below it so the bloom fades in
+    float brightness = max(color.r, max(color.g, color.b));
+    float threshold = post.params.x;
+    float knee = max(threshold * post.params.y, 1e-4);
+
+    float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee);
+
+    float contribution = max(soft, brightness - threshold) / max(brightness, 1e-4);
+
+    target0 = vec4(color * contribution, 1.0);
+}
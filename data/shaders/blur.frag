@@ -0,0 +1,34 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+//IN
+layout(location = 0) in vec2 v_uv;
+
+//UNIFORMS
+layout(set = 0, binding = 0) uniform texture2D u_source;
+layout(set = 0, binding = 1) uniform sampler u_sampler;
+
+//See PostState::constants in post.rs
+layout(push_constant) uniform PostData {
+    vec4 params;    //xy: direction of the blur
+} post;
+
+//OUT
+layout(location = 0) out vec4 target0;
+
+//NOTE: A 9 tap gaussian, the linear filter blends two texels per sample
+const float OFFSETS[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float WEIGHTS[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(u_source, u_sampler), 0));
+    vec2 direction = post.params.xy * texel;
+
+    vec3 color = texture(sampler2D(u_source, u_sampler), v_uv).rgb * WEIGHTS[0];
+    for (int i = 1; i < 3; i++) {
+        color += texture(sampler2D(u_source, u_sampler), v_uv + direction * OFFSETS[i]).rgb * WEIGHTS[i];
+        color += texture(sampler2D(u_source, u_sampler), v_uv - direction * OFFSETS[i]).rgb * WEIGHTS[i];
+    }
+
+    target0 = vec4(color, 1.0);
+}
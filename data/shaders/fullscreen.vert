@@ -0,0 +1,18 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+//One triangle that covers the screen, drawn with 3 vertices and no buffers
+
+//OUT
+layout(location = 0) out vec2 v_uv;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    v_uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+
+    //NOTE: The top of the screen is -1, so uv (0, 0) is the top left corner like the images
+    gl_Position = vec4(v_uv * 2.0 - 1.0, 0.0, 1.0);
+}
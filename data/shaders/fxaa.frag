@@ -0,0 +1,159 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+//IN
+layout(location = 0) in vec2 v_uv;
+
+//UNIFORMS
+layout(set = 0, binding = 0) uniform texture2D u_source;
+layout(set = 0, binding = 1) uniform sampler u_sampler;
+
+//See PostState::constants in post.rs
+layout(push_constant) uniform PostData {
+    vec4 params;    //x: subpixel blending, y: edge threshold, z: minimum threshold
+    vec4 effects;
+    vec4 display;   //z: the target is sRGB
+} post;
+
+//OUT
+layout(location = 0) out vec4 target0;
+
+const int SEARCH_STEPS = 8;
+
+float luma(vec3 color) {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+vec3 sample_color(vec2 uv) {
+    return textureLod(sampler2D(u_source, u_sampler), uv, 0.0).rgb;
+}
+
+float sample_luma(vec2 uv) {
+    return luma(sample_color(uv));
+}
+
+vec3 srgb_to_linear(vec3 color) {
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+
+    return mix(high, low, vec3(lessThanEqual(color, vec3(0.04045))));
+}
+
+//FXAA 3.11 quality, by Timothy Lottes, simplified
+vec3 fxaa(vec2 uv, vec2 texel) {
+    vec3 center = sample_color(uv);
+    float luma_center = luma(center);
+    float luma_n = sample_luma(uv + vec2(0.0, -texel.y));
+    float luma_s = sample_luma(uv + vec2(0.0, texel.y));
+    float luma_e = sample_luma(uv + vec2(texel.x, 0.0));
+    float luma_w = sample_luma(uv + vec2(-texel.x, 0.0));
+
+    float luma_max = max(luma_center, max(max(luma_n, luma_s), max(luma_e, luma_w)));
+    float luma_min = min(luma_center, min(min(luma_n, luma_s), min(luma_e, luma_w)));
+    float range = luma_max - luma_min;
+
+    //Not an edge
+    if (range < max(post.params.z, luma_max * post.params.y)) {
+        return center;
+    }
+
+    float luma_nw = sample_luma(uv + vec2(-texel.x, -texel.y));
+    float luma_ne = sample_luma(uv + vec2(texel.x, -texel.y));
+    float luma_sw = sample_luma(uv + vec2(-texel.x, texel.y));
+    float luma_se = sample_luma(uv + vec2(texel.x, texel.y));
+
+    float edge_horizontal = abs(luma_nw + luma_ne - 2.0 * luma_n)
+        + 2.0 * abs(luma_w + luma_e - 2.0 * luma_center)
+        + abs(luma_sw + luma_se - 2.0 * luma_s);
+    float edge_vertical = abs(luma_nw + luma_sw - 2.0 * luma_w)
+        + 2.0 * abs(luma_n + luma_s - 2.0 * luma_center)
+        + abs(luma_ne + luma_se - 2.0 * luma_e);
+    bool is_horizontal = edge_horizontal >= edge_vertical;
+
+    //Which side of the pixel the edge is on
+    float luma_negative = is_horizontal ? luma_n : luma_w;
+    float luma_positive = is_horizontal ? luma_s : luma_e;
+    float gradient_negative = abs(luma_negative - luma_center);
+    float gradient_positive = abs(luma_positive - luma_center);
+
+    float step_length = is_horizontal ? texel.y : texel.x;
+    float luma_edge;
+    float gradient;
+    if (gradient_negative >= gradient_positive) {
+        step_length = -step_length;
+        luma_edge = (luma_negative + luma_center) * 0.5;
+        gradient = gradient_negative * 0.25;
+    } else {
+        luma_edge = (luma_positive + luma_center) * 0.5;
+        gradient = gradient_positive * 0.25;
+    }
+
+    vec2 edge_uv = uv;
+    vec2 along = is_horizontal ? vec2(texel.x, 0.0) : vec2(0.0, texel.y);
+    if (is_horizontal) {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+
+    //Walk along the edge in both directions until it ends
+    vec2 uv_negative = edge_uv - along;
+    vec2 uv_positive = edge_uv + along;
+    float end_negative = sample_luma(uv_negative) - luma_edge;
+    float end_positive = sample_luma(uv_positive) - luma_edge;
+    bool done_negative = abs(end_negative) >= gradient;
+    bool done_positive = abs(end_positive) >= gradient;
+
+    for (int i = 1; i < SEARCH_STEPS && !(done_negative && done_positive); i++) {
+        if (!done_negative) {
+            uv_negative -= along * 1.5;
+            end_negative = sample_luma(uv_negative) - luma_edge;
+            done_negative = abs(end_negative) >= gradient;
+        }
+
+        if (!done_positive) {
+            uv_positive += along * 1.5;
+            end_positive = sample_luma(uv_positive) - luma_edge;
+            done_positive = abs(end_positive) >= gradient;
+        }
+    }
+
+    float distance_negative = is_horizontal ? uv.x - uv_negative.x : uv.y - uv_negative.y;
+    float distance_positive = is_horizontal ? uv_positive.x - uv.x : uv_positive.y - uv.y;
+    bool is_negative_closer = distance_negative < distance_positive;
+    float closest = min(distance_negative, distance_positive);
+
+    //Only blend if the end of the edge moves away from the luma of the pixel
+    bool is_center_smaller = luma_center < luma_edge;
+    bool is_valid = ((is_negative_closer ? end_negative : end_positive) < 0.0) != is_center_smaller;
+    float edge_offset = is_valid ? 0.5 - closest / (distance_negative + distance_positive) : 0.0;
+
+    //Subpixel aliasing, from the average of the neighbourhood
+    float luma_average = (2.0 * (luma_n + luma_s + luma_e + luma_w) + luma_nw + luma_ne + luma_sw + luma_se) / 12.0;
+    float subpixel = clamp(abs(luma_average - luma_center) / range, 0.0, 1.0);
+    subpixel = smoothstep(0.0, 1.0, subpixel);
+    float subpixel_offset = subpixel * subpixel * post.params.x;
+
+    float offset = max(edge_offset, subpixel_offset);
+
+    vec2 final_uv = uv;
+    if (is_horizontal) {
+        final_uv.y += offset * step_length;
+    } else {
+        final_uv.x += offset * step_length;
+    }
+
+    return sample_color(final_uv);
+}
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(u_source, u_sampler), 0));
+    vec3 color = fxaa(v_uv, texel);
+
+    //The colors are already corrected, undo the encoding the hardware does for sRGB targets
+    if (post.display.z > 0.0) {
+        color = srgb_to_linear(color);
+    }
+
+    target0 = vec4(color, 1.0);
+}
@@ -0,0 +1,337 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+const float PI = 3.14159265359;
+
+//Light kinds, see light.rs
+const float LIGHT_DIRECTIONAL = 0.0;
+const float LIGHT_SPOT = 2.0;
+
+//See constants.rs
+const int MAX_SHADOW_TILES = 16;
+
+struct Light {
+    vec4 position;  //w: kind
+    vec4 direction; //w: range
+    vec4 color;     //w: intensity
+    vec4 cone;      //x: cos of the inner cone, y: cos of the outer cone, z: first shadow tile or -1, w: shadow tile count
+};
+
+struct ShadowTile {
+    mat4 matrix;
+    vec4 rect;      //xy: offset in the atlas, zw: scale
+};
+
+//IN
+layout(location = 0) in vec4 v_color;
+layout(location = 1) in vec2 v_uv;
+layout(location = 2) in vec3 v_pos;
+layout(location = 3) in vec3 v_normal;
+layout(location = 4) in vec3 v_world;
+layout(location = 5) in vec4 v_tangent;
+
+//UNIFORMS
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+layout(set = 1, binding = 0) readonly buffer LightBuffer {
+    vec4 ambient;   //w: intensity of the environment
+    uint count;
+    Light lights[];
+} light_data;
+//NOTE: The cube maps are in the space of the vertices, see ibl.rs
+layout(set = 1, binding = 1) uniform textureCube u_irradiance;
+layout(set = 1, binding = 2) uniform textureCube u_prefiltered;
+layout(set = 1, binding = 3) uniform texture2D u_brdf_lut;
+layout(set = 1, binding = 4) uniform sampler u_environment_sampler;
+
+layout(set = 2, binding = 0) uniform ShadowData {
+    vec4 splits;    //The far distance of each cascade
+    vec4 params;    //x: pcf radius, y: size of a texel of the atlas
+    ShadowTile tiles[MAX_SHADOW_TILES];
+} shadow_data;
+layout(set = 2, binding = 1) uniform texture2D u_shadow_map;
+layout(set = 2, binding = 2) uniform samplerShadow u_shadow_sampler;
+
+layout(set = 3, binding = 0) uniform MaterialData {
+    vec4 base_color;
+    vec3 emissive_factor;
+    float metallic;
+    float roughness;
+    float occlusion_strength;
+    float normal_scale;
+    float height_scale;
+    float surface_mode;
+} material;
+
+layout(set = 3, binding = 1) uniform texture2D u_albedo;
+layout(set = 3, binding = 2) uniform sampler u_albedo_sampler;
+
+//g: roughness, b: metallic
+layout(set = 3, binding = 3) uniform texture2D u_metallic_roughness;
+layout(set = 3, binding = 4) uniform sampler u_metallic_roughness_sampler;
+
+layout(set = 3, binding = 5) uniform texture2D u_normal;
+layout(set = 3, binding = 6) uniform sampler u_normal_sampler;
+
+layout(set = 3, binding = 7) uniform texture2D u_emissive;
+layout(set = 3, binding = 8) uniform sampler u_emissive_sampler;
+
+//r: occlusion
+layout(set = 3, binding = 9) uniform texture2D u_occlusion;
+layout(set = 3, binding = 10) uniform sampler u_occlusion_sampler;
+
+//OUT
+layout(location = 0) out vec4 target0;
+
+//NOTE: Same as quad.frag
+//1 is lit, 0 is in shadow
+float sample_shadow(int tile) {
+    ShadowTile shadow = shadow_data.tiles[tile];
+
+    vec4 light_pos = vec4(v_world, 1.0) * shadow.matrix;
+    vec3 coords = light_pos.xyz / light_pos.w;
+    vec2 uv = coords.xy * 0.5 + 0.5;
+
+    if (coords.z > 1.0 || any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        return 1.0;
+    }
+
+    //PCF, the samples are kept inside the tile
+    int radius = int(shadow_data.params.x);
+    float texel = shadow_data.params.y;
+    vec2 tile_uv = uv * shadow.rect.zw;
+
+    float lit = 0.0;
+    for (int x = -radius; x <= radius; x++) {
+        for (int y = -radius; y <= radius; y++) {
+            vec2 offset = clamp(tile_uv + vec2(x, y) * texel, vec2(texel), shadow.rect.zw - texel);
+            lit += texture(sampler2DShadow(u_shadow_map, u_shadow_sampler), vec3(shadow.rect.xy + offset, coords.z));
+        }
+    }
+
+    float kernel = float(2 * radius + 1);
+    return lit / (kernel * kernel);
+}
+
+float shadow_factor(Light light) {
+    int tile = int(light.cone.z);
+
+    if (tile < 0) {
+        return 1.0;
+    }
+
+    //Directional lights pick the cascade by the view distance
+    if (light.position.w == LIGHT_DIRECTIONAL) {
+        int cascades = int(light.cone.w);
+        float depth = -v_pos.z;
+
+        if (depth > shadow_data.splits[cascades - 1]) {
+            return 1.0;
+        }
+
+        int cascade = 0;
+        while (cascade < cascades - 1 && depth > shadow_data.splits[cascade]) {
+            cascade++;
+        }
+
+        tile += cascade;
+    }
+
+    return sample_shadow(tile);
+}
+
+//Surface modes, see material.rs
+const float SURFACE_BUMP = 1.0;
+const float SURFACE_PARALLAX = 2.0;
+const int PARALLAX_MAX_STEPS = 32;
+
+mat3 tangent_frame(vec3 normal) {
+    //NOTE: Derivatives are only defined outside of branches
+    vec3 dp1 = dFdx(v_pos);
+    vec3 dp2 = dFdy(v_pos);
+    vec2 duv1 = dFdx(v_uv);
+    vec2 duv2 = dFdy(v_uv);
+
+    if (dot(v_tangent.xyz, v_tangent.xyz) > 0.0) {
+        vec3 tangent = normalize(v_tangent.xyz - normal * dot(normal, v_tangent.xyz));
+        return mat3(tangent, cross(normal, tangent) * v_tangent.w, normal);
+    }
+
+    //Meshes without tangents get a frame from the screen space derivatives
+
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+
+    //The UVs are flipped, the bitangent has to point up the image
+    float scale = inversesqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 1e-12));
+    return mat3(tangent * scale, -bitangent * scale, normal);
+}
+
+//The alpha of the normal texture, 1 is the top of the surface
+float height_at(vec2 uv) {
+    //NOTE: No implicit derivatives inside the parallax loop
+    return textureLod(sampler2D(u_normal, u_normal_sampler), uv, 0.0).a;
+}
+
+//Parallax occlusion mapping, steps into the height along the view until it hits the surface
+vec2 parallax_uv(vec2 uv, vec3 view_tangent) {
+    int steps = int(mix(float(PARALLAX_MAX_STEPS), 8.0, clamp(view_tangent.z, 0.0, 1.0)));
+    float layer_depth = 1.0 / float(steps);
+
+    //The bitangent points against v
+    vec2 shift = view_tangent.xy / max(view_tangent.z, 0.1) * material.height_scale;
+    vec2 delta = vec2(shift.x, -shift.y) * layer_depth;
+
+    float depth = 0.0;
+    float surface = 1.0 - height_at(uv);
+    for (int i = 0; i < steps && depth < surface; i++) {
+        uv -= delta;
+        depth += layer_depth;
+        surface = 1.0 - height_at(uv);
+    }
+
+    //Interpolate between the last step above the surface and the first below it
+    float after = surface - depth;
+    float before = (1.0 - height_at(uv + delta)) - (depth - layer_depth);
+    float weight = after / (after - before + 1e-6);
+
+    return mix(uv, uv + delta, clamp(weight, 0.0, 1.0));
+}
+
+vec3 surface_normal(mat3 tbn, vec2 uv) {
+    vec3 mapped;
+
+    if (material.surface_mode == SURFACE_BUMP) {
+        vec2 texel = 1.0 / vec2(textureSize(sampler2D(u_normal, u_normal_sampler), 0));
+        float height = texture(sampler2D(u_normal, u_normal_sampler), uv).a;
+        float dh_du = (texture(sampler2D(u_normal, u_normal_sampler), uv + vec2(texel.x, 0.0)).a - height) / texel.x;
+        float dh_dv = (texture(sampler2D(u_normal, u_normal_sampler), uv + vec2(0.0, texel.y)).a - height) / texel.y;
+
+        //The slope of the height, v runs down the image
+        mapped = vec3(-dh_du * material.height_scale, dh_dv * material.height_scale, 1.0);
+    } else {
+        mapped = texture(sampler2D(u_normal, u_normal_sampler), uv).xyz * 2.0 - 1.0;
+        mapped.xy *= material.normal_scale;
+    }
+
+    return normalize(tbn * mapped);
+}
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float alpha = roughness * roughness;
+    float alpha_2 = alpha * alpha;
+    float denom = n_dot_h * n_dot_h * (alpha_2 - 1.0) + 1.0;
+
+    return alpha_2 / (PI * denom * denom);
+}
+
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+
+    return n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+vec3 fresnel_schlick_roughness(float cos_theta, vec3 f0, float roughness) {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+void main() {
+    vec3 view_dir = normalize(-v_pos);
+    mat3 tbn = tangent_frame(normalize(v_normal));
+
+    vec2 uv = v_uv;
+    if (material.surface_mode == SURFACE_PARALLAX) {
+        uv = parallax_uv(uv, normalize(view_dir * tbn));
+    }
+
+    vec4 albedo = texture(sampler2D(u_albedo, u_albedo_sampler), uv) * material.base_color * v_color;
+
+    if (albedo.a <= 0.0) {
+        discard;
+    }
+
+    vec3 metallic_roughness = texture(sampler2D(u_metallic_roughness, u_metallic_roughness_sampler), uv).rgb;
+    float occlusion = mix(1.0, texture(sampler2D(u_occlusion, u_occlusion_sampler), uv).r, material.occlusion_strength);
+    float roughness = clamp(metallic_roughness.g * material.roughness, 0.04, 1.0);
+    float metallic = clamp(metallic_roughness.b * material.metallic, 0.0, 1.0);
+
+    vec3 normal = surface_normal(tbn, uv);
+    float n_dot_v = max(dot(normal, view_dir), 1e-4);
+
+    //Dielectrics reflect 4% head on, metals tint the reflection with their albedo
+    vec3 f0 = mix(vec3(0.04), albedo.rgb, metallic);
+
+    vec3 direct = vec3(0.0);
+
+    for (uint i = 0; i < light_data.count; i++) {
+        Light light = light_data.lights[i];
+
+        vec3 light_dir;
+        float attenuation = 1.0;
+
+        if (light.position.w == LIGHT_DIRECTIONAL) {
+            light_dir = -normalize((vec4(light.direction.xyz, 0.0) * ubo.model * ubo.view).xyz);
+        } else {
+            vec3 light_pos = (vec4(light.position.xyz, 1.0) * ubo.model * ubo.view).xyz;
+            vec3 to_light = light_pos - v_pos;
+            float distance = length(to_light);
+            light_dir = to_light / distance;
+
+            //Smooth falloff that reaches zero at the range
+            float falloff = clamp(1.0 - pow(distance / light.direction.w, 4.0), 0.0, 1.0);
+            attenuation = falloff * falloff / (distance * distance + 1.0);
+
+            if (light.position.w == LIGHT_SPOT) {
+                vec3 spot_dir = normalize((vec4(light.direction.xyz, 0.0) * ubo.model * ubo.view).xyz);
+                attenuation *= smoothstep(light.cone.y, light.cone.x, dot(-light_dir, spot_dir));
+            }
+        }
+
+        float n_dot_l = max(dot(normal, light_dir), 0.0);
+        if (n_dot_l <= 0.0) {
+            continue;
+        }
+
+        vec3 radiance = light.color.rgb * light.color.w * attenuation * shadow_factor(light);
+
+        //Cook-Torrance
+        vec3 halfway = normalize(light_dir + view_dir);
+        float d = distribution_ggx(max(dot(normal, halfway), 0.0), roughness);
+        float g = geometry_smith(n_dot_v, n_dot_l, roughness);
+        vec3 f = fresnel_schlick(max(dot(halfway, view_dir), 0.0), f0);
+
+        vec3 specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 1e-4);
+        vec3 diffuse = (1.0 - f) * (1.0 - metallic) * albedo.rgb / PI;
+
+        direct += (diffuse + specular) * radiance * n_dot_l;
+    }
+
+    //Image based lighting, with the split sum approximation for the specular part
+    mat3 view_to_world = inverse(mat3(ubo.model * ubo.view));
+    vec3 world_normal = normalize(normal * view_to_world);
+    vec3 world_reflection = normalize(reflect(-view_dir, normal) * view_to_world);
+
+    vec3 f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    vec3 irradiance = texture(samplerCube(u_irradiance, u_environment_sampler), world_normal).rgb;
+    vec3 diffuse = (1.0 - f) * (1.0 - metallic) * irradiance * albedo.rgb;
+
+    float max_lod = float(textureQueryLevels(samplerCube(u_prefiltered, u_environment_sampler)) - 1);
+    vec3 prefiltered = textureLod(samplerCube(u_prefiltered, u_environment_sampler), world_reflection, roughness * max_lod).rgb;
+    vec2 brdf = texture(sampler2D(u_brdf_lut, u_environment_sampler), vec2(n_dot_v, roughness)).rg;
+    vec3 specular = prefiltered * (f * brdf.x + brdf.y);
+
+    vec3 ambient = (diffuse + specular) * occlusion * light_data.ambient.w;
+    vec3 emissive = texture(sampler2D(u_emissive, u_emissive_sampler), uv).rgb * material.emissive_factor;
+
+    target0 = vec4(direct + ambient + emissive, albedo.a);
+}
@@ -9,25 +9,74 @@ layout(set = 0, binding = 0) uniform UniformBufferObject {
     mat4 view;
     mat4 proj;
 } ubo;
+
+//The joint palettes of every skinned object in the frame
+layout(set = 0, binding = 1) readonly buffer JointData {
+    mat4 joints[];
+} joint_data;
+
+//NOTE: Negative when the object is not skinned
+layout(push_constant) uniform ObjectData {
+    int joint_offset;
+} object;
  
 //Inputs
 layout(location = 0) in vec3 a_pos;
 layout(location = 1) in vec4 a_color;
 layout(location = 2) in vec2 a_uv;
+layout(location = 3) in vec3 a_normal;
+layout(location = 4) in vec4 a_tangent;
+layout(location = 5) in vec4 a_joints;
+layout(location = 6) in vec4 a_weights;
+//Per instance, see Instance in model.rs
+layout(location = 7) in mat4 a_instance_model;
+layout(location = 11) in vec4 a_instance_color;
 
 // Outputs
 layout(location = 0) out vec4 v_color;
 layout(location = 1) out vec2 v_uv;
+layout(location = 2) out vec3 v_pos;
+layout(location = 3) out vec3 v_normal;
+//NOTE: The lights and the shadow maps are in the space of the vertices
+layout(location = 4) out vec3 v_world;
+//w: handedness of the bitangent, zero when the mesh has no tangents
+layout(location = 5) out vec4 v_tangent;
 
 
 out gl_PerVertex {
     vec4 gl_Position;
 };
 
+mat4 skin_matrix() {
+    float total_weight = a_weights.x + a_weights.y + a_weights.z + a_weights.w;
+    if (object.joint_offset < 0 || total_weight == 0.0) {
+        return mat4(1.0);
+    }
+
+    ivec4 joints = ivec4(a_joints) + object.joint_offset;
+
+    return joint_data.joints[joints.x] * a_weights.x
+        + joint_data.joints[joints.y] * a_weights.y
+        + joint_data.joints[joints.z] * a_weights.z
+        + joint_data.joints[joints.w] * a_weights.w;
+}
+
 void main() {
-    gl_Position = vec4(a_pos, 1.0) * ubo.model * ubo.view * ubo.proj;
+    mat4 skin = skin_matrix() * a_instance_model;
+    vec4 pos = vec4(a_pos, 1.0) * skin;
+
+    //NOTE: Lighting is done in view space
+    vec4 view_pos = pos * ubo.model * ubo.view;
+
+    gl_Position = view_pos * ubo.proj;
     gl_Position.y = -gl_Position.y;
     
-    v_color = a_color;
+    v_color = a_color * a_instance_color;
     v_uv = a_uv;
+    v_pos = view_pos.xyz;
+    //NOTE: Normals take the inverse transpose, the instances can scale unevenly
+    mat3 model_view = mat3(skin * ubo.model * ubo.view);
+    v_normal = normalize(a_normal * transpose(inverse(model_view)));
+    v_world = pos.xyz;
+    v_tangent = vec4(a_tangent.xyz * model_view, a_tangent.w);
 }
@@ -0,0 +1,43 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+//The matrix of the shadow map tile, see shadow.rs
+//joint_offset is negative when the object is not skinned
+layout(push_constant) uniform ShadowTile {
+    mat4 light_matrix;
+    int joint_offset;
+} tile;
+
+//The joint palettes of the camera set, see quad.vert
+layout(set = 0, binding = 1) readonly buffer JointData {
+    mat4 joints[];
+} joint_data;
+
+//Inputs
+layout(location = 0) in vec3 a_pos;
+layout(location = 5) in vec4 a_joints;
+layout(location = 6) in vec4 a_weights;
+layout(location = 7) in mat4 a_instance_model;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+mat4 skin_matrix() {
+    float total_weight = a_weights.x + a_weights.y + a_weights.z + a_weights.w;
+    if (tile.joint_offset < 0 || total_weight == 0.0) {
+        return mat4(1.0);
+    }
+
+    ivec4 joints = ivec4(a_joints) + tile.joint_offset;
+
+    return joint_data.joints[joints.x] * a_weights.x
+        + joint_data.joints[joints.y] * a_weights.y
+        + joint_data.joints[joints.z] * a_weights.z
+        + joint_data.joints[joints.w] * a_weights.w;
+}
+
+void main() {
+    //NOTE: Not flipped, the shadow map is sampled with the same orientation
+    gl_Position = vec4(a_pos, 1.0) * skin_matrix() * a_instance_model * tile.light_matrix;
+}
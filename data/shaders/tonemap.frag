@@ -0,0 +1,109 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+//IN
+layout(location = 0) in vec2 v_uv;
+
+//UNIFORMS
+layout(set = 0, binding = 0) uniform texture2D u_source;
+layout(set = 0, binding = 1) uniform sampler u_sampler;
+//NOTE: Black when bloom is off
+layout(set = 0, binding = 2) uniform texture2D u_bloom;
+//The color grading LUT, its slices side by side in a square, see post.rs
+layout(set = 0, binding = 3) uniform texture2D u_lut;
+
+//See PostState::constants in post.rs
+layout(push_constant) uniform PostData {
+    vec4 params;    //x: exposure, y: bloom intensity, z: vignette strength
+    vec4 effects;   //x: tonemapping, y: bloom, z: color grading, w: vignette
+    vec4 display;   //x: gamma correction, y: gamma, z: the target is sRGB
+} post;
+
+//OUT
+layout(location = 0) out vec4 target0;
+
+//Filmic curve of Uncharted 2, by John Hable
+vec3 filmic_curve(vec3 x) {
+    const float A = 0.15;
+    const float B = 0.50;
+    const float C = 0.10;
+    const float D = 0.20;
+    const float E = 0.02;
+    const float F = 0.30;
+
+    return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
+}
+
+vec3 filmic(vec3 color) {
+    const float WHITE = 11.2;
+
+    //NOTE: The curve is darker than linear, the exposure bias brings it back
+    return filmic_curve(color * 2.0) / filmic_curve(vec3(WHITE));
+}
+
+vec3 grade(vec3 color) {
+    float side = float(textureSize(sampler2D(u_lut, u_sampler), 0).x);
+    float size = floor(pow(side * side, 1.0 / 3.0) + 0.5);
+    float tiles = side / size;
+
+    //The blue picks the slice, the two closest are blended
+    float blue = color.b * (size - 1.0);
+    float slice = floor(blue);
+    vec2 texel = (clamp(color.rg, 0.0, 1.0) * (size - 1.0) + 0.5) / side;
+
+    vec2 first = vec2(mod(slice, tiles), floor(slice / tiles)) * size / side + texel;
+    float next = min(slice + 1.0, size - 1.0);
+    vec2 second = vec2(mod(next, tiles), floor(next / tiles)) * size / side + texel;
+
+    vec3 graded = mix(
+        textureLod(sampler2D(u_lut, u_sampler), first, 0.0).rgb,
+        textureLod(sampler2D(u_lut, u_sampler), second, 0.0).rgb,
+        blue - slice
+    );
+
+    return graded;
+}
+
+vec3 srgb_to_linear(vec3 color) {
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+
+    return mix(high, low, vec3(lessThanEqual(color, vec3(0.04045))));
+}
+
+void main() {
+    vec3 color = texture(sampler2D(u_source, u_sampler), v_uv).rgb;
+
+    if (post.effects.y > 0.0) {
+        color += texture(sampler2D(u_bloom, u_sampler), v_uv).rgb * post.params.y;
+    }
+
+    color *= post.params.x;
+
+    if (post.effects.x > 0.0) {
+        color = filmic(color);
+    }
+
+    color = clamp(color, 0.0, 1.0);
+
+    if (post.display.x > 0.0) {
+        color = pow(color, vec3(1.0 / post.display.y));
+    }
+
+    //NOTE: LUTs are made for display colors, they are applied after the gamma
+    if (post.effects.z > 0.0) {
+        color = grade(color);
+    }
+
+    if (post.effects.w > 0.0) {
+        vec2 offset = v_uv - 0.5;
+        color *= clamp(1.0 - dot(offset, offset) * post.params.z, 0.0, 1.0);
+    }
+
+    //The colors are already corrected, undo the encoding the hardware does for sRGB targets
+    if (post.display.z > 0.0) {
+        color = srgb_to_linear(color);
+    }
+
+    target0 = vec4(color, 1.0);
+}
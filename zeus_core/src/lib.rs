@@ -9,3 +9,4 @@ extern crate winit;
 pub mod input;
 pub mod math;
 pub mod time;
+pub mod vfs;
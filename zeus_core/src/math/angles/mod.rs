@@ -1,5 +1,5 @@
-pub use euler::Euler;
 pub use quartenion::Quartenion;
 
-mod euler;
-mod quartenion;
\ No newline at end of file
+//TODO: Euler angles
+// mod euler;
+mod quartenion;
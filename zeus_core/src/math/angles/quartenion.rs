@@ -0,0 +1,198 @@
+use std::ops::Mul;
+
+use crate::math::{
+    Matrix4,
+    Vector3
+};
+
+/// Represents a rotation as a unit quaternion, stored as `x, y, z, w` with `w` being the real part.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quartenion {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Quartenion {
+    pub fn new(
+        x: f32,
+        y: f32,
+        z: f32,
+        w: f32,
+    ) -> Self {
+        Quartenion { x, y, z, w }
+    }
+
+    pub fn identity() -> Self {
+        Quartenion::new(0.0, 0.0, 0.0, 1.0)
+    }
+
+    /// `theta` is in degrees, like the rotations of `Matrix4`.
+    pub fn from_axis_angle(axis: Vector3, theta: f32) -> Self {
+        let axis = axis.normalize();
+        let half = theta.to_radians() / 2.0;
+
+        Quartenion::new(axis.x * half.sin(), axis.y * half.sin(), axis.z * half.sin(), half.cos())
+    }
+
+    //Methods
+    pub fn dot(
+        &self,
+        rhs: &Quartenion,
+    ) -> f32 {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z + self.w * rhs.w
+    }
+
+    pub fn magn(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalize(&self) -> Quartenion {
+        let magn = self.magn();
+        if magn == 0.0 {
+            return Quartenion::identity();
+        }
+
+        Quartenion::new(self.x / magn, self.y / magn, self.z / magn, self.w / magn)
+    }
+
+    /// The inverse rotation of a unit quaternion.
+    pub fn conjugate(&self) -> Quartenion {
+        Quartenion::new(-self.x, -self.y, -self.z, self.w)
+    }
+
+    /// Interpolates along the shortest arc, `t` goes from 0 (`self`) to 1 (`rhs`).
+    pub fn slerp(
+        &self,
+        rhs: &Quartenion,
+        t: f32,
+    ) -> Quartenion {
+        //NOTE: q and -q are the same rotation, flipping one keeps the path short
+        let mut cos_theta = self.dot(rhs);
+        let rhs = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            Quartenion::new(-rhs.x, -rhs.y, -rhs.z, -rhs.w)
+        } else {
+            *rhs
+        };
+
+        //Close rotations fall back to a normalized lerp, the sine gets too small to divide by
+        let (a, b) = if cos_theta > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            (((1.0 - t) * theta).sin() / theta.sin(), (t * theta).sin() / theta.sin())
+        };
+
+        Quartenion::new(
+            self.x * a + rhs.x * b,
+            self.y * a + rhs.y * b,
+            self.z * a + rhs.z * b,
+            self.w * a + rhs.w * b,
+        ).normalize()
+    }
+
+    pub fn rotate(
+        &self,
+        vec: Vector3,
+    ) -> Vector3 {
+        let axis = Vector3::new(self.x, self.y, self.z);
+        let t = axis.cross(&vec) * 2.0;
+
+        vec + t * self.w + axis.cross(&t)
+    }
+
+    /// The rotation matrix for column vectors, the same layout as the translation of `Matrix4`.
+    pub fn to_matrix(&self) -> Matrix4 {
+        let Quartenion { x, y, z, w } = *self;
+        let mut res = Matrix4::new();
+
+        res[0] = 1.0 - 2.0 * (y * y + z * z);
+        res[1] = 2.0 * (x * y - z * w);
+        res[2] = 2.0 * (x * z + y * w);
+
+        res[4] = 2.0 * (x * y + z * w);
+        res[5] = 1.0 - 2.0 * (x * x + z * z);
+        res[6] = 2.0 * (y * z - x * w);
+
+        res[8] = 2.0 * (x * z - y * w);
+        res[9] = 2.0 * (y * z + x * w);
+        res[10] = 1.0 - 2.0 * (x * x + y * y);
+
+        res
+    }
+}
+
+/// Applies `rhs` first, then `self`.
+impl Mul for Quartenion {
+    type Output = Quartenion;
+
+    fn mul(
+        self,
+        rhs: Quartenion,
+    ) -> Self::Output {
+        Quartenion::new(
+            self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+            self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+        )
+    }
+}
+
+impl Default for Quartenion {
+    fn default() -> Self {
+        Quartenion::identity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{Quartenion, Vector3, Vector4};
+
+    fn assert_close(a: Vector3, b: Vector3) {
+        assert!((a - b).magn() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn rotate() {
+        let quat = Quartenion::from_axis_angle(Vector3::Z, 90.0);
+
+        assert_close(quat.rotate(Vector3::X), Vector3::Y);
+        assert_close(quat.conjugate().rotate(Vector3::Y), Vector3::X);
+    }
+
+    #[test]
+    fn mul_applies_rhs_first() {
+        let x = Quartenion::from_axis_angle(Vector3::X, 90.0);
+        let z = Quartenion::from_axis_angle(Vector3::Z, 90.0);
+
+        //Y goes to Z through x, then stays on the axis of z
+        assert_close((z * x).rotate(Vector3::Y), Vector3::Z);
+        assert_close((x * z).rotate(Vector3::Y), Vector3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn slerp() {
+        let from = Quartenion::identity();
+        let to = Quartenion::from_axis_angle(Vector3::Y, 90.0);
+
+        assert_close(from.slerp(&to, 0.5).rotate(Vector3::Z), Quartenion::from_axis_angle(Vector3::Y, 45.0).rotate(Vector3::Z));
+
+        //The negated quaternion is the same rotation, the path stays short
+        let negated = Quartenion::new(-to.x, -to.y, -to.z, -to.w);
+        assert_close(from.slerp(&negated, 0.5).rotate(Vector3::Z), from.slerp(&to, 0.5).rotate(Vector3::Z));
+    }
+
+    #[test]
+    fn to_matrix() {
+        let quat = Quartenion::from_axis_angle(Vector3::new(1.0, 1.0, 0.0), 60.0);
+        let vec = Vector3::new(0.3, -2.0, 5.0);
+
+        let rotated = quat.to_matrix() * Vector4::new(vec.x, vec.y, vec.z, 1.0);
+
+        assert_close(Vector3::new(rotated.x, rotated.y, rotated.z), quat.rotate(vec));
+        assert_eq!(rotated.w, 1.0);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::math::{
+    Matrix4,
+    Vector3
+};
+
+/// An axis aligned bounding box. The empty box has `min` above `max`, so any point extends it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3,
+    pub max: Vector3,
+}
+
+impl Aabb {
+    pub fn new(
+        min: Vector3,
+        max: Vector3,
+    ) -> Self {
+        Aabb { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Aabb::new(
+            Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        )
+    }
+
+    pub fn from_points<I: IntoIterator<Item = Vector3>>(points: I) -> Self {
+        points.into_iter()
+            .fold(Aabb::empty(), |aabb, point| aabb.extend(point))
+    }
+
+    //Methods
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn extend(
+        &self,
+        point: Vector3,
+    ) -> Aabb {
+        Aabb::new(
+            Vector3::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z)),
+            Vector3::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z)),
+        )
+    }
+
+    pub fn union(
+        &self,
+        rhs: &Aabb,
+    ) -> Aabb {
+        if rhs.is_empty() {
+            return *self;
+        }
+
+        self.extend(rhs.min).extend(rhs.max)
+    }
+
+    pub fn center(&self) -> Vector3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn size(&self) -> Vector3 {
+        self.max - self.min
+    }
+
+    /// The box around the transformed box, the same size or bigger than the box of the transformed points.
+    pub fn transform(
+        &self,
+        matrix: &Matrix4,
+    ) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+
+        let center = self.center();
+        let extent = self.size() * 0.5;
+
+        let row = |row: usize| {
+            let center = matrix[row * 4] * center.x + matrix[row * 4 + 1] * center.y + matrix[row * 4 + 2] * center.z + matrix[row * 4 + 3];
+            let extent = matrix[row * 4].abs() * extent.x + matrix[row * 4 + 1].abs() * extent.y + matrix[row * 4 + 2].abs() * extent.z;
+
+            (center - extent, center + extent)
+        };
+
+        let (x, y, z) = (row(0), row(1), row(2));
+        Aabb::new(Vector3::new(x.0, y.0, z.0), Vector3::new(x.1, y.1, z.1))
+    }
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Aabb::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{Aabb, Matrix4, Vector3};
+
+    #[test]
+    fn from_points() {
+        let aabb = Aabb::from_points(vec![
+            Vector3::new(1.0, -2.0, 0.0),
+            Vector3::new(-1.0, 4.0, 2.0),
+            Vector3::new(0.0, 0.0, -2.0),
+        ]);
+
+        assert_eq!(aabb, Aabb::new(Vector3::new(-1.0, -2.0, -2.0), Vector3::new(1.0, 4.0, 2.0)));
+        assert_eq!(aabb.center(), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(aabb.size(), Vector3::new(2.0, 6.0, 4.0));
+    }
+
+    #[test]
+    fn empty_boxes() {
+        let aabb = Aabb::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0));
+
+        assert!(Aabb::from_points(Vec::new()).is_empty());
+        assert!(!aabb.is_empty());
+        assert_eq!(aabb.union(&Aabb::empty()), aabb);
+        assert_eq!(Aabb::empty().union(&aabb), aabb);
+    }
+
+    #[test]
+    fn transform() {
+        let aabb = Aabb::new(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0));
+
+        let mut matrix = Matrix4::new_rotation_y(45.0);
+        matrix.translate(0.0, 2.0, 0.0);
+        let transformed = aabb.transform(&matrix);
+
+        let half_diagonal = 2.0_f32.sqrt();
+        assert!((transformed.max.x - half_diagonal).abs() < 1e-5);
+        assert!((transformed.min.z + half_diagonal).abs() < 1e-5);
+        assert!((transformed.min.y - 1.0).abs() < 1e-5 && (transformed.max.y - 3.0).abs() < 1e-5);
+        assert!(Aabb::empty().transform(&matrix).is_empty());
+    }
+}
@@ -0,0 +1,5 @@
+pub use aabb::Aabb;
+pub use sphere::BoundingSphere;
+
+mod aabb;
+mod sphere;
@@ -0,0 +1,144 @@
+use crate::math::{
+    Matrix4,
+    Vector3,
+    Vector4
+};
+
+/// A sphere that contains a set of points, not necessarily the smallest one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vector3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn new(
+        center: Vector3,
+        radius: f32,
+    ) -> Self {
+        BoundingSphere { center, radius }
+    }
+
+    /// Ritter's algorithm, about 5% bigger than the smallest sphere.
+    pub fn from_points(points: &[Vector3]) -> Self {
+        let first = match points.first() {
+            Some(first) => *first,
+            None => return BoundingSphere::new(Vector3::default(), 0.0)
+        };
+
+        let farthest = |from: Vector3| points.iter()
+            .cloned()
+            .fold(from, |farthest, point| {
+                if (point - from).magn() > (farthest - from).magn() { point } else { farthest }
+            });
+
+        //Starts from two points that are far apart
+        let a = farthest(first);
+        let b = farthest(a);
+        let mut sphere = BoundingSphere::new((a + b) * 0.5, (b - a).magn() * 0.5);
+
+        for point in points.iter() {
+            let distance = (*point - sphere.center).magn();
+            if distance > sphere.radius {
+                //Grows towards the point, keeping the opposite side of the sphere in place
+                let radius = (sphere.radius + distance) * 0.5;
+                sphere.center = sphere.center + (*point - sphere.center) * ((radius - sphere.radius) / distance);
+                sphere.radius = radius;
+            }
+        }
+
+        sphere
+    }
+
+    //Methods
+    pub fn contains(
+        &self,
+        point: Vector3,
+    ) -> bool {
+        (point - self.center).magn() <= self.radius
+    }
+
+    /// The sphere moved by the matrix, the longest of its axes scales the radius.
+    pub fn transform(
+        &self,
+        matrix: &Matrix4,
+    ) -> BoundingSphere {
+        let center = *matrix * Vector4::new(self.center.x, self.center.y, self.center.z, 1.0);
+        let scale = (0..3)
+            .map(|column| Vector3::new(matrix[column], matrix[4 + column], matrix[8 + column]).magn())
+            .fold(0.0, f32::max);
+
+        BoundingSphere::new(Vector3::new(center.x, center.y, center.z), self.radius * scale)
+    }
+
+    /// The smallest sphere that contains both spheres.
+    pub fn union(
+        &self,
+        rhs: &BoundingSphere,
+    ) -> BoundingSphere {
+        let offset = rhs.center - self.center;
+        let distance = offset.magn();
+
+        if distance + rhs.radius <= self.radius {
+            return *self;
+        }
+
+        if distance + self.radius <= rhs.radius {
+            return *rhs;
+        }
+
+        let radius = (self.radius + distance + rhs.radius) * 0.5;
+        BoundingSphere::new(self.center + offset * ((radius - self.radius) / distance), radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{BoundingSphere, Matrix4, Vector3};
+
+    #[test]
+    fn from_points() {
+        let points: Vec<Vector3> = (0..50)
+            .map(|i| {
+                let angle = i as f32 * 0.7;
+                Vector3::new(angle.cos() * 2.0 + 1.0, angle.sin() * 2.0, (i % 7) as f32 * 0.25 - 0.75)
+            })
+            .collect();
+
+        let sphere = BoundingSphere::from_points(&points);
+
+        for point in points.iter() {
+            assert!((*point - sphere.center).magn() <= sphere.radius * 1.0001);
+        }
+
+        //The points span a circle of radius 2 and a little bit of depth, the smallest sphere is about 2.14
+        assert!(sphere.radius < 2.5);
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(BoundingSphere::from_points(&[]).radius, 0.0);
+        assert_eq!(BoundingSphere::from_points(&[Vector3::new(1.0, 2.0, 3.0)]), BoundingSphere::new(Vector3::new(1.0, 2.0, 3.0), 0.0));
+    }
+
+    #[test]
+    fn union() {
+        let a = BoundingSphere::new(Vector3::new(-2.0, 0.0, 0.0), 1.0);
+        let b = BoundingSphere::new(Vector3::new(3.0, 0.0, 0.0), 2.0);
+
+        assert_eq!(a.union(&b), BoundingSphere::new(Vector3::new(1.0, 0.0, 0.0), 4.0));
+        assert_eq!(b.union(&BoundingSphere::new(Vector3::new(3.5, 0.0, 0.0), 0.5)), b);
+        assert_eq!(a.union(&b), b.union(&a));
+    }
+
+    #[test]
+    fn transform() {
+        let mut matrix = Matrix4::new_scale(1.0, 3.0, 2.0);
+        matrix.translate(1.0, 2.0, 3.0);
+
+        let sphere = BoundingSphere::new(Vector3::new(0.0, 0.0, 0.0), 1.0).transform(&matrix);
+
+        assert_eq!(sphere.radius, 3.0);
+        assert!((sphere.center - Vector3::new(1.0, 2.0, 3.0)).magn() < 1e-5);
+    }
+}
@@ -254,6 +254,40 @@ impl Matrix4 {
         }
     }
 
+    /// Same depth convention as `perspective`, the view looks down -z.
+    pub fn orthographic(
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        near: f32,
+        far: f32,
+    ) -> Self {
+        Matrix4 {
+            entries: [
+                2.0 / (right - left),
+                0.0,
+                0.0,
+                -(right + left) / (right - left),
+
+                0.0,
+                2.0 / (top - bottom),
+                0.0,
+                -(top + bottom) / (top - bottom),
+
+                0.0,
+                0.0,
+                -2.0 / (far - near),
+                -(far + near) / (far - near),
+
+                0.0,
+                0.0,
+                0.0,
+                1.0,
+            ],
+        }
+    }
+
     pub fn new_projection() -> Self {
         let mut res = Matrix4::new();
         res[0] = -1.0;
@@ -412,6 +446,37 @@ impl Matrix4 {
         result
     }
 
+    /// Returns None if the matrix is singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let m = &self.entries;
+        let mut inv = [0.0; 16];
+
+        //NOTE: Cofactor expansion, the adjugate is divided by the determinant
+        inv[0] = m[5] * m[10] * m[15] - m[5] * m[11] * m[14] - m[9] * m[6] * m[15] + m[9] * m[7] * m[14] + m[13] * m[6] * m[11] - m[13] * m[7] * m[10];
+        inv[4] = -m[4] * m[10] * m[15] + m[4] * m[11] * m[14] + m[8] * m[6] * m[15] - m[8] * m[7] * m[14] - m[12] * m[6] * m[11] + m[12] * m[7] * m[10];
+        inv[8] = m[4] * m[9] * m[15] - m[4] * m[11] * m[13] - m[8] * m[5] * m[15] + m[8] * m[7] * m[13] + m[12] * m[5] * m[11] - m[12] * m[7] * m[9];
+        inv[12] = -m[4] * m[9] * m[14] + m[4] * m[10] * m[13] + m[8] * m[5] * m[14] - m[8] * m[6] * m[13] - m[12] * m[5] * m[10] + m[12] * m[6] * m[9];
+        inv[1] = -m[1] * m[10] * m[15] + m[1] * m[11] * m[14] + m[9] * m[2] * m[15] - m[9] * m[3] * m[14] - m[13] * m[2] * m[11] + m[13] * m[3] * m[10];
+        inv[5] = m[0] * m[10] * m[15] - m[0] * m[11] * m[14] - m[8] * m[2] * m[15] + m[8] * m[3] * m[14] + m[12] * m[2] * m[11] - m[12] * m[3] * m[10];
+        inv[9] = -m[0] * m[9] * m[15] + m[0] * m[11] * m[13] + m[8] * m[1] * m[15] - m[8] * m[3] * m[13] - m[12] * m[1] * m[11] + m[12] * m[3] * m[9];
+        inv[13] = m[0] * m[9] * m[14] - m[0] * m[10] * m[13] - m[8] * m[1] * m[14] + m[8] * m[2] * m[13] + m[12] * m[1] * m[10] - m[12] * m[2] * m[9];
+        inv[2] = m[1] * m[6] * m[15] - m[1] * m[7] * m[14] - m[5] * m[2] * m[15] + m[5] * m[3] * m[14] + m[13] * m[2] * m[7] - m[13] * m[3] * m[6];
+        inv[6] = -m[0] * m[6] * m[15] + m[0] * m[7] * m[14] + m[4] * m[2] * m[15] - m[4] * m[3] * m[14] - m[12] * m[2] * m[7] + m[12] * m[3] * m[6];
+        inv[10] = m[0] * m[5] * m[15] - m[0] * m[7] * m[13] - m[4] * m[1] * m[15] + m[4] * m[3] * m[13] + m[12] * m[1] * m[7] - m[12] * m[3] * m[5];
+        inv[14] = -m[0] * m[5] * m[14] + m[0] * m[6] * m[13] + m[4] * m[1] * m[14] - m[4] * m[2] * m[13] - m[12] * m[1] * m[6] + m[12] * m[2] * m[5];
+        inv[3] = -m[1] * m[6] * m[11] + m[1] * m[7] * m[10] + m[5] * m[2] * m[11] - m[5] * m[3] * m[10] - m[9] * m[2] * m[7] + m[9] * m[3] * m[6];
+        inv[7] = m[0] * m[6] * m[11] - m[0] * m[7] * m[10] - m[4] * m[2] * m[11] + m[4] * m[3] * m[10] + m[8] * m[2] * m[7] - m[8] * m[3] * m[6];
+        inv[11] = -m[0] * m[5] * m[11] + m[0] * m[7] * m[9] + m[4] * m[1] * m[11] - m[4] * m[3] * m[9] - m[8] * m[1] * m[7] + m[8] * m[3] * m[5];
+        inv[15] = m[0] * m[5] * m[10] - m[0] * m[6] * m[9] - m[4] * m[1] * m[10] + m[4] * m[2] * m[9] + m[8] * m[1] * m[6] - m[8] * m[2] * m[5];
+
+        let det = m[0] * inv[0] + m[1] * inv[4] + m[2] * inv[8] + m[3] * inv[12];
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+
+        Some(Matrix4 { entries: inv } * (1.0 / det))
+    }
+
     pub fn transpose(&self) -> Self {
         let mut result = Matrix4::zero();
 
@@ -801,6 +866,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn inverse() {
+        let mat = Matrix4::new_traslation(3.0, -4.0, 5.0) * Matrix4::new_rotation_y(30.0) * Matrix4::new_scale(2.0, 2.0, 2.0);
+
+        let identity = mat * mat.inverse().unwrap();
+
+        for i in 0..16 {
+            let expected = if i % 5 == 0 { 1.0 } else { 0.0 };
+            assert!((identity[i] - expected).abs() < 1e-5);
+        }
+
+        assert!(Matrix4::zero().inverse().is_none());
+    }
+
+    #[test]
+    fn orthographic() {
+        let mat = Matrix4::orthographic(-2.0, 2.0, -1.0, 1.0, 1.0, 11.0);
+
+        let corner = mat * Vector4::new(2.0, -1.0, -1.0, 1.0);
+        assert_eq!(corner.x, 1.0);
+        assert_eq!(corner.y, -1.0);
+        assert_eq!(corner.z, -1.0);
+
+        let far = mat * Vector4::new(0.0, 0.0, -11.0, 1.0);
+        assert_eq!(far.z, 1.0);
+        assert_eq!(far.w, 1.0);
+    }
+
     //Translation
     #[test]
     fn translate() {
@@ -1,3 +1,10 @@
+pub use angles::Quartenion;
+
+pub use bounds::{
+    Aabb,
+    BoundingSphere
+};
+
 pub use matrix::{
     Matrix2,
     Matrix3,
@@ -16,6 +23,8 @@ pub use point::{
     Point4
 };
 
+mod angles;
+mod bounds;
 mod matrix;
 mod point;
 mod vector;
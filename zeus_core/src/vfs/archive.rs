@@ -0,0 +1,250 @@
+//NOTE: This file is also compiled by the root build.rs to pack the data directory,
+//so it should only depend on std and flate2.
+
+use flate2::{
+    read::DeflateDecoder,
+    write::DeflateEncoder,
+    Compression
+};
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{
+        self, BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write
+    },
+    path::Path,
+    sync::Mutex
+};
+
+pub const ARCHIVE_MAGIC: &[u8; 4] = b"ZPAK";
+pub const ARCHIVE_VERSION: u32 = 2;
+
+const FLAG_DEFLATE: u32 = 1;
+
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    pub offset: u64,
+    pub size: u64,
+    pub uncompressed_size: u64,
+    pub flags: u32,
+}
+
+impl ArchiveEntry {
+    pub fn is_compressed(&self) -> bool {
+        self.flags & FLAG_DEFLATE != 0
+    }
+}
+
+/// A packed asset file.
+/// Layout: magic, version, entry count, index of (path, offset, size, uncompressed size, flags)
+/// and then the file blobs. Paths are relative to the packed directory and use '/' separators.
+#[derive(Debug)]
+pub struct Archive {
+    file: Mutex<BufReader<File>>,
+    entries: BTreeMap<String, ArchiveEntry>,
+}
+
+impl Archive {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut magic = [0_u8; 4];
+        file.read_exact(&mut magic)?;
+        if &magic != ARCHIVE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not an asset archive"));
+        }
+
+        let version = read_u32(&mut file)?;
+        if version != ARCHIVE_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported archive version {}, expected {}", version, ARCHIVE_VERSION),
+            ));
+        }
+
+        let entry_count = read_u32(&mut file)?;
+        let mut entries = BTreeMap::new();
+
+        for _ in 0..entry_count {
+            let name_len = read_u32(&mut file)? as usize;
+            let mut name = vec![0_u8; name_len];
+            file.read_exact(&mut name)?;
+
+            let name = String::from_utf8(name)
+                .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid entry name"))?;
+
+            let entry = ArchiveEntry {
+                offset: read_u64(&mut file)?,
+                size: read_u64(&mut file)?,
+                uncompressed_size: read_u64(&mut file)?,
+                flags: read_u32(&mut file)?,
+            };
+
+            entries.insert(name, entry);
+        }
+
+        Ok(Archive {
+            file: Mutex::new(file),
+            entries,
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &ArchiveEntry)> {
+        self.entries.iter()
+    }
+
+    pub fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let entry = self.entries.get(name)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("{} is not in the archive", name)))?;
+
+        let mut data = vec![0_u8; entry.size as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(entry.offset))?;
+            file.read_exact(&mut data)?;
+        }
+
+        if !entry.is_compressed() {
+            return Ok(data);
+        }
+
+        let mut decompressed = Vec::with_capacity(entry.uncompressed_size as usize);
+        DeflateDecoder::new(&data[..]).read_to_end(&mut decompressed)?;
+
+        Ok(decompressed)
+    }
+}
+
+/// Builds an archive in memory and writes it out in one go.
+#[derive(Debug, Default)]
+pub struct ArchiveWriter {
+    entries: Vec<(String, Vec<u8>, u64, u32)>,
+}
+
+impl ArchiveWriter {
+    pub fn new() -> Self {
+        ArchiveWriter {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds a file. When compression is requested the data is only stored compressed if that makes it smaller.
+    pub fn add_file(&mut self, name: &str, data: Vec<u8>, compress: bool) -> io::Result<()> {
+        let uncompressed_size = data.len() as u64;
+
+        let (data, flags) = if compress {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&data)?;
+            let compressed = encoder.finish()?;
+
+            if compressed.len() < data.len() {
+                (compressed, FLAG_DEFLATE)
+            } else {
+                (data, 0)
+            }
+        } else {
+            (data, 0)
+        };
+
+        self.entries.push((name.to_string(), data, uncompressed_size, flags));
+
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let index_size: usize = self.entries.iter()
+            .map(|(name, ..)| 4 + name.len() + 8 + 8 + 8 + 4)
+            .sum();
+
+        let mut offset = (ARCHIVE_MAGIC.len() + 4 + 4 + index_size) as u64;
+
+        writer.write_all(ARCHIVE_MAGIC)?;
+        writer.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+
+        for (name, data, uncompressed_size, flags) in self.entries.iter() {
+            writer.write_all(&(name.len() as u32).to_le_bytes())?;
+            writer.write_all(name.as_bytes())?;
+            writer.write_all(&offset.to_le_bytes())?;
+            writer.write_all(&(data.len() as u64).to_le_bytes())?;
+            writer.write_all(&uncompressed_size.to_le_bytes())?;
+            writer.write_all(&flags.to_le_bytes())?;
+
+            offset += data.len() as u64;
+        }
+
+        for (_, data, ..) in self.entries.iter() {
+            writer.write_all(data)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        self.write(&mut file)
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0_u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0_u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Archive, ArchiveWriter};
+
+    use std::{env, fs, process};
+
+    #[test]
+    fn write_and_read() {
+        let path = env::temp_dir().join(format!("zeus_archive_test_{}.zpk", process::id()));
+
+        let text = "Lorem ipsum dolor sit amet ".repeat(64).into_bytes();
+        let binary = vec![0_u8, 1, 2, 3, 255];
+
+        let mut writer = ArchiveWriter::new();
+        writer.add_file("textures/text.txt", text.clone(), true).unwrap();
+        writer.add_file("shaders/binary.spv", binary.clone(), false).unwrap();
+        writer.write_to_file(&path).unwrap();
+
+        let archive = Archive::open(&path).unwrap();
+
+        assert!(archive.contains("textures/text.txt"));
+        assert!(!archive.contains("textures/missing.txt"));
+        assert_eq!(archive.read("textures/text.txt").unwrap(), text);
+        assert_eq!(archive.read("shaders/binary.spv").unwrap(), binary);
+        assert!(archive.read("textures/missing.txt").is_err());
+
+        let (_, entry) = archive.entries()
+            .find(|(name, _)| name.as_str() == "textures/text.txt")
+            .unwrap();
+        assert!(entry.is_compressed());
+        assert!(entry.size < entry.uncompressed_size);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn invalid_magic() {
+        let path = env::temp_dir().join(format!("zeus_archive_invalid_{}.zpk", process::id()));
+        fs::write(&path, b"NOPE0000").unwrap();
+
+        assert!(Archive::open(&path).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,42 @@
+use std::{
+    fs,
+    io,
+    path::PathBuf,
+    time::SystemTime
+};
+
+use super::FileSource;
+
+/// Loose files in a directory on disk.
+#[derive(Debug)]
+pub struct DirectorySource {
+    root: PathBuf,
+}
+
+impl DirectorySource {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        DirectorySource {
+            root: root.into(),
+        }
+    }
+}
+
+impl FileSource for DirectorySource {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.root.join(path))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.root.join(path).is_file()
+    }
+
+    fn disk_path(&self, path: &str) -> Option<PathBuf> {
+        Some(self.root.join(path))
+    }
+
+    fn modified(&self, path: &str) -> Option<SystemTime> {
+        fs::metadata(self.root.join(path))
+            .and_then(|meta| meta.modified())
+            .ok()
+    }
+}
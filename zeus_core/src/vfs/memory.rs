@@ -0,0 +1,44 @@
+use std::{
+    collections::BTreeMap,
+    io::{
+        self, Error, ErrorKind
+    }
+};
+
+use super::{
+    normalize_path, FileSource
+};
+
+/// Files kept in memory, for assets that only exist inside other files like the images of a glTF binary.
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        MemorySource {
+            files: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_file(&mut self, path: &str, data: Vec<u8>) {
+        self.files.insert(normalize_path(path), data);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+impl FileSource for MemorySource {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        self.files.get(path)
+            .cloned()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, path.to_string()))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+}
@@ -0,0 +1,292 @@
+pub use archive::{
+    Archive, ArchiveWriter
+};
+pub use directory::DirectorySource;
+pub use memory::MemorySource;
+
+mod archive;
+mod directory;
+mod memory;
+
+use std::{
+    io::{
+        self, Error, ErrorKind
+    },
+    path::{
+        Path, PathBuf
+    },
+    sync::RwLock,
+    time::SystemTime
+};
+
+lazy_static! {
+    static ref VFS: RwLock<FileSystem> = RwLock::new(FileSystem::new());
+}
+
+/// Something that can provide files to the virtual file system.
+/// Paths are relative to the mount point and use '/' separators.
+pub trait FileSource: Send + Sync {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+
+    fn exists(&self, path: &str) -> bool;
+
+    /// The path of the file on disk, if it is a loose file.
+    fn disk_path(&self, _path: &str) -> Option<PathBuf> {
+        None
+    }
+
+    fn modified(&self, _path: &str) -> Option<SystemTime> {
+        None
+    }
+}
+
+impl FileSource for Archive {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        Archive::read(self, path)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.contains(path)
+    }
+}
+
+//Public methods
+pub fn mount(mount_point: &str, source: Box<dyn FileSource>) {
+    VFS.write().unwrap().mount(mount_point, source)
+}
+
+pub fn mount_dir<P: Into<PathBuf>>(mount_point: &str, dir: P) {
+    let dir = dir.into();
+    info!("Mounting directory {:?} at '{}'", dir, mount_point);
+
+    mount(mount_point, Box::new(DirectorySource::new(dir)))
+}
+
+pub fn mount_archive<P: AsRef<Path>>(mount_point: &str, path: P) -> io::Result<()> {
+    let archive = Archive::open(path.as_ref())?;
+    info!("Mounting archive {:?} at '{}'", path.as_ref(), mount_point);
+
+    mount(mount_point, Box::new(archive));
+
+    Ok(())
+}
+
+pub fn unmount(mount_point: &str) {
+    VFS.write().unwrap().unmount(mount_point)
+}
+
+pub fn read(path: &str) -> io::Result<Vec<u8>> {
+    VFS.read().unwrap().read(path)
+}
+
+pub fn read_to_string(path: &str) -> io::Result<String> {
+    String::from_utf8(read(path)?)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+pub fn exists(path: &str) -> bool {
+    VFS.read().unwrap().exists(path)
+}
+
+pub fn disk_path(path: &str) -> Option<PathBuf> {
+    VFS.read().unwrap().disk_path(path)
+}
+
+pub fn modified(path: &str) -> Option<SystemTime> {
+    VFS.read().unwrap().modified(path)
+}
+
+pub fn is_up_to_date(source_path: &str, built_path: &str) -> bool {
+    VFS.read().unwrap().is_up_to_date(source_path, built_path)
+}
+
+struct Mount {
+    point: String,
+    source: Box<dyn FileSource>,
+}
+
+/// Resolves virtual paths to the mounted sources.
+/// Sources mounted later take precedence, so loose files can override a packed archive.
+pub struct FileSystem {
+    mounts: Vec<Mount>,
+}
+
+impl FileSystem {
+    pub fn new() -> Self {
+        FileSystem {
+            mounts: Vec::new(),
+        }
+    }
+
+    pub fn mount(&mut self, mount_point: &str, source: Box<dyn FileSource>) {
+        self.mounts.push(Mount {
+            point: normalize_path(mount_point),
+            source,
+        });
+    }
+
+    pub fn unmount(&mut self, mount_point: &str) {
+        let mount_point = normalize_path(mount_point);
+        self.mounts.retain(|mount| mount.point != mount_point);
+    }
+
+    pub fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        match self.find(path) {
+            Some((source, relative)) => source.read(&relative),
+            None => Err(Error::new(ErrorKind::NotFound, format!("{} was not found in any mount", path)))
+        }
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.find(path).is_some()
+    }
+
+    pub fn disk_path(&self, path: &str) -> Option<PathBuf> {
+        self.find(path)
+            .and_then(|(source, relative)| source.disk_path(&relative))
+    }
+
+    pub fn modified(&self, path: &str) -> Option<SystemTime> {
+        self.find(path)
+            .and_then(|(source, relative)| source.modified(&relative))
+    }
+
+    /// Whether a file built from a source, like a compiled shader, can be used instead of building it again.
+    /// Packed files have no timestamps, a packed build is only trusted if its source is not a loose file that could have been edited.
+    pub fn is_up_to_date(&self, source_path: &str, built_path: &str) -> bool {
+        if !self.exists(built_path) {
+            return false;
+        }
+
+        //NOTE: Shipped builds may only contain the built files
+        if !self.exists(source_path) {
+            return true;
+        }
+
+        match (self.modified(source_path), self.modified(built_path)) {
+            (Some(source_modified), Some(built_modified)) => built_modified >= source_modified,
+            (Some(_), None) => false,
+            (None, _) => true
+        }
+    }
+
+    fn find(&self, path: &str) -> Option<(&dyn FileSource, String)> {
+        let path = normalize_path(path);
+
+        self.mounts.iter().rev()
+            .filter_map(|mount| {
+                relative_path(&mount.point, &path)
+                    .map(|relative| (mount.source.as_ref(), relative))
+            })
+            .find(|(source, relative)| source.exists(relative))
+    }
+}
+
+impl Default for FileSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Turns a path like `./data\textures/../models/a.obj` into `data/models/a.obj`.
+pub fn normalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {},
+            ".." => {
+                parts.pop();
+            },
+            _ => parts.push(part),
+        }
+    }
+
+    parts.join("/")
+}
+
+fn relative_path(mount_point: &str, path: &str) -> Option<String> {
+    if mount_point.is_empty() {
+        return Some(path.to_string());
+    }
+
+    if path == mount_point {
+        return Some(String::new());
+    }
+
+    path.strip_prefix(mount_point)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .map(|rest| rest.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_path, DirectorySource, FileSystem, MemorySource};
+
+    use std::{
+        env, fs as std_fs, process
+    };
+
+    fn memory(files: &[(&str, &str)]) -> Box<MemorySource> {
+        let mut source = MemorySource::new();
+        for (name, data) in files {
+            source.add_file(name, data.as_bytes().to_vec());
+        }
+
+        Box::new(source)
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_path("./data/textures/logo.png"), "data/textures/logo.png");
+        assert_eq!(normalize_path("data\\textures\\logo.png"), "data/textures/logo.png");
+        assert_eq!(normalize_path("data/models/../textures//logo.png"), "data/textures/logo.png");
+        assert_eq!(normalize_path("./"), "");
+    }
+
+    #[test]
+    fn read_from_mount() {
+        let mut fs = FileSystem::new();
+        fs.mount("data", memory(&[("textures/logo.png", "logo")]));
+
+        assert_eq!(fs.read("./data/textures/logo.png").unwrap(), b"logo");
+        assert!(fs.exists("data/textures/logo.png"));
+        assert!(!fs.exists("textures/logo.png"));
+        assert!(fs.read("data/textures/missing.png").is_err());
+    }
+
+    #[test]
+    fn later_mounts_take_precedence() {
+        let mut fs = FileSystem::new();
+        fs.mount("data", memory(&[("a.txt", "archive"), ("b.txt", "archive")]));
+        fs.mount("data", memory(&[("a.txt", "loose")]));
+
+        assert_eq!(fs.read("data/a.txt").unwrap(), b"loose");
+        assert_eq!(fs.read("data/b.txt").unwrap(), b"archive");
+
+        fs.unmount("data");
+
+        assert!(!fs.exists("data/a.txt"));
+    }
+
+    #[test]
+    fn loose_source_outdates_packed_build() {
+        let dir = env::temp_dir().join(format!("zeus_vfs_up_to_date_{}", process::id()));
+        std_fs::create_dir_all(&dir).unwrap();
+        std_fs::write(dir.join("a.vert"), "void main() {}").unwrap();
+
+        let mut fs = FileSystem::new();
+        fs.mount("data", memory(&[("a.vert", "packed"), ("a.vert.spv", "packed")]));
+        assert!(fs.is_up_to_date("data/a.vert", "data/a.vert.spv"));
+        assert!(!fs.is_up_to_date("data/a.vert", "data/b.vert.spv"));
+
+        //The loose source could be newer than the packed binary
+        fs.mount("data", Box::new(DirectorySource::new(dir.clone())));
+        assert!(!fs.is_up_to_date("data/a.vert", "data/a.vert.spv"));
+
+        std_fs::write(dir.join("a.vert.spv"), "loose").unwrap();
+        assert!(fs.is_up_to_date("data/a.vert", "data/a.vert.spv"));
+
+        std_fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,12 +1,11 @@
 use gfx_hal::{
-    adapter::{Adapter, MemoryType, PhysicalDevice},
+    adapter::{Adapter, PhysicalDevice},
     Backend,
     Limits,
 };
 
 pub struct AdapterState<B: Backend> {
     pub adapter: Option<Adapter<B>>,
-    pub memory_types: Vec<MemoryType>,
     pub limits: Limits,
 }
 
@@ -16,12 +15,10 @@ impl<B: Backend> AdapterState<B> {
     }
 
     fn new_adapter(adapter: Adapter<B>) -> Self {
-        let memory_types = adapter.physical_device.memory_properties().memory_types;
         let limits = adapter.physical_device.limits();
 
         AdapterState {
             adapter: Some(adapter),
-            memory_types,
             limits,
         }
     }
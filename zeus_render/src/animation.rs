@@ -0,0 +1,563 @@
+use zeus_core::math::{
+    Matrix4,
+    Quartenion,
+    Vector3
+};
+
+use super::gltf_import::{
+    Animation, GltfModel, Interpolation, Keyframes, Transform
+};
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Joint {
+    pub name: String,
+    /// An index into the joints of the same skeleton
+    pub parent: Option<usize>,
+    /// The local transform when no clip animates the joint
+    pub rest: Transform,
+    /// Takes the mesh from the bind pose into the local space of the joint
+    pub inverse_bind_matrix: Matrix4,
+    /// The nodes between the parent joint and this one, or above a root joint, they are not animated
+    offset: Matrix4,
+    node: usize,
+}
+
+/// The joint hierarchy of a skin. The joint indices of the vertices point into `joints`.
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    /// Parents come before their children
+    order: Vec<usize>,
+}
+
+impl Skeleton {
+    pub fn from_gltf(model: &GltfModel, skin: usize) -> Self {
+        let skin = &model.skins[skin];
+        let joint_of = |node: usize| skin.joints.iter().position(|joint| *joint == node);
+
+        let joints: Vec<Joint> = skin.joints.iter()
+            .enumerate()
+            .map(|(index, &node)| {
+                //Walks up to the closest joint, the nodes on the way are baked into the offset
+                let mut offset = Matrix4::new();
+                let mut parent = model.nodes[node].parent;
+                while let Some(parent_node) = parent {
+                    if joint_of(parent_node).is_some() {
+                        break;
+                    }
+
+                    offset = model.nodes[parent_node].transform.matrix() * offset;
+                    parent = model.nodes[parent_node].parent;
+                }
+
+                Joint {
+                    name: model.nodes[node].name.clone(),
+                    parent: parent.and_then(joint_of),
+                    rest: model.nodes[node].transform,
+                    inverse_bind_matrix: skin.inverse_bind_matrices.get(index)
+                        .cloned()
+                        .unwrap_or_else(Matrix4::new),
+                    offset,
+                    node,
+                }
+            })
+            .collect();
+
+        let depth = |mut joint: usize| {
+            let mut depth = 0;
+            while let Some(parent) = joints[joint].parent {
+                joint = parent;
+                depth += 1;
+            }
+
+            depth
+        };
+
+        let mut order: Vec<usize> = (0..joints.len()).collect();
+        order.sort_by_key(|joint| depth(*joint));
+
+        Skeleton {
+            joints,
+            order,
+        }
+    }
+
+    pub fn rest_pose(&self) -> Vec<Transform> {
+        self.joints.iter()
+            .map(|joint| joint.rest)
+            .collect()
+    }
+
+    /// The matrix of each joint that takes a vertex from the bind pose to the pose, in the space of the model.
+    pub fn joint_matrices(&self, pose: &[Transform]) -> Vec<Matrix4> {
+        let mut globals = vec![Matrix4::new(); self.joints.len()];
+
+        for &index in self.order.iter() {
+            let joint = &self.joints[index];
+            let parent = match joint.parent {
+                Some(parent) => globals[parent],
+                None => Matrix4::new(),
+            };
+
+            globals[index] = parent * joint.offset * pose[index].matrix();
+        }
+
+        globals.iter()
+            .zip(self.joints.iter())
+            .map(|(global, joint)| *global * joint.inverse_bind_matrix)
+            .collect()
+    }
+
+    fn find_joint(&self, node: usize) -> Option<usize> {
+        self.joints.iter().position(|joint| joint.node == node)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Track {
+    joint: usize,
+    interpolation: Interpolation,
+    times: Vec<f32>,
+    keyframes: Keyframes,
+}
+
+/// The keyframes of the joints of one skeleton.
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    /// In seconds
+    pub duration: f32,
+    tracks: Vec<Track>,
+}
+
+impl AnimationClip {
+    /// Only the channels of the joints are kept, morph targets are not supported.
+    pub fn from_gltf(animation: &Animation, skeleton: &Skeleton) -> Self {
+        let tracks = animation.channels.iter()
+            .filter(|channel| !matches!(channel.keyframes, Keyframes::Weights(_)))
+            .filter_map(|channel| skeleton.find_joint(channel.node)
+                .map(|joint| Track {
+                    joint,
+                    interpolation: channel.interpolation,
+                    times: channel.times.clone(),
+                    keyframes: channel.keyframes.clone(),
+                }))
+            .collect();
+
+        AnimationClip {
+            name: animation.name.clone(),
+            duration: animation.duration,
+            tracks,
+        }
+    }
+
+    /// Overwrites the properties of the animated joints, the rest of `pose` is kept.
+    pub fn sample(&self, time: f32, pose: &mut [Transform]) {
+        for track in self.tracks.iter() {
+            let transform = &mut pose[track.joint];
+
+            match &track.keyframes {
+                Keyframes::Translations(values) => {
+                    transform.translation = sample_vector(values, &track.times, track.interpolation, time);
+                },
+                Keyframes::Rotations(values) => {
+                    transform.rotation = sample_rotation(values, &track.times, track.interpolation, time);
+                },
+                Keyframes::Scales(values) => {
+                    transform.scale = sample_vector(values, &track.times, track.interpolation, time);
+                },
+                Keyframes::Weights(_) => {}
+            }
+        }
+    }
+}
+
+/// The keyframes around `time` and how far between them it is, times outside the clip hold the first or last keyframe.
+fn keyframe_span(times: &[f32], time: f32) -> (usize, usize, f32) {
+    let next = times.iter().position(|key| *key > time).unwrap_or(times.len());
+
+    if next == 0 {
+        return (0, 0, 0.0);
+    }
+
+    if next == times.len() {
+        return (next - 1, next - 1, 0.0);
+    }
+
+    let span = times[next] - times[next - 1];
+    (next - 1, next, (time - times[next - 1]) / span)
+}
+
+/// Hermite weights of the start value, start tangent, end value and end tangent.
+fn hermite(t: f32) -> [f32; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+
+    [
+        2.0 * t3 - 3.0 * t2 + 1.0,
+        t3 - 2.0 * t2 + t,
+        -2.0 * t3 + 3.0 * t2,
+        t3 - t2,
+    ]
+}
+
+//NOTE: Cubic spline keyframes are stored as in tangent, value and out tangent
+fn sample_vector(values: &[Vector3], times: &[f32], interpolation: Interpolation, time: f32) -> Vector3 {
+    let (from, to, t) = keyframe_span(times, time);
+
+    match interpolation {
+        Interpolation::Step => values[from],
+        Interpolation::Linear => values[from] * (1.0 - t) + values[to] * t,
+        Interpolation::CubicSpline => {
+            let span = times[to] - times[from];
+            let [a, b, c, d] = hermite(t);
+
+            values[from * 3 + 1] * a
+                + values[from * 3 + 2] * (b * span)
+                + values[to * 3 + 1] * c
+                + values[to * 3] * (d * span)
+        }
+    }
+}
+
+fn sample_rotation(values: &[Quartenion], times: &[f32], interpolation: Interpolation, time: f32) -> Quartenion {
+    let (from, to, t) = keyframe_span(times, time);
+
+    match interpolation {
+        Interpolation::Step => values[from],
+        Interpolation::Linear => values[from].slerp(&values[to], t),
+        Interpolation::CubicSpline => {
+            let span = times[to] - times[from];
+            let [a, b, c, d] = hermite(t);
+
+            weighted_sum(&[
+                (values[from * 3 + 1], a),
+                (values[from * 3 + 2], b * span),
+                (values[to * 3 + 1], c),
+                (values[to * 3], d * span),
+            ]).normalize()
+        }
+    }
+}
+
+fn weighted_sum(values: &[(Quartenion, f32)]) -> Quartenion {
+    values.iter()
+        .fold(Quartenion::new(0.0, 0.0, 0.0, 0.0), |sum, (value, weight)| Quartenion::new(
+            sum.x + value.x * weight,
+            sum.y + value.y * weight,
+            sum.z + value.z * weight,
+            sum.w + value.w * weight,
+        ))
+}
+
+#[derive(Debug, Clone)]
+struct Layer {
+    clip: usize,
+    /// In seconds
+    time: f32,
+    speed: f32,
+    looping: bool,
+    weight: f32,
+    target_weight: f32,
+    /// How much the weight moves towards the target every second
+    fade_rate: f32,
+}
+
+/// Plays clips of one skeleton, the poses of the playing clips are blended by their weights.
+#[derive(Debug, Clone, Default)]
+pub struct AnimationPlayer {
+    layers: Vec<Layer>,
+}
+
+impl AnimationPlayer {
+    pub fn new() -> Self {
+        AnimationPlayer {
+            layers: Vec::new(),
+        }
+    }
+
+    /// Stops every clip and plays `clip` from the start.
+    pub fn play(&mut self, clip: usize, looping: bool) {
+        self.layers.clear();
+        self.blend(clip, 1.0, looping);
+    }
+
+    /// Plays `clip` on top of the playing clips.
+    pub fn blend(&mut self, clip: usize, weight: f32, looping: bool) {
+        self.layers.retain(|layer| layer.clip != clip);
+        self.layers.push(Layer {
+            clip,
+            time: 0.0,
+            speed: 1.0,
+            looping,
+            weight,
+            target_weight: weight,
+            fade_rate: 0.0,
+        });
+    }
+
+    /// Fades `clip` in and every other clip out over `duration` seconds.
+    /// A clip that is already playing keeps its time.
+    pub fn crossfade(&mut self, clip: usize, duration: f32, looping: bool) {
+        if duration <= 0.0 {
+            self.play(clip, looping);
+            return;
+        }
+
+        if !self.layers.iter().any(|layer| layer.clip == clip) {
+            self.blend(clip, 0.0, looping);
+        }
+
+        for layer in self.layers.iter_mut() {
+            layer.target_weight = if layer.clip == clip { 1.0 } else { 0.0 };
+            layer.fade_rate = 1.0 / duration;
+
+            if layer.clip == clip {
+                layer.looping = looping;
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_weight(&mut self, clip: usize, weight: f32) {
+        for layer in self.layers.iter_mut().filter(|layer| layer.clip == clip) {
+            layer.weight = weight;
+            layer.target_weight = weight;
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_speed(&mut self, clip: usize, speed: f32) {
+        for layer in self.layers.iter_mut().filter(|layer| layer.clip == clip) {
+            layer.speed = speed;
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn stop(&mut self) {
+        self.layers.clear();
+    }
+
+    /// The clip that is playing or fading in at the highest weight.
+    pub fn current_clip(&self) -> Option<usize> {
+        self.layers.iter()
+            .max_by(|a, b| a.target_weight.partial_cmp(&b.target_weight).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|layer| layer.clip)
+    }
+
+    /// Advances the clips and the fades, `delta` is in seconds.
+    pub fn update(&mut self, delta: f32, clips: &[AnimationClip]) {
+        for layer in self.layers.iter_mut() {
+            let duration = clips[layer.clip].duration;
+            layer.time += delta * layer.speed;
+
+            layer.time = if layer.looping && duration > 0.0 {
+                layer.time.rem_euclid(duration)
+            } else {
+                layer.time.max(0.0).min(duration)
+            };
+
+            let step = layer.fade_rate * delta;
+            layer.weight = if layer.weight < layer.target_weight {
+                (layer.weight + step).min(layer.target_weight)
+            } else {
+                (layer.weight - step).max(layer.target_weight)
+            };
+        }
+
+        //Faded out clips are done
+        self.layers.retain(|layer| layer.weight > 0.0 || layer.target_weight > 0.0);
+    }
+
+    /// The weighted average of the poses of the playing clips, the rest pose when nothing plays.
+    pub fn pose(&self, skeleton: &Skeleton, clips: &[AnimationClip]) -> Vec<Transform> {
+        let rest = skeleton.rest_pose();
+
+        let total_weight: f32 = self.layers.iter().map(|layer| layer.weight).sum();
+        if total_weight <= 0.0 {
+            return rest;
+        }
+
+        let mut translations = vec![Vector3::new(0.0, 0.0, 0.0); rest.len()];
+        let mut scales = vec![Vector3::new(0.0, 0.0, 0.0); rest.len()];
+        let mut rotations = vec![Quartenion::new(0.0, 0.0, 0.0, 0.0); rest.len()];
+
+        for layer in self.layers.iter().filter(|layer| layer.weight > 0.0) {
+            let mut pose = rest.clone();
+            clips[layer.clip].sample(layer.time, &mut pose);
+
+            let weight = layer.weight / total_weight;
+            for (joint, transform) in pose.iter().enumerate() {
+                translations[joint] += transform.translation * weight;
+                scales[joint] += transform.scale * weight;
+
+                //NOTE: q and -q are the same rotation, they have to point the same way to be averaged
+                let rotation = if rotations[joint].dot(&transform.rotation) < 0.0 {
+                    -weight
+                } else {
+                    weight
+                };
+                rotations[joint] = weighted_sum(&[(rotations[joint], 1.0), (transform.rotation, rotation)]);
+            }
+        }
+
+        (0..rest.len())
+            .map(|joint| Transform {
+                translation: translations[joint],
+                rotation: rotations[joint].normalize(),
+                scale: scales[joint],
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        keyframe_span, sample_vector, AnimationClip, AnimationPlayer, Skeleton
+    };
+
+    use crate::gltf_import::{
+        Animation, Channel, GltfModel, Interpolation, Keyframes, Node, Skin, Transform
+    };
+
+    use zeus_core::math::{
+        Matrix4, Quartenion, Vector3, Vector4
+    };
+
+    fn node(name: &str, parent: Option<usize>, children: Vec<usize>, translation: Vector3) -> Node {
+        Node {
+            name: name.to_string(),
+            parent,
+            children,
+            transform: Transform {
+                translation,
+                ..Transform::default()
+            },
+            mesh: None,
+            skin: None,
+        }
+    }
+
+    //An arm of two joints below a node that is not a joint
+    fn arm() -> GltfModel {
+        GltfModel {
+            meshes: Vec::new(),
+            nodes: vec![
+                node("armature", None, vec![1], Vector3::new(0.0, 0.0, 5.0)),
+                node("shoulder", Some(0), vec![2], Vector3::new(0.0, 1.0, 0.0)),
+                node("elbow", Some(1), vec![], Vector3::new(1.0, 0.0, 0.0)),
+            ],
+            roots: vec![0],
+            materials: Vec::new(),
+            skins: vec![Skin {
+                name: "arm".to_string(),
+                //Children before parents, the order of the file is kept
+                joints: vec![2, 1],
+                inverse_bind_matrices: vec![
+                    Matrix4::new_traslation(-1.0, -1.0, -5.0),
+                    Matrix4::new_traslation(0.0, -1.0, -5.0),
+                ],
+                skeleton: Some(1),
+            }],
+            animations: vec![Animation {
+                name: "raise".to_string(),
+                channels: vec![Channel {
+                    node: 1,
+                    interpolation: Interpolation::Linear,
+                    times: vec![0.0, 1.0],
+                    keyframes: Keyframes::Rotations(vec![
+                        Quartenion::identity(),
+                        Quartenion::from_axis_angle(Vector3::Z, 90.0),
+                    ]),
+                }],
+                duration: 1.0,
+            }],
+        }
+    }
+
+    fn transform_point(matrix: Matrix4, point: Vector3) -> Vector3 {
+        let point = matrix * Vector4::new(point.x, point.y, point.z, 1.0);
+        Vector3::new(point.x, point.y, point.z)
+    }
+
+    fn assert_close(a: Vector3, b: Vector3) {
+        assert!((a - b).magn() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn rest_pose_is_the_bind_pose() {
+        let skeleton = Skeleton::from_gltf(&arm(), 0);
+
+        assert_eq!(skeleton.joints[0].parent, Some(1));
+        assert_eq!(skeleton.joints[1].parent, None);
+
+        for matrix in skeleton.joint_matrices(&skeleton.rest_pose()) {
+            assert_close(transform_point(matrix, Vector3::new(1.0, 2.0, 3.0)), Vector3::new(1.0, 2.0, 3.0));
+        }
+    }
+
+    #[test]
+    fn animated_joints_move_their_children() {
+        let model = arm();
+        let skeleton = Skeleton::from_gltf(&model, 0);
+        let clip = AnimationClip::from_gltf(&model.animations[0], &skeleton);
+
+        let mut pose = skeleton.rest_pose();
+        clip.sample(1.0, &mut pose);
+        let matrices = skeleton.joint_matrices(&pose);
+
+        //The hand at the end of the elbow swings from +x to +y around the shoulder
+        assert_close(transform_point(matrices[0], Vector3::new(2.0, 1.0, 5.0)), Vector3::new(0.0, 3.0, 5.0));
+    }
+
+    #[test]
+    fn sample_keyframes() {
+        let times = [1.0, 2.0, 4.0];
+        let values = [Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 0.0, 0.0), Vector3::new(2.0, 4.0, 0.0)];
+
+        assert_eq!(keyframe_span(&times, 0.0), (0, 0, 0.0));
+        assert_eq!(keyframe_span(&times, 3.0), (1, 2, 0.5));
+        assert_eq!(keyframe_span(&times, 5.0), (2, 2, 0.0));
+
+        assert_eq!(sample_vector(&values, &times, Interpolation::Linear, 1.5), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(sample_vector(&values, &times, Interpolation::Step, 1.5), Vector3::new(0.0, 0.0, 0.0));
+
+        //Flat tangents ease in and out, but still go through the keyframes
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        let cubic = [zero, values[0], zero, zero, values[1], zero, zero, values[2], zero];
+        assert_eq!(sample_vector(&cubic, &times, Interpolation::CubicSpline, 2.0), values[1]);
+        assert_eq!(sample_vector(&cubic, &times, Interpolation::CubicSpline, 1.5), Vector3::new(1.0, 0.0, 0.0));
+        assert!(sample_vector(&cubic, &times, Interpolation::CubicSpline, 1.25).x < 0.5);
+    }
+
+    #[test]
+    fn crossfade_blends_the_clips() {
+        let model = arm();
+        let skeleton = Skeleton::from_gltf(&model, 0);
+        let clips = vec![
+            AnimationClip::from_gltf(&model.animations[0], &skeleton),
+            AnimationClip {
+                name: "rest".to_string(),
+                duration: 1.0,
+                tracks: Vec::new(),
+            },
+        ];
+
+        let mut player = AnimationPlayer::new();
+        player.play(0, false);
+        player.update(1.0, &clips);
+
+        player.crossfade(1, 2.0, true);
+        assert_eq!(player.current_clip(), Some(1));
+
+        //Half way through the fade the shoulder is half way back to rest
+        player.update(1.0, &clips);
+        let pose = player.pose(&skeleton, &clips);
+        assert_close(pose[1].rotation.rotate(Vector3::X), Quartenion::from_axis_angle(Vector3::Z, 45.0).rotate(Vector3::X));
+
+        //The faded out clip is dropped
+        player.update(1.0, &clips);
+        assert_eq!(player.layers.len(), 1);
+    }
+}
@@ -1,30 +1,20 @@
 use super::{
     adapter::AdapterState,
-    desc::DescSetLayout,
     device::DeviceState,
-    image::ImageState,
+    memory::Allocation,
     model::Dimensions
 };
 
 use gfx_hal::{
-    adapter::MemoryType,
     buffer::Usage,
     command::{
         BufferCopy, CommandBuffer, CommandBufferFlags, Level
     },
     device::Device,
-    format::{
-        Format, ImageFeature
-    },
     memory::{
         Properties, Segment
     },
-    pool::{
-        CommandPool, CommandPoolCreateFlags
-    },
-    pso::{
-        DescriptorPoolCreateFlags, DescriptorRangeDesc, DescriptorSetLayoutBinding, DescriptorType, ImageDescriptorType, ShaderStageFlags
-    },
+    pool::CommandPool,
     queue::CommandQueue,
     Backend,
 };
@@ -39,7 +29,7 @@ use std::{
 
 #[derive(Debug)]
 pub struct BufferState<B: Backend> {
-    memory: Option<B::Memory>,
+    memory: Option<Allocation>,
     pub buffer: Option<B::Buffer>,
     device: Rc<RefCell<DeviceState<B>>>,
     size: u64,
@@ -53,7 +43,6 @@ impl<B: Backend> BufferState<B> {
         device_ptr: Rc<RefCell<DeviceState<B>>>,
         data_source: &[T],
         usage: Usage,
-        memory_types: &[MemoryType],
         memory_properties: Properties,
     ) -> Self
     where
@@ -63,7 +52,6 @@ impl<B: Backend> BufferState<B> {
             Rc::clone(&device_ptr),
             data_source.len(),
             usage,
-            memory_types,
             memory_properties,
         );
 
@@ -77,38 +65,26 @@ impl<B: Backend> BufferState<B> {
         device_ptr: Rc<RefCell<DeviceState<B>>>,
         data_length: usize,
         usage: Usage,
-        memory_types: &[MemoryType],
         memory_properties: Properties,
     ) -> Self {
-        let memory: B::Memory;
-        let mut buffer: B::Buffer;
-        let size: u64;
-
         let stride = size_of::<T>();
         let upload_size = data_length * stride;
 
-        unsafe {
-            let device = &device_ptr.borrow().device;
+        let (buffer, memory) = {
+            let state = &mut *device_ptr.borrow_mut();
 
             //TODO: Can we set sharing mode?
-            buffer = device.create_buffer(upload_size as u64, usage)
-                .unwrap();
-            let mem_req = device.get_buffer_requirements(&buffer);
-
-            let upload_type = memory_types
-                .iter().enumerate()
-                .position(|(id, mem_type)| {
-                    mem_req.type_mask & (1 << id) != 0
-                        && mem_type.properties.contains(memory_properties)
-                }).unwrap()
-                .into();
-
-            memory = device.allocate_memory(upload_type, mem_req.size)
-                .unwrap();
-            device.bind_buffer_memory(&memory, 0, &mut buffer)
-                .unwrap();
-            size = mem_req.size;
-        }
+            let mut buffer = unsafe {
+                state.device.create_buffer(upload_size as u64, usage)
+            }.unwrap();
+
+            let memory = state.allocator.bind_buffer(&state.device, &mut buffer, memory_properties)
+                .unwrap_or_else(|err| panic!("{}", err.message));
+
+            (buffer, memory)
+        };
+
+        let size = memory.size;
 
         BufferState {
             memory: Some(memory),
@@ -121,7 +97,7 @@ impl<B: Backend> BufferState<B> {
     /// Creates a new buffer to save textures
     pub fn new_texture(
         device_ptr: Rc<RefCell<DeviceState<B>>>,
-        device: &B::Device,
+        device_state: &mut DeviceState<B>,
         img: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>,
         adapter: &AdapterState<B>,
         usage: Usage,
@@ -134,34 +110,23 @@ impl<B: Backend> BufferState<B> {
         let row_pitch = (width * stride as u32 + row_alignment_mask) & !row_alignment_mask;
         let upload_size = (height * row_pitch) as u64;
 
-        let memory: B::Memory;
-        let mut buffer: B::Buffer;
-        let size: u64;
+        let device = &device_state.device;
+
+        let mut buffer = unsafe {
+            device.create_buffer(upload_size, usage)
+        }.unwrap();
+
+        let memory = device_state.allocator
+            .bind_buffer(device, &mut buffer, Properties::CPU_VISIBLE | Properties::COHERENT)
+            .unwrap_or_else(|err| panic!("{}", err.message));
+        let size = memory.size;
 
         unsafe {
-            buffer = device.create_buffer(upload_size, usage)
-                .unwrap();
-            let mem_reqs = device.get_buffer_requirements(&buffer);
-
-            let upload_type = adapter
-                .memory_types.iter().enumerate()
-                .position(|(id, mem_type)| {
-                    mem_reqs.type_mask & (1 << id) != 0
-                        && mem_type
-                            .properties
-                            .contains(Properties::CPU_VISIBLE | Properties::COHERENT)
-                }).unwrap()
-                .into();
-
-            memory = device.allocate_memory(upload_type, mem_reqs.size)
-                .unwrap();
-            device.bind_buffer_memory(&memory, 0, &mut buffer)
-                .unwrap();
-            size = mem_reqs.size;
+            let block = device_state.allocator.memory(&memory);
 
             //copy image data into staging buffer
-            let mapping = device.map_memory(&memory, Segment {
-                offset: 0,
+            let mapping = device.map_memory(block, Segment {
+                offset: memory.offset,
                 size: Some(size)
             }).expect("Unable to map texture memory");
             
@@ -174,7 +139,7 @@ impl<B: Backend> BufferState<B> {
                     data_source_slice.len(),
                 );
             }
-            device.unmap_memory(&memory);
+            device.unmap_memory(block);
         }
 
         (
@@ -197,7 +162,6 @@ impl<B: Backend> BufferState<B> {
     pub fn new_vertex_buffer<T>(
         device_ptr: Rc<RefCell<DeviceState<B>>>,
         data_source: &[T],
-        memory_types: &[MemoryType],
         staging_pool: &mut B::CommandPool,
     ) -> Self
     where
@@ -207,7 +171,6 @@ impl<B: Backend> BufferState<B> {
             Rc::clone(&device_ptr),
             data_source,
             Usage::TRANSFER_SRC,
-            memory_types,
             Properties::CPU_VISIBLE | Properties::COHERENT,
         );
 
@@ -215,7 +178,6 @@ impl<B: Backend> BufferState<B> {
             Rc::clone(&device_ptr),
             data_source.len(),
             Usage::TRANSFER_DST | Usage::VERTEX,
-            memory_types,
             Properties::DEVICE_LOCAL,
         );
 
@@ -239,7 +201,6 @@ impl<B: Backend> BufferState<B> {
     pub fn new_index_buffer<T>(
         device_ptr: Rc<RefCell<DeviceState<B>>>,
         data_source: &[T],
-        memory_types: &[MemoryType],
         staging_pool: &mut B::CommandPool,
     ) -> Self
     where
@@ -249,7 +210,6 @@ impl<B: Backend> BufferState<B> {
             Rc::clone(&device_ptr),
             data_source,
             Usage::TRANSFER_SRC,
-            memory_types,
             Properties::CPU_VISIBLE | Properties::COHERENT,
         );
 
@@ -257,7 +217,6 @@ impl<B: Backend> BufferState<B> {
             Rc::clone(&device_ptr),
             data_source.len(),
             Usage::TRANSFER_DST | Usage::INDEX,
-            memory_types,
             Properties::DEVICE_LOCAL,
         );
 
@@ -281,13 +240,23 @@ impl<B: Backend> BufferState<B> {
     pub fn new_uniform_buffer<T>(
         device_ptr: Rc<RefCell<DeviceState<B>>>,
         data_length: usize,
-        memory_types: &[MemoryType],
     ) -> Self {
         BufferState::new_unmapped::<T>(
             Rc::clone(&device_ptr),
             data_length,
             Usage::UNIFORM,
-            memory_types,
+            Properties::CPU_VISIBLE | Properties::COHERENT,
+        )
+    }
+
+    pub fn new_storage_buffer<T>(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        data_length: usize,
+    ) -> Self {
+        BufferState::new_unmapped::<T>(
+            Rc::clone(&device_ptr),
+            data_length,
+            Usage::STORAGE,
             Properties::CPU_VISIBLE | Properties::COHERENT,
         )
     }
@@ -305,22 +274,23 @@ impl<B: Backend> BufferState<B> {
     ) where
         T: Copy,
     {
-        let device = &self.device.borrow().device;
+        let state = self.device.borrow();
 
         let stride = size_of::<T>();
         let upload_size = data_source.len() * stride;
 
         assert!(offset + upload_size as u64 <= self.size);
-        let memory = self.memory.as_ref()
+        let allocation = self.memory.as_ref()
             .unwrap();
+        let memory = state.allocator.memory(allocation);
 
         unsafe {
-            let mapping = device.map_memory(memory, Segment {
-                offset,
-                size: Some(self.size)
+            let mapping = state.device.map_memory(memory, Segment {
+                offset: allocation.offset + offset,
+                size: Some(upload_size as u64)
             }).unwrap();
             ptr::copy_nonoverlapping(data_source.as_ptr() as *const u8, mapping, upload_size);
-            device.unmap_memory(memory);
+            state.device.unmap_memory(memory);
         }
     }
 
@@ -363,101 +333,10 @@ impl<B: Backend> BufferState<B> {
 
 impl<B: Backend> Drop for BufferState<B> {
     fn drop(&mut self) {
-        let device = &self.device.borrow().device;
+        let state = &mut *self.device.borrow_mut();
         unsafe {
-            device.destroy_buffer(self.buffer.take().unwrap());
-            device.free_memory(self.memory.take().unwrap());
-        }
-    }
-}
-
-pub struct DepthBuffer<B: Backend> {
-    pub depth_buffer: ImageState<B>,
-    #[allow(dead_code)]
-    depth_desc_pool: Option<B::DescriptorPool>,
-}
-
-impl<B: Backend> DepthBuffer<B> {
-    pub fn new(
-        device: Rc<RefCell<DeviceState<B>>>,
-        adapter: &AdapterState<B>,
-        dims: Dimensions<u32>
-    ) -> Self {
-        let depth_desc = DescSetLayout::new(
-            Rc::clone(&device),
-            vec![
-                DescriptorSetLayoutBinding {
-                    binding: 0,
-                    ty: DescriptorType::Image {
-                        ty:ImageDescriptorType::Sampled {
-                            with_sampler: false
-                        }
-                    },
-                    count: 1,
-                    stage_flags: ShaderStageFlags::FRAGMENT,
-                    immutable_samplers: false,
-                },
-                DescriptorSetLayoutBinding {
-                    binding: 1,
-                    ty: DescriptorType::Sampler,
-                    count: 1,
-                    stage_flags: ShaderStageFlags::FRAGMENT,
-                    immutable_samplers: false
-                }
-            ]
-        );
-
-        let mut depth_desc_pool = unsafe {
-            device.borrow().device.create_descriptor_pool(
-                1,
-                &[
-                    DescriptorRangeDesc {
-                        ty: DescriptorType::Image {
-                            ty: ImageDescriptorType::Sampled {
-                                with_sampler: false
-                            }
-                        },
-                        count: 1
-                    },
-                    DescriptorRangeDesc {
-                        ty: DescriptorType::Sampler,
-                        count: 1
-                    }
-                ],
-                DescriptorPoolCreateFlags::empty()
-            )
-        }.ok();
-
-        let depth_desc = depth_desc.create_desc_set(depth_desc_pool.as_mut().unwrap());
-
-        let mut staging_pool = unsafe {
-            device.borrow().device.create_command_pool(
-                device.borrow().queues.family,
-                CommandPoolCreateFlags::empty(),
-            )
-        }.expect("Can't create Command Pool");
-
-        let depth_buffer = ImageState::new_depth_image(
-            depth_desc,
-            dims,
-            &adapter,
-            &mut device.borrow_mut(),
-            &mut staging_pool
-        );
-
-        depth_buffer.wait_for_transfer_completion();
-
-        DepthBuffer {
-            depth_buffer,
-            depth_desc_pool
+            state.device.destroy_buffer(self.buffer.take().unwrap());
         }
-    }
-
-    pub fn stencil_support(device: Rc<RefCell<DeviceState<B>>>, format: Format) -> bool {
-        let properties = device.borrow()
-        .physical_device_format_properties(Some(format));
-        // Format::D32SfloatS8Uint
-
-        properties.linear_tiling.contains(ImageFeature::DEPTH_STENCIL_ATTACHMENT) || properties.optimal_tiling.contains(ImageFeature::DEPTH_STENCIL_ATTACHMENT)
+        state.allocator.free(&state.device, self.memory.take().unwrap());
     }
 }
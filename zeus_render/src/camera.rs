@@ -1,5 +1,4 @@
 use gfx_hal::{
-    adapter::MemoryType,
     buffer::SubRange,
     device::Device,
     pso::{
@@ -10,6 +9,7 @@ use gfx_hal::{
 
 use super::{
     buffer::BufferState,
+    constants::MAX_JOINT_MATRICES,
     desc::{
         DescSet, DescSetLayout, DescSetWrite
     },
@@ -17,7 +17,11 @@ use super::{
     model::UniformBufferObject,
 };
 
-use zeus_core::math::Matrix4;
+use zeus_core::math::{
+    Matrix4,
+    Quartenion,
+    Vector3
+};
 
 use std::{
     cell::RefCell,
@@ -25,9 +29,145 @@ use std::{
     rc::Rc
 };
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// The vertical field of view, in degrees
+    Perspective { fov: f32 },
+    /// The height of the view in world units, the width follows the aspect
+    Orthographic { height: f32 },
+}
+
+/// A point of view in the scene. The camera looks down its -z with +y up, like the views of `Matrix4`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub position: Vector3,
+    pub orientation: Quartenion,
+    pub projection: Projection,
+    aspect: f32,
+    near: f32,
+    far: f32,
+}
+
+impl Camera {
+    /// `fov` is the vertical field of view, in degrees.
+    pub fn perspective(fov: f32, aspect: f32, near: f32, far: f32) -> Self {
+        Camera {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            orientation: Quartenion::identity(),
+            projection: Projection::Perspective { fov },
+            aspect,
+            near,
+            far,
+        }
+    }
+
+    /// `height` is the height of the view in world units.
+    pub fn orthographic(height: f32, aspect: f32, near: f32, far: f32) -> Self {
+        Camera {
+            projection: Projection::Orthographic { height },
+            ..Camera::perspective(90.0, aspect, near, far)
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn get_aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    /// Called when the window is resized.
+    pub fn set_viewport(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.aspect = width as f32 / height as f32;
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn get_planes(&self) -> (f32, f32) {
+        (self.near, self.far)
+    }
+
+    #[allow(dead_code)]
+    pub fn set_planes(&mut self, near: f32, far: f32) {
+        self.near = near;
+        self.far = far.max(near + f32::EPSILON);
+    }
+
+    /// `None` for orthographic cameras.
+    pub fn get_fov(&self) -> Option<f32> {
+        match self.projection {
+            Projection::Perspective { fov } => Some(fov),
+            Projection::Orthographic { .. } => None
+        }
+    }
+
+    /// In degrees, orthographic cameras switch to a perspective projection.
+    pub fn set_fov(&mut self, fov: f32) {
+        self.projection = Projection::Perspective { fov: fov.clamp(1.0, 179.0) };
+    }
+
+    pub fn forward(&self) -> Vector3 {
+        self.orientation.rotate(Vector3::new(0.0, 0.0, -1.0))
+    }
+
+    pub fn right(&self) -> Vector3 {
+        self.orientation.rotate(Vector3::new(1.0, 0.0, 0.0))
+    }
+
+    pub fn up(&self) -> Vector3 {
+        self.orientation.rotate(Vector3::new(0.0, 1.0, 0.0))
+    }
+
+    /// Turns the camera towards the point, keeping +y up.
+    pub fn look_at(&mut self, target: Vector3) {
+        let (yaw, pitch) = yaw_pitch(target - self.position);
+        self.orientation = orientation(yaw, pitch);
+    }
+
+    /// From the space of the scene to the space of the camera.
+    pub fn view(&self) -> Matrix4 {
+        self.orientation.conjugate().to_matrix() * Matrix4::new_traslation(-self.position.x, -self.position.y, -self.position.z)
+    }
+
+    pub fn proj(&self) -> Matrix4 {
+        match self.projection {
+            Projection::Perspective { fov } => Matrix4::perspective(fov.to_radians(), self.aspect, self.near, self.far),
+            Projection::Orthographic { height } => {
+                let (half_width, half_height) = (height * self.aspect * 0.5, height * 0.5);
+                Matrix4::orthographic(-half_width, half_width, -half_height, half_height, self.near, self.far)
+            }
+        }
+    }
+}
+
+/// The yaw around +y and the pitch above the horizon of a direction, in degrees. Zero looks down -z.
+pub fn yaw_pitch(direction: Vector3) -> (f32, f32) {
+    let length = direction.magn();
+    if length <= f32::EPSILON {
+        return (0.0, 0.0);
+    }
+
+    let horizontal = (direction.x * direction.x + direction.z * direction.z).sqrt();
+    let yaw = if horizontal > f32::EPSILON {
+        (-direction.x).atan2(-direction.z).to_degrees()
+    } else {
+        0.0
+    };
+
+    (yaw, (direction.y / length).clamp(-1.0, 1.0).asin().to_degrees())
+}
+
+/// The orientation of a camera turned by `yaw` around +y, then tilted by `pitch`. In degrees.
+pub fn orientation(yaw: f32, pitch: f32) -> Quartenion {
+    Quartenion::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), yaw) * Quartenion::from_axis_angle(Vector3::new(1.0, 0.0, 0.0), pitch)
+}
+
+/// The uniform buffers of the camera matrices and the joint palettes, one of each per frame in flight.
 pub struct CameraState<B: Backend> {
     pub buffers: Vec<Option<BufferState<B>>>,
-    pub desc: Option<DescSet<B>>,
+    //One set per frame in flight, each pointing to the buffer of its frame
+    pub descs: Vec<DescSet<B>>,
+    //The joint palettes of the skinned objects, one buffer per frame as well
+    joint_buffers: Vec<Option<BufferState<B>>>,
     device: Rc<RefCell<DeviceState<B>>>,
     camera_desc_pool: Option<B::DescriptorPool>,
     ubo: UniformBufferObject,
@@ -38,82 +178,121 @@ impl<B: Backend> CameraState<B> {
     pub fn new(
         size: usize,
         device: Rc<RefCell<DeviceState<B>>>,
-        memory_types: &[MemoryType],
     ) -> Self {
         let binding = 0;
-
-        //Create descriptors
-        let camera_desc = DescSetLayout::new(
-            Rc::clone(&device),
-            vec![DescriptorSetLayoutBinding {
-                binding,
-                ty: DescriptorType::Buffer {
-                    ty: BufferDescriptorType::Uniform,
-                    format: BufferDescriptorFormat::Structured {
-                        dynamic_offset: false
-                    }
-                },
-                count: 1,
-                stage_flags: ShaderStageFlags::VERTEX,
-                immutable_samplers: false,
-            }],
-        );
+        let joint_binding = 1;
 
         let mut camera_desc_pool = unsafe {
             device.borrow().device.create_descriptor_pool(
                 size,
-                &[DescriptorRangeDesc {
+                [DescriptorRangeDesc {
                     ty: DescriptorType::Buffer {
                         ty: BufferDescriptorType::Uniform,
                         format: BufferDescriptorFormat::Structured {
                             dynamic_offset: false
                         }
                     },
-                    count: 1,
+                    count: size,
+                },
+                DescriptorRangeDesc {
+                    ty: DescriptorType::Buffer {
+                        ty: BufferDescriptorType::Storage {
+                            read_only: true
+                        },
+                        format: BufferDescriptorFormat::Structured {
+                            dynamic_offset: false
+                        }
+                    },
+                    count: size,
                 }],
                 DescriptorPoolCreateFlags::empty(),
             )
         }.ok();
 
-        let mut camera_desc = camera_desc.create_desc_set(
-            camera_desc_pool.as_mut().unwrap()
-        );
-
-        //Create buffers
+        //Create buffers and descriptors
         let mut buffers = Vec::default();
+        let mut joint_buffers = Vec::default();
+        let mut descs = Vec::default();
         for _i in 0..size {
-            buffers.push(Some(
-                BufferState::new_uniform_buffer::<UniformBufferObject>(
-                    Rc::clone(&device),
-                    1,
-                    memory_types,
-                ),
-            ));
-        }
+            let buffer = BufferState::new_uniform_buffer::<UniformBufferObject>(
+                Rc::clone(&device),
+                1,
+            );
 
-        //Create desc set
-        let mut desc_set_write = Vec::default();
-        for buf in &buffers {
-            desc_set_write.push(DescSetWrite {
-                binding,
-                array_offset: 0,
-                descriptors: Some(Descriptor::Buffer(
-                    buf.as_ref().unwrap().get_buffer(),
-                    SubRange {
-                        offset: 0,
-                        size: Some(size_of::<UniformBufferObject>() as u64)
-                    }
-                )),
-            });
-        }
+            let joint_buffer = BufferState::new_storage_buffer::<Matrix4>(
+                Rc::clone(&device),
+                MAX_JOINT_MATRICES,
+            );
+
+            let camera_desc = DescSetLayout::new(
+                Rc::clone(&device),
+                vec![DescriptorSetLayoutBinding {
+                    binding,
+                    ty: DescriptorType::Buffer {
+                        ty: BufferDescriptorType::Uniform,
+                        format: BufferDescriptorFormat::Structured {
+                            dynamic_offset: false
+                        }
+                    },
+                    count: 1,
+                    //NOTE: The fragment shader needs the view to light in view space
+                    stage_flags: ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                DescriptorSetLayoutBinding {
+                    binding: joint_binding,
+                    ty: DescriptorType::Buffer {
+                        ty: BufferDescriptorType::Storage {
+                            read_only: true
+                        },
+                        format: BufferDescriptorFormat::Structured {
+                            dynamic_offset: false
+                        }
+                    },
+                    count: 1,
+                    stage_flags: ShaderStageFlags::VERTEX,
+                    immutable_samplers: false,
+                }],
+            );
 
-        camera_desc.write_to_state(desc_set_write, &mut device.borrow_mut().device);
+            let mut camera_desc = camera_desc.create_desc_set(
+                camera_desc_pool.as_mut().unwrap()
+            );
+
+            camera_desc.write_to_state(
+                vec![DescSetWrite {
+                    binding,
+                    array_offset: 0,
+                    descriptors: Some(Descriptor::Buffer(
+                        buffer.get_buffer(),
+                        SubRange {
+                            offset: 0,
+                            size: Some(size_of::<UniformBufferObject>() as u64)
+                        }
+                    )),
+                },
+                DescSetWrite {
+                    binding: joint_binding,
+                    array_offset: 0,
+                    descriptors: Some(Descriptor::Buffer(
+                        joint_buffer.get_buffer(),
+                        SubRange::WHOLE
+                    )),
+                }],
+                &mut device.borrow_mut().device
+            );
+
+            buffers.push(Some(buffer));
+            joint_buffers.push(Some(joint_buffer));
+            descs.push(camera_desc);
+        }
 
         let ubo = UniformBufferObject::new();
 
         CameraState {
             buffers,
-            desc: Some(camera_desc),
+            descs,
+            joint_buffers,
             device,
             camera_desc_pool,
             ubo,
@@ -140,11 +319,16 @@ impl<B: Backend> CameraState<B> {
         self.has_updated_ubo = true;
     }
 
-    #[allow(dead_code)]
+    /// The model is left as identity, the camera is the view.
+    pub fn set_camera(&mut self, camera: &Camera) {
+        self.update_ubo(Matrix4::new(), camera.view(), camera.proj());
+    }
+
     pub fn get_model(&self) -> Matrix4 {
         self.ubo.model
     }
 
+    #[allow(dead_code)]
     pub fn update_model(
         &mut self,
         update: Matrix4,
@@ -153,7 +337,6 @@ impl<B: Backend> CameraState<B> {
         self.has_updated_ubo = true;
     }
 
-    #[allow(dead_code)]
     pub fn get_view(&self) -> Matrix4 {
         self.ubo.view
     }
@@ -167,7 +350,6 @@ impl<B: Backend> CameraState<B> {
         self.has_updated_ubo = true;
     }
 
-    #[allow(dead_code)]
     pub fn get_proj(&self) -> Matrix4 {
         self.ubo.proj
     }
@@ -178,6 +360,7 @@ impl<B: Backend> CameraState<B> {
         self.has_updated_ubo = true;
     }
 
+    #[allow(dead_code)]
     pub fn set_proj(&mut self, update: Matrix4) {
         self.ubo.proj = update;
         self.has_updated_ubo = true;
@@ -201,28 +384,39 @@ impl<B: Backend> CameraState<B> {
         }
     }
 
+    /// Uploads the joint palettes of the frame, the offsets of the objects point into it.
+    //NOTE: Has to fit in MAX_JOINT_MATRICES
+    pub fn update_joints(&mut self, idx: usize, joints: &[Matrix4]) {
+        if !joints.is_empty() {
+            self.joint_buffers[idx].as_mut().unwrap()
+                .update_data(0, joints);
+        }
+    }
+
     #[allow(dead_code)]
-    pub fn get_desc_set(&self) -> &B::DescriptorSet {
-        self.desc.as_ref().unwrap().set.as_ref().unwrap()
+    pub fn get_desc_set(&self, idx: usize) -> &B::DescriptorSet {
+        self.descs[idx].set.as_ref().unwrap()
     }
 
     pub fn append_desc_set<'a>(
         &'a self,
+        idx: usize,
         vec: &mut Vec<&'a B::DescriptorSet>,
     ) {
-        vec.push(self.desc.as_ref().unwrap().set.as_ref().unwrap())
+        vec.push(self.descs[idx].set.as_ref().unwrap())
     }
 
     #[allow(dead_code)]
     pub fn get_layout(&self) -> &B::DescriptorSetLayout {
-        self.desc.as_ref().unwrap().get_layout()
+        self.descs[0].get_layout()
     }
 
+    //NOTE: The layouts of all the frames are identical
     pub fn append_layout<'a>(
         &'a self,
-        vec: &mut Vec<&'a B::DescriptorSetLayout>,
+        vec: &mut Vec<&'a DescSetLayout<B>>,
     ) {
-        vec.push(self.desc.as_ref().unwrap().get_layout())
+        vec.push(&self.descs[0].layout)
     }
 }
 
@@ -237,3 +431,53 @@ impl<B: Backend> Drop for CameraState<B> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        orientation, yaw_pitch, Camera
+    };
+
+    use zeus_core::math::{
+        Vector3, Vector4
+    };
+
+    #[test]
+    fn view_looks_at_target() {
+        let mut camera = Camera::perspective(90.0, 1.0, 0.1, 100.0);
+        camera.position = Vector3::new(1.0, 0.5, 0.0);
+        camera.look_at(Vector3::new(0.0, 0.5, 0.0));
+
+        assert!((camera.forward() - Vector3::new(-1.0, 0.0, 0.0)).magn() < 1e-5);
+
+        //The target ends up straight ahead, down -z
+        let target = camera.view() * Vector4::new(0.0, 0.5, 0.0, 1.0);
+        assert!((Vector3::new(target.x, target.y, target.z) - Vector3::new(0.0, 0.0, -1.0)).magn() < 1e-5);
+
+        let position = camera.view() * Vector4::new(1.0, 0.5, 0.0, 1.0);
+        assert!(Vector3::new(position.x, position.y, position.z).magn() < 1e-5);
+    }
+
+    #[test]
+    fn yaw_pitch_round_trip() {
+        for &(yaw, pitch) in [(0.0, 0.0), (90.0, 0.0), (-135.0, 30.0), (45.0, -60.0)].iter() {
+            let forward = orientation(yaw, pitch).rotate(Vector3::new(0.0, 0.0, -1.0));
+            let (found_yaw, found_pitch) = yaw_pitch(forward);
+
+            assert!((found_yaw - yaw).abs() < 1e-3 && (found_pitch - pitch).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn aspect_follows_viewport() {
+        let mut camera = Camera::orthographic(10.0, 1.0, 0.1, 100.0);
+        camera.set_viewport(1600, 800);
+
+        assert_eq!(camera.get_aspect(), 2.0);
+        assert_eq!(camera.proj()[0], 0.1);
+        assert_eq!(camera.get_fov(), None);
+
+        camera.set_viewport(0, 0);
+        assert_eq!(camera.get_aspect(), 2.0);
+    }
+}
@@ -6,12 +6,6 @@ use gfx_hal::{
     window::Extent2D
 };
 
-use super::model::Vertex;
-
-use crate::zeus_core::math::{
-    Vector2, Vector3, Vector4
-};
-
 pub const VERSION: &str = "0.1.2";
 
 pub const DIMS: Extent2D = Extent2D {
@@ -28,6 +22,7 @@ pub const COLOR_RANGE: SubresourceRange = SubresourceRange {
     layer_count: Some(1)
 };
 
+#[allow(dead_code)]
 pub const DEPTH_RANGE: SubresourceRange = SubresourceRange {
     aspects: Aspects::DEPTH,
     level_start: 0,
@@ -36,57 +31,125 @@ pub const DEPTH_RANGE: SubresourceRange = SubresourceRange {
     layer_count: Some(1)
 };
 
-#[allow(dead_code)]
-pub const VERTICES: [Vertex; 8] = [
-    Vertex {
-        a_pos: Vector3 { x: 0.5, y: -0.33, z: 2.5 },
-        a_color: Vector4 { x: 1.0, y: 0.0, z: 0.0, w: 1.0 },
-        a_uv: Vector2 { x: 0.0, y: 1.0 },
-    },
-    Vertex {
-        a_pos: Vector3 { x: -0.5, y: -0.33, z: 2.5 },
-        a_color: Vector4 {x: 0.0, y: 1.0, z: 0.0, w: 1.0 },
-        a_uv: Vector2 { x: 1.0, y: 1.0 },
-    },
-    Vertex {
-        a_pos: Vector3 { x: -0.5, y: 0.33, z: 2.5 },
-        a_color: Vector4 { x: 0.0, y: 0.0, z: 1.0, w: 1.0 },
-        a_uv: Vector2 { x: 1.0, y: 0.0 },
-    },
-    Vertex {
-        a_pos: Vector3 { x: 0.5, y: 0.33, z: 2.5 },
-        a_color: Vector4 {x: 1.0, y: 1.0, z: 1.0, w: 1.0 },
-        a_uv: Vector2 { x: 0.0, y: 0.0 },
-    },
-
-    Vertex {
-        a_pos: Vector3 { x: 1.5, y: -0.33, z: 3.5 },
-        a_color: Vector4 {x: 1.0, y: 0.0, z: 0.0, w: 1.0 },
-        a_uv: Vector2 { x: 0.0, y: 1.0 },
-    },
-    Vertex {
-        a_pos: Vector3 { x: 0.5, y: -0.33, z: 3.5 },
-        a_color: Vector4 {x: 0.0, y: 1.0, z: 0.0, w: 1.0 },
-        a_uv: Vector2 { x: 1.0, y: 1.0 },
-    },
-    Vertex {
-        a_pos: Vector3 { x: 0.5, y: 0.33, z: 3.5 },
-        a_color: Vector4 { x: 0.0, y: 0.0, z: 1.0, w: 1.0 },
-        a_uv: Vector2 { x: 1.0, y: 0.0 },
-    },
-    Vertex {
-        a_pos: Vector3 { x: 1.5, y: 0.33, z: 3.5 },
-        a_color: Vector4 {x: 1.0, y: 1.0, z: 1.0, w: 1.0 },
-        a_uv: Vector2 { x: 0.0, y: 0.0 },
-    },
-];
-
-#[allow(dead_code)]
-pub const INDICES: [u32; 12] = [
-    0, 1, 2, 2, 3, 0, //obj 1
-    4, 5, 6, 6, 7, 4 //obj 2
-];
-
+pub const DATA_MOUNT_POINT: &str = "data";
+pub const DATA_DIR: &str = "./data";
+pub const DATA_ARCHIVE_NAME: &str = "data.zpk";
 pub const FILE_EXT: &str = r"[.]([a-zA-Z]*)$";
+pub const ERROR_TEXTURE_PATH: &str = "./data/textures/error.png";
+pub const VERTEX_SHADER_PATH: &str = "./data/shaders/quad.vert";
+pub const FRAGMENT_SHADER_PATH: &str = "./data/shaders/quad.frag";
+pub const PBR_FRAGMENT_SHADER_PATH: &str = "./data/shaders/pbr.frag";
+pub const WHITE_TEXTURE_PATH: &str = "./data/textures/white.png";
+pub const FLAT_NORMAL_TEXTURE_PATH: &str = "./data/textures/flat_normal.png";
+pub const SHADOW_VERTEX_SHADER_PATH: &str = "./data/shaders/shadow.vert";
+pub const FULLSCREEN_VERTEX_SHADER_PATH: &str = "./data/shaders/fullscreen.vert";
+pub const BLOOM_EXTRACT_SHADER_PATH: &str = "./data/shaders/bloom_extract.frag";
+pub const BLUR_SHADER_PATH: &str = "./data/shaders/blur.frag";
+pub const TONEMAP_SHADER_PATH: &str = "./data/shaders/tonemap.frag";
+pub const FXAA_SHADER_PATH: &str = "./data/shaders/fxaa.frag";
+pub const SHADER_BINARY_EXT: &str = "spv";
+//NOTE: Bump when the layout of the mesh cache changes, older caches are imported again
+pub const MESH_CACHE_EXT: &str = "zmesh";
+pub const MESH_CACHE_VERSION: u32 = 2;
+//NOTE: Shader inputs the vertex layout of a mesh doesn't have read their defaults from the buffer at this binding
+pub const DEFAULT_ATTRIBUTE_BINDING: u32 = 8;
+//NOTE: The textures of a material follow its uniform block in the same descriptor set
+pub const MATERIAL_UNIFORM_BINDING: u32 = 0;
+//NOTE: Imported vertices closer than this in every attribute are merged
+pub const WELD_EPSILON: f32 = 1e-6;
+//NOTE: Each LOD aims for a fraction of the triangles of the previous one, the error is relative to the size of the mesh
+pub const LOD_COUNT: usize = 4;
+pub const LOD_REDUCTION: f32 = 0.5;
+pub const LOD_ERROR: f32 = 0.02;
+//NOTE: The projected radius, relative to the height of the screen, that still draws the full mesh
+pub const LOD_SCREEN_SIZE: f32 = 0.25;
+//NOTE: Device memory is sub-allocated from blocks of this size, see memory.rs
+pub const MEMORY_BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+//NOTE: The swapchain image in the render graph
+pub const BACKBUFFER_ATTACHMENT: &str = "backbuffer";
+pub const COLOR_ATTACHMENT: &str = "color";
+pub const DEPTH_ATTACHMENT: &str = "depth";
+pub const MAIN_PASS: &str = "main";
+//NOTE: The scene is drawn to the HDR attachment, the post-processing passes bring it to the backbuffer
+pub const HDR_ATTACHMENT: &str = "hdr";
+pub const LDR_ATTACHMENT: &str = "ldr";
+pub const BLOOM_EXTRACT_ATTACHMENT: &str = "bloom_extract";
+pub const BLOOM_BLUR_ATTACHMENT: &str = "bloom_blur";
+pub const BLOOM_ATTACHMENT: &str = "bloom";
+pub const BLOOM_EXTRACT_PASS: &str = "bloom_extract";
+pub const BLOOM_HORIZONTAL_PASS: &str = "bloom_horizontal";
+pub const BLOOM_VERTICAL_PASS: &str = "bloom_vertical";
+pub const TONEMAP_PASS: &str = "tonemap";
+pub const FXAA_PASS: &str = "fxaa";
+//NOTE: Of the swapchain extent, the blur reaches further at a lower resolution
+pub const BLOOM_SCALE: f32 = 0.5;
+//NOTE: The identity color grading LUT, a cube of this many texels per side laid out as a square image
+pub const COLOR_LUT_SIZE: u32 = 16;
+//NOTE: How much FXAA blends thin details, and the local contrast it treats as an edge
+pub const FXAA_SUBPIXEL: f32 = 0.75;
+pub const FXAA_EDGE_THRESHOLD: f32 = 0.166;
+pub const FXAA_EDGE_THRESHOLD_MIN: f32 = 0.0833;
+pub const SHADOW_ATTACHMENT: &str = "shadow_map";
+pub const SHADOW_PASS: &str = "shadow";
+//NOTE: The passes of the render targets of camera views are named after the target, e.g. "view:mirror"
+pub const RENDER_TARGET_PASS_PREFIX: &str = "view:";
+//NOTE: Clamped to what the device supports, 1 disables MSAA
+pub const MSAA_SAMPLES: u8 = 4;
+//NOTE: The size of the light buffer, lights past it are not drawn
+pub const MAX_LIGHTS: usize = 16;
+//NOTE: The size of the joint buffer, shared by the palettes of every skinned object
+pub const MAX_JOINT_MATRICES: usize = 1024;
+//NOTE: Objects per leaf of the culling BVH
+pub const BVH_LEAF_SIZE: usize = 4;
+//NOTE: The depth buffer occlusion culling draws the occluders to on the CPU, keep it small
+pub const OCCLUSION_BUFFER_WIDTH: usize = 256;
+pub const OCCLUSION_BUFFER_HEIGHT: usize = 128;
+//NOTE: Units per second
+pub const CAMERA_SPEED: f32 = 5.0;
+//NOTE: Degrees per pixel of mouse motion
+pub const CAMERA_MOUSE_SENSITIVITY: f32 = 0.1;
+//NOTE: Degrees of field of view per line of the mouse wheel
+pub const CAMERA_ZOOM_STEP: f32 = 5.0;
+pub const CAMERA_MIN_FOV: f32 = 20.0;
+pub const CAMERA_MAX_FOV: f32 = 120.0;
+//NOTE: The orbit and follow distances are scaled by this per line of the mouse wheel
+pub const CAMERA_ZOOM_FACTOR: f32 = 0.9;
+pub const CAMERA_MIN_DISTANCE: f32 = 0.5;
+pub const CAMERA_MAX_DISTANCE: f32 = 100.0;
+pub const CAMERA_FOLLOW_STIFFNESS: f32 = 4.0;
+//NOTE: In seconds
+pub const ANIMATION_CROSSFADE: f32 = 0.3;
+//NOTE: How many frames the CPU can record ahead of the GPU
+pub const FRAMES_IN_FLIGHT: usize = 2;
+//NOTE: The shadow maps of every light share one atlas, split in a grid of tiles
+pub const SHADOW_ATLAS_SIZE: u32 = 4096;
+pub const SHADOW_ATLAS_GRID: u32 = 4;
+//NOTE: Has to match the size of the tile array in the shaders
+pub const MAX_SHADOW_TILES: usize = 16;
+//NOTE: The cascade splits are a vec4 in the shaders
+pub const MAX_SHADOW_CASCADES: usize = 4;
+pub const SHADOW_DISTANCE: f32 = 20.0;
+//NOTE: The maps of image based lighting are generated when the environment is loaded, keep them small
+pub const ENVIRONMENT_SIZE: usize = 128;
+pub const IRRADIANCE_SIZE: usize = 16;
+pub const PREFILTERED_SIZE: usize = 64;
+pub const PREFILTERED_LEVELS: usize = 5;
+pub const BRDF_LUT_SIZE: usize = 64;
+pub const IBL_SAMPLES: usize = 64;
 pub const IMAGE_FORMAT:Format = Format::Rgba8Srgb;
-pub const DEPTH_IMAGE_FORMAT:Format = Format::D32SfloatS8Uint;
\ No newline at end of file
+//NOTE: For textures that hold data instead of colors, like normal maps
+pub const LINEAR_IMAGE_FORMAT:Format = Format::Rgba8Unorm;
+pub const ENVIRONMENT_FORMAT:Format = Format::Rgba16Sfloat;
+pub const HDR_FORMAT:Format = Format::Rgba16Sfloat;
+//NOTE: Holds the tonemapped colors already gamma corrected, FXAA works on them
+pub const LDR_FORMAT:Format = Format::Rgba8Unorm;
+pub const BRDF_LUT_FORMAT:Format = Format::Rg16Sfloat;
+pub const DEPTH_IMAGE_FORMAT:Format = Format::D32SfloatS8Uint;
+//NOTE: Command line argument that loads the instancing benchmark instead of the level
+pub const BENCHMARK_ARG: &str = "--benchmark";
+//NOTE: The benchmark draws a square grid of cubes, this many per side
+pub const BENCHMARK_GRID_SIZE: usize = 200;
+pub const BENCHMARK_SPACING: f32 = 0.25;
+pub const BENCHMARK_MATERIAL: &str = "benchmark";
+//NOTE: A wall across the middle of the grid, an occluder that hides the rows behind it
+pub const BENCHMARK_WALL_HEIGHT: f32 = 2.0;
@@ -0,0 +1,266 @@
+use super::{
+    camera::{
+        orientation, yaw_pitch, Camera
+    },
+    constants::{
+        CAMERA_FOLLOW_STIFFNESS, CAMERA_MAX_DISTANCE, CAMERA_MAX_FOV, CAMERA_MIN_DISTANCE, CAMERA_MIN_FOV, CAMERA_MOUSE_SENSITIVITY, CAMERA_SPEED, CAMERA_ZOOM_FACTOR, CAMERA_ZOOM_STEP
+    },
+};
+
+use zeus_core::math::{
+    Quartenion,
+    Vector2,
+    Vector3
+};
+
+/// What the controllers read every frame, gathered from the keyboard and the mouse.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ControllerInput {
+    /// x is right, y is up and z is forward, each in [-1, 1]
+    pub movement: Vector3,
+    /// The mouse motion since the last frame, in pixels
+    pub look: Vector2,
+    /// The wheel lines since the last frame, positive zooms in
+    pub zoom: f32,
+}
+
+/// Moves a camera from the input. `delta` is in seconds.
+pub trait CameraController {
+    fn update(&mut self, camera: &mut Camera, input: &ControllerInput, delta: f32);
+
+    /// The point the controller turns around or follows, ignored by the ones that don't have one.
+    fn set_target(&mut self, _target: Vector3) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControllerKind {
+    FreeFly,
+    Orbit,
+    Follow,
+}
+
+impl ControllerKind {
+    pub fn next(self) -> Self {
+        match self {
+            ControllerKind::FreeFly => ControllerKind::Orbit,
+            ControllerKind::Orbit => ControllerKind::Follow,
+            ControllerKind::Follow => ControllerKind::FreeFly,
+        }
+    }
+
+    /// The controller starts from where the camera is, so switching doesn't jump.
+    pub fn create(self, camera: &Camera, target: Vector3) -> Box<dyn CameraController> {
+        match self {
+            ControllerKind::FreeFly => Box::new(FreeFlyController::new(camera)),
+            ControllerKind::Orbit => Box::new(OrbitController::new(camera, target)),
+            ControllerKind::Follow => Box::new(FollowController::new(camera.position - target, target)),
+        }
+    }
+}
+
+fn turn(yaw: f32, pitch: f32, look: Vector2, sensitivity: f32) -> (f32, f32) {
+    //NOTE: Stop short of straight up or down, the yaw is lost there
+    (yaw - look.x * sensitivity, (pitch - look.y * sensitivity).clamp(-89.0, 89.0))
+}
+
+fn zoom_distance(distance: f32, zoom: f32) -> f32 {
+    (distance * CAMERA_ZOOM_FACTOR.powf(zoom)).clamp(CAMERA_MIN_DISTANCE, CAMERA_MAX_DISTANCE)
+}
+
+/// Flies around with WASD and looks with the mouse. The wheel narrows the field of view.
+pub struct FreeFlyController {
+    yaw: f32,
+    pitch: f32,
+    pub speed: f32,
+    pub sensitivity: f32,
+}
+
+impl FreeFlyController {
+    pub fn new(camera: &Camera) -> Self {
+        let (yaw, pitch) = yaw_pitch(camera.forward());
+
+        FreeFlyController {
+            yaw,
+            pitch,
+            speed: CAMERA_SPEED,
+            sensitivity: CAMERA_MOUSE_SENSITIVITY,
+        }
+    }
+}
+
+impl CameraController for FreeFlyController {
+    fn update(&mut self, camera: &mut Camera, input: &ControllerInput, delta: f32) {
+        let (yaw, pitch) = turn(self.yaw, self.pitch, input.look, self.sensitivity);
+        self.yaw = yaw;
+        self.pitch = pitch;
+        camera.orientation = orientation(yaw, pitch);
+
+        let movement = camera.right() * input.movement.x
+            + Vector3::new(0.0, 1.0, 0.0) * input.movement.y
+            + camera.forward() * input.movement.z;
+        camera.position += movement * (self.speed * delta);
+
+        if input.zoom != 0.0 {
+            if let Some(fov) = camera.get_fov() {
+                camera.set_fov((fov - input.zoom * CAMERA_ZOOM_STEP).clamp(CAMERA_MIN_FOV, CAMERA_MAX_FOV));
+            }
+        }
+    }
+}
+
+/// Turns around a target with the mouse, the wheel moves closer and the keys pan the target.
+pub struct OrbitController {
+    target: Vector3,
+    distance: f32,
+    yaw: f32,
+    pitch: f32,
+    pub speed: f32,
+    pub sensitivity: f32,
+}
+
+impl OrbitController {
+    pub fn new(camera: &Camera, target: Vector3) -> Self {
+        let (yaw, pitch) = yaw_pitch(target - camera.position);
+
+        OrbitController {
+            target,
+            distance: (target - camera.position).magn().clamp(CAMERA_MIN_DISTANCE, CAMERA_MAX_DISTANCE),
+            yaw,
+            pitch,
+            speed: CAMERA_SPEED,
+            sensitivity: CAMERA_MOUSE_SENSITIVITY,
+        }
+    }
+}
+
+impl CameraController for OrbitController {
+    fn update(&mut self, camera: &mut Camera, input: &ControllerInput, delta: f32) {
+        let (yaw, pitch) = turn(self.yaw, self.pitch, input.look, self.sensitivity);
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self.distance = zoom_distance(self.distance, input.zoom);
+
+        camera.orientation = orientation(yaw, pitch);
+
+        //Panning moves the target in the plane of the view
+        let pan = camera.right() * input.movement.x + camera.up() * input.movement.y;
+        self.target += pan * (self.speed * delta);
+        self.distance = (self.distance - input.movement.z * self.speed * delta).clamp(CAMERA_MIN_DISTANCE, CAMERA_MAX_DISTANCE);
+
+        camera.position = self.target - camera.forward() * self.distance;
+    }
+
+    fn set_target(&mut self, target: Vector3) {
+        self.target = target;
+    }
+}
+
+/// Trails a moving target from an offset. The mouse turns the offset around the target and the wheel shortens it.
+pub struct FollowController {
+    target: Vector3,
+    offset: Vector3,
+    /// How fast the camera catches up, higher is stiffer
+    pub stiffness: f32,
+    pub sensitivity: f32,
+}
+
+impl FollowController {
+    pub fn new(offset: Vector3, target: Vector3) -> Self {
+        FollowController {
+            target,
+            offset,
+            stiffness: CAMERA_FOLLOW_STIFFNESS,
+            sensitivity: CAMERA_MOUSE_SENSITIVITY,
+        }
+    }
+}
+
+impl CameraController for FollowController {
+    fn update(&mut self, camera: &mut Camera, input: &ControllerInput, delta: f32) {
+        if input.look.x != 0.0 {
+            let rotation = Quartenion::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), -input.look.x * self.sensitivity);
+            self.offset = rotation.rotate(self.offset);
+        }
+
+        let length = self.offset.magn();
+        if input.zoom != 0.0 && length > f32::EPSILON {
+            self.offset *= zoom_distance(length, input.zoom) / length;
+        }
+
+        //NOTE: Framerate independent smoothing, the same share of the gap is closed every second
+        let blend = 1.0 - (-self.stiffness * delta).exp();
+        let desired = self.target + self.offset;
+        camera.position += (desired - camera.position) * blend;
+        camera.look_at(self.target);
+    }
+
+    fn set_target(&mut self, target: Vector3) {
+        self.target = target;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CameraController, ControllerInput, FollowController, FreeFlyController, OrbitController
+    };
+
+    use crate::camera::Camera;
+
+    use zeus_core::math::{
+        Vector2, Vector3
+    };
+
+    fn camera() -> Camera {
+        let mut camera = Camera::perspective(90.0, 1.0, 0.1, 100.0);
+        camera.position = Vector3::new(0.0, 0.0, 5.0);
+        camera.look_at(Vector3::new(0.0, 0.0, 0.0));
+
+        camera
+    }
+
+    #[test]
+    fn free_fly_moves_forward_and_zooms() {
+        let mut camera = camera();
+        let mut controller = FreeFlyController::new(&camera);
+        controller.speed = 1.0;
+
+        let input = ControllerInput {
+            movement: Vector3::new(0.0, 0.0, 1.0),
+            zoom: 2.0,
+            ..ControllerInput::default()
+        };
+        controller.update(&mut camera, &input, 2.0);
+
+        assert!((camera.position - Vector3::new(0.0, 0.0, 3.0)).magn() < 1e-5);
+        assert!(camera.get_fov().unwrap() < 90.0);
+    }
+
+    #[test]
+    fn orbit_keeps_distance() {
+        let mut camera = camera();
+        let mut controller = OrbitController::new(&camera, Vector3::new(0.0, 0.0, 0.0));
+
+        let input = ControllerInput {
+            look: Vector2::new(300.0, -200.0),
+            ..ControllerInput::default()
+        };
+        controller.update(&mut camera, &input, 0.016);
+
+        assert!((camera.position.magn() - 5.0).abs() < 1e-4);
+        assert!((camera.forward() + camera.position * 0.2).magn() < 1e-4);
+    }
+
+    #[test]
+    fn follow_catches_up() {
+        let mut camera = camera();
+        let mut controller = FollowController::new(Vector3::new(0.0, 1.0, 5.0), Vector3::new(0.0, 0.0, 0.0));
+        controller.set_target(Vector3::new(10.0, 0.0, 0.0));
+
+        for _ in 0..200 {
+            controller.update(&mut camera, &ControllerInput::default(), 0.05);
+        }
+
+        assert!((camera.position - Vector3::new(10.0, 1.0, 5.0)).magn() < 1e-3);
+    }
+}
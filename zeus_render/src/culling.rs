@@ -0,0 +1,701 @@
+use gfx_hal::Backend;
+
+use zeus_core::math::{
+    Aabb,
+    BoundingSphere,
+    Matrix4,
+    Vector3,
+    Vector4
+};
+
+use super::{
+    constants::{
+        BVH_LEAF_SIZE, OCCLUSION_BUFFER_HEIGHT, OCCLUSION_BUFFER_WIDTH
+    },
+    obj::RenderObject,
+};
+
+use std::{
+    cmp::Ordering,
+    fmt,
+    ops::Range,
+    time::{
+        Duration, Instant
+    }
+};
+
+/// Which visibility tests run before drawing. Occlusion culling rasterizes the occluders on the CPU,
+/// so it only pays off in scenes with big occluders, see `RenderObject::set_occluder`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CullingSettings {
+    pub frustum: bool,
+    pub occlusion: bool,
+    /// The size of the depth buffer the occluders are drawn to
+    pub occlusion_width: usize,
+    pub occlusion_height: usize,
+}
+
+impl Default for CullingSettings {
+    fn default() -> Self {
+        CullingSettings {
+            frustum: true,
+            occlusion: false,
+            occlusion_width: OCCLUSION_BUFFER_WIDTH,
+            occlusion_height: OCCLUSION_BUFFER_HEIGHT,
+        }
+    }
+}
+
+/// What the culling did in the last frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CullingStats {
+    /// The instance batches of the objects, see `RenderObject::set_batched_instances`
+    pub batches: usize,
+    pub visible: usize,
+    pub frustum_culled: usize,
+    pub occlusion_culled: usize,
+    pub occluder_triangles: usize,
+    pub nodes_visited: usize,
+    pub time: Duration,
+}
+
+impl fmt::Display for CullingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} batches visible, {} outside the frustum, {} occluded, {} occluder triangles, {} BVH nodes in {} us",
+            self.visible,
+            self.batches,
+            self.frustum_culled,
+            self.occlusion_culled,
+            self.occluder_triangles,
+            self.nodes_visited,
+            self.time.as_micros()
+        )
+    }
+}
+
+/// How a box lies against the frustum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Containment {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+/// The planes of a view projection, facing inwards. A point is inside a plane when `dot(normal, point) + w >= 0`.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vector4; 6],
+}
+
+impl Frustum {
+    /// Expects the depth range of `Matrix4::perspective`, -1 to 1.
+    pub fn from_matrix(view_proj: &Matrix4) -> Self {
+        let row = |row: usize| Vector4::new(view_proj[row * 4], view_proj[row * 4 + 1], view_proj[row * 4 + 2], view_proj[row * 4 + 3]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        let normalize = |plane: Vector4| plane / Vector3::new(plane.x, plane.y, plane.z).magn();
+
+        Frustum {
+            planes: [
+                normalize(w + x),
+                normalize(w - x),
+                normalize(w + y),
+                normalize(w - y),
+                normalize(w + z),
+                normalize(w - z),
+            ],
+        }
+    }
+
+    fn distance(plane: &Vector4, point: Vector3) -> f32 {
+        plane.x * point.x + plane.y * point.y + plane.z * point.z + plane.w
+    }
+
+    #[allow(dead_code)]
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter()
+            .all(|plane| Self::distance(plane, sphere.center) >= -sphere.radius)
+    }
+
+    pub fn classify_aabb(&self, aabb: &Aabb) -> Containment {
+        if aabb.is_empty() {
+            return Containment::Outside;
+        }
+
+        let mut containment = Containment::Inside;
+
+        for plane in self.planes.iter() {
+            //The corners farthest along and against the normal of the plane
+            let corner = |towards: bool| Vector3::new(
+                if (plane.x >= 0.0) == towards { aabb.max.x } else { aabb.min.x },
+                if (plane.y >= 0.0) == towards { aabb.max.y } else { aabb.min.y },
+                if (plane.z >= 0.0) == towards { aabb.max.z } else { aabb.min.z },
+            );
+
+            if Self::distance(plane, corner(true)) < 0.0 {
+                return Containment::Outside;
+            }
+
+            if Self::distance(plane, corner(false)) < 0.0 {
+                containment = Containment::Intersecting;
+            }
+        }
+
+        containment
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.classify_aabb(aabb) != Containment::Outside
+    }
+}
+
+#[derive(Debug, Clone)]
+enum BvhNode {
+    /// A range of `Bvh::items`
+    Leaf { bounds: Aabb, start: usize, end: usize },
+    Branch { bounds: Aabb, left: usize, right: usize },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            BvhNode::Leaf { bounds, .. } | BvhNode::Branch { bounds, .. } => bounds
+        }
+    }
+}
+
+/// A bounding volume hierarchy over the bounds of the objects. Every node is split at the median of the centers,
+/// along the longest axis of the node. Empty bounds are left out, they are never visible.
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    /// The index of the object and its bounds
+    items: Vec<(usize, Aabb)>,
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Self {
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            items: bounds.iter()
+                .cloned()
+                .enumerate()
+                .filter(|(_, bounds)| !bounds.is_empty())
+                .collect(),
+        };
+
+        if !bvh.items.is_empty() {
+            bvh.build(0, bvh.items.len());
+        }
+
+        bvh
+    }
+
+    /// Returns the index of the node.
+    fn build(&mut self, start: usize, end: usize) -> usize {
+        let bounds = self.items[start..end].iter()
+            .fold(Aabb::empty(), |bounds, (_, item)| bounds.union(item));
+
+        let index = self.nodes.len();
+        if end - start <= BVH_LEAF_SIZE {
+            self.nodes.push(BvhNode::Leaf { bounds, start, end });
+            return index;
+        }
+
+        let size = bounds.size();
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        };
+        let center = |item: &Aabb| {
+            let center = item.center();
+            [center.x, center.y, center.z][axis]
+        };
+
+        let middle = (start + end) / 2;
+        self.items[start..end].select_nth_unstable_by(middle - start, |(_, a), (_, b)| {
+            center(a).partial_cmp(&center(b)).unwrap_or(Ordering::Equal)
+        });
+
+        //The children are filled in once they are built
+        self.nodes.push(BvhNode::Leaf { bounds, start, end });
+        let left = self.build(start, middle);
+        let right = self.build(middle, end);
+        self.nodes[index] = BvhNode::Branch { bounds, left, right };
+
+        index
+    }
+
+    /// Calls `visit` with every object whose bounds intersect the frustum, the objects of nodes inside the frustum
+    /// are not tested. Returns how many nodes were tested.
+    pub fn query(&self, frustum: &Frustum, mut visit: impl FnMut(usize)) -> usize {
+        if self.nodes.is_empty() {
+            return 0;
+        }
+
+        let mut nodes_visited = 0;
+        let mut stack = vec![(0, false)];
+
+        while let Some((index, inside)) = stack.pop() {
+            let node = &self.nodes[index];
+
+            let inside = if inside {
+                true
+            } else {
+                nodes_visited += 1;
+
+                match frustum.classify_aabb(node.bounds()) {
+                    Containment::Outside => continue,
+                    Containment::Inside => true,
+                    Containment::Intersecting => false,
+                }
+            };
+
+            match node {
+                BvhNode::Leaf { start, end, .. } => {
+                    for (item, bounds) in self.items[*start..*end].iter() {
+                        if inside || frustum.intersects_aabb(bounds) {
+                            visit(*item);
+                        }
+                    }
+                },
+                BvhNode::Branch { left, right, .. } => {
+                    stack.push((*right, inside));
+                    stack.push((*left, inside));
+                }
+            }
+        }
+
+        nodes_visited
+    }
+}
+
+/// A small depth buffer the occluders are rasterized to on the CPU, with a pyramid where every texel
+/// holds the farthest depth of the 2x2 texels below it. Depths are the NDC depths of the view projection.
+pub struct OcclusionBuffer {
+    width: usize,
+    height: usize,
+    /// The full size buffer first, every level is half the size of the one before, rounded up
+    levels: Vec<Vec<f32>>,
+}
+
+impl OcclusionBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        let width = width.max(1);
+        let height = height.max(1);
+
+        let mut levels = Vec::new();
+        let (mut level_width, mut level_height) = (width, height);
+        loop {
+            levels.push(vec![1.0; level_width * level_height]);
+
+            if level_width == 1 && level_height == 1 {
+                break;
+            }
+
+            level_width = level_width.div_ceil(2);
+            level_height = level_height.div_ceil(2);
+        }
+
+        OcclusionBuffer {
+            width,
+            height,
+            levels,
+        }
+    }
+
+    fn level_size(&self, level: usize) -> (usize, usize) {
+        let mut size = (self.width, self.height);
+        for _ in 0..level {
+            size = (size.0.div_ceil(2), size.1.div_ceil(2));
+        }
+
+        size
+    }
+
+    pub fn clear(&mut self) {
+        for level in self.levels.iter_mut() {
+            for depth in level.iter_mut() {
+                *depth = 1.0;
+            }
+        }
+    }
+
+    /// From clip space to the pixels of the buffer and the NDC depth.
+    fn to_screen(&self, clip: &Vector4) -> Vector3 {
+        Vector3::new(
+            (clip.x / clip.w * 0.5 + 0.5) * self.width as f32,
+            (clip.y / clip.w * 0.5 + 0.5) * self.height as f32,
+            clip.z / clip.w,
+        )
+    }
+
+    /// Draws a triangle in clip space, keeping the nearest depth. Triangles crossing the near plane are skipped,
+    /// they can only hide less. Returns whether it was drawn.
+    pub fn rasterize(&mut self, triangle: &[Vector4; 3]) -> bool {
+        if triangle.iter().any(|corner| corner.w <= f32::EPSILON || corner.z < -corner.w) {
+            return false;
+        }
+
+        let [a, b, c] = [self.to_screen(&triangle[0]), self.to_screen(&triangle[1]), self.to_screen(&triangle[2])];
+
+        let area = (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+        if area.abs() <= f32::EPSILON {
+            return false;
+        }
+
+        let min_x = a.x.min(b.x).min(c.x).floor().max(0.0) as usize;
+        let min_y = a.y.min(b.y).min(c.y).floor().max(0.0) as usize;
+        let max_x = (a.x.max(b.x).max(c.x).ceil().max(0.0) as usize).min(self.width);
+        let max_y = (a.y.max(b.y).max(c.y).ceil().max(0.0) as usize).min(self.height);
+
+        let edge = |from: &Vector3, to: &Vector3, x: f32, y: f32| (to.x - from.x) * (y - from.y) - (to.y - from.y) * (x - from.x);
+
+        //NOTE: Both windings are drawn, the occluders don't have to be closed
+        let depth = &mut self.levels[0];
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let wa = edge(&b, &c, px, py) / area;
+                let wb = edge(&c, &a, px, py) / area;
+                let wc = 1.0 - wa - wb;
+
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+
+                let z = wa * a.z + wb * b.z + wc * c.z;
+                let texel = &mut depth[y * self.width + x];
+                if z < *texel {
+                    *texel = z;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Has to be called after the occluders are drawn and before testing against them.
+    pub fn build_pyramid(&mut self) {
+        for level in 1..self.levels.len() {
+            let (width, height) = self.level_size(level - 1);
+            let (level_width, level_height) = self.level_size(level);
+
+            let (below, above) = self.levels.split_at_mut(level);
+            let below = &below[level - 1];
+
+            for y in 0..level_height {
+                for x in 0..level_width {
+                    let (x0, y0) = (x * 2, y * 2);
+                    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+
+                    above[0][y * level_width + x] = below[y0 * width + x0]
+                        .max(below[y0 * width + x1])
+                        .max(below[y1 * width + x0])
+                        .max(below[y1 * width + x1]);
+                }
+            }
+        }
+    }
+
+    /// Whether the box is behind the occluders everywhere it covers the screen. Boxes crossing the near plane
+    /// are never occluded.
+    pub fn is_occluded(&self, aabb: &Aabb, view_proj: &Matrix4) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+
+        let mut min = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+
+        for corner in 0..8 {
+            let point = Vector4::new(
+                if corner & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if corner & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if corner & 4 == 0 { aabb.min.z } else { aabb.max.z },
+                1.0,
+            );
+
+            let clip = *view_proj * point;
+            if clip.w <= f32::EPSILON || clip.z < -clip.w {
+                return false;
+            }
+
+            let screen = self.to_screen(&clip);
+            min = Vector3::new(min.x.min(screen.x), min.y.min(screen.y), min.z.min(screen.z));
+            max = Vector3::new(max.x.max(screen.x), max.y.max(screen.y), max.z.max(screen.z));
+        }
+
+        //Off screen, the frustum culling deals with it
+        if max.x < 0.0 || max.y < 0.0 || min.x >= self.width as f32 || min.y >= self.height as f32 {
+            return false;
+        }
+
+        let min_x = min.x.floor().max(0.0) as usize;
+        let min_y = min.y.floor().max(0.0) as usize;
+        let max_x = (max.x.ceil() as usize).min(self.width).max(min_x + 1);
+        let max_y = (max.y.ceil() as usize).min(self.height).max(min_y + 1);
+
+        //The level where the box covers at most 2x2 texels
+        let size = (max_x - min_x).max(max_y - min_y);
+        let mut level = 0;
+        while (1 << level) < size && level + 1 < self.levels.len() {
+            level += 1;
+        }
+        let level = level.saturating_sub(1);
+
+        let (level_width, _) = self.level_size(level);
+        let depth = &self.levels[level];
+
+        for y in (min_y >> level)..=((max_y - 1) >> level) {
+            for x in (min_x >> level)..=((max_x - 1) >> level) {
+                if min.z <= depth[y * level_width + x] {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Picks the instance batches to draw every frame: frustum culling through a BVH of the batch bounds,
+/// then occlusion culling against the occluders. The BVH is rebuilt when the bounds change.
+pub struct CullingState {
+    settings: CullingSettings,
+    bvh: Bvh,
+    /// The batches of every object, one after the other
+    bounds: Vec<Aabb>,
+    visible: Vec<bool>,
+    /// Where the batches of every object are in `bounds` and `visible`
+    batches: Vec<Range<usize>>,
+    occlusion: OcclusionBuffer,
+    stats: CullingStats,
+}
+
+impl CullingState {
+    pub fn new(settings: CullingSettings) -> Self {
+        CullingState {
+            settings,
+            bvh: Bvh::default(),
+            bounds: Vec::new(),
+            visible: Vec::new(),
+            batches: Vec::new(),
+            occlusion: OcclusionBuffer::new(settings.occlusion_width, settings.occlusion_height),
+            stats: CullingStats::default(),
+        }
+    }
+
+    pub fn get_settings(&self) -> CullingSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: CullingSettings) {
+        if (settings.occlusion_width, settings.occlusion_height) != (self.settings.occlusion_width, self.settings.occlusion_height) {
+            self.occlusion = OcclusionBuffer::new(settings.occlusion_width, settings.occlusion_height);
+        }
+
+        self.settings = settings;
+    }
+
+    pub fn get_stats(&self) -> CullingStats {
+        self.stats
+    }
+
+    /// Objects and batches added since the last update are visible.
+    pub fn is_visible(&self, index: usize, batch: usize) -> bool {
+        match self.batches.get(index) {
+            Some(batches) if batch < batches.len() => self.visible[batches.start + batch],
+            _ => true
+        }
+    }
+
+    /// `view_proj` takes the space of the lights to clip space, like the camera matrices in the shaders.
+    pub fn update<B: Backend>(&mut self, objects: &[RenderObject<B>], view_proj: &Matrix4) {
+        let start = Instant::now();
+
+        self.batches.clear();
+        let mut bounds = Vec::new();
+        for object in objects.iter() {
+            let start = bounds.len();
+            bounds.extend(object.get_batches().iter().map(|batch| batch.bounds));
+            self.batches.push(start..bounds.len());
+        }
+
+        if bounds != self.bounds {
+            self.bvh = Bvh::new(&bounds);
+            self.bounds = bounds;
+        }
+
+        let mut stats = CullingStats {
+            batches: self.bounds.len(),
+            ..CullingStats::default()
+        };
+
+        if self.settings.frustum {
+            let visible = &mut self.visible;
+            visible.clear();
+            visible.resize(self.bounds.len(), false);
+
+            stats.nodes_visited = self.bvh.query(&Frustum::from_matrix(view_proj), |index| visible[index] = true);
+            stats.frustum_culled = visible.iter().filter(|visible| !**visible).count();
+        } else {
+            self.visible.clear();
+            self.visible.resize(self.bounds.len(), true);
+        }
+
+        if self.settings.occlusion {
+            self.occlusion.clear();
+
+            for (object, batches) in objects.iter().zip(self.batches.iter()) {
+                if object.is_occluder() && self.visible[batches.clone()].iter().any(|visible| *visible) {
+                    stats.occluder_triangles += draw_occluder(&mut self.occlusion, object, view_proj);
+                }
+            }
+
+            self.occlusion.build_pyramid();
+
+            for (_, batches) in objects.iter().zip(self.batches.iter()).filter(|(object, _)| !object.is_occluder()) {
+                for batch in batches.clone() {
+                    if self.visible[batch] && self.occlusion.is_occluded(&self.bounds[batch], view_proj) {
+                        self.visible[batch] = false;
+                        stats.occlusion_culled += 1;
+                    }
+                }
+            }
+        }
+
+        stats.visible = self.visible.iter().filter(|visible| **visible).count();
+        stats.time = start.elapsed();
+        self.stats = stats;
+    }
+}
+
+/// Draws the first LOD of every instance of the object, returns how many triangles were drawn.
+fn draw_occluder<B: Backend>(occlusion: &mut OcclusionBuffer, object: &RenderObject<B>, view_proj: &Matrix4) -> usize {
+    let mut triangles = 0;
+
+    for instance in object.get_instances() {
+        let mvp = *view_proj * instance.transform;
+        let clip = |index: u32| {
+            let position = object.vertices()[index as usize].a_pos;
+            mvp * Vector4::new(position.x, position.y, position.z, 1.0)
+        };
+
+        for range in object.occluder_ranges() {
+            for triangle in object.indices()[range.start as usize..range.end as usize].chunks_exact(3) {
+                if occlusion.rasterize(&[clip(triangle[0]), clip(triangle[1]), clip(triangle[2])]) {
+                    triangles += 1;
+                }
+            }
+        }
+    }
+
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Bvh, Containment, Frustum, OcclusionBuffer
+    };
+
+    use zeus_core::math::{
+        Aabb, BoundingSphere, Matrix4, Vector3, Vector4
+    };
+
+    fn view_proj() -> Matrix4 {
+        Matrix4::perspective(90.0_f32.to_radians(), 1.0, 0.1, 100.0)
+    }
+
+    fn cube(center: Vector3, half_size: f32) -> Aabb {
+        let half = Vector3::new(half_size, half_size, half_size);
+        Aabb::new(center - half, center + half)
+    }
+
+    #[test]
+    fn frustum_classifies_volumes() {
+        let frustum = Frustum::from_matrix(&view_proj());
+
+        //The view looks down -z
+        assert_eq!(frustum.classify_aabb(&cube(Vector3::new(0.0, 0.0, -10.0), 1.0)), Containment::Inside);
+        assert_eq!(frustum.classify_aabb(&cube(Vector3::new(0.0, 0.0, 10.0), 1.0)), Containment::Outside);
+        assert_eq!(frustum.classify_aabb(&cube(Vector3::new(10.0, 0.0, -10.0), 0.5)), Containment::Intersecting);
+        assert_eq!(frustum.classify_aabb(&cube(Vector3::new(0.0, 0.0, -200.0), 1.0)), Containment::Outside);
+        assert_eq!(frustum.classify_aabb(&Aabb::empty()), Containment::Outside);
+
+        assert!(frustum.intersects_sphere(&BoundingSphere::new(Vector3::new(11.0, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&BoundingSphere::new(Vector3::new(13.0, 0.0, -10.0), 1.0)));
+    }
+
+    #[test]
+    fn bvh_matches_brute_force() {
+        let bounds: Vec<Aabb> = (0..500)
+            .map(|i| {
+                let angle = i as f32 * 0.37;
+                let distance = (i % 50) as f32 * 2.0;
+                if i % 97 == 0 {
+                    return Aabb::empty();
+                }
+
+                cube(Vector3::new(angle.cos() * distance, (i % 7) as f32 - 3.0, angle.sin() * distance), 0.5)
+            })
+            .collect();
+
+        let mut matrix = view_proj();
+        matrix = matrix * Matrix4::new_rotation_y(30.0);
+        let frustum = Frustum::from_matrix(&matrix);
+
+        let bvh = Bvh::new(&bounds);
+        let mut found = Vec::new();
+        let nodes_visited = bvh.query(&frustum, |index| found.push(index));
+        found.sort_unstable();
+
+        let expected: Vec<usize> = (0..bounds.len())
+            .filter(|index| frustum.intersects_aabb(&bounds[*index]))
+            .collect();
+
+        assert_eq!(found, expected);
+        assert!(!found.is_empty() && found.len() < bounds.len());
+        assert!(nodes_visited < bvh.nodes.len());
+    }
+
+    #[test]
+    fn occluders_hide_boxes_behind_them() {
+        let matrix = view_proj();
+        let mut occlusion = OcclusionBuffer::new(64, 64);
+
+        //A wall across the view, 5 units away
+        let wall = [
+            Vector3::new(-10.0, -10.0, -5.0),
+            Vector3::new(10.0, -10.0, -5.0),
+            Vector3::new(10.0, 10.0, -5.0),
+            Vector3::new(-10.0, 10.0, -5.0),
+        ];
+        let clip = |point: Vector3| matrix * Vector4::new(point.x, point.y, point.z, 1.0);
+
+        assert!(occlusion.rasterize(&[clip(wall[0]), clip(wall[1]), clip(wall[2])]));
+        assert!(occlusion.rasterize(&[clip(wall[0]), clip(wall[2]), clip(wall[3])]));
+        occlusion.build_pyramid();
+
+        assert!(occlusion.is_occluded(&cube(Vector3::new(0.0, 0.0, -20.0), 1.0), &matrix));
+        assert!(occlusion.is_occluded(&cube(Vector3::new(2.0, 1.0, -8.0), 2.0), &matrix));
+        assert!(!occlusion.is_occluded(&cube(Vector3::new(0.0, 0.0, -3.0), 1.0), &matrix));
+        assert!(!occlusion.is_occluded(&cube(Vector3::new(0.0, 0.0, -5.0), 1.0), &matrix));
+
+        //Half of the wall leaves the right of the view open
+        let mut occlusion = OcclusionBuffer::new(64, 64);
+        occlusion.rasterize(&[clip(wall[0]), clip(Vector3::new(0.0, -10.0, -5.0)), clip(Vector3::new(0.0, 10.0, -5.0))]);
+        occlusion.rasterize(&[clip(wall[0]), clip(Vector3::new(0.0, 10.0, -5.0)), clip(wall[3])]);
+        occlusion.build_pyramid();
+
+        assert!(occlusion.is_occluded(&cube(Vector3::new(-8.0, 0.0, -20.0), 1.0), &matrix));
+        assert!(!occlusion.is_occluded(&cube(Vector3::new(8.0, 0.0, -20.0), 1.0), &matrix));
+        assert!(!occlusion.is_occluded(&cube(Vector3::new(0.0, 0.0, -20.0), 1.0), &matrix));
+    }
+}
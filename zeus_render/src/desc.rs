@@ -14,6 +14,7 @@ use super::device::DeviceState;
 #[derive(Debug)]
 pub struct DescSetLayout<B: Backend> {
     pub layout: Option<B::DescriptorSetLayout>,
+    pub bindings: Vec<DescriptorSetLayoutBinding>,
     pub device: Rc<RefCell<DeviceState<B>>>,
 }
 
@@ -24,11 +25,12 @@ impl<B: Backend> DescSetLayout<B> {
     ) -> Self {
         let desc_set_layout = unsafe {
             device.borrow()
-                .device.create_descriptor_set_layout(binding, &[])
+                .device.create_descriptor_set_layout(binding.clone(), &[])
         }.ok();
 
         DescSetLayout {
             layout: desc_set_layout,
+            bindings: binding,
             device,
         }
     }
@@ -1,16 +1,20 @@
 use gfx_hal::{
     adapter::{Adapter, PhysicalDevice},
     format:: {self, Properties},
+    image::NumSamples,
     queue::{QueueFamily, QueueGroup},
     window::Surface,
     Backend,
 };
 
+use super::memory::MemoryAllocator;
+
 #[derive(Debug)]
 pub struct DeviceState<B: Backend> {
     pub device: B::Device,
     pub physical_device: B::PhysicalDevice,
     pub queues: QueueGroup<B>,
+    pub allocator: MemoryAllocator<B>,
 }
 
 impl<B: Backend> DeviceState<B> {
@@ -31,14 +35,63 @@ impl<B: Backend> DeviceState<B> {
                 .unwrap()
         };
 
+        let memory_types = adapter.physical_device.memory_properties().memory_types;
+
         DeviceState {
             device: gpu.device,
             queues: gpu.queue_groups.pop().unwrap(),
             physical_device: adapter.physical_device,
+            allocator: MemoryAllocator::new(memory_types),
         }
     }
 
     pub fn physical_device_format_properties(&self, format: Option<format::Format>) -> Properties {
         self.physical_device.format_properties(format)
     }
+
+    /// The highest sample count up to `requested` that color and depth attachments support.
+    pub fn clamp_samples(&self, requested: NumSamples) -> NumSamples {
+        let limits = self.physical_device.limits();
+
+        clamp_samples(
+            requested,
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts
+        )
+    }
+
+    pub fn supports_depth_attachment(&self, format: format::Format) -> bool {
+        let properties = self.physical_device_format_properties(Some(format));
+
+        properties.linear_tiling.contains(format::ImageFeature::DEPTH_STENCIL_ATTACHMENT) || properties.optimal_tiling.contains(format::ImageFeature::DEPTH_STENCIL_ATTACHMENT)
+    }
+}
+
+impl<B: Backend> Drop for DeviceState<B> {
+    fn drop(&mut self) {
+        self.allocator.dispose(&self.device);
+    }
+}
+
+/// `supported` is a mask of the sample counts, 1 is always supported.
+fn clamp_samples(requested: NumSamples, supported: NumSamples) -> NumSamples {
+    let mut samples = 1;
+    while samples < 64 && samples * 2 <= requested && supported & (samples * 2) != 0 {
+        samples *= 2;
+    }
+
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::clamp_samples;
+
+    #[test]
+    fn clamps_to_supported_samples() {
+        assert_eq!(clamp_samples(4, 0b1111), 4);
+        assert_eq!(clamp_samples(8, 0b0111), 4);
+        assert_eq!(clamp_samples(3, 0b1111), 2);
+        assert_eq!(clamp_samples(0, 0b1111), 1);
+        assert_eq!(clamp_samples(8, 0b0001), 1);
+    }
 }
@@ -5,4 +5,55 @@ pub struct NoLevelLoadedError {
     pub message: String
 }
 
-// endregion
\ No newline at end of file
+// endregion
+
+// region Assets
+
+#[derive(Debug, Clone)]
+pub struct AssetError {
+    pub message: String
+}
+
+impl AssetError {
+    pub fn new(message: String) -> Self {
+        AssetError {
+            message
+        }
+    }
+}
+
+// endregion
+
+// region Memory
+
+#[derive(Debug, Clone)]
+pub struct MemoryError {
+    pub message: String
+}
+
+impl MemoryError {
+    pub fn new(message: String) -> Self {
+        MemoryError {
+            message
+        }
+    }
+}
+
+// endregion
+
+// region Render Graph
+
+#[derive(Debug, Clone)]
+pub struct RenderGraphError {
+    pub message: String
+}
+
+impl RenderGraphError {
+    pub fn new(message: String) -> Self {
+        RenderGraphError {
+            message
+        }
+    }
+}
+
+// endregion
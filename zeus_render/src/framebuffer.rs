@@ -13,21 +13,30 @@ use super::{
     device::DeviceState
 };
 
+/// The resources of every frame in flight. A frame's resources are reused only after its fence signalled.
+///
+/// NOTE: The swapchain image is acquired through `PresentationSurface`, which already waits until the
+/// image is ready and takes no semaphore, so there is no acquire semaphore. The render graph keeps the
+/// framebuffers of its own images, only the framebuffers of the swapchain image are kept with the frame.
 pub struct FramebufferState<B: Backend> {
     command_pools: Option<Vec<B::CommandPool>>,
     command_buffer_lists: Vec<Vec<B::CommandBuffer>>,
-    present_semaphores: Option<Vec<B::Semaphore>>,
+    render_finished_semaphores: Option<Vec<B::Semaphore>>,
+    frame_fences: Option<Vec<B::Fence>>,
+    framebuffers: Vec<Vec<B::Framebuffer>>,
     device: Rc<RefCell<DeviceState<B>>>,
 }
 
 impl<B: Backend> FramebufferState<B> {
     pub unsafe fn new(
         device: Rc<RefCell<DeviceState<B>>>,
-        num_frames: u32
+        num_frames: usize
     ) -> Self {
         let mut command_pools: Vec<_> = vec![];
         let mut command_buffer_lists = Vec::new();
-        let mut present_semaphores: Vec<B::Semaphore> = vec![];
+        let mut render_finished_semaphores: Vec<B::Semaphore> = vec![];
+        let mut frame_fences: Vec<B::Fence> = vec![];
+        let mut framebuffers = Vec::new();
 
         for _ in 0..num_frames {
             command_pools.push(
@@ -39,17 +48,41 @@ impl<B: Backend> FramebufferState<B> {
             );
             command_buffer_lists.push(Vec::new());
 
-            present_semaphores.push(device.borrow().device.create_semaphore().unwrap());
+            render_finished_semaphores.push(device.borrow().device.create_semaphore().unwrap());
+            //Signalled, so the first wait on each frame doesn't block
+            frame_fences.push(device.borrow().device.create_fence(true).unwrap());
+            framebuffers.push(Vec::new());
         }
 
         FramebufferState {
             command_pools: Some(command_pools),
             command_buffer_lists,
-            present_semaphores: Some(present_semaphores),
+            render_finished_semaphores: Some(render_finished_semaphores),
+            frame_fences: Some(frame_fences),
+            framebuffers,
             device
         }
     }
 
+    pub fn num_frames(&self) -> usize {
+        self.framebuffers.len()
+    }
+
+    /// Waits until the GPU is done with the frame and frees the framebuffers it used.
+    /// The fence is reset right before the next submission, so an early return doesn't leave it unsignalled.
+    pub fn wait_for_frame(&mut self, idx: usize) {
+        let device = &self.device.borrow().device;
+
+        unsafe {
+            device.wait_for_fence(&self.frame_fences.as_ref().unwrap()[idx], !0)
+                .expect("Could not wait for the frame fence");
+
+            for framebuffer in self.framebuffers[idx].drain(..) {
+                device.destroy_framebuffer(framebuffer);
+            }
+        }
+    }
+
     pub fn get_frame_data(
         &mut self,
         idx: usize
@@ -57,8 +90,9 @@ impl<B: Backend> FramebufferState<B> {
         FrameData {
             cmd_pool: &mut self.command_pools.as_mut().unwrap()[idx],
             cmd_buffers: &mut self.command_buffer_lists[idx],
-            present_sem: &mut self.present_semaphores.as_mut().unwrap()[idx]
-
+            render_finished_sem: &mut self.render_finished_semaphores.as_mut().unwrap()[idx],
+            fence: &mut self.frame_fences.as_mut().unwrap()[idx],
+            framebuffers: &mut self.framebuffers[idx],
         }
     }
 }
@@ -68,7 +102,16 @@ impl<B: Backend> Drop for FramebufferState<B> {
         let device = &self.device.borrow().device;
 
         unsafe {
-            for (mut command_pool, command_buffer_list) in 
+            for fence in self.frame_fences.take().unwrap() {
+                device.wait_for_fence(&fence, !0).unwrap();
+                device.destroy_fence(fence);
+            }
+
+            for framebuffer in self.framebuffers.drain(..).flatten() {
+                device.destroy_framebuffer(framebuffer);
+            }
+
+            for (mut command_pool, command_buffer_list) in
                 self.command_pools.take()
                 .unwrap().into_iter().zip(self.command_buffer_lists.drain(..))
             {
@@ -76,8 +119,8 @@ impl<B: Backend> Drop for FramebufferState<B> {
                 device.destroy_command_pool(command_pool);
             }
 
-            for present_semaphore in self.present_semaphores.take().unwrap() {
-                device.destroy_semaphore(present_semaphore);
+            for render_finished_semaphore in self.render_finished_semaphores.take().unwrap() {
+                device.destroy_semaphore(render_finished_semaphore);
             }
         }
     }
@@ -85,8 +128,10 @@ impl<B: Backend> Drop for FramebufferState<B> {
 
 #[derive(Debug)]
 pub struct FrameData<'a, B: Backend> {
-    
     pub cmd_pool: &'a mut B::CommandPool,
     pub cmd_buffers: &'a mut Vec<B::CommandBuffer>,
-    pub present_sem: &'a mut B::Semaphore
+    pub render_finished_sem: &'a mut B::Semaphore,
+    pub fence: &'a mut B::Fence,
+    /// The framebuffers of the swapchain image the frame renders to, destroyed once the frame's fence signalled.
+    pub framebuffers: &'a mut Vec<B::Framebuffer>,
 }
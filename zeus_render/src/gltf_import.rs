@@ -0,0 +1,807 @@
+use gltf::{
+    animation::{
+        util::ReadOutputs,
+        Interpolation as GltfInterpolation
+    },
+    buffer::Source as BufferSource,
+    image::Source as ImageSource,
+    material::AlphaMode,
+    mesh::Mode,
+    Gltf
+};
+
+use zeus_core::{
+    math::{
+        Matrix4,
+        Quartenion,
+        Vector2,
+        Vector3,
+        Vector4
+    },
+    time::Stopwatch,
+    vfs::{
+        self, MemorySource
+    }
+};
+
+use super::{
+    error::AssetError,
+    material::{
+        BlendMode,
+        MaterialDesc,
+        MaterialParam
+    },
+    mesh::generate_smooth_normals,
+    model::Vertex,
+    obj::MaterialGroup,
+    tangent::generate_tangents,
+};
+
+use std::path::Path;
+
+/// The local transform of a node, applied as scale, then rotation, then translation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3,
+    pub rotation: Quartenion,
+    pub scale: Vector3,
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Matrix4 {
+        Matrix4::new_traslation(self.translation.x, self.translation.y, self.translation.z)
+            * self.rotation.to_matrix()
+            * Matrix4::new_scale_vector(self.scale)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quartenion::identity(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// A triangle list with one material.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Primitive {
+    /// `a_tangent` is zero when the file has no tangents, they are generated once the primitive is placed in the scene
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    /// An index into `GltfModel::materials`, `None` uses the material of the object
+    pub material: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Mesh {
+    pub name: String,
+    pub primitives: Vec<Primitive>,
+}
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Node {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub transform: Transform,
+    pub mesh: Option<usize>,
+    pub skin: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Skin {
+    pub name: String,
+    /// The nodes that are the joints, the joint indices of the vertices point in here
+    pub joints: Vec<usize>,
+    /// One for each joint, takes the mesh into the local space of the joint
+    pub inverse_bind_matrices: Vec<Matrix4>,
+    /// The root of the joint hierarchy, if the file names one
+    pub skeleton: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    /// Every keyframe is stored as in tangent, value and out tangent
+    CubicSpline,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Keyframes {
+    Translations(Vec<Vector3>),
+    Rotations(Vec<Quartenion>),
+    Scales(Vec<Vector3>),
+    /// Morph target weights, the weights of all the targets for each keyframe
+    Weights(Vec<f32>),
+}
+
+/// The keyframes of one property of a node.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Channel {
+    pub node: usize,
+    pub interpolation: Interpolation,
+    /// In seconds
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+}
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Animation {
+    pub name: String,
+    pub channels: Vec<Channel>,
+    /// In seconds, the time of the last keyframe
+    pub duration: f32,
+}
+
+/// Everything a glTF 2.0 file (`.gltf` or `.glb`) describes, besides cameras and lights.
+///
+/// Images that live inside the file are mounted in the vfs under the model path, like `data/models/fox.glb/images/0.png`,
+/// so the materials can reference every texture by path.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct GltfModel {
+    pub meshes: Vec<Mesh>,
+    pub nodes: Vec<Node>,
+    /// The root nodes of the default scene
+    pub roots: Vec<usize>,
+    pub materials: Vec<MaterialDesc>,
+    pub skins: Vec<Skin>,
+    pub animations: Vec<Animation>,
+}
+
+impl GltfModel {
+    pub fn load(model_path: &str) -> Result<Self, AssetError> {
+        let bytes = vfs::read(model_path)
+            .map_err(|err| AssetError::new(format!("Could not read model {}: {:?}", model_path, err)))?;
+
+        Self::from_slice(&bytes, model_path)
+    }
+
+    /// Parses a glTF file, external buffers and images are resolved relative to `model_path`.
+    pub fn from_slice(bytes: &[u8], model_path: &str) -> Result<Self, AssetError> {
+        let mut timer = Stopwatch::new();
+
+        let gltf = Gltf::from_slice(bytes)
+            .map_err(|err| AssetError::new(format!("Could not parse model {}: {:?}", model_path, err)))?;
+
+        let model_dir = Path::new(model_path).parent().unwrap_or_else(|| Path::new(""));
+        let buffers = load_buffers(&gltf, model_path, model_dir)?;
+        let images = load_images(&gltf, &buffers, model_path, model_dir)?;
+
+        let names = unique_names(gltf.materials()
+            .map(|material| material.name().unwrap_or("").to_string())
+            .collect());
+        let materials = gltf.materials()
+            .zip(names)
+            .map(|(material, name)| material_desc(&material, &name, &images))
+            .collect();
+
+        let mut meshes = Vec::new();
+        for mesh in gltf.meshes() {
+            let mut primitives = Vec::new();
+            for primitive in mesh.primitives() {
+                if let Some(primitive) = load_primitive(&primitive, &buffers, model_path)? {
+                    primitives.push(primitive);
+                }
+            }
+
+            meshes.push(Mesh {
+                name: mesh.name().unwrap_or("").to_string(),
+                primitives,
+            });
+        }
+
+        let mut nodes: Vec<Node> = gltf.nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+
+                Node {
+                    name: node.name().unwrap_or("").to_string(),
+                    parent: None,
+                    children: node.children().map(|child| child.index()).collect(),
+                    transform: Transform {
+                        translation: Vector3::new(translation[0], translation[1], translation[2]),
+                        rotation: Quartenion::new(rotation[0], rotation[1], rotation[2], rotation[3]),
+                        scale: Vector3::new(scale[0], scale[1], scale[2]),
+                    },
+                    mesh: node.mesh().map(|mesh| mesh.index()),
+                    skin: node.skin().map(|skin| skin.index()),
+                }
+            })
+            .collect();
+
+        for node in gltf.nodes() {
+            for child in node.children() {
+                nodes[child.index()].parent = Some(node.index());
+            }
+        }
+
+        //NOTE: Files without scenes are libraries, every node without a parent is shown
+        let roots = match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            None => (0..nodes.len()).filter(|node| nodes[*node].parent.is_none()).collect(),
+        };
+
+        let skins = gltf.skins()
+            .map(|skin| {
+                let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+                let inverse_bind_matrices = match skin.reader(|buffer| buffers.get(buffer.index()).map(|data| &data[..]))
+                    .read_inverse_bind_matrices()
+                {
+                    Some(matrices) => matrices.map(matrix_from_columns).collect(),
+                    None => vec![Matrix4::new(); joints.len()],
+                };
+
+                Skin {
+                    name: skin.name().unwrap_or("").to_string(),
+                    joints,
+                    inverse_bind_matrices,
+                    skeleton: skin.skeleton().map(|node| node.index()),
+                }
+            })
+            .collect();
+
+        let mut animations = Vec::new();
+        for animation in gltf.animations() {
+            let mut channels = Vec::new();
+            for channel in animation.channels() {
+                let reader = channel.reader(|buffer| buffers.get(buffer.index()).map(|data| &data[..]));
+
+                let (times, outputs) = match (reader.read_inputs(), reader.read_outputs()) {
+                    (Some(times), Some(outputs)) => (times.collect(), outputs),
+                    _ => {
+                        warn!("Model {}: skipping an animation channel without keyframes", model_path);
+                        continue;
+                    }
+                };
+
+                let keyframes = match outputs {
+                    ReadOutputs::Translations(values) => Keyframes::Translations(values.map(vector3).collect()),
+                    ReadOutputs::Rotations(values) => Keyframes::Rotations(values.into_f32()
+                        .map(|[x, y, z, w]| Quartenion::new(x, y, z, w))
+                        .collect()),
+                    ReadOutputs::Scales(values) => Keyframes::Scales(values.map(vector3).collect()),
+                    ReadOutputs::MorphTargetWeights(values) => Keyframes::Weights(values.into_f32().collect()),
+                };
+
+                channels.push(Channel {
+                    node: channel.target().node().index(),
+                    interpolation: match channel.sampler().interpolation() {
+                        GltfInterpolation::Step => Interpolation::Step,
+                        GltfInterpolation::Linear => Interpolation::Linear,
+                        GltfInterpolation::CubicSpline => Interpolation::CubicSpline,
+                    },
+                    times,
+                    keyframes,
+                });
+            }
+
+            let duration = channels.iter()
+                .filter_map(|channel| channel.times.last().cloned())
+                .fold(0.0, f32::max);
+
+            animations.push(Animation {
+                name: animation.name().unwrap_or("").to_string(),
+                channels,
+                duration,
+            });
+        }
+
+        timer.update_time();
+        debug!("Parsed glTF model {} in {} ms", model_path, timer.get_delta());
+
+        Ok(GltfModel {
+            meshes,
+            nodes,
+            roots,
+            materials,
+            skins,
+            animations,
+        })
+    }
+
+    /// The transform of every node from its local space to the space of the model.
+    pub fn world_transforms(&self) -> Vec<Matrix4> {
+        (0..self.nodes.len())
+            .map(|node| {
+                let mut transform = self.nodes[node].transform.matrix();
+                let mut parent = self.nodes[node].parent;
+
+                while let Some(index) = parent {
+                    transform = self.nodes[index].transform.matrix() * transform;
+                    parent = self.nodes[index].parent;
+                }
+
+                transform
+            })
+            .collect()
+    }
+
+    /// The primitives of the scene placed by their nodes into one vertex list, with an index list per primitive
+    /// and its material. Tangents are generated for the primitives without them.
+    pub fn flatten(&self) -> (Vec<Vertex>, Vec<MaterialGroup>) {
+        let transforms = self.world_transforms();
+
+        let mut vertices = Vec::new();
+        let mut groups = Vec::new();
+
+        let mut stack: Vec<usize> = self.roots.iter().rev().cloned().collect();
+        while let Some(node) = stack.pop() {
+            stack.extend(self.nodes[node].children.iter().rev());
+
+            let mesh = match self.nodes[node].mesh {
+                Some(mesh) => &self.meshes[mesh],
+                None => continue,
+            };
+
+            //NOTE: Skinned meshes are placed by their joints, the transform of their node is ignored
+            let transform = if self.nodes[node].skin.is_some() {
+                Matrix4::new()
+            } else {
+                transforms[node]
+            };
+
+            for primitive in mesh.primitives.iter() {
+                let mut primitive_vertices = primitive.vertices.clone();
+                let mut indices = primitive.indices.clone();
+
+                if primitive_vertices.iter().any(|vertex| vertex.a_tangent.w == 0.0) {
+                    generate_tangents(&mut primitive_vertices, &mut indices);
+                }
+
+                transform_vertices(&mut primitive_vertices, &transform);
+
+                let offset = vertices.len() as u32;
+                groups.push((primitive.material, indices.iter().map(|index| index + offset).collect()));
+                vertices.extend(primitive_vertices);
+            }
+        }
+
+        (vertices, groups)
+    }
+}
+
+fn load_buffers(gltf: &Gltf, model_path: &str, model_dir: &Path) -> Result<Vec<Vec<u8>>, AssetError> {
+    gltf.buffers()
+        .map(|buffer| {
+            let data = match buffer.source() {
+                BufferSource::Bin => gltf.blob.clone()
+                    .ok_or_else(|| AssetError::new(format!("Model {} has no binary chunk", model_path)))?,
+                BufferSource::Uri(uri) => read_uri(uri, model_dir)?,
+            };
+
+            if data.len() < buffer.length() {
+                return Err(AssetError::new(format!("Buffer {} of model {} is too short", buffer.index(), model_path)));
+            }
+
+            Ok(data)
+        })
+        .collect()
+}
+
+/// The vfs path of every image, the images inside the file are mounted under the model path.
+fn load_images(gltf: &Gltf, buffers: &[Vec<u8>], model_path: &str, model_dir: &Path) -> Result<Vec<String>, AssetError> {
+    let mut embedded = MemorySource::new();
+    let mut paths = Vec::new();
+
+    for image in gltf.images() {
+        let (data, mime_type) = match image.source() {
+            ImageSource::Uri { uri, .. } if !uri.starts_with("data:") => {
+                paths.push(model_dir.join(percent_decode(uri)).to_string_lossy().into_owned());
+                continue;
+            },
+            ImageSource::Uri { uri, mime_type } => (read_uri(uri, model_dir)?, mime_type.unwrap_or(uri)),
+            ImageSource::View { view, mime_type } => {
+                let start = view.offset();
+                let data = buffers[view.buffer().index()].get(start..start + view.length())
+                    .ok_or_else(|| AssetError::new(format!("Image {} of model {} is out of its buffer", image.index(), model_path)))?;
+
+                (data.to_vec(), mime_type)
+            },
+        };
+
+        //NOTE: The image loader picks the decoder from the extension
+        let extension = if mime_type.contains("image/jpeg") { "jpg" } else { "png" };
+        let file = format!("images/{}.{}", image.index(), extension);
+
+        paths.push(format!("{}/{}", model_path, file));
+        embedded.add_file(&file, data);
+    }
+
+    //Replaces the images of an earlier load of the model
+    vfs::unmount(model_path);
+    if !embedded.is_empty() {
+        vfs::mount(model_path, Box::new(embedded));
+    }
+
+    Ok(paths)
+}
+
+/// External files are relative to the model, data URIs are decoded in place.
+fn read_uri(uri: &str, model_dir: &Path) -> Result<Vec<u8>, AssetError> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (header, payload) = data.split_at(data.find(',')
+            .ok_or_else(|| AssetError::new("Data URI without a payload".to_string()))?);
+
+        if !header.ends_with(";base64") {
+            return Err(AssetError::new(format!("Data URI {} is not base64 encoded", header)));
+        }
+
+        return base64::decode(&payload[1..])
+            .map_err(|err| AssetError::new(format!("Could not decode data URI: {:?}", err)));
+    }
+
+    let path = model_dir.join(percent_decode(uri));
+    vfs::read(&path.to_string_lossy())
+        .map_err(|err| AssetError::new(format!("Could not read {:?}: {:?}", path, err)))
+}
+
+/// URIs escape spaces and other characters as `%XX`.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// glTF does not require unique or any material names.
+fn unique_names(names: Vec<String>) -> Vec<String> {
+    let mut unique: Vec<String> = Vec::with_capacity(names.len());
+
+    for (index, name) in names.into_iter().enumerate() {
+        let name = if name.is_empty() {
+            format!("material_{}", index)
+        } else if unique.contains(&name) {
+            format!("{}_{}", name, index)
+        } else {
+            name
+        };
+
+        unique.push(name);
+    }
+
+    unique
+}
+
+/// A PBR material, glTF packs roughness in G and metallic in B like `MaterialDesc::new_pbr`.
+fn material_desc(material: &gltf::Material, name: &str, images: &[String]) -> MaterialDesc {
+    let mut desc = MaterialDesc::new_pbr(name);
+    let pbr = material.pbr_metallic_roughness();
+
+    let image_path = |texture: gltf::texture::Texture, tex_coord: u32| {
+        if tex_coord != 0 {
+            warn!("Material {}: only the first UV set is supported", name);
+        }
+
+        images[texture.source().index()].clone()
+    };
+
+    desc.set_param("base_color", MaterialParam::Color(pbr.base_color_factor()));
+    desc.set_param("metallic", MaterialParam::Float(pbr.metallic_factor()));
+    desc.set_param("roughness", MaterialParam::Float(pbr.roughness_factor()));
+
+    if let Some(info) = pbr.base_color_texture() {
+        desc.set_param("albedo", MaterialParam::Texture(image_path(info.texture(), info.tex_coord())));
+    }
+
+    if let Some(info) = pbr.metallic_roughness_texture() {
+        desc.set_param("metallic_roughness", MaterialParam::LinearTexture(image_path(info.texture(), info.tex_coord())));
+    }
+
+    //NOTE: The occlusion is often packed in the red channel of the metallic roughness texture, then both use the same image
+    if let Some(occlusion) = material.occlusion_texture() {
+        desc.set_param("occlusion", MaterialParam::LinearTexture(image_path(occlusion.texture(), occlusion.tex_coord())));
+        desc.set_param("occlusion_strength", MaterialParam::Float(occlusion.strength()));
+    }
+
+    if let Some(normal) = material.normal_texture() {
+        desc.set_param("normal", MaterialParam::LinearTexture(image_path(normal.texture(), normal.tex_coord())));
+        desc.set_param("normal_scale", MaterialParam::Float(normal.scale()));
+    }
+
+    if let Some(info) = material.emissive_texture() {
+        desc.set_param("emissive", MaterialParam::Texture(image_path(info.texture(), info.tex_coord())));
+    }
+
+    let [r, g, b] = material.emissive_factor();
+    desc.set_param("emissive_factor", MaterialParam::Vector3(Vector3::new(r, g, b)));
+
+    //NOTE: There is no alpha test yet, masked materials are blended
+    desc.render_state.blend = match material.alpha_mode() {
+        AlphaMode::Opaque => BlendMode::Opaque,
+        AlphaMode::Mask | AlphaMode::Blend => BlendMode::Alpha,
+    };
+
+    //TODO: Cull the back faces of single sided materials once the winding of the projection is settled
+
+    desc
+}
+
+/// Reads a primitive as a triangle list, points and lines are skipped.
+fn load_primitive(primitive: &gltf::Primitive, buffers: &[Vec<u8>], model_path: &str) -> Result<Option<Primitive>, AssetError> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data[..]));
+
+    let positions: Vec<[f32; 3]> = reader.read_positions()
+        .ok_or_else(|| AssetError::new(format!("Model {} has a primitive without positions", model_path)))?
+        .collect();
+
+    let indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+
+    let indices = match triangle_list(primitive.mode(), indices) {
+        Some(indices) => indices,
+        None => {
+            warn!("Model {}: skipping a primitive drawn as {:?}", model_path, primitive.mode());
+            return Ok(None);
+        }
+    };
+
+    if indices.iter().any(|index| *index as usize >= positions.len()) {
+        return Err(AssetError::new(format!("Model {} has a primitive with indices out of range", model_path)));
+    }
+
+    let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|normals| normals.collect());
+    let tangents: Option<Vec<[f32; 4]>> = reader.read_tangents().map(|tangents| tangents.collect());
+    let uvs: Option<Vec<[f32; 2]>> = reader.read_tex_coords(0).map(|uvs| uvs.into_f32().collect());
+    let colors: Option<Vec<[f32; 4]>> = reader.read_colors(0).map(|colors| colors.into_rgba_f32().collect());
+    //Indices into the joints of the skin of the node
+    let joints: Option<Vec<[u16; 4]>> = reader.read_joints(0).map(|joints| joints.into_u16().collect());
+    let weights: Option<Vec<[f32; 4]>> = reader.read_weights(0).map(|weights| weights.into_f32().collect());
+
+    //NOTE: glTF UVs already start at the top of the image, unlike OBJ they are not flipped
+    let mut vertices: Vec<Vertex> = positions.iter()
+        .enumerate()
+        .map(|(i, position)| {
+            let uv = uvs.as_ref().and_then(|uvs| uvs.get(i)).cloned().unwrap_or([0.0, 0.0]);
+            let color = colors.as_ref().and_then(|colors| colors.get(i)).cloned().unwrap_or([1.0, 1.0, 1.0, 1.0]);
+            let normal = normals.as_ref().and_then(|normals| normals.get(i)).cloned().unwrap_or([0.0, 0.0, 0.0]);
+            let tangent = tangents.as_ref().and_then(|tangents| tangents.get(i)).cloned().unwrap_or([0.0, 0.0, 0.0, 0.0]);
+            let joint = joints.as_ref().and_then(|joints| joints.get(i)).cloned().unwrap_or([0, 0, 0, 0]);
+            let weight = weights.as_ref().and_then(|weights| weights.get(i)).cloned().unwrap_or([0.0, 0.0, 0.0, 0.0]);
+
+            Vertex {
+                a_pos: vector3(*position),
+                a_color: Vector4::new(color[0], color[1], color[2], color[3]),
+                a_uv: Vector2::new(uv[0], uv[1]),
+                a_normal: vector3(normal),
+                a_tangent: Vector4::new(tangent[0], tangent[1], tangent[2], tangent[3]),
+                a_joints: Vector4::new(joint[0] as f32, joint[1] as f32, joint[2] as f32, joint[3] as f32),
+                a_weights: Vector4::new(weight[0], weight[1], weight[2], weight[3]),
+            }
+        })
+        .collect();
+
+    if normals.is_none() {
+        generate_smooth_normals(&mut vertices, &indices);
+    }
+
+    Ok(Some(Primitive {
+        vertices,
+        indices,
+        material: primitive.material().index(),
+    }))
+}
+
+/// Unrolls strips and fans, `None` for the modes that are not triangles.
+fn triangle_list(mode: Mode, indices: Vec<u32>) -> Option<Vec<u32>> {
+    match mode {
+        Mode::Triangles => Some(indices),
+        Mode::TriangleStrip => Some((2..indices.len())
+            .flat_map(|i| {
+                //Every other triangle of a strip is wound the other way
+                if i % 2 == 0 {
+                    vec![indices[i - 2], indices[i - 1], indices[i]]
+                } else {
+                    vec![indices[i - 1], indices[i - 2], indices[i]]
+                }
+            })
+            .collect()),
+        Mode::TriangleFan => Some((2..indices.len())
+            .flat_map(|i| vec![indices[0], indices[i - 1], indices[i]])
+            .collect()),
+        _ => None,
+    }
+}
+
+fn transform_vertices(vertices: &mut [Vertex], transform: &Matrix4) {
+    //NOTE: Normals are transformed by the inverse transpose, so non uniform scales keep them perpendicular
+    let normal_transform = transform.inverse()
+        .map(|inverse| inverse.transpose())
+        .unwrap_or(*transform);
+
+    //A mirroring transform flips the handedness of the tangent space
+    let handedness = if determinant3(transform) < 0.0 { -1.0 } else { 1.0 };
+
+    for vertex in vertices.iter_mut() {
+        let position = *transform * Vector4::from_vector3(&vertex.a_pos);
+        vertex.a_pos = Vector3::new(position.x, position.y, position.z);
+
+        let normal = normal_transform * Vector4::new(vertex.a_normal.x, vertex.a_normal.y, vertex.a_normal.z, 0.0);
+        vertex.a_normal = safe_normalize(Vector3::new(normal.x, normal.y, normal.z));
+
+        let tangent = *transform * Vector4::new(vertex.a_tangent.x, vertex.a_tangent.y, vertex.a_tangent.z, 0.0);
+        let tangent = safe_normalize(Vector3::new(tangent.x, tangent.y, tangent.z));
+        vertex.a_tangent = Vector4::new(tangent.x, tangent.y, tangent.z, vertex.a_tangent.w * handedness);
+    }
+}
+
+fn determinant3(m: &Matrix4) -> f32 {
+    m[0] * (m[5] * m[10] - m[6] * m[9])
+        - m[1] * (m[4] * m[10] - m[6] * m[8])
+        + m[2] * (m[4] * m[9] - m[5] * m[8])
+}
+
+fn safe_normalize(vec: Vector3) -> Vector3 {
+    if vec.magn() > 0.0 {
+        vec.normalize()
+    } else {
+        vec
+    }
+}
+
+fn vector3(value: [f32; 3]) -> Vector3 {
+    Vector3::new(value[0], value[1], value[2])
+}
+
+/// glTF matrices are stored column by column.
+fn matrix_from_columns(columns: [[f32; 4]; 4]) -> Matrix4 {
+    let mut matrix = Matrix4::zero();
+
+    for (col, column) in columns.iter().enumerate() {
+        for (row, value) in column.iter().enumerate() {
+            matrix[row * 4 + col] = *value;
+        }
+    }
+
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        percent_decode, triangle_list, GltfModel, Interpolation, Keyframes
+    };
+
+    use crate::material::{
+        BlendMode, MaterialParam
+    };
+
+    use gltf::mesh::Mode;
+
+    use std::path::Path;
+
+    use zeus_core::math::{
+        Vector3, Vector4
+    };
+
+    //A triangle in a child node moved up by its parent, with one material and one animation
+    fn triangle_gltf() -> String {
+        let floats: [f32; 12] = [
+            0.0, 0.0, 0.0,
+            1.0, 0.0, 0.0,
+            0.0, 1.0, 0.0,
+            //Keyframe times
+            0.0, 2.5, 0.0,
+        ];
+        let bytes: Vec<u8> = floats.iter().flat_map(|value| value.to_le_bytes().to_vec()).collect();
+
+        format!(r#"{{
+            "asset": {{"version": "2.0"}},
+            "scene": 0,
+            "scenes": [{{"nodes": [0]}}],
+            "nodes": [
+                {{"name": "root", "translation": [0.0, 2.0, 0.0], "children": [1]}},
+                {{"name": "triangle", "mesh": 0, "scale": [2.0, 2.0, 2.0]}}
+            ],
+            "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "material": 0}}]}}],
+            "materials": [{{
+                "name": "red",
+                "pbrMetallicRoughness": {{"baseColorFactor": [1.0, 0.0, 0.0, 1.0], "metallicFactor": 0.25}},
+                "occlusionTexture": {{"index": 0, "strength": 0.5}},
+                "alphaMode": "OPAQUE"
+            }}],
+            "textures": [{{"source": 0}}],
+            "images": [{{"uri": "occlusion.png"}}],
+            "animations": [{{
+                "name": "spin",
+                "channels": [{{"sampler": 0, "target": {{"node": 1, "path": "scale"}}}}],
+                "samplers": [{{"input": 1, "output": 2, "interpolation": "STEP"}}]
+            }}],
+            "accessors": [
+                {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0]}},
+                {{"bufferView": 1, "componentType": 5126, "count": 2, "type": "SCALAR", "min": [0.0], "max": [2.5]}},
+                {{"bufferView": 0, "byteOffset": 12, "componentType": 5126, "count": 2, "type": "VEC3"}}
+            ],
+            "bufferViews": [
+                {{"buffer": 0, "byteLength": 36}},
+                {{"buffer": 0, "byteOffset": 36, "byteLength": 12}}
+            ],
+            "buffers": [{{"byteLength": 48, "uri": "data:application/octet-stream;base64,{}"}}]
+        }}"#, base64::encode(&bytes))
+    }
+
+    #[test]
+    fn load_scene() {
+        let model = GltfModel::from_slice(triangle_gltf().as_bytes(), "models/triangle.gltf").unwrap();
+
+        assert_eq!(model.roots, vec![0]);
+        assert_eq!(model.nodes[1].parent, Some(0));
+        assert_eq!(model.meshes[0].primitives[0].indices, vec![0, 1, 2]);
+        assert_eq!(model.meshes[0].primitives[0].material, Some(0));
+
+        let material = &model.materials[0];
+        assert_eq!(material.name, "red");
+        assert_eq!(material.render_state.blend, BlendMode::Opaque);
+        assert_eq!(material.get_param("base_color"), Some(&MaterialParam::Color([1.0, 0.0, 0.0, 1.0])));
+        assert_eq!(material.get_param("metallic"), Some(&MaterialParam::Float(0.25)));
+        assert_eq!(material.get_param("occlusion_strength"), Some(&MaterialParam::Float(0.5)));
+
+        let occlusion = Path::new("models").join("occlusion.png").to_string_lossy().into_owned();
+        assert_eq!(material.get_param("occlusion"), Some(&MaterialParam::LinearTexture(occlusion)));
+
+        let animation = &model.animations[0];
+        assert_eq!(animation.duration, 2.5);
+        assert_eq!(animation.channels[0].node, 1);
+        assert_eq!(animation.channels[0].interpolation, Interpolation::Step);
+        assert_eq!(animation.channels[0].keyframes, Keyframes::Scales(vec![Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)]));
+    }
+
+    #[test]
+    fn flatten_applies_node_transforms() {
+        let model = GltfModel::from_slice(triangle_gltf().as_bytes(), "models/triangle.gltf").unwrap();
+
+        let (vertices, groups) = model.flatten();
+
+        assert_eq!(groups, vec![(Some(0), vec![0, 1, 2])]);
+        assert_eq!(vertices[1].a_pos, Vector3::new(2.0, 2.0, 0.0));
+        assert_eq!(vertices[2].a_pos, Vector3::new(0.0, 4.0, 0.0));
+        //Generated, the file has no normals or tangents
+        assert_eq!(vertices[0].a_normal, Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(vertices[0].a_tangent.w, 1.0);
+        assert_eq!(vertices[0].a_color, Vector4::new(1.0, 1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn unroll_strips_and_fans() {
+        assert_eq!(triangle_list(Mode::TriangleStrip, vec![0, 1, 2, 3]), Some(vec![0, 1, 2, 2, 1, 3]));
+        assert_eq!(triangle_list(Mode::TriangleFan, vec![0, 1, 2, 3]), Some(vec![0, 1, 2, 0, 2, 3]));
+        assert_eq!(triangle_list(Mode::Lines, vec![0, 1]), None);
+    }
+
+    #[test]
+    fn decode_uris() {
+        assert_eq!(percent_decode("textures/old%20wood.png"), "textures/old wood.png");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+}
@@ -0,0 +1,1052 @@
+use gfx_hal::{
+    command::{
+        ClearColor, ClearDepthStencil, ClearValue, CommandBuffer, SubpassContents
+    },
+    device::Device,
+    format::Format,
+    image::{
+        Access, Extent, Layout, NumSamples, SubresourceRange, Usage
+    },
+    memory::{
+        Barrier, Dependencies
+    },
+    pass::{
+        Attachment, AttachmentLoadOp, AttachmentOps, AttachmentStoreOp
+    },
+    pso::{
+        ColorValue, PipelineStage, Rect, Viewport
+    },
+    Backend,
+};
+
+use super::{
+    constants::BACKBUFFER_ATTACHMENT,
+    device::DeviceState,
+    error::RenderGraphError,
+    image::AttachmentImage,
+    pass::RenderPassState,
+};
+
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    ops::Range,
+    rc::Rc,
+    slice
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttachmentSize {
+    /// Scale of the swapchain extent
+    Relative(f32),
+    Absolute(u32, u32),
+}
+
+impl AttachmentSize {
+    pub fn resolve(&self, extent: Extent) -> Extent {
+        match *self {
+            AttachmentSize::Relative(scale) => Extent {
+                width: ((extent.width as f32 * scale) as u32).max(1),
+                height: ((extent.height as f32 * scale) as u32).max(1),
+                depth: 1
+            },
+            AttachmentSize::Absolute(width, height) => Extent {
+                width,
+                height,
+                depth: 1
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttachmentClear {
+    Color(ColorValue),
+    DepthStencil(f32, u32),
+}
+
+impl AttachmentClear {
+    pub fn value(&self) -> ClearValue {
+        match *self {
+            AttachmentClear::Color(color) => ClearValue {
+                color: ClearColor {
+                    float32: color
+                }
+            },
+            AttachmentClear::DepthStencil(depth, stencil) => ClearValue {
+                depth_stencil: ClearDepthStencil {
+                    depth,
+                    stencil
+                }
+            }
+        }
+    }
+}
+
+/// An image the graph allocates and owns. The backbuffer is not declared, its name is `BACKBUFFER_ATTACHMENT`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttachmentDesc {
+    pub name: String,
+    pub format: Format,
+    pub size: AttachmentSize,
+    pub samples: NumSamples,
+    /// Cleared on its first write every frame, the contents are undefined otherwise
+    pub clear: Option<AttachmentClear>,
+}
+
+impl AttachmentDesc {
+    pub fn new(name: &str, format: Format) -> Self {
+        AttachmentDesc {
+            name: name.to_string(),
+            format,
+            size: AttachmentSize::Relative(1.0),
+            samples: 1,
+            clear: None
+        }
+    }
+}
+
+/// The attachments a pass renders to and the attachments it samples.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PassDesc {
+    pub name: String,
+    pub colors: Vec<String>,
+    /// Single sampled targets the multisampled colors are resolved into, one per color
+    pub resolves: Vec<String>,
+    pub depth: Option<String>,
+    pub inputs: Vec<String>,
+}
+
+impl PassDesc {
+    pub fn new(name: &str) -> Self {
+        PassDesc {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn add_color(&mut self, attachment: &str) {
+        self.colors.push(attachment.to_string());
+    }
+
+    /// Resolves the color added at the same position into the attachment.
+    pub fn add_resolve(&mut self, attachment: &str) {
+        self.resolves.push(attachment.to_string());
+    }
+
+    pub fn set_depth(&mut self, attachment: &str) {
+        self.depth = Some(attachment.to_string());
+    }
+
+    pub fn add_input(&mut self, attachment: &str) {
+        self.inputs.push(attachment.to_string());
+    }
+
+    fn writes(&self) -> impl Iterator<Item = &String> {
+        self.colors.iter()
+            .chain(self.resolves.iter())
+            .chain(self.depth.iter())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Resource {
+    Backbuffer,
+    Attachment(usize),
+}
+
+/// How an attachment is used by the render pass of a compiled pass.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassAttachment {
+    pub resource: Resource,
+    pub load: AttachmentLoadOp,
+    pub store: AttachmentStoreOp,
+    pub layouts: Range<Layout>,
+}
+
+/// A transition recorded before the render pass of a compiled pass begins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassBarrier {
+    pub resource: Resource,
+    pub states: Range<(Access, Layout)>,
+    pub stages: Range<PipelineStage>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledPass {
+    /// Index of the pass in the description
+    pub index: usize,
+    /// Colors first, then the resolves and the depth attachment
+    pub attachments: Vec<PassAttachment>,
+    pub color_count: usize,
+    pub has_resolves: bool,
+    pub has_depth: bool,
+    pub samples: NumSamples,
+    pub size: AttachmentSize,
+    pub barriers: Vec<PassBarrier>,
+}
+
+/// One physical image, shared by the attachments whose lifetimes don't overlap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageSlot {
+    pub format: Format,
+    pub size: AttachmentSize,
+    pub samples: NumSamples,
+    pub usage: Usage,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledGraph {
+    /// The live passes in execution order
+    pub passes: Vec<CompiledPass>,
+    /// The image slot of each attachment, None if no live pass uses it
+    pub slots: Vec<Option<usize>>,
+    pub images: Vec<ImageSlot>,
+}
+
+/// Passes declare what they read and write, the graph works out the rest on `compile`:
+/// the execution order, which passes can be culled, the transient images and the transitions between passes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RenderGraphDesc {
+    attachments: Vec<AttachmentDesc>,
+    passes: Vec<PassDesc>,
+    backbuffer_clear: Option<AttachmentClear>,
+}
+
+impl RenderGraphDesc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_attachment(&mut self, attachment: AttachmentDesc) {
+        self.attachments.push(attachment);
+    }
+
+    pub fn add_pass(&mut self, pass: PassDesc) {
+        self.passes.push(pass);
+    }
+
+    pub fn set_clear(&mut self, attachment: &str, clear: AttachmentClear) {
+        if attachment == BACKBUFFER_ATTACHMENT {
+            self.backbuffer_clear = Some(clear);
+        } else if let Some(attachment) = self.attachments.iter_mut().find(|desc| desc.name == attachment) {
+            attachment.clear = Some(clear);
+        } else {
+            warn!("Render graph has no attachment {}", attachment);
+        }
+    }
+
+    fn get_clear(&self, resource: Resource) -> Option<AttachmentClear> {
+        match resource {
+            Resource::Backbuffer => self.backbuffer_clear,
+            Resource::Attachment(index) => self.attachments[index].clear
+        }
+    }
+
+    fn get_size(&self, resource: Resource) -> AttachmentSize {
+        match resource {
+            Resource::Backbuffer => AttachmentSize::Relative(1.0),
+            Resource::Attachment(index) => self.attachments[index].size
+        }
+    }
+
+    fn get_samples(&self, resource: Resource) -> NumSamples {
+        match resource {
+            Resource::Backbuffer => 1,
+            Resource::Attachment(index) => self.attachments[index].samples
+        }
+    }
+
+    fn resource(&self, pass: &PassDesc, name: &str) -> Result<Resource, RenderGraphError> {
+        if name == BACKBUFFER_ATTACHMENT {
+            return Ok(Resource::Backbuffer);
+        }
+
+        self.attachments.iter()
+            .position(|attachment| attachment.name == name)
+            .map(Resource::Attachment)
+            .ok_or_else(|| RenderGraphError::new(format!("Pass {} uses unknown attachment {}", pass.name, name)))
+    }
+
+    pub fn compile(&self) -> Result<CompiledGraph, RenderGraphError> {
+        let mut names = BTreeSet::new();
+        for attachment in self.attachments.iter() {
+            if attachment.name == BACKBUFFER_ATTACHMENT || !names.insert(&attachment.name) {
+                return Err(RenderGraphError::new(format!("Attachment {} is declared twice", attachment.name)));
+            }
+        }
+
+        //Resolve the names
+        let mut writes = Vec::new();
+        let mut reads = Vec::new();
+        for pass in self.passes.iter() {
+            let pass_writes = pass.writes()
+                .map(|name| self.resource(pass, name))
+                .collect::<Result<Vec<_>, _>>()?;
+            let pass_reads = pass.inputs.iter()
+                .map(|name| self.resource(pass, name))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if pass_writes.is_empty() {
+                return Err(RenderGraphError::new(format!("Pass {} has no attachments", pass.name)));
+            }
+            if pass_reads.contains(&Resource::Backbuffer) {
+                return Err(RenderGraphError::new(format!("Pass {} samples the backbuffer", pass.name)));
+            }
+            if let Some(resource) = pass_reads.iter().find(|resource| pass_writes.contains(resource)) {
+                return Err(RenderGraphError::new(format!("Pass {} reads and writes {:?}", pass.name, resource)));
+            }
+
+            let size = self.get_size(pass_writes[0]);
+            if pass_writes.iter().any(|&resource| self.get_size(resource) != size) {
+                return Err(RenderGraphError::new(format!("The attachments of pass {} have different sizes", pass.name)));
+            }
+
+            //The resolves are single sampled, everything else has the same sample count
+            let color_count = pass.colors.len();
+            let resolve_count = pass.resolves.len();
+            let samples = self.get_samples(pass_writes[0]);
+
+            if resolve_count > 0 && (resolve_count != color_count || samples == 1) {
+                return Err(RenderGraphError::new(format!("Pass {} must resolve every multisampled color", pass.name)));
+            }
+
+            let is_valid = pass_writes.iter().enumerate().all(|(slot, &resource)| {
+                let is_resolve = slot >= color_count && slot < color_count + resolve_count;
+                self.get_samples(resource) == if is_resolve { 1 } else { samples }
+            });
+            if !is_valid {
+                return Err(RenderGraphError::new(format!("The attachments of pass {} have different sample counts", pass.name)));
+            }
+
+            writes.push(pass_writes);
+            reads.push(pass_reads);
+        }
+
+        let (writes, reads) = (&writes, &reads);
+
+        let writers = |resource: Resource| (0..self.passes.len())
+            .filter(move |&pass| writes[pass].contains(&resource));
+
+        //Keep the passes that contribute to the backbuffer, every writer of a used resource is kept
+        let mut live = vec![false; self.passes.len()];
+        let mut used: Vec<Resource> = vec![Resource::Backbuffer];
+        while let Some(resource) = used.pop() {
+            for pass in writers(resource) {
+                if live[pass] {
+                    continue;
+                }
+
+                live[pass] = true;
+                used.extend(writes[pass].iter().chain(reads[pass].iter()).copied());
+            }
+        }
+
+        if !live.iter().any(|&live| live) {
+            return Err(RenderGraphError::new("No pass writes to the backbuffer".to_string()));
+        }
+
+        for (pass, _) in live.iter().enumerate().filter(|(_, &live)| !live) {
+            debug!("Culling pass {}, nothing uses its output", self.passes[pass].name);
+        }
+
+        //A pass runs after the writers of its inputs, writers of the same resource keep their declaration order
+        let mut dependencies = vec![BTreeSet::new(); self.passes.len()];
+        for pass in (0..self.passes.len()).filter(|&pass| live[pass]) {
+            for &resource in reads[pass].iter() {
+                let mut has_writer = false;
+                for writer in writers(resource).filter(|&writer| live[writer]) {
+                    dependencies[pass].insert(writer);
+                    has_writer = true;
+                }
+
+                if !has_writer {
+                    return Err(RenderGraphError::new(format!(
+                        "Pass {} reads {:?} but no pass writes it", self.passes[pass].name, resource
+                    )));
+                }
+            }
+
+            for &resource in writes[pass].iter() {
+                for writer in writers(resource).filter(|&writer| writer < pass && live[writer]) {
+                    dependencies[pass].insert(writer);
+                }
+            }
+        }
+
+        //NOTE: Kahn's algorithm, ties are broken by the declaration order
+        let mut order = Vec::new();
+        let mut done = vec![false; self.passes.len()];
+        while order.len() < live.iter().filter(|&&live| live).count() {
+            let next = (0..self.passes.len()).find(|&pass| {
+                live[pass] && !done[pass] && dependencies[pass].iter().all(|&dependency| done[dependency])
+            });
+
+            match next {
+                Some(pass) => {
+                    done[pass] = true;
+                    order.push(pass);
+                },
+                None => {
+                    let cycle: Vec<&str> = (0..self.passes.len())
+                        .filter(|&pass| live[pass] && !done[pass])
+                        .map(|pass| self.passes[pass].name.as_str())
+                        .collect();
+
+                    return Err(RenderGraphError::new(format!("The passes {:?} depend on each other", cycle)));
+                }
+            }
+        }
+
+        let used_after = |resource: Resource, position: usize| order[position + 1..].iter()
+            .any(|&pass| writes[pass].contains(&resource) || reads[pass].contains(&resource));
+
+        let last_write = |resource: Resource| order.iter()
+            .rposition(|&pass| writes[pass].contains(&resource));
+
+        //Walk the passes in order and track the layout of every resource
+        let mut layouts = vec![Layout::Undefined; self.attachments.len()];
+        let mut written = vec![false; self.attachments.len()];
+        let mut backbuffer_layout = Layout::Undefined;
+        let mut backbuffer_written = false;
+        let mut passes = Vec::new();
+
+        for (position, &pass) in order.iter().enumerate() {
+            let mut barriers = Vec::new();
+
+            for &resource in reads[pass].iter() {
+                if let Resource::Attachment(index) = resource {
+                    if layouts[index] == Layout::ShaderReadOnlyOptimal {
+                        continue;
+                    }
+
+                    let (access, stage) = attachment_state(layouts[index]);
+                    barriers.push(PassBarrier {
+                        resource,
+                        states: (access, layouts[index]) .. (Access::SHADER_READ, Layout::ShaderReadOnlyOptimal),
+                        stages: stage .. PipelineStage::FRAGMENT_SHADER,
+                    });
+                    layouts[index] = Layout::ShaderReadOnlyOptimal;
+                }
+            }
+
+            let has_depth = self.passes[pass].depth.is_some();
+            let color_count = self.passes[pass].colors.len();
+            let resolve_count = self.passes[pass].resolves.len();
+            let mut attachments = Vec::new();
+
+            for (slot, &resource) in writes[pass].iter().enumerate() {
+                let layout = if has_depth && slot == writes[pass].len() - 1 {
+                    Layout::DepthStencilAttachmentOptimal
+                } else {
+                    Layout::ColorAttachmentOptimal
+                };
+
+                let (is_written, current_layout) = match resource {
+                    Resource::Backbuffer => (backbuffer_written, backbuffer_layout),
+                    Resource::Attachment(index) => (written[index], layouts[index])
+                };
+
+                let is_resolve = slot >= color_count && slot < color_count + resolve_count;
+
+                //Only keep the old contents if an earlier pass wrote them this frame, resolves overwrite them anyway
+                let (load, initial_layout) = if is_resolve {
+                    (AttachmentLoadOp::DontCare, Layout::Undefined)
+                } else if is_written {
+                    (AttachmentLoadOp::Load, current_layout)
+                } else if self.get_clear(resource).is_some() {
+                    (AttachmentLoadOp::Clear, Layout::Undefined)
+                } else {
+                    (AttachmentLoadOp::DontCare, Layout::Undefined)
+                };
+
+                let store = if resource == Resource::Backbuffer || used_after(resource, position) {
+                    AttachmentStoreOp::Store
+                } else {
+                    AttachmentStoreOp::DontCare
+                };
+
+                let final_layout = match resource {
+                    Resource::Backbuffer if last_write(resource) == Some(position) => Layout::Present,
+                    _ => layout
+                };
+
+                match resource {
+                    Resource::Backbuffer => {
+                        backbuffer_written = true;
+                        backbuffer_layout = final_layout;
+                    },
+                    Resource::Attachment(index) => {
+                        written[index] = true;
+                        layouts[index] = final_layout;
+                    }
+                }
+
+                attachments.push(PassAttachment {
+                    resource,
+                    load,
+                    store,
+                    layouts: initial_layout .. final_layout
+                });
+            }
+
+            passes.push(CompiledPass {
+                index: pass,
+                attachments,
+                color_count,
+                has_resolves: resolve_count > 0,
+                has_depth,
+                samples: self.get_samples(writes[pass][0]),
+                size: self.get_size(writes[pass][0]),
+                barriers
+            });
+        }
+
+        let (slots, images) = self.assign_slots(&order, writes, reads);
+
+        Ok(CompiledGraph {
+            passes,
+            slots,
+            images
+        })
+    }
+
+    /// Attachments with the same format, size and usage share an image when their lifetimes don't overlap.
+    fn assign_slots(
+        &self,
+        order: &[usize],
+        writes: &[Vec<Resource>],
+        reads: &[Vec<Resource>],
+    ) -> (Vec<Option<usize>>, Vec<ImageSlot>) {
+        let mut lifetimes: Vec<(usize, Range<usize>, ImageSlot)> = Vec::new();
+
+        for (index, attachment) in self.attachments.iter().enumerate() {
+            let resource = Resource::Attachment(index);
+            let mut usage = Usage::empty();
+            let mut first = None;
+            let mut last = 0;
+
+            for (position, &pass) in order.iter().enumerate() {
+                let mut is_used = false;
+
+                if writes[pass].contains(&resource) {
+                    usage |= if self.passes[pass].depth.as_ref() == Some(&attachment.name) {
+                        Usage::DEPTH_STENCIL_ATTACHMENT
+                    } else {
+                        Usage::COLOR_ATTACHMENT
+                    };
+                    is_used = true;
+                }
+
+                if reads[pass].contains(&resource) {
+                    usage |= Usage::SAMPLED;
+                    is_used = true;
+                }
+
+                if is_used {
+                    first.get_or_insert(position);
+                    last = position;
+                }
+            }
+
+            if let Some(first) = first {
+                lifetimes.push((index, first..last, ImageSlot {
+                    format: attachment.format,
+                    size: attachment.size,
+                    samples: attachment.samples,
+                    usage
+                }));
+            }
+        }
+
+        lifetimes.sort_by_key(|(_, lifetime, _)| lifetime.start);
+
+        let mut slots = vec![None; self.attachments.len()];
+        let mut images: Vec<ImageSlot> = Vec::new();
+        //The last pass using each image
+        let mut image_ends: Vec<usize> = Vec::new();
+
+        for (index, lifetime, slot) in lifetimes {
+            let free = images.iter()
+                .zip(image_ends.iter())
+                .position(|(image, &end)| *image == slot && end < lifetime.start);
+
+            match free {
+                Some(image) => {
+                    image_ends[image] = lifetime.end;
+                    slots[index] = Some(image);
+                },
+                None => {
+                    images.push(slot);
+                    image_ends.push(lifetime.end);
+                    slots[index] = Some(images.len() - 1);
+                }
+            }
+        }
+
+        (slots, images)
+    }
+}
+
+/// The access and the stage that last touched an attachment in the layout.
+fn attachment_state(layout: Layout) -> (Access, PipelineStage) {
+    match layout {
+        Layout::DepthStencilAttachmentOptimal => (
+            Access::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            PipelineStage::LATE_FRAGMENT_TESTS
+        ),
+        Layout::ColorAttachmentOptimal => (
+            Access::COLOR_ATTACHMENT_WRITE,
+            PipelineStage::COLOR_ATTACHMENT_OUTPUT
+        ),
+        _ => (Access::empty(), PipelineStage::TOP_OF_PIPE)
+    }
+}
+
+/// The render passes and transient images of a compiled `RenderGraphDesc`.
+/// It is rebuilt when the swapchain changes, the description is kept.
+pub struct RenderGraph<B: Backend> {
+    device: Rc<RefCell<DeviceState<B>>>,
+    desc: RenderGraphDesc,
+    compiled: CompiledGraph,
+    render_passes: Vec<RenderPassState<B>>,
+    images: Vec<AttachmentImage<B>>,
+    /// The framebuffers of the passes that don't write the backbuffer, they live as long as the images
+    framebuffers: Vec<Option<B::Framebuffer>>,
+    extent: Extent,
+}
+
+impl<B: Backend> RenderGraph<B> {
+    pub fn new(
+        device: Rc<RefCell<DeviceState<B>>>,
+        desc: RenderGraphDesc,
+        backbuffer_format: Format,
+        extent: Extent,
+    ) -> Result<Self, RenderGraphError> {
+        let compiled = desc.compile()?;
+
+        let render_passes = compiled.passes.iter()
+            .map(|pass| {
+                let attachments = pass.attachments.iter()
+                    .map(|attachment| Attachment {
+                        format: Some(match attachment.resource {
+                            Resource::Backbuffer => backbuffer_format,
+                            Resource::Attachment(index) => desc.attachments[index].format
+                        }),
+                        samples: desc.get_samples(attachment.resource),
+                        ops: AttachmentOps::new(attachment.load, attachment.store),
+                        stencil_ops: AttachmentOps::DONT_CARE,
+                        layouts: attachment.layouts.clone()
+                    })
+                    .collect();
+
+                RenderPassState::new(
+                    Rc::clone(&device),
+                    attachments,
+                    pass.color_count,
+                    pass.has_resolves,
+                    pass.has_depth
+                )
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(pass) = compiled.passes.iter().zip(render_passes.iter())
+            .find(|(_, render_pass)| render_pass.render_pass.is_none())
+            .map(|(pass, _)| &desc.passes[pass.index])
+        {
+            return Err(RenderGraphError::new(format!("Could not create the render pass of {}", pass.name)));
+        }
+
+        let images = compiled.images.iter()
+            .map(|image| AttachmentImage::new(
+                Rc::clone(&device),
+                image.format,
+                image.size.resolve(extent),
+                image.samples,
+                image.usage
+            ))
+            .collect::<Vec<_>>();
+
+        let framebuffers = compiled.passes.iter().zip(render_passes.iter())
+            .map(|(pass, render_pass)| {
+                if pass.attachments.iter().any(|attachment| attachment.resource == Resource::Backbuffer) {
+                    return None;
+                }
+
+                let views = pass.attachments.iter()
+                    .map(|attachment| match attachment.resource {
+                        Resource::Attachment(index) => images[compiled.slots[index].unwrap()]
+                            .get_image_view()
+                            .unwrap(),
+                        Resource::Backbuffer => unreachable!()
+                    });
+
+                let framebuffer = unsafe {
+                    device.borrow().device.create_framebuffer(
+                        render_pass.render_pass.as_ref().unwrap(),
+                        views,
+                        pass.size.resolve(extent)
+                    )
+                }.expect("Could not create framebuffer");
+
+                Some(framebuffer)
+            })
+            .collect();
+
+        debug!(
+            "Render graph: {} passes, {} transient images for {} attachments",
+            compiled.passes.len(),
+            compiled.images.len(),
+            desc.attachments.len()
+        );
+
+        Ok(RenderGraph {
+            device,
+            desc,
+            compiled,
+            render_passes,
+            images,
+            framebuffers,
+            extent
+        })
+    }
+
+    /// Recreates the render passes and the images, for a new swapchain or a changed description.
+    pub fn rebuild(
+        &mut self,
+        desc: RenderGraphDesc,
+        backbuffer_format: Format,
+        extent: Extent,
+    ) -> Result<(), RenderGraphError> {
+        *self = Self::new(
+            Rc::clone(&self.device),
+            desc,
+            backbuffer_format,
+            extent
+        )?;
+
+        Ok(())
+    }
+
+    /// Clear values are read when the passes are recorded, they don't need a rebuild.
+    pub fn set_clear(&mut self, attachment: &str, clear: AttachmentClear) {
+        self.desc.set_clear(attachment, clear);
+    }
+
+    /// The render pass of a pass, None if the pass was culled.
+    pub fn get_render_pass(&self, pass: &str) -> Option<&B::RenderPass> {
+        self.compiled.passes.iter()
+            .position(|compiled| self.desc.passes[compiled.index].name == pass)
+            .and_then(|index| self.render_passes[index].render_pass.as_ref())
+    }
+
+    /// The sample count of a pass, the pipelines used in it have to match it.
+    pub fn get_samples(&self, pass: &str) -> NumSamples {
+        self.compiled.passes.iter()
+            .find(|compiled| self.desc.passes[compiled.index].name == pass)
+            .map_or(1, |compiled| compiled.samples)
+    }
+
+    /// The view of an attachment, for passes that sample it.
+    pub fn get_image_view(&self, attachment: &str) -> Option<&B::ImageView> {
+        self.desc.attachments.iter()
+            .position(|desc| desc.name == attachment)
+            .and_then(|index| self.compiled.slots[index])
+            .and_then(|slot| self.images[slot].get_image_view())
+    }
+
+    /// Records every pass. `record` is called inside the render pass of each one with its name.
+    /// The framebuffers of the backbuffer passes are added to `framebuffers`, they have to live until the command buffer finished executing.
+    pub unsafe fn execute<F>(
+        &self,
+        cmd_buffer: &mut B::CommandBuffer,
+        backbuffer: &B::ImageView,
+        framebuffers: &mut Vec<B::Framebuffer>,
+        mut record: F,
+    ) where
+        F: FnMut(&str, &B::RenderPass, &mut B::CommandBuffer),
+    {
+        for (index, (compiled, render_pass)) in self.compiled.passes.iter().zip(self.render_passes.iter()).enumerate() {
+            let pass = &self.desc.passes[compiled.index];
+            let render_pass = render_pass.render_pass.as_ref()
+                .expect("Render Pass is empty!");
+
+            if cfg!(debug_assertions) {
+                cmd_buffer.begin_debug_marker(&pass.name, 0);
+            }
+
+            for barrier in compiled.barriers.iter() {
+                let image = match barrier.resource {
+                    Resource::Attachment(index) => &self.images[self.compiled.slots[index].unwrap()],
+                    Resource::Backbuffer => continue
+                };
+
+                cmd_buffer.pipeline_barrier(
+                    barrier.stages.clone(),
+                    Dependencies::empty(),
+                    &[Barrier::Image {
+                        states: barrier.states.clone(),
+                        target: image.get_image(),
+                        families: None,
+                        range: SubresourceRange {
+                            aspects: image.get_format().surface_desc().aspects,
+                            level_start: 0,
+                            level_count: Some(1),
+                            layer_start: 0,
+                            layer_count: Some(1)
+                        }
+                    }]
+                );
+            }
+
+            let extent = compiled.size.resolve(self.extent);
+
+            //NOTE: Framebuffers of the swapchain image are owned by the surface, which destroys them
+            //when the image is acquired again, so they can't be kept with the graph
+            let frame_framebuffer = match self.framebuffers[index] {
+                Some(_) => None,
+                None => {
+                    let views = compiled.attachments.iter()
+                        .map(|attachment| match attachment.resource {
+                            Resource::Backbuffer => backbuffer,
+                            Resource::Attachment(index) => self.images[self.compiled.slots[index].unwrap()]
+                                .get_image_view()
+                                .unwrap()
+                        })
+                        .collect::<Vec<_>>();
+
+                    Some(self.device.borrow()
+                        .device.create_framebuffer(render_pass, views, extent)
+                        .expect("Could not create framebuffer"))
+                }
+            };
+
+            let clear_values = compiled.attachments.iter()
+                .map(|attachment| self.desc.get_clear(attachment.resource)
+                    .unwrap_or(AttachmentClear::Color([0.0, 0.0, 0.0, 1.0]))
+                    .value())
+                .collect::<Vec<_>>();
+
+            let viewport = Viewport {
+                rect: Rect {
+                    x: 0,
+                    y: 0,
+                    w: extent.width as i16,
+                    h: extent.height as i16,
+                },
+                depth: -1.0 .. 1.0,
+            };
+
+            cmd_buffer.set_viewports(0, slice::from_ref(&viewport));
+            cmd_buffer.set_scissors(0, [viewport.rect]);
+
+            cmd_buffer.begin_render_pass(
+                render_pass,
+                self.framebuffers[index].as_ref().or(frame_framebuffer.as_ref()).unwrap(),
+                viewport.rect,
+                clear_values,
+                SubpassContents::Inline,
+            );
+
+            record(&pass.name, render_pass, cmd_buffer);
+
+            cmd_buffer.end_render_pass();
+
+            if cfg!(debug_assertions) {
+                cmd_buffer.end_debug_marker();
+            }
+
+            framebuffers.extend(frame_framebuffer);
+        }
+    }
+}
+
+impl<B: Backend> Drop for RenderGraph<B> {
+    fn drop(&mut self) {
+        let device = &self.device.borrow().device;
+
+        for framebuffer in self.framebuffers.drain(..).flatten() {
+            unsafe {
+                device.destroy_framebuffer(framebuffer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gfx_hal::{
+        format::Format,
+        image::{
+            Layout, Usage
+        },
+        pass::{
+            AttachmentLoadOp, AttachmentStoreOp
+        },
+    };
+
+    use super::{
+        AttachmentClear, AttachmentDesc, PassDesc, RenderGraphDesc, Resource, BACKBUFFER_ATTACHMENT
+    };
+
+    fn scene_and_post() -> RenderGraphDesc {
+        let mut graph = RenderGraphDesc::new();
+
+        let mut scene_color = AttachmentDesc::new("scene_color", Format::Rgba16Sfloat);
+        scene_color.clear = Some(AttachmentClear::Color([0.0, 0.0, 0.0, 1.0]));
+        graph.add_attachment(scene_color);
+
+        let mut depth = AttachmentDesc::new("depth", Format::D32Sfloat);
+        depth.clear = Some(AttachmentClear::DepthStencil(1.0, 0));
+        graph.add_attachment(depth);
+
+        //Declared before the pass it depends on
+        let mut post = PassDesc::new("post");
+        post.add_input("scene_color");
+        post.add_color(BACKBUFFER_ATTACHMENT);
+        graph.add_pass(post);
+
+        let mut scene = PassDesc::new("scene");
+        scene.add_color("scene_color");
+        scene.set_depth("depth");
+        graph.add_pass(scene);
+
+        graph
+    }
+
+    #[test]
+    fn orders_passes_and_inserts_barriers() {
+        let compiled = scene_and_post().compile().unwrap();
+
+        let order: Vec<usize> = compiled.passes.iter().map(|pass| pass.index).collect();
+        assert_eq!(order, vec![1, 0]);
+
+        let scene = &compiled.passes[0];
+        assert!(scene.has_depth);
+        assert!(scene.barriers.is_empty());
+        assert_eq!(scene.attachments[0].load, AttachmentLoadOp::Clear);
+        assert_eq!(scene.attachments[0].store, AttachmentStoreOp::Store);
+        //Nothing reads the depth afterwards
+        assert_eq!(scene.attachments[1].store, AttachmentStoreOp::DontCare);
+
+        let post = &compiled.passes[1];
+        assert_eq!(post.barriers.len(), 1);
+        assert_eq!(post.barriers[0].resource, Resource::Attachment(0));
+        assert_eq!(post.barriers[0].states.end.1, Layout::ShaderReadOnlyOptimal);
+        assert_eq!(post.attachments[0].layouts, Layout::Undefined..Layout::Present);
+
+        assert_eq!(compiled.images[compiled.slots[0].unwrap()].usage, Usage::COLOR_ATTACHMENT | Usage::SAMPLED);
+    }
+
+    #[test]
+    fn culls_unused_passes() {
+        let mut graph = scene_and_post();
+        graph.add_attachment(AttachmentDesc::new("debug", Format::Rgba8Unorm));
+
+        let mut debug = PassDesc::new("debug");
+        debug.add_color("debug");
+        graph.add_pass(debug);
+
+        let compiled = graph.compile().unwrap();
+        assert_eq!(compiled.passes.len(), 2);
+        assert_eq!(compiled.slots[2], None);
+    }
+
+    #[test]
+    fn aliases_attachments_with_disjoint_lifetimes() {
+        let mut graph = RenderGraphDesc::new();
+        for name in ["a", "b", "c"].iter() {
+            graph.add_attachment(AttachmentDesc::new(name, Format::Rgba8Unorm));
+        }
+
+        //a -> b -> c -> backbuffer, a and c are never alive at the same time
+        let mut first = PassDesc::new("first");
+        first.add_color("a");
+        graph.add_pass(first);
+
+        let mut second = PassDesc::new("second");
+        second.add_input("a");
+        second.add_color("b");
+        graph.add_pass(second);
+
+        let mut third = PassDesc::new("third");
+        third.add_input("b");
+        third.add_color("c");
+        graph.add_pass(third);
+
+        let mut last = PassDesc::new("last");
+        last.add_input("c");
+        last.add_color(BACKBUFFER_ATTACHMENT);
+        graph.add_pass(last);
+
+        let compiled = graph.compile().unwrap();
+        assert_eq!(compiled.images.len(), 2);
+        assert_eq!(compiled.slots[0], compiled.slots[2]);
+        assert_ne!(compiled.slots[0], compiled.slots[1]);
+    }
+
+    #[test]
+    fn resolves_multisampled_colors() {
+        let mut graph = RenderGraphDesc::new();
+
+        let mut color = AttachmentDesc::new("color", Format::Bgra8Srgb);
+        color.samples = 4;
+        color.clear = Some(AttachmentClear::Color([0.0, 0.0, 0.0, 1.0]));
+        graph.add_attachment(color);
+
+        let mut depth = AttachmentDesc::new("depth", Format::D32Sfloat);
+        depth.samples = 4;
+        graph.add_attachment(depth);
+
+        let mut scene = PassDesc::new("scene");
+        scene.add_color("color");
+        scene.add_resolve(BACKBUFFER_ATTACHMENT);
+        scene.set_depth("depth");
+        graph.add_pass(scene.clone());
+
+        let compiled = graph.compile().unwrap();
+        let pass = &compiled.passes[0];
+        assert_eq!(pass.samples, 4);
+        assert!(pass.has_resolves);
+        assert_eq!(pass.attachments[1].resource, Resource::Backbuffer);
+        assert_eq!(pass.attachments[1].load, AttachmentLoadOp::DontCare);
+        assert_eq!(pass.attachments[1].layouts, Layout::Undefined..Layout::Present);
+        //The multisampled color is only needed until it is resolved
+        assert_eq!(pass.attachments[0].store, AttachmentStoreOp::DontCare);
+
+        //A single sampled color can't be resolved
+        let mut single = RenderGraphDesc::new();
+        single.add_attachment(AttachmentDesc::new("color", Format::Bgra8Srgb));
+        single.add_pass(scene);
+        assert!(single.compile().is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_graphs() {
+        let mut cycle = RenderGraphDesc::new();
+        cycle.add_attachment(AttachmentDesc::new("a", Format::Rgba8Unorm));
+        cycle.add_attachment(AttachmentDesc::new("b", Format::Rgba8Unorm));
+
+        let mut first = PassDesc::new("first");
+        first.add_input("b");
+        first.add_color("a");
+        cycle.add_pass(first);
+
+        let mut second = PassDesc::new("second");
+        second.add_input("a");
+        second.add_color("b");
+        second.add_color(BACKBUFFER_ATTACHMENT);
+        cycle.add_pass(second);
+
+        assert!(cycle.compile().is_err());
+
+        let mut unknown = RenderGraphDesc::new();
+        let mut pass = PassDesc::new("pass");
+        pass.add_input("missing");
+        pass.add_color(BACKBUFFER_ATTACHMENT);
+        unknown.add_pass(pass);
+
+        assert!(unknown.compile().is_err());
+        assert!(RenderGraphDesc::new().compile().is_err());
+    }
+}
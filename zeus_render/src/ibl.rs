@@ -0,0 +1,544 @@
+use super::{
+    constants::FILE_EXT,
+    error::AssetError,
+};
+
+use image::hdr::HDRDecoder;
+
+use regex::Regex;
+
+use std::{
+    f32::consts::PI,
+    io::Cursor
+};
+
+use zeus_core::{
+    math::Vector3,
+    vfs
+};
+
+/// A cube map on the CPU with linear RGB texels.
+/// The faces are in the Vulkan order +X, -X, +Y, -Y, +Z, -Z and each face is stored row by row.
+#[derive(Debug, Clone)]
+pub struct CubeMap {
+    pub size: usize,
+    pub faces: Vec<Vec<Vector3>>,
+}
+
+impl CubeMap {
+    /// Fills every texel with the value of `f` in the direction of its center.
+    pub fn from_fn<F: Fn(Vector3) -> Vector3>(
+        size: usize,
+        f: F
+    ) -> Self {
+        let faces = (0..6)
+            .map(|face| {
+                (0..size * size)
+                    .map(|texel| {
+                        let (u, v) = texel_coords(texel % size, texel / size, size);
+                        f(cube_direction(face, u, v))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        CubeMap { size, faces }
+    }
+
+    /// Resamples an equirectangular panorama, the top row looks up (+Y).
+    pub fn from_equirect(
+        width: usize,
+        height: usize,
+        texels: &[Vector3],
+        size: usize
+    ) -> Self {
+        Self::from_fn(size, |dir| {
+            let u = 0.5 + dir.z.atan2(dir.x) / (2.0 * PI);
+            let v = dir.y.clamp(-1.0, 1.0).acos() / PI;
+
+            let x = ((u * width as f32) as usize).min(width - 1);
+            let y = ((v * height as f32) as usize).min(height - 1);
+            texels[y * width + x]
+        })
+    }
+
+    /// Bilinear lookup inside the face the direction points at.
+    pub fn sample(&self, dir: Vector3) -> Vector3 {
+        let (face, u, v) = direction_to_face(dir);
+        let max = (self.size - 1) as f32;
+
+        let x = ((u + 1.0) * 0.5 * self.size as f32 - 0.5).max(0.0).min(max);
+        let y = ((v + 1.0) * 0.5 * self.size as f32 - 0.5).max(0.0).min(max);
+
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.size - 1), (y0 + 1).min(self.size - 1));
+        let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+
+        let top = self.texel(face, x0, y0) * (1.0 - tx) + self.texel(face, x1, y0) * tx;
+        let bottom = self.texel(face, x0, y1) * (1.0 - tx) + self.texel(face, x1, y1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    /// Half the size, every texel is the average of the four it covers.
+    pub fn downsample(&self) -> Self {
+        if self.size <= 1 {
+            return self.clone();
+        }
+
+        let size = self.size / 2;
+        let faces = (0..6)
+            .map(|face| {
+                (0..size * size)
+                    .map(|texel| {
+                        let (x, y) = (texel % size * 2, texel / size * 2);
+                        (self.texel(face, x, y) + self.texel(face, x + 1, y)
+                            + self.texel(face, x, y + 1) + self.texel(face, x + 1, y + 1)) * 0.25
+                    })
+                    .collect()
+            })
+            .collect();
+
+        CubeMap { size, faces }
+    }
+
+    /// The texels as Rgba16Sfloat, face after face.
+    pub fn to_rgba16f(&self) -> Vec<u16> {
+        self.faces.iter()
+            .flat_map(|face| face.iter())
+            .flat_map(|texel| vec![
+                f32_to_f16(texel.x),
+                f32_to_f16(texel.y),
+                f32_to_f16(texel.z),
+                f32_to_f16(1.0)
+            ])
+            .collect()
+    }
+
+    fn texel(&self, face: usize, x: usize, y: usize) -> Vector3 {
+        self.faces[face][y * self.size + x]
+    }
+}
+
+/// The maps the shaders light the scene with, generated from an environment cube map.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMaps {
+    /// Diffuse light by normal, already divided by π
+    pub irradiance: CubeMap,
+    /// Specular light by reflection, one map per mip level with the roughness going from 0 to 1
+    pub prefiltered: Vec<CubeMap>,
+}
+
+impl EnvironmentMaps {
+    pub fn new(
+        environment: &CubeMap,
+        irradiance_size: usize,
+        prefiltered_size: usize,
+        prefiltered_levels: usize,
+        samples: usize
+    ) -> Self {
+        EnvironmentMaps {
+            irradiance: irradiance_map(environment, irradiance_size),
+            prefiltered: prefiltered_map(environment, prefiltered_size, prefiltered_levels, samples),
+        }
+    }
+}
+
+/// Loads an equirectangular panorama, .hdr files keep their range and the rest are treated as sRGB.
+pub fn load_environment(path: &str, size: usize) -> Result<CubeMap, AssetError> {
+    let re = Regex::new(FILE_EXT).unwrap();
+    let file_ext = re.captures(path)
+        .and_then(|captures| captures.get(1))
+        .map_or("", |ext| ext.as_str());
+
+    let bytes = vfs::read(path)
+        .map_err(|err| AssetError::new(format!("Could not read environment {}: {:?}", path, err)))?;
+
+    let (width, height, texels): (usize, usize, Vec<Vector3>) = if file_ext.eq_ignore_ascii_case("hdr") {
+        let decoder = HDRDecoder::new(Cursor::new(&bytes[..]))
+            .map_err(|err| AssetError::new(format!("Could not decode environment {}: {:?}", path, err)))?;
+        let metadata = decoder.metadata();
+
+        let texels = decoder.read_image_hdr()
+            .map_err(|err| AssetError::new(format!("Could not decode environment {}: {:?}", path, err)))?
+            .iter()
+            .map(|texel| Vector3::new(texel[0], texel[1], texel[2]))
+            .collect();
+
+        (metadata.width as usize, metadata.height as usize, texels)
+    } else {
+        let img = image::load_from_memory(&bytes)
+            .map_err(|err| AssetError::new(format!("Could not decode environment {}: {:?}", path, err)))?
+            .to_rgb();
+
+        let texels = img.pixels()
+            .map(|texel| Vector3::new(
+                srgb_to_linear(texel[0]),
+                srgb_to_linear(texel[1]),
+                srgb_to_linear(texel[2])
+            ))
+            .collect();
+
+        (img.width() as usize, img.height() as usize, texels)
+    };
+
+    if width == 0 || height == 0 {
+        return Err(AssetError::new(format!("Environment {} is empty", path)));
+    }
+
+    Ok(CubeMap::from_equirect(width, height, &texels, size))
+}
+
+/// A simple sky, used until an environment is loaded.
+pub fn default_sky(size: usize) -> CubeMap {
+    let zenith = Vector3::new(0.25, 0.45, 0.85);
+    let horizon = Vector3::new(0.8, 0.85, 0.9);
+    let ground = Vector3::new(0.25, 0.22, 0.2);
+
+    CubeMap::from_fn(size, |dir| {
+        if dir.y > 0.0 {
+            let t = dir.y.sqrt();
+            horizon * (1.0 - t) + zenith * t
+        } else {
+            let t = (-dir.y).sqrt();
+            horizon * (1.0 - t) + ground * t
+        }
+    })
+}
+
+/// The cosine weighted integral of the environment around each normal, divided by π.
+//NOTE: Brute force over every texel of a small copy of the environment
+pub fn irradiance_map(environment: &CubeMap, size: usize) -> CubeMap {
+    let mut source = environment.clone();
+    while source.size > 16 {
+        source = source.downsample();
+    }
+
+    let mut texels = Vec::with_capacity(6 * source.size * source.size);
+    for face in 0..6 {
+        for texel in 0..source.size * source.size {
+            let (u, v) = texel_coords(texel % source.size, texel / source.size, source.size);
+            let solid_angle = texel_solid_angle(u, v, source.size);
+            texels.push((cube_direction(face, u, v), source.faces[face][texel] * solid_angle));
+        }
+    }
+
+    CubeMap::from_fn(size, |normal| {
+        let mut sum = Vector3::default();
+        for (dir, radiance) in texels.iter() {
+            let cos_theta = normal.dot(dir);
+            if cos_theta > 0.0 {
+                sum += radiance * cos_theta;
+            }
+        }
+
+        sum / PI
+    })
+}
+
+/// Filters the environment with GGX for increasing roughness, assuming the view is along the normal.
+pub fn prefiltered_map(
+    environment: &CubeMap,
+    size: usize,
+    levels: usize,
+    samples: usize
+) -> Vec<CubeMap> {
+    let mut chain = vec![environment.clone()];
+    while chain.last().unwrap().size > 1 {
+        let next = chain.last().unwrap().downsample();
+        chain.push(next);
+    }
+
+    (0..levels)
+        .map(|level| {
+            let level_size = (size >> level).max(1);
+
+            if level == 0 {
+                //A mirror reflects the environment as it is
+                CubeMap::from_fn(level_size, |dir| sample_chain(&chain, dir, 0.0))
+            } else {
+                let roughness = level as f32 / (levels - 1) as f32;
+                CubeMap::from_fn(level_size, |normal| prefilter(&chain, normal, roughness, samples))
+            }
+        })
+        .collect()
+}
+
+/// The scale (r) and bias (g) of F0 in the split sum approximation, by n·v along a row and roughness down the rows.
+pub fn brdf_lut(size: usize, samples: usize) -> Vec<[f32; 2]> {
+    (0..size * size)
+        .map(|texel| {
+            let n_dot_v = ((texel % size) as f32 + 0.5) / size as f32;
+            let roughness = ((texel / size) as f32 + 0.5) / size as f32;
+            integrate_brdf(n_dot_v, roughness, samples)
+        })
+        .collect()
+}
+
+/// The bits of the half float nearest to `value`, values out of range become infinity.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        //Infinity or NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if exponent <= 0 {
+        //Subnormal, the implicit bit becomes part of the mantissa
+        if exponent < -10 {
+            return sign;
+        }
+
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = (mantissa >> shift) as u16;
+        let round = ((mantissa >> (shift - 1)) & 1) as u16;
+        return sign | (half + round);
+    }
+
+    //A carry from rounding moves into the exponent, which is still the nearest value
+    let half = sign | ((exponent as u16) << 10) | (mantissa >> 13) as u16;
+    half + ((mantissa >> 12) & 1) as u16
+}
+
+/// The direction through `u`, `v` (-1..1) of a face, following the Vulkan cube map conventions.
+pub fn cube_direction(face: usize, u: f32, v: f32) -> Vector3 {
+    let dir = match face {
+        0 => Vector3::new(1.0, -v, -u),
+        1 => Vector3::new(-1.0, -v, u),
+        2 => Vector3::new(u, 1.0, v),
+        3 => Vector3::new(u, -1.0, -v),
+        4 => Vector3::new(u, -v, 1.0),
+        _ => Vector3::new(-u, -v, -1.0),
+    };
+
+    dir.normalize()
+}
+
+/// The face a direction points at and the `u`, `v` (-1..1) on it.
+pub fn direction_to_face(dir: Vector3) -> (usize, f32, f32) {
+    let (ax, ay, az) = (dir.x.abs(), dir.y.abs(), dir.z.abs());
+
+    if ax >= ay && ax >= az {
+        if dir.x > 0.0 {
+            (0, -dir.z / ax, -dir.y / ax)
+        } else {
+            (1, dir.z / ax, -dir.y / ax)
+        }
+    } else if ay >= az {
+        if dir.y > 0.0 {
+            (2, dir.x / ay, dir.z / ay)
+        } else {
+            (3, dir.x / ay, -dir.z / ay)
+        }
+    } else if dir.z > 0.0 {
+        (4, dir.x / az, -dir.y / az)
+    } else {
+        (5, -dir.x / az, -dir.y / az)
+    }
+}
+
+fn texel_coords(x: usize, y: usize, size: usize) -> (f32, f32) {
+    (
+        2.0 * (x as f32 + 0.5) / size as f32 - 1.0,
+        2.0 * (y as f32 + 0.5) / size as f32 - 1.0
+    )
+}
+
+fn texel_solid_angle(u: f32, v: f32, size: usize) -> f32 {
+    let texel = 2.0 / size as f32;
+    texel * texel / (1.0 + u * u + v * v).powf(1.5)
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Reads between the two nearest levels of the mip chain.
+fn sample_chain(chain: &[CubeMap], dir: Vector3, lod: f32) -> Vector3 {
+    let lod = lod.max(0.0).min((chain.len() - 1) as f32);
+    let low = lod.floor() as usize;
+    let high = (low + 1).min(chain.len() - 1);
+    let t = lod - low as f32;
+
+    chain[low].sample(dir) * (1.0 - t) + chain[high].sample(dir) * t
+}
+
+fn prefilter(chain: &[CubeMap], normal: Vector3, roughness: f32, samples: usize) -> Vector3 {
+    let alpha = roughness * roughness;
+    let (tangent, bitangent) = basis(normal);
+    //The solid angle of a texel of the full size environment
+    let texel_angle = 4.0 * PI / (6.0 * (chain[0].size * chain[0].size) as f32);
+
+    let mut sum = Vector3::default();
+    let mut weight = 0.0;
+    for i in 0..samples {
+        let (halfway, cos_theta) = importance_sample_ggx(hammersley(i, samples), normal, tangent, bitangent, alpha);
+        let light = halfway * (2.0 * normal.dot(&halfway)) - normal;
+
+        let n_dot_l = normal.dot(&light);
+        if n_dot_l > 0.0 {
+            //Samples that cover more of the sphere read from smaller mips, to avoid noise from bright spots
+            let pdf = distribution_ggx(cos_theta, alpha) / 4.0;
+            let sample_angle = 1.0 / (samples as f32 * pdf + 0.0001);
+            let lod = 0.5 * (sample_angle / texel_angle).log2() + 1.0;
+
+            sum += sample_chain(chain, light, lod) * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+
+    if weight > 0.0 {
+        sum / weight
+    } else {
+        sample_chain(chain, normal, 0.0)
+    }
+}
+
+fn integrate_brdf(n_dot_v: f32, roughness: f32, samples: usize) -> [f32; 2] {
+    let alpha = roughness * roughness;
+    let normal = Vector3::new(0.0, 0.0, 1.0);
+    let view = Vector3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+    //The Schlick-GGX k of image based lighting
+    let k = alpha / 2.0;
+
+    let mut scale = 0.0;
+    let mut bias = 0.0;
+    for i in 0..samples {
+        let (halfway, _) = importance_sample_ggx(
+            hammersley(i, samples),
+            normal,
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            alpha
+        );
+        let v_dot_h = view.dot(&halfway).max(0.0);
+        let light = halfway * (2.0 * v_dot_h) - view;
+
+        let n_dot_l = light.z.max(0.0);
+        let n_dot_h = halfway.z.max(0.0);
+        if n_dot_l > 0.0 {
+            let geometry = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+            let visibility = geometry * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = (1.0 - v_dot_h).powi(5);
+
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+
+    [scale / samples as f32, bias / samples as f32]
+}
+
+/// A halfway vector around `normal` distributed like GGX, and its cosine to the normal.
+fn importance_sample_ggx(
+    (xi_1, xi_2): (f32, f32),
+    normal: Vector3,
+    tangent: Vector3,
+    bitangent: Vector3,
+    alpha: f32
+) -> (Vector3, f32) {
+    let phi = 2.0 * PI * xi_1;
+    let cos_theta = ((1.0 - xi_2) / (1.0 + (alpha * alpha - 1.0) * xi_2)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+    let halfway = tangent * (sin_theta * phi.cos())
+        + bitangent * (sin_theta * phi.sin())
+        + normal * cos_theta;
+
+    (halfway.normalize(), cos_theta)
+}
+
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha_2 = alpha * alpha;
+    let denom = n_dot_h * n_dot_h * (alpha_2 - 1.0) + 1.0;
+    alpha_2 / (PI * denom * denom)
+}
+
+fn hammersley(i: usize, count: usize) -> (f32, f32) {
+    (i as f32 / count as f32, (i as u32).reverse_bits() as f32 * 2.328_306_4e-10)
+}
+
+fn basis(normal: Vector3) -> (Vector3, Vector3) {
+    let up = if normal.z.abs() < 0.999 {
+        Vector3::new(0.0, 0.0, 1.0)
+    } else {
+        Vector3::new(1.0, 0.0, 0.0)
+    };
+
+    let tangent = up.cross(&normal).normalize();
+    let bitangent = normal.cross(&tangent);
+    (tangent, bitangent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{brdf_lut, cube_direction, direction_to_face, f32_to_f16, irradiance_map, CubeMap};
+
+    use zeus_core::math::Vector3;
+
+    #[test]
+    fn cube_faces_round_trip() {
+        for face in 0..6 {
+            for &(u, v) in &[(0.0, 0.0), (0.5, -0.25), (-0.75, 0.9)] {
+                let (found, found_u, found_v) = direction_to_face(cube_direction(face, u, v));
+                assert_eq!(found, face);
+                assert!((found_u - u).abs() < 1e-5 && (found_v - v).abs() < 1e-5);
+            }
+        }
+
+        //The center of +Y looks up
+        let up = cube_direction(2, 0.0, 0.0);
+        assert!((up.y - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn uniform_environment_irradiance() {
+        //A constant radiance of 1 integrates to π, which is stored divided by π
+        let environment = CubeMap::from_fn(8, |_| Vector3::new(1.0, 1.0, 1.0));
+        let irradiance = irradiance_map(&environment, 4);
+
+        for face in irradiance.faces.iter() {
+            for texel in face.iter() {
+                assert!((texel.x - 1.0).abs() < 0.02, "{}", texel.x);
+            }
+        }
+    }
+
+    #[test]
+    fn brdf_lut_range() {
+        let lut = brdf_lut(8, 64);
+        for texel in lut.iter() {
+            assert!(texel[0] >= 0.0 && texel[1] >= 0.0 && texel[0] + texel[1] <= 1.01);
+        }
+
+        //A smooth surface seen head on reflects everything
+        let smooth = lut[7];
+        assert!(smooth[0] + smooth[1] > 0.9);
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(f32_to_f16(0.0), 0);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        //The smallest subnormal
+        assert_eq!(f32_to_f16(5.96e-8), 1);
+    }
+}
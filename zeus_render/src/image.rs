@@ -2,13 +2,11 @@ use super::{
     adapter::AdapterState,
     buffer::BufferState,
     constants::{
-        DEPTH_RANGE, FILE_EXT, IMAGE_FORMAT
-    },
-    desc::{
-        DescSet,DescSetWrite
+        ERROR_TEXTURE_PATH, FILE_EXT
     },
     device::DeviceState,
-    model::Dimensions
+    error::AssetError,
+    memory::Allocation
 };
 
 use gfx_hal::{Backend, buffer, command::{
@@ -16,57 +14,77 @@ use gfx_hal::{Backend, buffer, command::{
     }, device::Device, format::{
         Aspects, Format, ImageFeature, Swizzle
     }, image::{
-        Access, Extent, Filter, Kind, Layout, Offset, SamplerDesc, Size, SubresourceLayers, Tiling, Usage, ViewCapabilities, ViewKind, WrapMode, Lod, PackedColor, SubresourceRange
+        Access, Extent, Filter, Kind, Layout, NumSamples, Offset, SamplerDesc, Size, SubresourceLayers, Tiling, Usage, ViewCapabilities, ViewKind, WrapMode, Lod, PackedColor, SubresourceRange
     }, memory::{
         Barrier, Dependencies, Properties
-    }, pool::CommandPool, pso::{
-        Descriptor, PipelineStage, Comparison
+    }, pool::{CommandPool, CommandPoolCreateFlags}, pso::{
+        PipelineStage, Comparison
     }, queue::{CommandQueue, QueueFamilyId}};
 
+use image::RgbaImage;
+
 use regex::Regex;
 
 use std::{
+    cell::RefCell,
     iter,
+    mem::size_of,
     rc::Rc,
-    fs,
     io::Cursor,
 };
 
+use zeus_core::vfs;
+
 
 #[derive(Debug)]
 pub struct ImageState<B: Backend> {
-    pub desc: DescSet<B>,
+    device: Rc<RefCell<DeviceState<B>>>,
     buffer: Option<BufferState<B>>,
     sampler: Option<B::Sampler>,
     image_view: Option<B::ImageView>,
     image: Option<B::Image>,
-    memory: Option<B::Memory>,
+    memory: Option<Allocation>,
     transfered_image_fence: Option<B::Fence>,
     mip_levels: u8
 }
 
 impl<B: Backend> ImageState<B> {
     pub fn new_texture(
-        mut desc: DescSet<B>,
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
         img_path: &str,
+        format: Format,
         adapter: &AdapterState<B>,
         usage: buffer::Usage,
         device_state: &mut DeviceState<B>,
         staging_pool: &mut B::CommandPool,
     ) -> Self {
+        let img = Self::load_image(img_path)
+            .unwrap_or_else(|err| {
+                error!("{}", err.message);
+                Self::load_image(ERROR_TEXTURE_PATH)
+                    .expect("Could not load error texture")
+            });
+
+        Self::new_texture_from_image(
+            device_ptr,
+            &img,
+            format,
+            adapter,
+            usage,
+            device_state,
+            staging_pool
+        )
+    }
 
+    /// Reads and decodes an image file, without touching the GPU.
+    pub fn load_image(img_path: &str) -> Result<RgbaImage, AssetError> {
         let re = Regex::new(FILE_EXT).unwrap();
-        let mut file_ext = re.captures(img_path).unwrap().get(1).unwrap().as_str();
-
-        //IMAGE
-        let image_bytes = match fs::read(img_path) {
-            Ok(img) => img,
-            Err(err) => {
-                error!("{:?}", err);
-                file_ext = "png";
-                fs::read("./data/textures/error.png").unwrap()
-            }
-        };
+        let file_ext = re.captures(img_path)
+            .and_then(|captures| captures.get(1))
+            .map_or("", |ext| ext.as_str());
+
+        let image_bytes = vfs::read(img_path)
+            .map_err(|err| AssetError::new(format!("Could not read image {}: {:?}", img_path, err)))?;
 
         let file_format = match file_ext {
             "png" => image::PNG,
@@ -79,9 +97,21 @@ impl<B: Backend> ImageState<B> {
         let img = image::load(
             Cursor::new(&image_bytes[..]),
             file_format
-        ).expect("Could not load Image")
+        ).map_err(|err| AssetError::new(format!("Could not decode image {}: {:?}", img_path, err)))?
         .to_rgba();
 
+        Ok(img)
+    }
+
+    pub fn new_texture_from_image(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        img: &RgbaImage,
+        format: Format,
+        adapter: &AdapterState<B>,
+        usage: buffer::Usage,
+        device_state: &mut DeviceState<B>,
+        staging_pool: &mut B::CommandPool,
+    ) -> Self {
         let width = if img.width() > img.height() {
             img.width() as f32
         } else {
@@ -92,53 +122,38 @@ impl<B: Backend> ImageState<B> {
 
         //BUFFER
         let (buffer, dims, row_pitch, stride) = BufferState::new_texture(
-            Rc::clone(&desc.layout.device),
-            &device_state.device,
-            &img,
+            Rc::clone(&device_ptr),
+            device_state,
+            img,
             adapter,
             usage,
         );
 
         let buffer = Some(buffer);
-        let device = &mut device_state.device;
 
         let mut image = unsafe {
-            device.create_image(
+            device_state.device.create_image(
                 Kind::D2(dims.width as Size, dims.height as Size, 1, 1),
                 mip_levels,
-                IMAGE_FORMAT,
+                format,
                 Tiling::Optimal,
                 Usage::TRANSFER_SRC | Usage::TRANSFER_DST | Usage::SAMPLED,
                 ViewCapabilities::empty(),
             )
         }.expect("Could not create image");
 
-        let req = unsafe { 
-            device.get_image_requirements(&image) 
-        };
-
-        let device_type = adapter
-            .memory_types.iter().enumerate()
-            .position(|(id, memory_type)| {
-                req.type_mask & (1 << id) != 0
-                    && memory_type.properties.contains(Properties::DEVICE_LOCAL)
-            }).unwrap()
-            .into();
-
-        let memory = unsafe { 
-            device.allocate_memory(device_type, req.size) 
-        }.expect("Could not allocate memory for image");
+        let memory = device_state.allocator
+            .bind_image(&device_state.device, &mut image, Properties::DEVICE_LOCAL)
+            .unwrap_or_else(|err| panic!("{}", err.message));
 
-        unsafe { 
-            device.bind_image_memory(&memory, 0, &mut image) 
-        }.expect("Could not bind image memroy");
+        let device = &mut device_state.device;
 
         //Create Image View and Sampler.
         let image_view = unsafe {
             device.create_image_view(
                 &image,
                 ViewKind::D2,
-                IMAGE_FORMAT,
+                format,
                 Swizzle::NO,
                 SubresourceRange {
                     aspects: Aspects::COLOR,
@@ -166,30 +181,11 @@ impl<B: Backend> ImageState<B> {
             })
         }.expect("Can't create sampler");
 
-        desc.write_to_state(
-            vec![
-                DescSetWrite {
-                    binding: 0,
-                    array_offset: 0,
-                    descriptors: Some(Descriptor::Image(
-                        &image_view,
-                        Layout::ShaderReadOnlyOptimal,
-                    )),
-                },
-                DescSetWrite {
-                    binding: 1,
-                    array_offset: 0,
-                    descriptors: Some(Descriptor::Sampler(&sampler)),
-                },
-            ],
-            device,
-        );
-
         let transfered_image_fence = device.create_fence(false)
             .expect("Can't create fence");
 
         let device_props = device_state
-            .physical_device_format_properties(Some(IMAGE_FORMAT));
+            .physical_device_format_properties(Some(format));
 
         //Copy buffer to texture
         unsafe {
@@ -347,7 +343,7 @@ impl<B: Backend> ImageState<B> {
         }
 
         ImageState {
-            desc,
+            device: device_ptr,
             buffer,
             sampler: Some(sampler),
             image_view: Some(image_view),
@@ -358,146 +354,320 @@ impl<B: Backend> ImageState<B> {
         }
     }
 
-    pub fn new_depth_image(
-        desc: DescSet<B>,
-        dims: Dimensions<u32>,
-        adapter: &AdapterState<B>,
-        device_state: &mut DeviceState<B>,
-        staging_pool: &mut B::CommandPool,
-    ) -> Self {
-        let device = &mut device_state.device;
+    pub fn wait_for_transfer_completion(&self) {
+        let device = &self.device.borrow().device;
+        unsafe {
+            device.wait_for_fence(self.transfered_image_fence.as_ref().unwrap(), !0)
+                .unwrap();
+        }
+    }
 
-        let mut depth_image = unsafe {
-            device.create_image(
-                Kind::D2(dims.width as Size, dims.height as Size, 1, 1),
-                1,
-                Format::D32SfloatS8Uint,
-                Tiling::Optimal,
-                Usage::DEPTH_STENCIL_ATTACHMENT,
-                ViewCapabilities::empty()
-            )
-        }.expect("Could not create depth image");
+    #[allow(dead_code)]
+    pub fn get_image_view(&self) -> Option<&B::ImageView> {
+        if self.image_view.is_none() {
+            None
+        } else {
+            self.image_view.as_ref()
+        }
+    }
 
-        let req = unsafe {
-            device.get_image_requirements(&depth_image)
-        };
+    pub fn get_sampler(&self) -> &B::Sampler {
+        self.sampler.as_ref().unwrap()
+    }
+}
 
-        let device_type = adapter.memory_types
-            .iter().enumerate().position(|(id, memory_type)| {
-                req.type_mask & (1 << id) != 0
-                    && memory_type.properties.contains(Properties::DEVICE_LOCAL)
-            }).unwrap().into();
-        
-        let memory = unsafe { 
-            device.allocate_memory(device_type, req.size) 
-        }.expect("Could not allocation memory for image");
+impl<B: Backend> Drop for ImageState<B> {
+    fn drop(&mut self) {
+        {
+            let state = &mut *self.device.borrow_mut();
 
-        unsafe { 
-            device.bind_image_memory(&memory, 0, &mut depth_image) 
-        }.expect("Could not bind image memory");
+            unsafe {
+                let device = &state.device;
 
-        //Create Image View
-        let depth_image_view = unsafe {
-            device.create_image_view(
-                &depth_image,
-                ViewKind::D2,
-                Format::D32SfloatS8Uint,
-                Swizzle::NO,
-                DEPTH_RANGE.clone()
-            )
-        }.expect("Could not create depth image view");
+                let fence = self.transfered_image_fence.take().unwrap();
+                device.wait_for_fence(&fence, !0).unwrap();
+                device.destroy_fence(fence);
 
-        let sampler = unsafe {
-            device.create_sampler(&SamplerDesc {
-                mag_filter: Filter::Linear,
-                min_filter: Filter::Linear,
-                mip_filter: Filter::Linear,
-                wrap_mode: (WrapMode::Clamp, WrapMode::Clamp, WrapMode::Clamp),
-                lod_bias: Lod(0.0_f32),
-                lod_range: Lod::RANGE,
-                comparison: Some(Comparison::Always),
-                border: PackedColor(0_u32),
-                normalized: true,
-                anisotropy_clamp: None
-            })
-        }.expect("Could not create depth sampler");
+                device.destroy_sampler(self.sampler.take().unwrap());
+                device.destroy_image_view(self.image_view.take().unwrap());
+                device.destroy_image(self.image.take().unwrap());
+            }
 
-        let transfered_image_fence = device.create_fence(false)
-            .expect("Could not create depth fence");
+            state.allocator.free(&state.device, self.memory.take().unwrap());
+        }
 
-        //Copy buffer to depth image
-        unsafe {
-            let mut cmd_buffer = staging_pool.allocate_one(Level::Primary);
-            cmd_buffer.begin_primary (CommandBufferFlags::ONE_TIME_SUBMIT);
+        if self.buffer.is_some() {
+            self.buffer.take().expect("No Buffer found!");
+        }
+        
+    }
+}
 
-            cmd_buffer.pipeline_barrier(
-                PipelineStage::TOP_OF_PIPE..PipelineStage::EARLY_FRAGMENT_TESTS,
-                Dependencies::empty(),
-                &[Barrier::Image {
-                    states: (Access::empty(), Layout::Undefined) .. (Access::DEPTH_STENCIL_ATTACHMENT_READ | Access::DEPTH_STENCIL_ATTACHMENT_WRITE, Layout::DepthStencilAttachmentOptimal),
-                    target: &depth_image,
-                    families: None,
-                    range: DEPTH_RANGE.clone()
-                }]
-            );
+/// A render target allocated by the render graph. It is not uploaded to and has no descriptors of its own.
+#[derive(Debug)]
+pub struct AttachmentImage<B: Backend> {
+    device: Rc<RefCell<DeviceState<B>>>,
+    image: Option<B::Image>,
+    image_view: Option<B::ImageView>,
+    memory: Option<Allocation>,
+    format: Format,
+}
 
-            cmd_buffer.finish();
+impl<B: Backend> AttachmentImage<B> {
+    pub fn new(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        format: Format,
+        extent: Extent,
+        samples: NumSamples,
+        usage: Usage,
+    ) -> Self {
+        let (image, image_view, memory) = {
+            let device_state = &mut *device_ptr.borrow_mut();
+
+            let mut image = unsafe {
+                device_state.device.create_image(
+                    Kind::D2(extent.width as Size, extent.height as Size, 1, samples),
+                    1,
+                    format,
+                    Tiling::Optimal,
+                    usage,
+                    ViewCapabilities::empty()
+                )
+            }.expect("Could not create attachment image");
+
+            let memory = device_state.allocator
+                .bind_image(&device_state.device, &mut image, Properties::DEVICE_LOCAL)
+                .unwrap_or_else(|err| panic!("{}", err.message));
+
+            let image_view = unsafe {
+                device_state.device.create_image_view(
+                    &image,
+                    ViewKind::D2,
+                    format,
+                    Swizzle::NO,
+                    SubresourceRange {
+                        aspects: format.surface_desc().aspects,
+                        level_start: 0,
+                        level_count: Some(1),
+                        layer_start: 0,
+                        layer_count: Some(1)
+                    }
+                )
+            }.expect("Could not create attachment image view");
 
-            device_state.queues.queues[0]
-                .submit_without_semaphores(iter::once(&cmd_buffer), Some(&transfered_image_fence));
-        }
+            (image, image_view, memory)
+        };
 
-        ImageState {
-            desc,
-            buffer: None,
-            sampler: Some(sampler),
-            image_view: Some(depth_image_view),
-            image: Some(depth_image),
+        AttachmentImage {
+            device: device_ptr,
+            image: Some(image),
+            image_view: Some(image_view),
             memory: Some(memory),
-            transfered_image_fence: Some(transfered_image_fence),
-            mip_levels: 0
+            format,
         }
     }
 
-    pub fn wait_for_transfer_completion(&self) {
-        let device = &self.desc.layout.device.borrow().device;
-        unsafe {
-            device.wait_for_fence(self.transfered_image_fence.as_ref().unwrap(), !0)
-                .unwrap();
-        }
+    pub fn get_image(&self) -> &B::Image {
+        self.image.as_ref().unwrap()
     }
 
-    pub fn get_layout(&self) -> &B::DescriptorSetLayout {
-        self.desc.get_layout()
+    pub fn get_image_view(&self) -> Option<&B::ImageView> {
+        self.image_view.as_ref()
     }
 
-    pub fn get_image_view(&self) -> Option<&B::ImageView> {
-        if self.image_view.is_none() {
-            None
-        } else {
-            self.image_view.as_ref()
-        }
+    pub fn get_format(&self) -> Format {
+        self.format
     }
 }
 
-impl<B: Backend> Drop for ImageState<B> {
+impl<B: Backend> Drop for AttachmentImage<B> {
     fn drop(&mut self) {
+        let state = &mut *self.device.borrow_mut();
+
         unsafe {
-            let device = &self.desc.layout.device.borrow().device;
+            state.device.destroy_image_view(self.image_view.take().unwrap());
+            state.device.destroy_image(self.image.take().unwrap());
+        }
 
-            let fence = self.transfered_image_fence.take().unwrap();
-            device.wait_for_fence(&fence, !0).unwrap();
-            device.destroy_fence(fence);
+        state.allocator.free(&state.device, self.memory.take().unwrap());
+    }
+}
+
+/// An image uploaded once from texels generated on the CPU, like the maps of image based lighting.
+/// Six layers make a cube map.
+#[derive(Debug)]
+pub struct DataImage<B: Backend> {
+    device: Rc<RefCell<DeviceState<B>>>,
+    image: Option<B::Image>,
+    image_view: Option<B::ImageView>,
+    memory: Option<Allocation>,
+}
+
+impl<B: Backend> DataImage<B> {
+    /// `levels` are the texels of every mip level, halving from `size`, with the layers of a level one after the other.
+    pub fn new<T: Copy>(
+        device_ptr: Rc<RefCell<DeviceState<B>>>,
+        format: Format,
+        size: u32,
+        layers: u16,
+        levels: &[Vec<T>],
+    ) -> Self {
+        let data: Vec<T> = levels.concat();
+
+        let staging_buffer = BufferState::new(
+            Rc::clone(&device_ptr),
+            &data,
+            buffer::Usage::TRANSFER_SRC,
+            Properties::CPU_VISIBLE | Properties::COHERENT,
+        );
+
+        let (image, image_view, memory) = {
+            let device_state = &mut *device_ptr.borrow_mut();
+            let mip_levels = levels.len() as u8;
+
+            let (view_kind, capabilities) = if layers == 6 {
+                (ViewKind::Cube, ViewCapabilities::KIND_CUBE)
+            } else {
+                (ViewKind::D2, ViewCapabilities::empty())
+            };
+
+            let mut image = unsafe {
+                device_state.device.create_image(
+                    Kind::D2(size as Size, size as Size, layers, 1),
+                    mip_levels,
+                    format,
+                    Tiling::Optimal,
+                    Usage::TRANSFER_DST | Usage::SAMPLED,
+                    capabilities,
+                )
+            }.expect("Could not create data image");
+
+            let memory = device_state.allocator
+                .bind_image(&device_state.device, &mut image, Properties::DEVICE_LOCAL)
+                .unwrap_or_else(|err| panic!("{}", err.message));
+
+            let range = SubresourceRange {
+                aspects: Aspects::COLOR,
+                level_start: 0,
+                level_count: Some(mip_levels),
+                layer_start: 0,
+                layer_count: Some(layers)
+            };
+
+            let image_view = unsafe {
+                device_state.device.create_image_view(
+                    &image,
+                    view_kind,
+                    format,
+                    Swizzle::NO,
+                    range.clone(),
+                )
+            }.expect("Could not create data image view");
+
+            let mut regions = Vec::with_capacity(levels.len());
+            let mut offset = 0;
+            for (level, texels) in levels.iter().enumerate() {
+                let level_size = (size >> level).max(1);
+
+                regions.push(BufferImageCopy {
+                    buffer_offset: offset,
+                    buffer_width: level_size,
+                    buffer_height: level_size,
+                    image_layers: SubresourceLayers {
+                        aspects: Aspects::COLOR,
+                        level: level as u8,
+                        layers: 0..layers,
+                    },
+                    image_offset: Offset { x: 0, y: 0, z: 0 },
+                    image_extent: Extent {
+                        width: level_size,
+                        height: level_size,
+                        depth: 1,
+                    },
+                });
 
-            device.destroy_sampler(self.sampler.take().unwrap());
-            device.destroy_image_view(self.image_view.take().unwrap());
-            device.destroy_image(self.image.take().unwrap());
-            device.free_memory(self.memory.take().unwrap());
+                offset += (texels.len() * size_of::<T>()) as u64;
+            }
+
+            unsafe {
+                let mut staging_pool = device_state.device.create_command_pool(
+                    device_state.queues.family,
+                    CommandPoolCreateFlags::TRANSIENT
+                ).expect("Can't create staging command pool");
+
+                let fence = device_state.device.create_fence(false)
+                    .expect("Can't create fence");
+
+                let mut cmd_buffer = staging_pool.allocate_one(Level::Primary);
+                cmd_buffer.begin_primary(CommandBufferFlags::ONE_TIME_SUBMIT);
+
+                cmd_buffer.pipeline_barrier(
+                    PipelineStage::TOP_OF_PIPE .. PipelineStage::TRANSFER,
+                    Dependencies::empty(),
+                    &[Barrier::Image {
+                        states: (Access::empty(), Layout::Undefined)
+                            ..(Access::TRANSFER_WRITE, Layout::TransferDstOptimal),
+                        target: &image,
+                        families: None,
+                        range: range.clone()
+                    }],
+                );
+
+                cmd_buffer.copy_buffer_to_image(
+                    staging_buffer.get_buffer(),
+                    &image,
+                    Layout::TransferDstOptimal,
+                    &regions,
+                );
+
+                cmd_buffer.pipeline_barrier(
+                    PipelineStage::TRANSFER .. PipelineStage::FRAGMENT_SHADER,
+                    Dependencies::empty(),
+                    &[Barrier::Image {
+                        states: (Access::TRANSFER_WRITE, Layout::TransferDstOptimal)
+                            ..(Access::SHADER_READ, Layout::ShaderReadOnlyOptimal),
+                        target: &image,
+                        families: None,
+                        range
+                    }],
+                );
+
+                cmd_buffer.finish();
+
+                device_state.queues.queues[0]
+                    .submit_without_semaphores(iter::once(&cmd_buffer), Some(&fence));
+
+                device_state.device.wait_for_fence(&fence, !0).unwrap();
+                device_state.device.destroy_fence(fence);
+                staging_pool.free(iter::once(cmd_buffer));
+                device_state.device.destroy_command_pool(staging_pool);
+            }
+
+            (image, image_view, memory)
+        };
+
+        DataImage {
+            device: device_ptr,
+            image: Some(image),
+            image_view: Some(image_view),
+            memory: Some(memory),
         }
+    }
 
-        if self.buffer.is_some() {
-            self.buffer.take().expect("No Buffer found!");
+    pub fn get_image_view(&self) -> &B::ImageView {
+        self.image_view.as_ref().unwrap()
+    }
+}
+
+impl<B: Backend> Drop for DataImage<B> {
+    fn drop(&mut self) {
+        let state = &mut *self.device.borrow_mut();
+
+        unsafe {
+            state.device.destroy_image_view(self.image_view.take().unwrap());
+            state.device.destroy_image(self.image.take().unwrap());
         }
-        
+
+        state.allocator.free(&state.device, self.memory.take().unwrap());
     }
 }
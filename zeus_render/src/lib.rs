@@ -10,26 +10,46 @@ extern crate glsl_to_spirv;
 extern crate image as img;
 
 mod adapter;
+mod animation;
 mod backend;
 mod buffer;
 mod camera;
 mod constants;
+mod controller;
+mod culling;
 mod desc;
 mod device;
 mod framebuffer;
+mod gltf_import;
+mod graph;
+mod ibl;
 mod image;
+mod light;
+mod material;
+mod memory;
+mod mesh;
+mod mesh_cache;
 mod model;
 mod obj;
 mod pass;
 mod pipeline;
+mod post;
+mod primitives;
+mod reflect;
 mod renderer;
+mod shader;
+mod shadow;
 mod swapchain;
+mod tangent;
+mod vertex_layout;
+mod view;
+mod watcher;
 mod error;
 
 use winit::{
     dpi::LogicalSize,
     event::{
-        DeviceEvent, Event, KeyboardInput, VirtualKeyCode, WindowEvent
+        DeviceEvent, ElementState, Event, KeyboardInput, MouseScrollDelta, VirtualKeyCode, WindowEvent
     },
     event_loop::{
         ControlFlow, EventLoop
@@ -39,16 +59,29 @@ use winit::{
 
 use self::{
     constants::{
-        DIMS, VERSION
+        BENCHMARK_ARG, DATA_ARCHIVE_NAME, DATA_DIR, DATA_MOUNT_POINT, DIMS, VERSION
     }, 
+    post::PostEffect,
     renderer::RendererState
 };
 
-use zeus_core::input;
+use std::{
+    env,
+    path::{
+        Path, PathBuf
+    }
+};
+
+use zeus_core::{
+    input,
+    vfs
+};
 
 pub fn render() {
     info!("Starting up Zeus Engine V{}", VERSION);
 
+    mount_data();
+
     let event_loop = EventLoop::new();
     let window_builder = WindowBuilder::new()
         .with_min_inner_size(LogicalSize::new(1.0, 1.0))
@@ -58,7 +91,11 @@ pub fn render() {
 
     let mut renderer_state = RendererState::new(backend);
 
-    renderer_state.load_level();
+    if env::args().any(|arg| arg == BENCHMARK_ARG) {
+        renderer_state.load_benchmark_level();
+    } else {
+        renderer_state.load_level();
+    }
 
     match renderer_state.draw() {
         Err(err) => {
@@ -97,8 +134,64 @@ pub fn render() {
                 } => {
                     if let Some(virtual_keycode) = virtual_keycode {
                         input::update_btn(virtual_keycode, state);
+
+                        if virtual_keycode == VirtualKeyCode::M && state == ElementState::Pressed {
+                            renderer_state.cycle_msaa();
+                        }
+
+                        if virtual_keycode == VirtualKeyCode::N && state == ElementState::Pressed {
+                            renderer_state.next_animation();
+                        }
+
+                        if virtual_keycode == VirtualKeyCode::O && state == ElementState::Pressed {
+                            renderer_state.toggle_occlusion_culling();
+                        }
+
+                        if virtual_keycode == VirtualKeyCode::P && state == ElementState::Pressed {
+                            info!("Culling: {}", renderer_state.get_culling_stats());
+                        }
+
+                        if virtual_keycode == VirtualKeyCode::Tab && state == ElementState::Pressed {
+                            renderer_state.cycle_camera_controller();
+                        }
+
+                        if virtual_keycode == VirtualKeyCode::I && state == ElementState::Pressed {
+                            renderer_state.toggle_overview();
+                        }
+
+                        if state == ElementState::Pressed {
+                            let effect = match virtual_keycode {
+                                VirtualKeyCode::F1 => Some(PostEffect::Tonemapping),
+                                VirtualKeyCode::F2 => Some(PostEffect::Bloom),
+                                VirtualKeyCode::F3 => Some(PostEffect::Fxaa),
+                                VirtualKeyCode::F4 => Some(PostEffect::ColorGrading),
+                                VirtualKeyCode::F5 => Some(PostEffect::Vignette),
+                                VirtualKeyCode::F6 => Some(PostEffect::GammaCorrection),
+                                _ => None
+                            };
+
+                            if let Some(effect) = effect {
+                                renderer_state.toggle_post_effect(effect);
+                            }
+                        }
+
+                        if virtual_keycode == VirtualKeyCode::PageUp && state == ElementState::Pressed {
+                            renderer_state.adjust_exposure(0.5);
+                        }
+
+                        if virtual_keycode == VirtualKeyCode::PageDown && state == ElementState::Pressed {
+                            renderer_state.adjust_exposure(-0.5);
+                        }
                     }
                 },
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let lines = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => y,
+                        //NOTE: Touchpads scroll in pixels, roughly 20 to a line
+                        MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                    };
+                    renderer_state.update_camera_zoom(lines);
+                },
                 _ => (),
             },
             Event::RedrawRequested(_) => {
@@ -129,6 +222,31 @@ pub fn render() {
     });
 }
 
+/// Mounts the packed data archive if there is one, and the loose data directory on top of it,
+/// so files on disk override the packed ones while developing.
+fn mount_data() {
+    if let Some(archive_path) = find_data_archive() {
+        if let Err(err) = vfs::mount_archive(DATA_MOUNT_POINT, &archive_path) {
+            error!("Could not mount archive {:?}: {:?}", archive_path, err);
+        }
+    }
+
+    if Path::new(DATA_DIR).is_dir() {
+        vfs::mount_dir(DATA_MOUNT_POINT, DATA_DIR);
+    }
+}
+
+fn find_data_archive() -> Option<PathBuf> {
+    let local = PathBuf::from(DATA_ARCHIVE_NAME);
+    if local.is_file() {
+        return Some(local);
+    }
+
+    env::current_exe().ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(DATA_ARCHIVE_NAME)))
+        .filter(|path| path.is_file())
+}
+
 
 //region Tests
 #[cfg(test)]
@@ -140,4 +258,4 @@ mod tests {
     }
 }
 
-//endregion
\ No newline at end of file
+//endregion
@@ -0,0 +1,608 @@
+use gfx_hal::{
+    buffer::SubRange,
+    device::Device,
+    image::{
+        Filter, Layout, Lod, PackedColor, SamplerDesc, WrapMode
+    },
+    pso::{
+        BufferDescriptorFormat, BufferDescriptorType, Descriptor, DescriptorPoolCreateFlags, DescriptorRangeDesc, DescriptorSetLayoutBinding, DescriptorType, ImageDescriptorType, ShaderStageFlags
+    },
+    Backend,
+};
+
+use super::{
+    buffer::BufferState,
+    constants::{
+        BRDF_LUT_FORMAT, BRDF_LUT_SIZE, ENVIRONMENT_FORMAT, ENVIRONMENT_SIZE, IBL_SAMPLES, IRRADIANCE_SIZE, PREFILTERED_LEVELS, PREFILTERED_SIZE
+    },
+    desc::{
+        DescSet, DescSetLayout, DescSetWrite
+    },
+    device::DeviceState,
+    ibl::{
+        brdf_lut, default_sky, f32_to_f16, EnvironmentMaps
+    },
+    image::DataImage,
+};
+
+use zeus_core::math::Vector3;
+
+use std::{
+    cell::RefCell,
+    mem::size_of,
+    ops::Range,
+    rc::Rc
+};
+
+//NOTE: Has to match the bindings of set 1 in the shaders
+const IRRADIANCE_BINDING: u32 = 1;
+const PREFILTERED_BINDING: u32 = 2;
+const BRDF_LUT_BINDING: u32 = 3;
+const SAMPLER_BINDING: u32 = 4;
+
+//NOTE: Has to match the light kinds in the shaders
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    Spot,
+}
+
+impl LightKind {
+    fn id(&self) -> f32 {
+        match self {
+            LightKind::Directional => 0.0,
+            LightKind::Point => 1.0,
+            LightKind::Spot => 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Ignored by directional lights
+    pub position: Vector3,
+    /// Ignored by point lights
+    pub direction: Vector3,
+    pub color: Vector3,
+    pub intensity: f32,
+    /// The distance the light reaches, ignored by directional lights
+    pub range: f32,
+    /// Spot lights have full intensity inside the inner cone and fade out until the outer cone, in degrees
+    pub inner_cone: f32,
+    pub outer_cone: f32,
+    /// Only directional and spot lights have shadows
+    pub cast_shadows: bool,
+}
+
+impl Light {
+    pub fn directional(
+        direction: Vector3,
+        color: Vector3,
+        intensity: f32
+    ) -> Self {
+        Light {
+            kind: LightKind::Directional,
+            position: Vector3::new(0.0, 0.0, 0.0),
+            direction,
+            color,
+            intensity,
+            range: 0.0,
+            inner_cone: 0.0,
+            outer_cone: 0.0,
+            cast_shadows: true,
+        }
+    }
+
+    pub fn point(
+        position: Vector3,
+        color: Vector3,
+        intensity: f32,
+        range: f32
+    ) -> Self {
+        Light {
+            kind: LightKind::Point,
+            position,
+            direction: Vector3::new(0.0, 0.0, 0.0),
+            color,
+            intensity,
+            range,
+            inner_cone: 0.0,
+            outer_cone: 0.0,
+            cast_shadows: false,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn spot(
+        position: Vector3,
+        direction: Vector3,
+        color: Vector3,
+        intensity: f32,
+        range: f32,
+        inner_cone: f32,
+        outer_cone: f32
+    ) -> Self {
+        Light {
+            kind: LightKind::Spot,
+            position,
+            direction,
+            color,
+            intensity,
+            range,
+            inner_cone,
+            outer_cone,
+            cast_shadows: true,
+        }
+    }
+
+    pub fn has_shadows(&self) -> bool {
+        self.cast_shadows && self.kind != LightKind::Point
+    }
+
+    fn to_data(self, shadow_tiles: Option<Range<usize>>) -> LightData {
+        let direction = if self.direction.magn() > 0.0 {
+            self.direction.normalize()
+        } else {
+            self.direction
+        };
+
+        //The cone can't be narrower inside than outside
+        let outer_cone = self.outer_cone.max(self.inner_cone);
+
+        let (first_tile, tile_count) = match shadow_tiles {
+            Some(tiles) => (tiles.start as f32, tiles.len() as f32),
+            None => (-1.0, 0.0)
+        };
+
+        LightData {
+            position: [self.position.x, self.position.y, self.position.z, self.kind.id()],
+            direction: [direction.x, direction.y, direction.z, self.range],
+            color: [self.color.x, self.color.y, self.color.z, self.intensity],
+            cone: [
+                (self.inner_cone / 2.0).to_radians().cos(),
+                (outer_cone / 2.0).to_radians().cos(),
+                first_tile,
+                tile_count
+            ],
+        }
+    }
+}
+
+/// The std430 layout of a light in the light buffer.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LightData {
+    //w: kind
+    position: [f32; 4],
+    //w: range
+    direction: [f32; 4],
+    //w: intensity
+    color: [f32; 4],
+    //x: cos of the inner cone, y: cos of the outer cone, z: first shadow tile or -1, w: shadow tile count
+    cone: [f32; 4],
+}
+
+/// Comes before the lights in the light buffer.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LightHeader {
+    //w: intensity of the environment
+    ambient: [f32; 4],
+    count: u32,
+    _padding: [u32; 3],
+}
+
+/// The lights of the scene, uploaded to a storage buffer per frame in flight.
+/// Lights past `max_lights` are not uploaded.
+/// The set also has the maps of image based lighting: irradiance, prefiltered environment and the BRDF lookup table.
+pub struct LightState<B: Backend> {
+    lights: Vec<Light>,
+    ambient: Vector3,
+    environment_intensity: f32,
+    max_lights: usize,
+    buffers: Vec<Option<BufferState<B>>>,
+    descs: Vec<DescSet<B>>,
+    device: Rc<RefCell<DeviceState<B>>>,
+    light_desc_pool: Option<B::DescriptorPool>,
+    irradiance: Option<DataImage<B>>,
+    prefiltered: Option<DataImage<B>>,
+    brdf_lut: Option<DataImage<B>>,
+    environment_sampler: Option<B::Sampler>,
+}
+
+impl<B: Backend> LightState<B> {
+    pub fn new(
+        size: usize,
+        max_lights: usize,
+        device: Rc<RefCell<DeviceState<B>>>,
+    ) -> Self {
+        let binding = 0;
+        let buffer_size = size_of::<LightHeader>() + max_lights * size_of::<LightData>();
+
+        let mut light_desc_pool = unsafe {
+            device.borrow().device.create_descriptor_pool(
+                size,
+                [DescriptorRangeDesc {
+                    ty: DescriptorType::Buffer {
+                        ty: BufferDescriptorType::Storage {
+                            read_only: true
+                        },
+                        format: BufferDescriptorFormat::Structured {
+                            dynamic_offset: false
+                        }
+                    },
+                    count: size,
+                },
+                DescriptorRangeDesc {
+                    ty: DescriptorType::Image {
+                        ty: ImageDescriptorType::Sampled {
+                            with_sampler: false
+                        }
+                    },
+                    count: size * 3,
+                },
+                DescriptorRangeDesc {
+                    ty: DescriptorType::Sampler,
+                    count: size,
+                }],
+                DescriptorPoolCreateFlags::empty(),
+            )
+        }.ok();
+
+        let mut buffers = Vec::default();
+        let mut descs = Vec::default();
+        for _i in 0..size {
+            let buffer = BufferState::new_storage_buffer::<u8>(
+                Rc::clone(&device),
+                buffer_size,
+            );
+
+            let mut bindings = vec![DescriptorSetLayoutBinding {
+                binding,
+                ty: DescriptorType::Buffer {
+                    ty: BufferDescriptorType::Storage {
+                        read_only: true
+                    },
+                    format: BufferDescriptorFormat::Structured {
+                        dynamic_offset: false
+                    }
+                },
+                count: 1,
+                stage_flags: ShaderStageFlags::FRAGMENT,
+                immutable_samplers: false,
+            }];
+
+            //Irradiance, prefiltered environment and BRDF lookup table
+            for image_binding in IRRADIANCE_BINDING..=BRDF_LUT_BINDING {
+                bindings.push(DescriptorSetLayoutBinding {
+                    binding: image_binding,
+                    ty: DescriptorType::Image {
+                        ty: ImageDescriptorType::Sampled {
+                            with_sampler: false
+                        }
+                    },
+                    count: 1,
+                    stage_flags: ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                });
+            }
+
+            bindings.push(DescriptorSetLayoutBinding {
+                binding: SAMPLER_BINDING,
+                ty: DescriptorType::Sampler,
+                count: 1,
+                stage_flags: ShaderStageFlags::FRAGMENT,
+                immutable_samplers: false,
+            });
+
+            let light_desc = DescSetLayout::new(
+                Rc::clone(&device),
+                bindings,
+            );
+
+            let mut light_desc = light_desc.create_desc_set(
+                light_desc_pool.as_mut().unwrap()
+            );
+
+            light_desc.write_to_state(
+                vec![DescSetWrite {
+                    binding,
+                    array_offset: 0,
+                    descriptors: Some(Descriptor::Buffer(
+                        buffer.get_buffer(),
+                        SubRange {
+                            offset: 0,
+                            size: Some(buffer_size as u64)
+                        }
+                    )),
+                }],
+                &mut device.borrow_mut().device
+            );
+
+            buffers.push(Some(buffer));
+            descs.push(light_desc);
+        }
+
+        let environment_sampler = unsafe {
+            device.borrow().device.create_sampler(&SamplerDesc {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                mip_filter: Filter::Linear,
+                wrap_mode: (WrapMode::Clamp, WrapMode::Clamp, WrapMode::Clamp),
+                lod_bias: Lod(0.0),
+                lod_range: Lod(0.0) .. Lod(PREFILTERED_LEVELS as f32),
+                comparison: None,
+                border: PackedColor(0_u32),
+                normalized: true,
+                anisotropy_clamp: None,
+            })
+        }.expect("Can't create environment sampler");
+
+        let lut: Vec<u16> = brdf_lut(BRDF_LUT_SIZE, IBL_SAMPLES * 4).iter()
+            .flat_map(|texel| vec![f32_to_f16(texel[0]), f32_to_f16(texel[1])])
+            .collect();
+
+        let brdf_lut = DataImage::new(
+            Rc::clone(&device),
+            BRDF_LUT_FORMAT,
+            BRDF_LUT_SIZE as u32,
+            1,
+            &[lut],
+        );
+
+        let mut light_state = LightState {
+            lights: Vec::new(),
+            ambient: Vector3::new(0.1, 0.1, 0.1),
+            environment_intensity: 1.0,
+            max_lights,
+            buffers,
+            descs,
+            device,
+            light_desc_pool,
+            irradiance: None,
+            prefiltered: None,
+            brdf_lut: Some(brdf_lut),
+            environment_sampler: Some(environment_sampler),
+        };
+
+        for desc in light_state.descs.iter_mut() {
+            desc.write_to_state(
+                vec![
+                    DescSetWrite {
+                        binding: BRDF_LUT_BINDING,
+                        array_offset: 0,
+                        descriptors: Some(Descriptor::Image(
+                            light_state.brdf_lut.as_ref().unwrap().get_image_view(),
+                            Layout::ShaderReadOnlyOptimal,
+                        )),
+                    },
+                    DescSetWrite {
+                        binding: SAMPLER_BINDING,
+                        array_offset: 0,
+                        descriptors: Some(Descriptor::Sampler(
+                            light_state.environment_sampler.as_ref().unwrap()
+                        )),
+                    },
+                ],
+                &mut light_state.device.borrow_mut().device
+            );
+        }
+
+        let sky = EnvironmentMaps::new(
+            &default_sky(ENVIRONMENT_SIZE),
+            IRRADIANCE_SIZE,
+            PREFILTERED_SIZE,
+            PREFILTERED_LEVELS,
+            IBL_SAMPLES
+        );
+        light_state.set_environment(&sky);
+
+        light_state
+    }
+
+    /// Uploads the maps of image based lighting and points every frame at them.
+    /// The caller has to make sure the device is idle.
+    pub fn set_environment(&mut self, maps: &EnvironmentMaps) {
+        let irradiance = DataImage::new(
+            Rc::clone(&self.device),
+            ENVIRONMENT_FORMAT,
+            maps.irradiance.size as u32,
+            6,
+            &[maps.irradiance.to_rgba16f()],
+        );
+
+        let levels: Vec<Vec<u16>> = maps.prefiltered.iter()
+            .map(|level| level.to_rgba16f())
+            .collect();
+
+        let prefiltered = DataImage::new(
+            Rc::clone(&self.device),
+            ENVIRONMENT_FORMAT,
+            maps.prefiltered[0].size as u32,
+            6,
+            &levels,
+        );
+
+        for desc in self.descs.iter_mut() {
+            desc.write_to_state(
+                vec![
+                    DescSetWrite {
+                        binding: IRRADIANCE_BINDING,
+                        array_offset: 0,
+                        descriptors: Some(Descriptor::Image(
+                            irradiance.get_image_view(),
+                            Layout::ShaderReadOnlyOptimal,
+                        )),
+                    },
+                    DescSetWrite {
+                        binding: PREFILTERED_BINDING,
+                        array_offset: 0,
+                        descriptors: Some(Descriptor::Image(
+                            prefiltered.get_image_view(),
+                            Layout::ShaderReadOnlyOptimal,
+                        )),
+                    },
+                ],
+                &mut self.device.borrow_mut().device
+            );
+        }
+
+        //The old maps are only freed after the descriptors stop pointing at them
+        self.irradiance = Some(irradiance);
+        self.prefiltered = Some(prefiltered);
+    }
+
+    /// Returns the index of the light.
+    pub fn add_light(&mut self, light: Light) -> usize {
+        if self.lights.len() >= self.max_lights {
+            warn!("More than {} lights, the rest will not be drawn", self.max_lights);
+        }
+
+        self.lights.push(light);
+        self.lights.len() - 1
+    }
+
+    /// The lights that fit in the light buffer.
+    pub fn get_lights(&self) -> &[Light] {
+        &self.lights[..self.lights.len().min(self.max_lights)]
+    }
+
+    #[allow(dead_code)]
+    pub fn get_light_mut(&mut self, index: usize) -> Option<&mut Light> {
+        self.lights.get_mut(index)
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_light(&mut self, index: usize) -> Light {
+        self.lights.remove(index)
+    }
+
+    #[allow(dead_code)]
+    pub fn clear(&mut self) {
+        self.lights.clear();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_ambient(&mut self, ambient: Vector3) {
+        self.ambient = ambient;
+    }
+
+    /// Scales the light of the environment, 0 turns image based lighting off.
+    #[allow(dead_code)]
+    pub fn set_environment_intensity(&mut self, intensity: f32) {
+        self.environment_intensity = intensity;
+    }
+
+    /// Uploads the lights to the buffer of the frame, `shadow_tiles` are the atlas tiles of each light.
+    pub fn update_buffer(&mut self, idx: usize, shadow_tiles: &[Option<Range<usize>>]) {
+        let (header, lights) = pack_lights(&self.lights, self.ambient, self.environment_intensity, self.max_lights, shadow_tiles);
+
+        let buffer = self.buffers[idx].as_mut().unwrap();
+        buffer.update_data(0, &[header]);
+        if !lights.is_empty() {
+            buffer.update_data(size_of::<LightHeader>() as u64, &lights);
+        }
+    }
+
+    pub fn append_desc_set<'a>(
+        &'a self,
+        idx: usize,
+        vec: &mut Vec<&'a B::DescriptorSet>,
+    ) {
+        vec.push(self.descs[idx].set.as_ref().unwrap())
+    }
+
+    //NOTE: The layouts of all the frames are identical
+    pub fn append_layout<'a>(
+        &'a self,
+        vec: &mut Vec<&'a DescSetLayout<B>>,
+    ) {
+        vec.push(&self.descs[0].layout)
+    }
+}
+
+impl<B: Backend> Drop for LightState<B> {
+    fn drop(&mut self) {
+        self.device.borrow().device.wait_idle().unwrap();
+        unsafe {
+            self.device
+                .borrow()
+                .device
+                .destroy_descriptor_pool(self.light_desc_pool.take().unwrap());
+            self.device
+                .borrow()
+                .device
+                .destroy_sampler(self.environment_sampler.take().unwrap());
+        }
+    }
+}
+
+fn pack_lights(
+    lights: &[Light],
+    ambient: Vector3,
+    environment_intensity: f32,
+    max_lights: usize,
+    shadow_tiles: &[Option<Range<usize>>],
+) -> (LightHeader, Vec<LightData>) {
+    let lights: Vec<LightData> = lights.iter()
+        .take(max_lights)
+        .enumerate()
+        .map(|(index, light)| light.to_data(shadow_tiles.get(index).cloned().flatten()))
+        .collect();
+
+    let header = LightHeader {
+        ambient: [ambient.x, ambient.y, ambient.z, environment_intensity],
+        count: lights.len() as u32,
+        _padding: [0; 3],
+    };
+
+    (header, lights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pack_lights, Light};
+
+    use zeus_core::math::Vector3;
+
+    #[test]
+    fn pack_light_data() {
+        let lights = [
+            Light::directional(Vector3::new(0.0, -2.0, 0.0), Vector3::new(1.0, 1.0, 1.0), 0.5),
+            Light::spot(
+                Vector3::new(1.0, 2.0, 3.0),
+                Vector3::new(0.0, 0.0, 1.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                2.0,
+                10.0,
+                60.0,
+                90.0
+            ),
+        ];
+
+        let (header, data) = pack_lights(&lights, Vector3::new(0.1, 0.1, 0.1), 1.0, 8, &[Some(0..4), None]);
+        assert_eq!(header.count, 2);
+
+        //Directions are normalized and the kind goes in w
+        assert_eq!(data[0].direction, [0.0, -1.0, 0.0, 0.0]);
+        assert_eq!(data[0].position[3], 0.0);
+        assert_eq!(data[0].cone[2..], [0.0, 4.0]);
+
+        assert_eq!(data[1].position, [1.0, 2.0, 3.0, 2.0]);
+        assert_eq!(data[1].color, [1.0, 0.0, 0.0, 2.0]);
+        assert!((data[1].cone[0] - 30.0_f32.to_radians().cos()).abs() < 1e-6);
+        assert!((data[1].cone[1] - 45.0_f32.to_radians().cos()).abs() < 1e-6);
+        assert_eq!(data[1].cone[2..], [-1.0, 0.0]);
+    }
+
+    #[test]
+    fn pack_respects_max_lights() {
+        let lights = vec![Light::point(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0), 1.0, 5.0); 4];
+
+        let (header, data) = pack_lights(&lights, Vector3::new(0.0, 0.0, 0.0), 1.0, 3, &[]);
+        assert_eq!(header.count, 3);
+        assert_eq!(data.len(), 3);
+    }
+}
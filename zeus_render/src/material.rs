@@ -0,0 +1,1030 @@
+use gfx_hal::{
+    buffer::Usage,
+    device::Device,
+    format::Format,
+    image::Layout,
+    pool::CommandPoolCreateFlags,
+    pso::{
+        BlendState, BufferDescriptorFormat, BufferDescriptorType, ColorValue, Descriptor, DescriptorPoolCreateFlags, DescriptorRangeDesc, DescriptorSetLayoutBinding, DescriptorType, Face, ImageDescriptorType, ShaderStageFlags
+    },
+    Backend,
+};
+
+use zeus_core::math::{
+    Vector2,
+    Vector3,
+    Vector4
+};
+
+use super::{
+    adapter::AdapterState,
+    constants::{
+        FLAT_NORMAL_TEXTURE_PATH, FRAGMENT_SHADER_PATH, IMAGE_FORMAT, LINEAR_IMAGE_FORMAT, MATERIAL_UNIFORM_BINDING, PBR_FRAGMENT_SHADER_PATH, VERTEX_SHADER_PATH, WHITE_TEXTURE_PATH
+    },
+    desc::{
+        DescSet, DescSetLayout, DescSetWrite
+    },
+    device::DeviceState,
+    error::AssetError,
+    image::ImageState,
+    model::{
+        Color,
+        Uniform
+    },
+    pipeline::PipelineKey,
+    vertex_layout::VertexLayout,
+};
+
+use std::{
+    cell::RefCell,
+    path::Path,
+    rc::Rc
+};
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub enum MaterialParam {
+    Float(f32),
+    Vector2(Vector2),
+    Vector3(Vector3),
+    Vector4(Vector4),
+    Color(ColorValue),
+    /// Sampled as sRGB, for colors
+    Texture(String),
+    /// Sampled as is, for data like normal maps
+    LinearTexture(String),
+    /// The render target of a camera view, by name, see `ViewTarget::Texture`
+    RenderTexture(String),
+}
+
+impl MaterialParam {
+    /// The std140 alignment and size in floats, textures are not part of the uniform block.
+    fn std140_layout(&self) -> Option<(usize, usize)> {
+        match self {
+            MaterialParam::Float(_) => Some((1, 1)),
+            MaterialParam::Vector2(_) => Some((2, 2)),
+            MaterialParam::Vector3(_) => Some((4, 3)),
+            MaterialParam::Vector4(_) | MaterialParam::Color(_) => Some((4, 4)),
+            MaterialParam::Texture(_) | MaterialParam::LinearTexture(_) | MaterialParam::RenderTexture(_) => None,
+        }
+    }
+
+    /// The path and image format of texture parameters.
+    /// Render textures are white until the renderer points them to their target.
+    fn texture(&self) -> Option<(&str, Format)> {
+        match self {
+            MaterialParam::Texture(path) => Some((path, IMAGE_FORMAT)),
+            MaterialParam::LinearTexture(path) => Some((path, LINEAR_IMAGE_FORMAT)),
+            MaterialParam::RenderTexture(_) => Some((WHITE_TEXTURE_PATH, IMAGE_FORMAT)),
+            _ => None,
+        }
+    }
+
+    fn render_target(&self) -> Option<&str> {
+        match self {
+            MaterialParam::RenderTexture(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn write(&self, data: &mut [f32]) {
+        match self {
+            MaterialParam::Float(value) => data[0] = *value,
+            MaterialParam::Vector2(value) => data.copy_from_slice(&[value.x, value.y]),
+            MaterialParam::Vector3(value) => data.copy_from_slice(&[value.x, value.y, value.z]),
+            MaterialParam::Vector4(value) => data.copy_from_slice(&[value.x, value.y, value.z, value.w]),
+            MaterialParam::Color(value) => data.copy_from_slice(value),
+            MaterialParam::Texture(_) | MaterialParam::LinearTexture(_) | MaterialParam::RenderTexture(_) => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(dead_code)]
+pub enum BlendMode {
+    Opaque,
+    Alpha,
+    Additive,
+}
+
+impl BlendMode {
+    pub fn blend_state(&self) -> Option<BlendState> {
+        match self {
+            BlendMode::Opaque => None,
+            BlendMode::Alpha => Some(BlendState::ALPHA),
+            BlendMode::Additive => Some(BlendState::ADD),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(dead_code)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+}
+
+impl CullMode {
+    pub fn face(&self) -> Face {
+        match self {
+            CullMode::None => Face::empty(),
+            CullMode::Front => Face::FRONT,
+            CullMode::Back => Face::BACK,
+        }
+    }
+}
+
+/// How the normal texture of the built in materials shapes the surface, its alpha channel is the height.
+//NOTE: Has to match the surface modes in the shaders
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum SurfaceMode {
+    /// The RGB channels are a tangent space normal
+    NormalMap,
+    /// The normal comes from the slope of the height
+    Bump,
+    /// Parallax occlusion mapping, the UVs are shifted along the view through the height, then the normal map is applied
+    Parallax,
+}
+
+impl SurfaceMode {
+    fn id(&self) -> f32 {
+        match self {
+            SurfaceMode::NormalMap => 0.0,
+            SurfaceMode::Bump => 1.0,
+            SurfaceMode::Parallax => 2.0,
+        }
+    }
+}
+
+/// The fixed function state a material needs from its pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RenderState {
+    pub blend: BlendMode,
+    pub cull: CullMode,
+    pub depth_test: bool,
+    pub depth_write: bool,
+}
+
+impl Default for RenderState {
+    fn default() -> Self {
+        RenderState {
+            blend: BlendMode::Alpha,
+            cull: CullMode::None,
+            depth_test: true,
+            depth_write: true,
+        }
+    }
+}
+
+/// Describes a material without any GPU resources.
+///
+/// The material uses one descriptor set after the camera, lights and shadows sets (set 3).
+/// The parameters that are not textures are packed in one uniform block at binding 0,
+/// in declaration order and following the std140 rules.
+/// Every texture parameter follows with its image and then its sampler, in declaration order from binding 1.
+#[derive(Debug, Clone)]
+pub struct MaterialDesc {
+    pub name: String,
+    pub vertex_shader: String,
+    pub fragment_shader: String,
+    pub render_state: RenderState,
+    params: Vec<(String, MaterialParam)>,
+}
+
+impl MaterialDesc {
+    pub fn new(
+        name: &str,
+        vertex_shader: &str,
+        fragment_shader: &str,
+    ) -> Self {
+        MaterialDesc {
+            name: name.to_string(),
+            vertex_shader: vertex_shader.to_string(),
+            fragment_shader: fragment_shader.to_string(),
+            render_state: RenderState::default(),
+            params: Vec::new(),
+        }
+    }
+
+    /// The metallic-roughness material of the PBR shader, textures start as neutral defaults.
+    ///
+    /// `metallic_roughness` packs roughness in G and metallic in B, and `occlusion` is read from R, like glTF.
+    /// The factors multiply the textures.
+    pub fn new_pbr(name: &str) -> Self {
+        let mut desc = Self::new(name, VERTEX_SHADER_PATH, PBR_FRAGMENT_SHADER_PATH);
+        desc.set_param("albedo", MaterialParam::Texture(WHITE_TEXTURE_PATH.to_string()));
+        desc.set_param("metallic_roughness", MaterialParam::LinearTexture(WHITE_TEXTURE_PATH.to_string()));
+        desc.set_param("normal", MaterialParam::LinearTexture(FLAT_NORMAL_TEXTURE_PATH.to_string()));
+        desc.set_param("emissive", MaterialParam::Texture(WHITE_TEXTURE_PATH.to_string()));
+        desc.set_param("occlusion", MaterialParam::LinearTexture(WHITE_TEXTURE_PATH.to_string()));
+        desc.set_param("base_color", MaterialParam::Color([1.0, 1.0, 1.0, 1.0]));
+        desc.set_param("emissive_factor", MaterialParam::Vector3(Vector3::new(0.0, 0.0, 0.0)));
+        desc.set_param("metallic", MaterialParam::Float(0.0));
+        desc.set_param("roughness", MaterialParam::Float(1.0));
+        desc.set_param("occlusion_strength", MaterialParam::Float(1.0));
+        desc.set_param("normal_scale", MaterialParam::Float(1.0));
+        desc.set_param("height_scale", MaterialParam::Float(0.05));
+        desc.set_surface_mode(SurfaceMode::NormalMap);
+
+        desc
+    }
+
+    /// For the built in shaders, `height_scale` is the depth of the height in UV units.
+    pub fn set_surface_mode(&mut self, mode: SurfaceMode) {
+        self.set_param("surface_mode", MaterialParam::Float(mode.id()));
+    }
+
+    /// The Blinn-Phong material of the default shader, textures start as neutral defaults.
+    pub fn new_phong(name: &str) -> Self {
+        let mut desc = Self::new(name, VERTEX_SHADER_PATH, FRAGMENT_SHADER_PATH);
+        desc.set_param("texture", MaterialParam::Texture(WHITE_TEXTURE_PATH.to_string()));
+        desc.set_param("specular_map", MaterialParam::Texture(WHITE_TEXTURE_PATH.to_string()));
+        desc.set_param("normal_map", MaterialParam::LinearTexture(FLAT_NORMAL_TEXTURE_PATH.to_string()));
+        desc.set_param("color", MaterialParam::Color([1.0, 1.0, 1.0, 1.0]));
+        desc.set_param("specular", MaterialParam::Vector3(Vector3::new(0.5, 0.5, 0.5)));
+        desc.set_param("shininess", MaterialParam::Float(32.0));
+        desc.set_param("normal_scale", MaterialParam::Float(1.0));
+        desc.set_param("height_scale", MaterialParam::Float(0.05));
+        desc.set_surface_mode(SurfaceMode::NormalMap);
+
+        desc
+    }
+
+    /// A material from an MTL material, texture paths are relative to `model_dir`.
+    /// Materials with the PBR extension (Pr, Pm, map_Pr, map_Pm) use the PBR shader and the rest use Blinn-Phong.
+    pub fn from_mtl(material: &tobj::Material, model_dir: &str) -> Self {
+        let is_pbr = ["Pr", "Pm", "map_Pr", "map_Pm"].iter()
+            .any(|param| material.unknown_param.contains_key(*param));
+
+        if is_pbr {
+            Self::pbr_from_mtl(material, model_dir)
+        } else {
+            Self::phong_from_mtl(material, model_dir)
+        }
+    }
+
+    fn phong_from_mtl(material: &tobj::Material, model_dir: &str) -> Self {
+        let mut desc = Self::new_phong(&material.name);
+
+        let [r, g, b] = material.diffuse;
+        desc.set_param("color", MaterialParam::Color([r, g, b, mtl_alpha(material)]));
+
+        let [r, g, b] = material.specular;
+        desc.set_param("specular", MaterialParam::Vector3(Vector3::new(r, g, b)));
+        //NOTE: Ns 0 would light every angle the same
+        desc.set_param("shininess", MaterialParam::Float(material.shininess.max(1.0)));
+
+        if !material.diffuse_texture.is_empty() {
+            desc.set_param("texture", MaterialParam::Texture(resolve_texture_path(model_dir, &material.diffuse_texture)));
+        }
+
+        if !material.specular_texture.is_empty() {
+            desc.set_param("specular_map", MaterialParam::Texture(resolve_texture_path(model_dir, &material.specular_texture)));
+        }
+
+        if let Some(normal_texture) = mtl_normal_texture(material) {
+            desc.set_param("normal_map", MaterialParam::LinearTexture(resolve_texture_path(model_dir, normal_texture)));
+        }
+
+        desc
+    }
+
+    /// Derives the roughness from Ns when Pr is missing.
+    fn pbr_from_mtl(material: &tobj::Material, model_dir: &str) -> Self {
+        let mut desc = Self::new_pbr(&material.name);
+
+        let [r, g, b] = material.diffuse;
+        desc.set_param("base_color", MaterialParam::Color([r, g, b, mtl_alpha(material)]));
+
+        if !material.diffuse_texture.is_empty() {
+            desc.set_param("albedo", MaterialParam::Texture(resolve_texture_path(model_dir, &material.diffuse_texture)));
+        }
+
+        if let Some(normal_texture) = mtl_normal_texture(material) {
+            desc.set_param("normal", MaterialParam::LinearTexture(resolve_texture_path(model_dir, normal_texture)));
+        }
+
+        if let Some(emissive_texture) = material.unknown_param.get("map_Ke") {
+            desc.set_param("emissive", MaterialParam::Texture(resolve_texture_path(model_dir, emissive_texture)));
+            desc.set_param("emissive_factor", MaterialParam::Vector3(Vector3::new(1.0, 1.0, 1.0)));
+        }
+
+        if let Some(ke) = mtl_floats(material, "Ke").filter(|ke| ke.len() == 3) {
+            desc.set_param("emissive_factor", MaterialParam::Vector3(Vector3::new(ke[0], ke[1], ke[2])));
+        }
+
+        if let Some(pm) = mtl_floats(material, "Pm").and_then(|pm| pm.first().cloned()) {
+            desc.set_param("metallic", MaterialParam::Float(pm));
+        }
+
+        let roughness = mtl_floats(material, "Pr")
+            .and_then(|pr| pr.first().cloned())
+            .unwrap_or_else(|| shininess_to_roughness(material.shininess));
+        desc.set_param("roughness", MaterialParam::Float(roughness));
+
+        if material.unknown_param.contains_key("map_Pr") || material.unknown_param.contains_key("map_Pm") {
+            warn!("Material {}: separate roughness and metallic maps are not supported, pack them in one texture", material.name);
+        }
+
+        desc
+    }
+
+    /// Sets the value of a parameter, new parameters are added at the end.
+    pub fn set_param(&mut self, name: &str, value: MaterialParam) {
+        match self.params.iter_mut().find(|(param, _)| param == name) {
+            Some((_, param_value)) => *param_value = value,
+            None => self.params.push((name.to_string(), value)),
+        }
+    }
+
+    pub fn get_param(&self, name: &str) -> Option<&MaterialParam> {
+        self.params.iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value)
+    }
+
+    /// The texture files of the parameters as (name, path), in declaration order. Render textures are left out.
+    pub fn textures(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter()
+            .filter(|(_, value)| value.render_target().is_none())
+            .filter_map(|(name, value)| value.texture()
+                .map(|(path, _)| (name.as_str(), path)))
+    }
+
+    /// The names of the render targets the material samples.
+    pub fn render_targets(&self) -> impl Iterator<Item = &str> {
+        self.params.iter()
+            .filter_map(|(_, value)| value.render_target())
+    }
+
+    fn texture_params(&self) -> impl Iterator<Item = (&str, &MaterialParam)> {
+        self.params.iter()
+            .filter(|(_, value)| value.texture().is_some())
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn has_uniforms(&self) -> bool {
+        self.params.iter()
+            .any(|(_, value)| value.std140_layout().is_some())
+    }
+
+    pub fn uses_shader(&self, path: &str) -> bool {
+        self.vertex_shader == path || self.fragment_shader == path
+    }
+
+    /// Packs the non texture parameters into the layout of a std140 uniform block.
+    pub fn pack_params(&self) -> Vec<f32> {
+        let mut data = Vec::new();
+
+        for (_, value) in self.params.iter() {
+            if let Some((align, size)) = value.std140_layout() {
+                let offset = data.len().div_ceil(align) * align;
+                data.resize(offset + size, 0.0);
+                value.write(&mut data[offset..offset + size]);
+            }
+        }
+
+        //NOTE: The size of a block is rounded up to the alignment of a vec4
+        let size = data.len().div_ceil(4) * 4;
+        data.resize(size, 0.0);
+
+        data
+    }
+}
+
+struct MaterialTexture<B: Backend> {
+    device: Rc<RefCell<DeviceState<B>>>,
+    param: String,
+    path: String,
+    format: Format,
+    render_target: Option<String>,
+    image: ImageState<B>,
+}
+
+impl<B: Backend> MaterialTexture<B> {
+    fn new(
+        device: Rc<RefCell<DeviceState<B>>>,
+        adapter: &AdapterState<B>,
+        param: &str,
+        value: &MaterialParam,
+        staging_pool: &mut B::CommandPool,
+    ) -> Self {
+        let (path, format) = value.texture().unwrap();
+
+        let image = ImageState::new_texture(
+            Rc::clone(&device),
+            path,
+            format,
+            adapter,
+            Usage::TRANSFER_SRC,
+            &mut device.borrow_mut(),
+            staging_pool,
+        );
+
+        //TODO: could save time by making it async
+        image.wait_for_transfer_completion();
+
+        MaterialTexture {
+            device,
+            param: param.to_string(),
+            path: path.to_string(),
+            format,
+            render_target: value.render_target().map(str::to_string),
+            image,
+        }
+    }
+
+    /// Loads the texture from the path, the current one is kept if the new one can't be decoded.
+    /// The descriptors of the material have to be written again.
+    fn reload(
+        &mut self,
+        path: &str,
+        format: Format,
+        adapter: &AdapterState<B>,
+        staging_pool: &mut B::CommandPool,
+    ) -> Result<(), AssetError> {
+        let img = ImageState::<B>::load_image(path)?;
+
+        let image = ImageState::new_texture_from_image(
+            Rc::clone(&self.device),
+            &img,
+            format,
+            adapter,
+            Usage::TRANSFER_SRC,
+            &mut self.device.borrow_mut(),
+            staging_pool,
+        );
+
+        image.wait_for_transfer_completion();
+
+        self.image = image;
+        self.path = path.to_string();
+        self.format = format;
+
+        Ok(())
+    }
+
+    /// Points the image and sampler bindings of the texture to its image, or to another view like a render target.
+    fn write_desc(&self, desc: &mut DescSet<B>, index: usize, image_view: Option<&B::ImageView>) {
+        let (image_binding, sampler_binding) = texture_bindings(index);
+        let image_view = image_view.or_else(|| self.image.get_image_view()).unwrap();
+
+        desc.write_to_state(
+            vec![
+                DescSetWrite {
+                    binding: image_binding,
+                    array_offset: 0,
+                    descriptors: Some(Descriptor::Image(
+                        image_view,
+                        Layout::ShaderReadOnlyOptimal,
+                    )),
+                },
+                DescSetWrite {
+                    binding: sampler_binding,
+                    array_offset: 0,
+                    descriptors: Some(Descriptor::Sampler(self.image.get_sampler())),
+                },
+            ],
+            &mut self.device.borrow_mut().device,
+        );
+    }
+}
+
+/// The GPU side of a material: its textures and the uniform block with the rest of the parameters,
+/// all in one descriptor set.
+pub struct Material<B: Backend> {
+    device: Rc<RefCell<DeviceState<B>>>,
+    desc: MaterialDesc,
+    textures: Vec<MaterialTexture<B>>,
+    desc_pool: Option<B::DescriptorPool>,
+    desc_set: Option<DescSet<B>>,
+    uniform: Option<Uniform<B>>,
+    pipeline_keys: Vec<PipelineKey>,
+}
+
+impl<B: Backend> Material<B> {
+    pub fn new(
+        device: Rc<RefCell<DeviceState<B>>>,
+        adapter: &AdapterState<B>,
+        desc: MaterialDesc,
+    ) -> Self {
+        let mut staging_pool = unsafe {
+            device.borrow().device.create_command_pool(
+                device.borrow().queues.family,
+                CommandPoolCreateFlags::empty(),
+            )
+        }.expect("Can't create Command Pool");
+
+        let textures: Vec<_> = desc.texture_params()
+            .map(|(param, value)| MaterialTexture::new(
+                Rc::clone(&device),
+                adapter,
+                param,
+                value,
+                &mut staging_pool,
+            ))
+            .collect();
+
+        unsafe {
+            device.borrow().device
+                .destroy_command_pool(staging_pool);
+        }
+
+        if textures.is_empty() && !desc.has_uniforms() {
+            return Material {
+                device,
+                desc,
+                textures,
+                desc_pool: None,
+                desc_set: None,
+                uniform: None,
+                pipeline_keys: Vec::new(),
+            };
+        }
+
+        let mut ranges = Vec::new();
+        if desc.has_uniforms() {
+            ranges.push(DescriptorRangeDesc {
+                ty: DescriptorType::Buffer {
+                    ty: BufferDescriptorType::Uniform,
+                    format: BufferDescriptorFormat::Structured {
+                        dynamic_offset: false
+                    }
+                },
+                count: 1,
+            });
+        }
+
+        if !textures.is_empty() {
+            ranges.push(DescriptorRangeDesc {
+                ty: DescriptorType::Image {
+                    ty: ImageDescriptorType::Sampled {
+                        with_sampler: false
+                    }
+                },
+                count: textures.len(),
+            });
+            ranges.push(DescriptorRangeDesc {
+                ty: DescriptorType::Sampler,
+                count: textures.len(),
+            });
+        }
+
+        let mut desc_pool = unsafe {
+            device.borrow().device.create_descriptor_pool(
+                1, //Number of sets
+                ranges,
+                DescriptorPoolCreateFlags::empty(),
+            )
+        }.ok();
+
+        let mut desc_set = create_desc_layout(Rc::clone(&device), desc.has_uniforms(), textures.len())
+            .create_desc_set(desc_pool.as_mut().unwrap());
+
+        for (index, texture) in textures.iter().enumerate() {
+            texture.write_desc(&mut desc_set, index, None);
+        }
+
+        let uniform = if desc.has_uniforms() {
+            Some(Uniform::new(
+                Rc::clone(&device),
+                &desc.pack_params(),
+                &mut desc_set,
+                MATERIAL_UNIFORM_BINDING,
+            ))
+        } else {
+            None
+        };
+
+        Material {
+            device,
+            desc,
+            textures,
+            desc_pool,
+            desc_set: Some(desc_set),
+            uniform,
+            pipeline_keys: Vec::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.desc.name
+    }
+
+    pub fn get_desc(&self) -> &MaterialDesc {
+        &self.desc
+    }
+
+    /// The keys of the pipelines the material is drawn with, one per vertex layout. They are set when the pipelines are created.
+    pub fn set_pipeline_keys(&mut self, keys: Vec<PipelineKey>) {
+        self.pipeline_keys = keys;
+    }
+
+    pub fn get_pipeline_keys(&self) -> &[PipelineKey] {
+        &self.pipeline_keys
+    }
+
+    pub fn get_pipeline_key(&self, vertex_layout: &VertexLayout) -> Option<&PipelineKey> {
+        self.pipeline_keys.iter()
+            .find(|key| &key.vertex_layout == vertex_layout)
+    }
+
+    pub fn uses_texture(&self, path: &str) -> bool {
+        self.textures.iter()
+            .any(|texture| texture.render_target.is_none() && texture.path == path)
+    }
+
+    pub fn samples_render_target(&self) -> bool {
+        self.textures.iter()
+            .any(|texture| texture.render_target.is_some())
+    }
+
+    /// Points the render textures of the target to its image, or back to white with `None`.
+    /// The caller has to make sure the device is idle.
+    pub fn set_render_target(&mut self, name: &str, image_view: Option<&B::ImageView>) {
+        let desc_set = self.desc_set.as_mut().unwrap();
+
+        for (index, texture) in self.textures.iter().enumerate() {
+            if texture.render_target.as_deref() == Some(name) {
+                texture.write_desc(desc_set, index, image_view);
+            }
+        }
+    }
+
+    /// Updates a parameter. Values are written to the uniform buffer and textures are loaded from their new path.
+    /// Render textures show their target after the next swapchain recreation.
+    /// A parameter can't change between a texture and a value, that would change the pipeline layout.
+    /// The caller has to make sure the device is idle when a texture is changed.
+    pub fn set_param(
+        &mut self,
+        name: &str,
+        value: MaterialParam,
+        adapter: &AdapterState<B>,
+    ) -> Result<(), AssetError> {
+        let current = self.desc.get_param(name)
+            .ok_or_else(|| AssetError::new(format!("Material {} has no parameter {}", self.desc.name, name)))?;
+
+        if current.std140_layout() != value.std140_layout() {
+            return Err(AssetError::new(format!(
+                "Parameter {} of material {} can't change from {:?} to {:?}",
+                name,
+                self.desc.name,
+                current,
+                value
+            )));
+        }
+
+        if let Some((path, format)) = value.texture() {
+            let index = self.textures.iter()
+                .position(|texture| texture.param == name)
+                .unwrap();
+            let texture = &mut self.textures[index];
+
+            let mut staging_pool = unsafe {
+                self.device.borrow().device.create_command_pool(
+                    self.device.borrow().queues.family,
+                    CommandPoolCreateFlags::empty(),
+                )
+            }.expect("Can't create Command Pool");
+
+            let result = texture.reload(path, format, adapter, &mut staging_pool);
+            if result.is_ok() {
+                texture.render_target = value.render_target().map(str::to_string);
+                texture.write_desc(self.desc_set.as_mut().unwrap(), index, None);
+            }
+
+            unsafe {
+                self.device.borrow().device
+                    .destroy_command_pool(staging_pool);
+            }
+
+            result?;
+        }
+
+        self.desc.set_param(name, value);
+
+        if let Some(uniform) = self.uniform.as_mut() {
+            uniform.buffer.as_mut().unwrap()
+                .update_data(0, &self.desc.pack_params());
+        }
+
+        Ok(())
+    }
+
+    /// Reloads every texture of the material that uses the path.
+    /// The current texture is kept if the new one can't be decoded.
+    /// The caller has to make sure the device is idle.
+    pub fn reload_texture(
+        &mut self,
+        path: &str,
+        adapter: &AdapterState<B>,
+    ) -> Result<(), AssetError> {
+        if !self.uses_texture(path) {
+            return Ok(());
+        }
+
+        let mut staging_pool = unsafe {
+            self.device.borrow().device.create_command_pool(
+                self.device.borrow().queues.family,
+                CommandPoolCreateFlags::empty(),
+            )
+        }.expect("Can't create Command Pool");
+
+        let desc_set = self.desc_set.as_mut().unwrap();
+        let result = self.textures.iter_mut()
+            .enumerate()
+            .filter(|(_, texture)| texture.render_target.is_none() && texture.path == path)
+            .map(|(index, texture)| {
+                let format = texture.format;
+                texture.reload(path, format, adapter, &mut staging_pool)?;
+                texture.write_desc(desc_set, index, None);
+
+                Ok(())
+            })
+            .collect::<Result<Vec<_>, AssetError>>();
+
+        unsafe {
+            self.device.borrow().device
+                .destroy_command_pool(staging_pool);
+        }
+
+        result?;
+
+        info!("Reloaded texture {} of material {}", path, self.desc.name);
+
+        Ok(())
+    }
+
+    pub fn update_color(
+        &mut self,
+        color: &Color,
+        value: f32,
+        adapter: &AdapterState<B>,
+    ) {
+        let mut color_value = match self.desc.get_param("color") {
+            Some(MaterialParam::Color(color_value)) => *color_value,
+            _ => {
+                info!("Material {} has no color parameter!", self.desc.name);
+                return;
+            }
+        };
+
+        match color {
+            Color::Red => color_value[0] = value / 255.0,
+            Color::Green => color_value[1] = value / 255.0,
+            Color::Blue => color_value[2] = value / 255.0,
+            Color::Alpha => color_value[3] = value / 255.0,
+        }
+
+        if let Err(err) = self.set_param("color", MaterialParam::Color(color_value), adapter) {
+            error!("{}", err.message);
+        }
+    }
+
+    pub fn append_desc_set<'a>(
+        &'a self,
+        vec: &mut Vec<&'a B::DescriptorSet>,
+    ) {
+        if let Some(desc_set) = &self.desc_set {
+            vec.push(desc_set.set.as_ref().unwrap());
+        }
+    }
+
+    pub fn append_layout<'a>(
+        &'a self,
+        vec: &mut Vec<&'a DescSetLayout<B>>,
+    ) {
+        if let Some(desc_set) = &self.desc_set {
+            vec.push(&desc_set.layout);
+        }
+    }
+}
+
+impl<B: Backend> Drop for Material<B> {
+    fn drop(&mut self) {
+        self.uniform = None;
+        self.desc_set = None;
+        if let Some(desc_pool) = self.desc_pool.take() {
+            unsafe {
+                self.device.borrow()
+                    .device.destroy_descriptor_pool(desc_pool);
+            }
+        }
+    }
+}
+
+/// The path of an MTL texture relative to the model. Texture options before the file name are skipped.
+pub fn resolve_texture_path(model_dir: &str, texture: &str) -> String {
+    //NOTE: Exporters on Windows write backslashes
+    let file = texture.split_whitespace()
+        .last()
+        .unwrap_or("")
+        .replace('\\', "/");
+
+    if Path::new(&file).is_absolute() {
+        return file;
+    }
+
+    Path::new(model_dir).join(file).to_string_lossy().into_owned()
+}
+
+fn mtl_floats(material: &tobj::Material, name: &str) -> Option<Vec<f32>> {
+    material.unknown_param.get(name)
+        .map(|value| value.split_whitespace().filter_map(|value| value.parse::<f32>().ok()).collect())
+}
+
+/// `norm` is the normal map of the PBR extension, tobj only knows some spellings of it.
+fn mtl_normal_texture(material: &tobj::Material) -> Option<&str> {
+    if !material.normal_texture.is_empty() {
+        Some(&material.normal_texture)
+    } else {
+        material.unknown_param.get("norm").map(|path| path.as_str())
+    }
+}
+
+fn mtl_alpha(material: &tobj::Material) -> f32 {
+    //MTL files leave out d when opaque, which tobj reads as 0
+    if material.dissolve > 0.0 {
+        material.dissolve
+    } else {
+        1.0
+    }
+}
+
+/// The Blinn-Phong exponent mapped to a GGX roughness with a similar highlight.
+fn shininess_to_roughness(shininess: f32) -> f32 {
+    (2.0 / (shininess.max(0.0) + 2.0)).sqrt()
+}
+
+/// The image and sampler bindings of the texture parameter at `index`, they follow the uniform block.
+fn texture_bindings(index: usize) -> (u32, u32) {
+    let image_binding = MATERIAL_UNIFORM_BINDING + 1 + 2 * index as u32;
+
+    (image_binding, image_binding + 1)
+}
+
+fn create_desc_layout<B: Backend>(
+    device: Rc<RefCell<DeviceState<B>>>,
+    has_uniforms: bool,
+    texture_count: usize,
+) -> DescSetLayout<B> {
+    let mut bindings = Vec::new();
+
+    if has_uniforms {
+        bindings.push(DescriptorSetLayoutBinding {
+            binding: MATERIAL_UNIFORM_BINDING,
+            ty: DescriptorType::Buffer {
+                ty: BufferDescriptorType::Uniform,
+                format: BufferDescriptorFormat::Structured {
+                    dynamic_offset: false
+                }
+            },
+            count: 1,
+            stage_flags: ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+            immutable_samplers: false,
+        });
+    }
+
+    for index in 0..texture_count {
+        let (image_binding, sampler_binding) = texture_bindings(index);
+
+        bindings.push(DescriptorSetLayoutBinding {
+            binding: image_binding,
+            ty: DescriptorType::Image {
+                ty: ImageDescriptorType::Sampled {
+                    with_sampler: false
+                }
+            },
+            count: 1,
+            stage_flags: ShaderStageFlags::FRAGMENT,
+            immutable_samplers: false,
+        });
+        bindings.push(DescriptorSetLayoutBinding {
+            binding: sampler_binding,
+            ty: DescriptorType::Sampler,
+            count: 1,
+            stage_flags: ShaderStageFlags::FRAGMENT,
+            immutable_samplers: false,
+        });
+    }
+
+    DescSetLayout::new(device, bindings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_texture_path, MaterialDesc, MaterialParam};
+
+    use crate::constants::FRAGMENT_SHADER_PATH;
+
+    use std::path::Path;
+
+    use zeus_core::math::{
+        Vector2,
+        Vector3
+    };
+
+    #[test]
+    fn pack_std140() {
+        let mut desc = MaterialDesc::new("test", "test.vert", "test.frag");
+        desc.set_param("roughness", MaterialParam::Float(0.5));
+        desc.set_param("albedo", MaterialParam::Texture("albedo.png".to_string()));
+        desc.set_param("tint", MaterialParam::Vector3(Vector3 { x: 1.0, y: 2.0, z: 3.0 }));
+        desc.set_param("metallic", MaterialParam::Float(0.25));
+        desc.set_param("offset", MaterialParam::Vector2(Vector2 { x: 4.0, y: 5.0 }));
+        desc.set_param("color", MaterialParam::Color([0.1, 0.2, 0.3, 0.4]));
+
+        assert_eq!(
+            desc.pack_params(),
+            vec![
+                0.5, 0.0, 0.0, 0.0,
+                1.0, 2.0, 3.0, 0.25,
+                4.0, 5.0, 0.0, 0.0,
+                0.1, 0.2, 0.3, 0.4,
+            ]
+        );
+        assert_eq!(desc.textures().collect::<Vec<_>>(), vec![("albedo", "albedo.png")]);
+    }
+
+    #[test]
+    fn render_textures_are_not_files() {
+        let mut desc = MaterialDesc::new("monitor", "test.vert", "test.frag");
+        desc.set_param("screen", MaterialParam::RenderTexture("security".to_string()));
+        desc.set_param("frame", MaterialParam::Texture("frame.png".to_string()));
+
+        assert_eq!(desc.textures().collect::<Vec<_>>(), vec![("frame", "frame.png")]);
+        assert_eq!(desc.render_targets().collect::<Vec<_>>(), vec!["security"]);
+        assert!(!desc.has_uniforms());
+    }
+
+    #[test]
+    fn set_param_keeps_order() {
+        let mut desc = MaterialDesc::new("test", "test.vert", "test.frag");
+        desc.set_param("a", MaterialParam::Float(1.0));
+        desc.set_param("b", MaterialParam::Float(2.0));
+        desc.set_param("a", MaterialParam::Float(3.0));
+
+        assert_eq!(desc.pack_params(), vec![3.0, 2.0, 0.0, 0.0]);
+        assert_eq!(desc.get_param("b"), Some(&MaterialParam::Float(2.0)));
+        assert!(desc.get_param("c").is_none());
+    }
+
+    #[test]
+    fn pbr_from_mtl() {
+        let mut material = tobj::Material {
+            name: "metal".to_string(),
+            diffuse: [0.5, 0.25, 1.0],
+            shininess: 0.0,
+            diffuse_texture: "albedo.png".to_string(),
+            ..Default::default()
+        };
+        material.unknown_param.insert("Pm".to_string(), "1.0".to_string());
+        material.unknown_param.insert("norm".to_string(), "normal.png".to_string());
+
+        let desc = MaterialDesc::from_mtl(&material, "models");
+
+        //A missing d is opaque and Ns 0 is fully rough
+        assert_eq!(desc.get_param("base_color"), Some(&MaterialParam::Color([0.5, 0.25, 1.0, 1.0])));
+        assert_eq!(desc.get_param("metallic"), Some(&MaterialParam::Float(1.0)));
+        assert_eq!(desc.get_param("roughness"), Some(&MaterialParam::Float(1.0)));
+
+        let albedo = Path::new("models").join("albedo.png").to_string_lossy().into_owned();
+        let normal = Path::new("models").join("normal.png").to_string_lossy().into_owned();
+        assert_eq!(desc.get_param("albedo"), Some(&MaterialParam::Texture(albedo)));
+        assert_eq!(desc.get_param("normal"), Some(&MaterialParam::LinearTexture(normal)));
+        assert_eq!(desc.textures().count(), 5);
+    }
+
+    #[test]
+    fn phong_from_mtl() {
+        let material = tobj::Material {
+            name: "wood".to_string(),
+            diffuse: [0.8, 0.8, 0.8],
+            specular: [0.25, 0.25, 0.25],
+            shininess: 64.0,
+            dissolve: 0.5,
+            specular_texture: "wood_spec.png".to_string(),
+            ..Default::default()
+        };
+
+        let desc = MaterialDesc::from_mtl(&material, "models");
+
+        assert_eq!(desc.fragment_shader, FRAGMENT_SHADER_PATH);
+        assert_eq!(desc.get_param("color"), Some(&MaterialParam::Color([0.8, 0.8, 0.8, 0.5])));
+        assert_eq!(desc.get_param("shininess"), Some(&MaterialParam::Float(64.0)));
+
+        let specular = Path::new("models").join("wood_spec.png").to_string_lossy().into_owned();
+        assert_eq!(desc.get_param("specular_map"), Some(&MaterialParam::Texture(specular)));
+    }
+
+    #[test]
+    fn resolve_mtl_texture_paths() {
+        let expected = Path::new("models").join("textures/brick.png").to_string_lossy().into_owned();
+
+        assert_eq!(resolve_texture_path("models", "textures/brick.png"), expected);
+        assert_eq!(resolve_texture_path("models", "textures\\brick.png"), expected);
+        assert_eq!(resolve_texture_path("models", "-bm 0.5 textures/brick.png"), expected);
+    }
+}
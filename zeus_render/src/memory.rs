@@ -0,0 +1,393 @@
+use gfx_hal::{
+    adapter::MemoryType,
+    device::Device,
+    memory::{
+        Properties, Requirements
+    },
+    Backend,
+    MemoryTypeId,
+};
+
+use std::{
+    collections::BTreeMap,
+    fmt
+};
+
+use super::{
+    constants::MEMORY_BLOCK_SIZE,
+    error::MemoryError
+};
+
+/// Buffers and optimally tiled images are kept in separate blocks,
+/// so we never have to care about the buffer-image granularity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ResourceKind {
+    Linear,
+    Optimal,
+}
+
+/// A range of device memory handed out by the `MemoryAllocator`.
+/// It has to be given back with `MemoryAllocator::free`.
+#[derive(Debug)]
+pub struct Allocation {
+    pool: (usize, ResourceKind),
+    block: usize,
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MemoryStats {
+    pub blocks: usize,
+    pub allocations: usize,
+    pub reserved: u64,
+    pub used: u64,
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} allocations in {} blocks, {:.2} MB used of {:.2} MB reserved",
+            self.allocations,
+            self.blocks,
+            self.used as f64 / (1024.0 * 1024.0),
+            self.reserved as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
+
+/// First fit free list over a range of memory. Neighbouring free ranges are merged on free.
+#[derive(Debug)]
+pub struct BlockAllocator {
+    size: u64,
+    used: u64,
+    allocations: usize,
+    //Sorted by offset
+    free_ranges: Vec<(u64, u64)>,
+}
+
+impl BlockAllocator {
+    pub fn new(size: u64) -> Self {
+        BlockAllocator {
+            size,
+            used: 0,
+            allocations: 0,
+            free_ranges: vec![(0, size)],
+        }
+    }
+
+    /// Returns the offset of the new range, or None if there is no free range big enough.
+    pub fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let alignment = alignment.max(1);
+
+        let (index, offset) = self.free_ranges.iter().enumerate()
+            .find_map(|(index, &(start, length))| {
+                let offset = start.div_ceil(alignment) * alignment;
+                if offset + size <= start + length {
+                    Some((index, offset))
+                } else {
+                    None
+                }
+            })?;
+
+        let (start, length) = self.free_ranges.remove(index);
+        let end = start + length;
+
+        //Keep the padding before and the rest after the allocation free
+        if offset + size < end {
+            self.free_ranges.insert(index, (offset + size, end - offset - size));
+        }
+        if start < offset {
+            self.free_ranges.insert(index, (start, offset - start));
+        }
+
+        self.used += size;
+        self.allocations += 1;
+
+        Some(offset)
+    }
+
+    pub fn free(&mut self, offset: u64, size: u64) {
+        let index = self.free_ranges
+            .iter()
+            .position(|&(start, _)| start > offset)
+            .unwrap_or(self.free_ranges.len());
+
+        self.free_ranges.insert(index, (offset, size));
+
+        //Merge with the next range
+        if index + 1 < self.free_ranges.len() {
+            let (next_start, next_length) = self.free_ranges[index + 1];
+            if offset + size == next_start {
+                self.free_ranges[index].1 += next_length;
+                self.free_ranges.remove(index + 1);
+            }
+        }
+
+        //Merge with the previous range
+        if index > 0 {
+            let (prev_start, prev_length) = self.free_ranges[index - 1];
+            if prev_start + prev_length == offset {
+                self.free_ranges[index - 1].1 += self.free_ranges[index].1;
+                self.free_ranges.remove(index);
+            }
+        }
+
+        self.used -= size;
+        self.allocations -= 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocations == 0
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_used(&self) -> u64 {
+        self.used
+    }
+
+    pub fn get_allocations(&self) -> usize {
+        self.allocations
+    }
+}
+
+#[derive(Debug)]
+struct MemoryBlock<B: Backend> {
+    memory: B::Memory,
+    allocator: BlockAllocator,
+}
+
+/// Sub-allocates buffers and images from big blocks of device memory, one list of blocks per memory type.
+/// Resources bigger than half a block get a block of their own. Empty blocks are given back to the driver.
+#[derive(Debug)]
+pub struct MemoryAllocator<B: Backend> {
+    memory_types: Vec<MemoryType>,
+    block_size: u64,
+    //Freed blocks leave a None behind so the block index of the other allocations stays valid
+    pools: BTreeMap<(usize, ResourceKind), Vec<Option<MemoryBlock<B>>>>,
+}
+
+impl<B: Backend> MemoryAllocator<B> {
+    pub fn new(memory_types: Vec<MemoryType>) -> Self {
+        MemoryAllocator {
+            memory_types,
+            block_size: MEMORY_BLOCK_SIZE,
+            pools: BTreeMap::new(),
+        }
+    }
+
+    /// Allocates memory for a resource, from the first memory type that supports the requirements and properties.
+    pub fn allocate(
+        &mut self,
+        device: &B::Device,
+        requirements: Requirements,
+        properties: Properties,
+        kind: ResourceKind,
+    ) -> Result<Allocation, MemoryError> {
+        let memory_type = self.memory_types.iter().enumerate()
+            .position(|(id, memory_type)| {
+                requirements.type_mask & (1 << id) != 0
+                    && memory_type.properties.contains(properties)
+            })
+            .ok_or_else(|| MemoryError::new(format!("No memory type supports {:?}", properties)))?;
+
+        let pool_key = (memory_type, kind);
+        let block_size = self.block_size;
+        let pool = self.pools.entry(pool_key).or_default();
+
+        //Try to fit it in one of the existing blocks
+        if requirements.size <= block_size / 2 {
+            for (index, block) in pool.iter_mut().enumerate() {
+                if let Some(block) = block {
+                    if let Some(offset) = block.allocator.allocate(requirements.size, requirements.alignment) {
+                        return Ok(Allocation {
+                            pool: pool_key,
+                            block: index,
+                            offset,
+                            size: requirements.size,
+                        });
+                    }
+                }
+            }
+        }
+
+        let size = if requirements.size <= block_size / 2 {
+            block_size
+        } else {
+            requirements.size
+        };
+
+        let memory = unsafe {
+            device.allocate_memory(MemoryTypeId(memory_type), size)
+        }.map_err(|err| MemoryError::new(format!("Could not allocate a block of {} bytes: {:?}", size, err)))?;
+
+        debug!("Allocated memory block of {} bytes from memory type {}", size, memory_type);
+
+        let mut allocator = BlockAllocator::new(size);
+        let offset = allocator.allocate(requirements.size, requirements.alignment)
+            .expect("New block is too small for the allocation");
+
+        let block = Some(MemoryBlock {
+            memory,
+            allocator,
+        });
+
+        let index = match pool.iter().position(|block| block.is_none()) {
+            Some(index) => {
+                pool[index] = block;
+                index
+            },
+            None => {
+                pool.push(block);
+                pool.len() - 1
+            }
+        };
+
+        Ok(Allocation {
+            pool: pool_key,
+            block: index,
+            offset,
+            size: requirements.size,
+        })
+    }
+
+    /// Allocates memory for the buffer and binds it.
+    pub fn bind_buffer(
+        &mut self,
+        device: &B::Device,
+        buffer: &mut B::Buffer,
+        properties: Properties,
+    ) -> Result<Allocation, MemoryError> {
+        let requirements = unsafe {
+            device.get_buffer_requirements(buffer)
+        };
+
+        let allocation = self.allocate(device, requirements, properties, ResourceKind::Linear)?;
+
+        unsafe {
+            device.bind_buffer_memory(self.memory(&allocation), allocation.offset, buffer)
+        }.map_err(|err| MemoryError::new(format!("Could not bind buffer memory: {:?}", err)))?;
+
+        Ok(allocation)
+    }
+
+    /// Allocates memory for the optimally tiled image and binds it.
+    pub fn bind_image(
+        &mut self,
+        device: &B::Device,
+        image: &mut B::Image,
+        properties: Properties,
+    ) -> Result<Allocation, MemoryError> {
+        let requirements = unsafe {
+            device.get_image_requirements(image)
+        };
+
+        let allocation = self.allocate(device, requirements, properties, ResourceKind::Optimal)?;
+
+        unsafe {
+            device.bind_image_memory(self.memory(&allocation), allocation.offset, image)
+        }.map_err(|err| MemoryError::new(format!("Could not bind image memory: {:?}", err)))?;
+
+        Ok(allocation)
+    }
+
+    /// The device memory the allocation lives in, offsets have to add `Allocation::offset`.
+    pub fn memory(&self, allocation: &Allocation) -> &B::Memory {
+        &self.pools[&allocation.pool][allocation.block]
+            .as_ref()
+            .expect("Allocation points to a freed block")
+            .memory
+    }
+
+    /// Gives the range back to its block. The resource using it has to be destroyed first.
+    pub fn free(&mut self, device: &B::Device, allocation: Allocation) {
+        let pool = self.pools.get_mut(&allocation.pool)
+            .expect("Allocation points to an unknown pool");
+
+        let is_empty = {
+            let block = pool[allocation.block].as_mut()
+                .expect("Allocation points to a freed block");
+
+            block.allocator.free(allocation.offset, allocation.size);
+            block.allocator.is_empty()
+        };
+
+        if is_empty {
+            let block = pool[allocation.block].take().unwrap();
+
+            debug!("Freeing memory block of {} bytes", block.allocator.get_size());
+
+            unsafe {
+                device.free_memory(block.memory);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        self.pools.values()
+            .flat_map(|pool| pool.iter().filter_map(|block| block.as_ref()))
+            .fold(MemoryStats::default(), |mut stats, block| {
+                stats.blocks += 1;
+                stats.allocations += block.allocator.get_allocations();
+                stats.reserved += block.allocator.get_size();
+                stats.used += block.allocator.get_used();
+                stats
+            })
+    }
+
+    /// Frees every block, called when the device is destroyed.
+    pub fn dispose(&mut self, device: &B::Device) {
+        let stats = self.stats();
+        if stats.allocations > 0 {
+            warn!("Destroying the memory allocator with live allocations: {}", stats);
+        }
+
+        for (_, pool) in std::mem::take(&mut self.pools) {
+            for block in pool.into_iter().flatten() {
+                unsafe {
+                    device.free_memory(block.memory);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlockAllocator;
+
+    #[test]
+    fn allocate_with_alignment() {
+        let mut allocator = BlockAllocator::new(1024);
+
+        assert_eq!(allocator.allocate(100, 1), Some(0));
+        assert_eq!(allocator.allocate(100, 256), Some(256));
+        assert_eq!(allocator.allocate(100, 1), Some(100));
+        assert_eq!(allocator.allocate(1024, 1), None);
+
+        assert_eq!(allocator.get_used(), 300);
+        assert_eq!(allocator.get_allocations(), 3);
+    }
+
+    #[test]
+    fn free_merges_ranges() {
+        let mut allocator = BlockAllocator::new(300);
+
+        let a = allocator.allocate(100, 1).unwrap();
+        let b = allocator.allocate(100, 1).unwrap();
+        let c = allocator.allocate(100, 1).unwrap();
+        assert_eq!(allocator.allocate(1, 1), None);
+
+        allocator.free(a, 100);
+        allocator.free(c, 100);
+        assert_eq!(allocator.allocate(150, 1), None);
+
+        allocator.free(b, 100);
+        assert!(allocator.is_empty());
+        assert_eq!(allocator.allocate(300, 1), Some(0));
+    }
+}
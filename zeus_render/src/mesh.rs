@@ -0,0 +1,755 @@
+use super::model::Vertex;
+
+use zeus_core::math::{
+    Aabb,
+    BoundingSphere,
+    Matrix4,
+    Vector3,
+    Vector4
+};
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap
+};
+
+//NOTE: Mesh processing on the vertices and indices before they are uploaded.
+//Every function works on triangle lists.
+
+/// Merges the vertices whose attributes are all within `epsilon` of each other.
+pub fn weld_vertices(vertices: &[Vertex], indices: &[u32], epsilon: f32) -> (Vec<Vertex>, Vec<u32>) {
+    //Positions are hashed into cells of the size of epsilon, so a match can only be in a neighbouring cell
+    let cell_size = epsilon.max(1e-7);
+    let cell = |position: Vector3| [
+        (position.x / cell_size).floor() as i64,
+        (position.y / cell_size).floor() as i64,
+        (position.z / cell_size).floor() as i64,
+    ];
+
+    let mut cells: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+    let mut welded: Vec<Vertex> = Vec::new();
+    let mut remap = vec![0; vertices.len()];
+
+    for (index, vertex) in vertices.iter().enumerate() {
+        let [x, y, z] = cell(vertex.a_pos);
+        let attributes = vertex_attributes(vertex);
+
+        let mut found = None;
+        'search: for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    for &other in cells.get(&[x + dx, y + dy, z + dz]).into_iter().flatten() {
+                        let other_attributes = vertex_attributes(&welded[other as usize]);
+
+                        if attributes.iter().zip(other_attributes.iter()).all(|(a, b)| (a - b).abs() <= epsilon) {
+                            found = Some(other);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+
+        remap[index] = found.unwrap_or_else(|| {
+            let welded_index = welded.len() as u32;
+            welded.push(*vertex);
+            cells.entry([x, y, z]).or_default().push(welded_index);
+
+            welded_index
+        });
+    }
+
+    let indices = indices.iter()
+        .map(|index| remap[*index as usize])
+        .collect();
+
+    (welded, indices)
+}
+
+fn vertex_attributes(vertex: &Vertex) -> [f32; 24] {
+    let Vertex { a_pos, a_color, a_uv, a_normal, a_tangent, a_joints, a_weights } = *vertex;
+    let vec4 = |v: Vector4| [v.x, v.y, v.z, v.w];
+    let [color, tangent, joints, weights] = [vec4(a_color), vec4(a_tangent), vec4(a_joints), vec4(a_weights)];
+
+    [
+        a_pos.x, a_pos.y, a_pos.z,
+        color[0], color[1], color[2], color[3],
+        a_uv.x, a_uv.y,
+        a_normal.x, a_normal.y, a_normal.z,
+        tangent[0], tangent[1], tangent[2], tangent[3],
+        joints[0], joints[1], joints[2], joints[3],
+        weights[0], weights[1], weights[2], weights[3],
+    ]
+}
+
+/// The first vertex with the same position as each vertex, vertices split on UV or normal seams share it.
+fn position_remap(vertices: &[Vertex]) -> Vec<usize> {
+    let mut positions: HashMap<[u32; 3], usize> = HashMap::new();
+
+    vertices.iter()
+        .enumerate()
+        .map(|(index, vertex)| {
+            let key = [vertex.a_pos.x.to_bits(), vertex.a_pos.y.to_bits(), vertex.a_pos.z.to_bits()];
+            *positions.entry(key).or_insert(index)
+        })
+        .collect()
+}
+
+/// Smooth normals for the vertices without one, the average of the normals of the faces around each position.
+/// Vertices split on UV seams get the same normal, so the seams don't show.
+pub fn generate_smooth_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    let remap = position_remap(vertices);
+    let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+
+    for face in indices.chunks_exact(3) {
+        let (a, b, c) = (face[0] as usize, face[1] as usize, face[2] as usize);
+
+        //NOTE: Not normalized, so bigger faces weigh more
+        let normal = (vertices[b].a_pos - vertices[a].a_pos)
+            .cross(&(vertices[c].a_pos - vertices[a].a_pos));
+
+        for &index in [a, b, c].iter() {
+            normals[remap[index]] += normal;
+        }
+    }
+
+    for (index, vertex) in vertices.iter_mut().enumerate() {
+        let normal = normals[remap[index]];
+
+        if vertex.a_normal.magn() == 0.0 && normal.magn() > 0.0 {
+            vertex.a_normal = normal.normalize();
+        }
+    }
+}
+
+/// Gives every face the normal of its plane, the vertices are split where the faces meet at an angle.
+pub fn generate_flat_normals(vertices: &[Vertex], indices: &[u32]) -> (Vec<Vertex>, Vec<u32>) {
+    let mut flat_vertices = Vec::with_capacity(indices.len());
+
+    for face in indices.chunks_exact(3) {
+        let [a, b, c] = [vertices[face[0] as usize], vertices[face[1] as usize], vertices[face[2] as usize]];
+        let normal = (b.a_pos - a.a_pos).cross(&(c.a_pos - a.a_pos));
+        let normal = if normal.magn() > 0.0 { normal.normalize() } else { normal };
+
+        for mut vertex in [a, b, c].iter().cloned() {
+            vertex.a_normal = normal;
+            flat_vertices.push(vertex);
+        }
+    }
+
+    let flat_indices: Vec<u32> = (0..flat_vertices.len() as u32).collect();
+
+    //Faces on the same plane share their vertices again
+    weld_vertices(&flat_vertices, &flat_indices, 0.0)
+}
+
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+/// Tom Forsyth's score of a vertex, higher for recently used vertices and ones with few triangles left.
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        //The vertices of the last triangle are penalized a bit, so strips don't go back and forth
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => (1.0 - (position - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(CACHE_DECAY_POWER),
+        None => 0.0,
+    };
+
+    cache_score + VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+/// Reorders the triangles so their vertices are reused while they are still in the post-transform cache,
+/// using Tom Forsyth's linear-speed vertex cache optimisation.
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+
+    let mut vertex_triangles = vec![Vec::new(); vertex_count];
+    for (triangle, face) in indices.chunks_exact(3).enumerate() {
+        for &vertex in face.iter() {
+            vertex_triangles[vertex as usize].push(triangle);
+        }
+    }
+
+    let mut cache_positions: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f32> = vertex_triangles.iter()
+        .map(|triangles| vertex_score(None, triangles.len()))
+        .collect();
+    let mut added = vec![false; triangle_count];
+
+    let mut optimized = Vec::with_capacity(indices.len());
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut best_triangle = None;
+    let mut next_unadded = 0;
+
+    for _ in 0..triangle_count {
+        //Nothing in the cache has triangles left, start from the next triangle in the original order
+        let triangle = match best_triangle {
+            Some(triangle) => triangle,
+            None => {
+                while added[next_unadded] {
+                    next_unadded += 1;
+                }
+                next_unadded
+            }
+        };
+
+        added[triangle] = true;
+        let face = &indices[triangle * 3..triangle * 3 + 3];
+        optimized.extend_from_slice(face);
+
+        for &vertex in face.iter() {
+            vertex_triangles[vertex as usize].retain(|other| *other != triangle);
+        }
+
+        //The vertices of the triangle move to the front of the cache
+        let mut new_cache: Vec<u32> = face.to_vec();
+        new_cache.extend(cache.iter().filter(|vertex| !face.contains(vertex)));
+
+        for &vertex in new_cache.iter().skip(CACHE_SIZE) {
+            cache_positions[vertex as usize] = None;
+        }
+
+        for (position, &vertex) in new_cache.iter().enumerate() {
+            if position < CACHE_SIZE {
+                cache_positions[vertex as usize] = Some(position);
+            }
+
+            vertex_scores[vertex as usize] = vertex_score(cache_positions[vertex as usize], vertex_triangles[vertex as usize].len());
+        }
+
+        best_triangle = None;
+        let mut best_score = f32::MIN;
+        for &vertex in new_cache.iter() {
+            for &other in vertex_triangles[vertex as usize].iter() {
+                let score = indices[other * 3..other * 3 + 3].iter()
+                    .map(|vertex| vertex_scores[*vertex as usize])
+                    .sum();
+
+                if score > best_score {
+                    best_score = score;
+                    best_triangle = Some(other);
+                }
+            }
+        }
+
+        new_cache.truncate(CACHE_SIZE);
+        cache = new_cache;
+    }
+
+    optimized
+}
+
+/// Groups the triangles in clusters that start where the vertex cache is cold, and draws the clusters
+/// facing out of the mesh first, so they hide the ones behind them. Run after `optimize_vertex_cache`.
+pub fn optimize_overdraw(indices: &[u32], vertices: &[Vertex]) -> Vec<u32> {
+    const FIFO_SIZE: usize = 16;
+
+    let mut clusters: Vec<Vec<u32>> = Vec::new();
+    let mut fifo: Vec<u32> = Vec::with_capacity(FIFO_SIZE);
+
+    for face in indices.chunks_exact(3) {
+        let misses = face.iter()
+            .filter(|vertex| !fifo.contains(vertex))
+            .count();
+
+        if misses == 3 || clusters.is_empty() {
+            clusters.push(Vec::new());
+        }
+
+        for &vertex in face.iter() {
+            if !fifo.contains(&vertex) {
+                if fifo.len() == FIFO_SIZE {
+                    fifo.remove(0);
+                }
+                fifo.push(vertex);
+            }
+        }
+
+        clusters.last_mut().unwrap().extend_from_slice(face);
+    }
+
+    let mesh_center = Aabb::from_points(indices.iter().map(|index| vertices[*index as usize].a_pos)).center();
+
+    let mut sorted: Vec<(f32, Vec<u32>)> = clusters.into_iter()
+        .map(|cluster| {
+            let mut center = Vector3::new(0.0, 0.0, 0.0);
+            let mut normal = Vector3::new(0.0, 0.0, 0.0);
+            let mut area = 0.0;
+
+            for face in cluster.chunks_exact(3) {
+                let [a, b, c] = [vertices[face[0] as usize].a_pos, vertices[face[1] as usize].a_pos, vertices[face[2] as usize].a_pos];
+                let face_normal = (b - a).cross(&(c - a));
+                let face_area = face_normal.magn();
+
+                center += (a + b + c) * (face_area / 3.0);
+                normal += face_normal;
+                area += face_area;
+            }
+
+            let key = if area > 0.0 {
+                (center / area - mesh_center).dot(&normal.normalize())
+            } else {
+                0.0
+            };
+
+            (key, cluster)
+        })
+        .collect();
+
+    sorted.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+
+    sorted.into_iter()
+        .flat_map(|(_, cluster)| cluster)
+        .collect()
+}
+
+/// Orders the vertices by their first use in the indices, unused vertices are dropped.
+pub fn optimize_vertex_fetch(vertices: &mut Vec<Vertex>, indices: &mut [u32]) {
+    let mut remap: Vec<Option<u32>> = vec![None; vertices.len()];
+    let mut fetched = Vec::with_capacity(vertices.len());
+
+    for index in indices.iter_mut() {
+        *index = *remap[*index as usize].get_or_insert_with(|| {
+            fetched.push(vertices[*index as usize]);
+            fetched.len() as u32 - 1
+        });
+    }
+
+    *vertices = fetched;
+}
+
+/// The bounding box and sphere of the vertices.
+pub fn bounding_volumes(vertices: &[Vertex]) -> (Aabb, BoundingSphere) {
+    let positions: Vec<Vector3> = vertices.iter()
+        .map(|vertex| vertex.a_pos)
+        .collect();
+
+    (Aabb::from_points(positions.iter().cloned()), BoundingSphere::from_points(&positions))
+}
+
+/// The squared distance to a set of planes, weighted by the area of their triangles.
+#[derive(Debug, Copy, Clone, Default)]
+struct Quadric {
+    //The upper triangle of the symmetric 4x4 matrix
+    a: [f64; 10],
+    weight: f64,
+}
+
+impl Quadric {
+    fn from_triangle(p0: Vector3, p1: Vector3, p2: Vector3) -> Self {
+        let normal = (p1 - p0).cross(&(p2 - p0));
+        let area = normal.magn() as f64 * 0.5;
+        if area == 0.0 {
+            return Quadric::default();
+        }
+
+        let normal = normal.normalize();
+        let (a, b, c) = (normal.x as f64, normal.y as f64, normal.z as f64);
+        let d = -normal.dot(&p0) as f64;
+
+        Quadric {
+            a: [a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|value| value * area),
+            weight: area,
+        }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.a.iter_mut().zip(other.a.iter()) {
+            *a += b;
+        }
+        self.weight += other.weight;
+    }
+
+    /// The average squared distance of the point to the planes.
+    fn error(&self, point: Vector3) -> f64 {
+        if self.weight == 0.0 {
+            return 0.0;
+        }
+
+        let (x, y, z) = (point.x as f64, point.y as f64, point.z as f64);
+        let q = &self.a;
+
+        let error = q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9];
+
+        error.max(0.0) / self.weight
+    }
+}
+
+/// Quadric edge-collapse simplification, the vertices are kept and only the indices change.
+/// Stops at `target_index_count` or once a collapse would move the surface by more than `target_error`,
+/// relative to the size of the mesh. Vertices on borders and seams don't move.
+pub fn simplify(vertices: &[Vertex], indices: &[u32], target_index_count: usize, target_error: f32) -> Vec<u32> {
+    let remap = position_remap(vertices);
+    let mut indices = indices.to_vec();
+
+    let extent = {
+        let size = Aabb::from_points(vertices.iter().map(|vertex| vertex.a_pos)).size();
+        size.x.max(size.y).max(size.z)
+    };
+    let max_error = (target_error * extent) as f64;
+
+    //Vertices split on seams would tear apart, and border edges have nothing to hold them in place
+    let mut locked = vec![false; vertices.len()];
+    {
+        let mut siblings = vec![0; vertices.len()];
+        for position in remap.iter() {
+            siblings[*position] += 1;
+        }
+
+        let mut edges: HashMap<(usize, usize), u32> = HashMap::new();
+        for face in indices.chunks_exact(3) {
+            for corner in 0..3 {
+                let (a, b) = (remap[face[corner] as usize], remap[face[(corner + 1) % 3] as usize]);
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+
+        for (index, position) in remap.iter().enumerate() {
+            locked[index] = siblings[*position] > 1;
+        }
+
+        for ((a, b), count) in edges {
+            if count == 1 {
+                locked[a] = true;
+                locked[b] = true;
+            }
+        }
+
+        for index in 0..vertices.len() {
+            locked[index] = locked[index] || locked[remap[index]];
+        }
+    }
+
+    let mut quadrics = vec![Quadric::default(); vertices.len()];
+    for face in indices.chunks_exact(3) {
+        let quadric = Quadric::from_triangle(
+            vertices[face[0] as usize].a_pos,
+            vertices[face[1] as usize].a_pos,
+            vertices[face[2] as usize].a_pos,
+        );
+
+        for &index in face.iter() {
+            quadrics[remap[index as usize]].add(&quadric);
+        }
+    }
+
+    while indices.len() > target_index_count {
+        let mut vertex_faces = vec![Vec::new(); vertices.len()];
+        for (face_index, face) in indices.chunks_exact(3).enumerate() {
+            for &index in face.iter() {
+                vertex_faces[index as usize].push(face_index);
+            }
+        }
+
+        let mut collapses: Vec<(f64, usize, usize)> = Vec::new();
+        for face in indices.chunks_exact(3) {
+            for corner in 0..3 {
+                for &(from, to) in [(corner, (corner + 1) % 3), ((corner + 1) % 3, corner)].iter() {
+                    let (from, to) = (face[from] as usize, face[to] as usize);
+                    if locked[from] || remap[from] == remap[to] {
+                        continue;
+                    }
+
+                    let mut quadric = quadrics[remap[from]];
+                    quadric.add(&quadrics[remap[to]]);
+
+                    collapses.push((quadric.error(vertices[to].a_pos), from, to));
+                }
+            }
+        }
+
+        collapses.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+        let faces_to_remove = (indices.len() - target_index_count) / 3;
+        let mut collapse_to: Vec<usize> = (0..vertices.len()).collect();
+        let mut touched = vec![false; vertices.len()];
+        let mut removed = 0;
+
+        for (error, from, to) in collapses {
+            if error.sqrt() > max_error || removed >= faces_to_remove {
+                break;
+            }
+
+            if touched[remap[from]] || touched[remap[to]] {
+                continue;
+            }
+
+            if collapse_flips_faces(vertices, &indices, &vertex_faces[from], from, to, &remap) {
+                continue;
+            }
+
+            collapse_to[from] = to;
+
+            //The faces around the collapse changed, they are looked at again in the next pass
+            for &face in vertex_faces[from].iter() {
+                let face = &indices[face * 3..face * 3 + 3];
+                if face.iter().any(|index| remap[*index as usize] == remap[to]) {
+                    removed += 1;
+                }
+
+                for &index in face.iter() {
+                    touched[remap[index as usize]] = true;
+                }
+            }
+
+            let quadric = quadrics[remap[from]];
+            quadrics[remap[to]].add(&quadric);
+        }
+
+        if removed == 0 {
+            break;
+        }
+
+        indices = indices.chunks_exact(3)
+            .map(|face| [collapse_to[face[0] as usize], collapse_to[face[1] as usize], collapse_to[face[2] as usize]])
+            .filter(|face| remap[face[0]] != remap[face[1]] && remap[face[1]] != remap[face[2]] && remap[face[2]] != remap[face[0]])
+            .flat_map(|face| face.iter().map(|index| *index as u32).collect::<Vec<u32>>())
+            .collect();
+    }
+
+    indices
+}
+
+/// Whether moving `from` onto `to` turns any of the faces that stay around.
+fn collapse_flips_faces(vertices: &[Vertex], indices: &[u32], faces: &[usize], from: usize, to: usize, remap: &[usize]) -> bool {
+    faces.iter().any(|&face| {
+        let face = &indices[face * 3..face * 3 + 3];
+
+        //The faces on the edge are removed by the collapse
+        if face.iter().any(|index| remap[*index as usize] == remap[to]) {
+            return false;
+        }
+
+        let positions: Vec<Vector3> = face.iter()
+            .map(|index| vertices[*index as usize].a_pos)
+            .collect();
+        let moved: Vec<Vector3> = face.iter()
+            .map(|index| if *index as usize == from { vertices[to].a_pos } else { vertices[*index as usize].a_pos })
+            .collect();
+
+        let before = (positions[1] - positions[0]).cross(&(positions[2] - positions[0]));
+        let after = (moved[1] - moved[0]).cross(&(moved[2] - moved[0]));
+
+        before.dot(&after) <= 0.0
+    })
+}
+
+/// Simplifies the mesh again and again by `reduction`, until `max_lods` levels or the error limit.
+/// Returns the indices of the levels after the original one.
+pub fn generate_lods(vertices: &[Vertex], indices: &[u32], max_lods: usize, reduction: f32, target_error: f32) -> Vec<Vec<u32>> {
+    let mut lods: Vec<Vec<u32>> = Vec::new();
+
+    while lods.len() + 1 < max_lods {
+        let previous = lods.last().map(|lod| &lod[..]).unwrap_or(indices);
+        let target = ((previous.len() / 3) as f32 * reduction) as usize * 3;
+
+        let lod = simplify(vertices, previous, target, target_error);
+
+        //Not worth a level of its own
+        if lod.is_empty() || lod.len() as f32 > previous.len() as f32 * 0.9 {
+            break;
+        }
+
+        lods.push(optimize_vertex_cache(&lod, vertices.len()));
+    }
+
+    lods
+}
+
+/// How much of the height of the screen the sphere covers, 1 fills the screen.
+pub fn screen_size(sphere: &BoundingSphere, model_view: &Matrix4, proj: &Matrix4) -> f32 {
+    let center = *model_view * Vector4::new(sphere.center.x, sphere.center.y, sphere.center.z, 1.0);
+    let distance = Vector3::new(center.x, center.y, center.z).magn();
+
+    //The longest axis of the model matrix scales the radius
+    let scale = (0..3)
+        .map(|column| Vector3::new(model_view[column], model_view[4 + column], model_view[8 + column]).magn())
+        .fold(0.0, f32::max);
+    let radius = sphere.radius * scale;
+
+    if distance <= radius {
+        return f32::INFINITY;
+    }
+
+    radius * proj[5].abs() / distance
+}
+
+/// The LOD to draw at a screen size, every level is used for half the size of the one before.
+pub fn select_lod(screen_size: f32, lod_count: usize, first_lod_size: f32) -> usize {
+    let mut lod = 0;
+    let mut threshold = first_lod_size;
+
+    while lod + 1 < lod_count && screen_size < threshold {
+        lod += 1;
+        threshold *= 0.5;
+    }
+
+    lod
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        generate_flat_normals, generate_lods, generate_smooth_normals, optimize_overdraw, optimize_vertex_cache,
+        optimize_vertex_fetch, screen_size, select_lod, simplify, weld_vertices
+    };
+
+    use crate::model::Vertex;
+
+    use zeus_core::math::{
+        BoundingSphere, Matrix4, Vector2, Vector3, Vector4
+    };
+
+    fn vertex(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex {
+            a_pos: Vector3::new(x, y, z),
+            a_color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            a_uv: Vector2::new(0.0, 0.0),
+            a_normal: Vector3::new(0.0, 0.0, 0.0),
+            a_tangent: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            a_joints: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            a_weights: Vector4::new(0.0, 0.0, 0.0, 0.0),
+        }
+    }
+
+    //A grid of quads on the xy plane, `bump` lifts the middle vertex
+    fn grid(size: usize, bump: f32) -> (Vec<Vertex>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        for y in 0..=size {
+            for x in 0..=size {
+                let z = if x == size / 2 && y == size / 2 { bump } else { 0.0 };
+                vertices.push(vertex(x as f32, y as f32, z));
+            }
+        }
+
+        let mut indices = Vec::new();
+        for y in 0..size as u32 {
+            for x in 0..size as u32 {
+                let i = y * (size as u32 + 1) + x;
+                let row = size as u32 + 1;
+                indices.extend_from_slice(&[i, i + 1, i + row + 1, i + row + 1, i + row, i]);
+            }
+        }
+
+        (vertices, indices)
+    }
+
+    fn acmr(indices: &[u32], cache_size: usize) -> f32 {
+        let mut cache: Vec<u32> = Vec::new();
+        let mut misses = 0;
+
+        for index in indices.iter() {
+            if !cache.contains(index) {
+                misses += 1;
+                cache.push(*index);
+                if cache.len() > cache_size {
+                    cache.remove(0);
+                }
+            }
+        }
+
+        misses as f32 / (indices.len() / 3) as f32
+    }
+
+    #[test]
+    fn weld_close_vertices() {
+        let vertices = vec![vertex(0.0, 0.0, 0.0), vertex(1.0, 0.0, 0.0), vertex(0.0, 1.0, 0.0), vertex(1.0, 1e-4, 0.0), vertex(1.1, 0.0, 0.0)];
+
+        let (welded, indices) = weld_vertices(&vertices, &[0, 1, 2, 3, 4, 2], 1e-3);
+        assert_eq!(welded.len(), 4);
+        assert_eq!(indices, vec![0, 1, 2, 1, 3, 2]);
+
+        let (welded, _) = weld_vertices(&vertices, &[0, 1, 2, 3, 4, 2], 0.0);
+        assert_eq!(welded.len(), 5);
+    }
+
+    #[test]
+    fn smooth_and_flat_normals() {
+        //Two faces folded along the y axis, the second copy of the edge has other UVs
+        let mut vertices = vec![vertex(0.0, 0.0, 0.0), vertex(0.0, 1.0, 0.0), vertex(-1.0, 0.0, 0.0), vertex(0.0, 0.0, 0.0), vertex(0.0, 1.0, 0.0), vertex(0.0, 0.0, -1.0)];
+        vertices[3].a_uv = Vector2::new(1.0, 0.0);
+        vertices[4].a_uv = Vector2::new(1.0, 1.0);
+        let indices = [0, 1, 2, 3, 5, 4];
+
+        let (flat, flat_indices) = generate_flat_normals(&vertices, &indices);
+        assert_eq!(flat_indices.len(), 6);
+        assert_eq!(flat[flat_indices[0] as usize].a_normal, Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(flat[flat_indices[3] as usize].a_normal, Vector3::new(1.0, 0.0, 0.0));
+
+        generate_smooth_normals(&mut vertices, &indices);
+        let expected = Vector3::new(1.0, 0.0, 1.0).normalize();
+        assert!((vertices[0].a_normal - expected).magn() < 1e-6);
+        assert!((vertices[3].a_normal - expected).magn() < 1e-6);
+        assert_eq!(vertices[2].a_normal, Vector3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn optimize_index_order() {
+        let (mut vertices, indices) = grid(16, 0.0);
+
+        //Columns first, every row of the grid misses the cache
+        let shuffled: Vec<u32> = (0..16).flat_map(|x| (0..16).map(move |y| y * 16 + x))
+            .flat_map(|quad| indices[quad * 6..quad * 6 + 6].to_vec())
+            .collect();
+
+        let optimized = optimize_vertex_cache(&shuffled, vertices.len());
+        assert!(acmr(&optimized, 16) < acmr(&shuffled, 16));
+
+        let sorted = |indices: &[u32]| {
+            let mut faces: Vec<Vec<u32>> = indices.chunks(3).map(|face| face.to_vec()).collect();
+            faces.sort();
+            faces
+        };
+        assert_eq!(sorted(&optimized), sorted(&shuffled));
+        assert_eq!(sorted(&optimize_overdraw(&optimized, &vertices)), sorted(&shuffled));
+
+        let mut fetched = optimized.clone();
+        optimize_vertex_fetch(&mut vertices, &mut fetched);
+        assert_eq!(fetched[..3], [0, 1, 2]);
+        assert_eq!(vertices.len(), 17 * 17);
+    }
+
+    #[test]
+    fn simplify_flat_grid() {
+        let (vertices, indices) = grid(8, 0.0);
+
+        //Only the border is left, it is locked
+        let simplified = simplify(&vertices, &indices, 0, 0.01);
+        assert!(simplified.len() < indices.len() / 2);
+
+        //The bump can't be flattened within the error
+        let (vertices, indices) = grid(8, 2.0);
+        let simplified = simplify(&vertices, &indices, 0, 0.01);
+        assert!(simplified.contains(&40));
+
+        let lods = generate_lods(&vertices, &indices, 4, 0.5, 1.0);
+        assert!(!lods.is_empty());
+        assert!(lods.windows(2).all(|lods| lods[1].len() < lods[0].len()));
+    }
+
+    #[test]
+    fn lod_by_screen_size() {
+        let sphere = BoundingSphere::new(Vector3::new(0.0, 0.0, 0.0), 1.0);
+        let proj = Matrix4::perspective(90.0_f32.to_radians(), 1.0, 0.1, 100.0);
+
+        let near = screen_size(&sphere, &Matrix4::new_traslation(0.0, 0.0, -2.0), &proj);
+        let far = screen_size(&sphere, &Matrix4::new_traslation(0.0, 0.0, -20.0), &proj);
+        assert!((near - 0.5).abs() < 1e-5);
+        assert!((far - 0.05).abs() < 1e-5);
+
+        assert_eq!(select_lod(near, 4, 0.25), 0);
+        assert_eq!(select_lod(far, 4, 0.25), 3);
+        assert_eq!(select_lod(0.2, 4, 0.25), 1);
+        assert_eq!(select_lod(0.0, 1, 0.25), 0);
+    }
+}
@@ -0,0 +1,464 @@
+use memmap2::Mmap;
+
+use zeus_core::{
+    math::{
+        Aabb,
+        Vector3
+    },
+    vfs
+};
+
+use super::{
+    constants::{
+        MESH_CACHE_EXT, MESH_CACHE_VERSION
+    },
+    error::AssetError,
+    model::Vertex,
+};
+
+use std::{
+    convert::TryInto,
+    fs::{
+        self, File
+    },
+    mem::{
+        align_of, size_of, size_of_val
+    },
+    ops::Range,
+    path::PathBuf,
+    ptr,
+    slice
+};
+
+const MAGIC: &[u8; 4] = b"ZMSH";
+//NOTE: The vertex data starts aligned, so it can be read straight out of the mapped file
+const DATA_ALIGNMENT: usize = 16;
+const NO_MATERIAL: u32 = u32::MAX;
+
+/// A range of the indices and the material it's drawn with, as an index into the materials of the model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedSubMesh {
+    pub indices: Range<u32>,
+    pub material: Option<u32>,
+    pub lod: u32,
+}
+
+/// The vertices and indices of a mesh, either owned or read in place from a mapped mesh cache.
+#[derive(Debug)]
+pub enum MeshData {
+    Owned {
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+    },
+    /// The byte ranges of the vertices and indices in the file, their alignment is checked when it's mapped
+    Mapped {
+        map: Mmap,
+        vertices: Range<usize>,
+        indices: Range<usize>,
+    },
+}
+
+impl MeshData {
+    pub fn vertices(&self) -> &[Vertex] {
+        match self {
+            MeshData::Owned { vertices, .. } => vertices,
+            MeshData::Mapped { map, vertices, .. } => unsafe { cast_slice(&map[vertices.clone()]) },
+        }
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        match self {
+            MeshData::Owned { indices, .. } => indices,
+            MeshData::Mapped { map, indices, .. } => unsafe { cast_slice(&map[indices.clone()]) },
+        }
+    }
+}
+
+impl PartialEq for MeshData {
+    fn eq(&self, other: &Self) -> bool {
+        self.vertices() == other.vertices() && self.indices() == other.indices()
+    }
+}
+
+/// The imported geometry of a model, stored next to the source as `<model>.zmesh`.
+///
+/// Layout, little endian: magic, version, vertex stride and attributes (location, format, offset),
+/// vertex and index counts, bounding box, submesh table with LODs, material libraries,
+/// then the vertices and indices as they are uploaded.
+#[derive(Debug, PartialEq)]
+pub struct MeshCache {
+    pub mesh: MeshData,
+    pub bounds: Aabb,
+    pub submeshes: Vec<CachedSubMesh>,
+    /// The material libraries of the model, relative to it
+    pub material_libraries: Vec<String>,
+}
+
+impl MeshCache {
+    /// Reads the cache of the model if it's newer than the source. Caches that are files on disk are memory-mapped,
+    /// the mesh is uploaded and kept straight from the mapping, packed ones are read.
+    pub fn load(model_path: &str) -> Option<Result<Self, AssetError>> {
+        let cache_path = cache_path(model_path);
+        if !vfs::is_up_to_date(model_path, &cache_path) {
+            return None;
+        }
+
+        debug!("Loading mesh cache {}", cache_path);
+
+        let cache = match vfs::disk_path(&cache_path) {
+            Some(disk_path) => File::open(&disk_path)
+                .and_then(|file| unsafe { Mmap::map(&file) })
+                .map_err(|err| AssetError::new(format!("Could not map mesh cache {}: {:?}", cache_path, err)))
+                .and_then(MeshCache::from_map),
+            None => vfs::read(&cache_path)
+                .map_err(|err| AssetError::new(format!("Could not read mesh cache {}: {:?}", cache_path, err)))
+                .and_then(|bytes| MeshCache::from_bytes(&bytes)),
+        };
+
+        Some(cache.map_err(|err| AssetError::new(format!("{}: {}", cache_path, err.message))))
+    }
+
+    /// Writes the cache next to the model. Models that are not files on disk, like packed ones, are not cached.
+    pub fn save(&self, model_path: &str) -> Result<(), AssetError> {
+        let disk_path = match vfs::disk_path(model_path) {
+            Some(disk_path) => PathBuf::from(format!("{}.{}", disk_path.display(), MESH_CACHE_EXT)),
+            None => return Ok(())
+        };
+
+        //NOTE: Replaced with a rename, objects can still have the old cache mapped
+        let temp_path = disk_path.with_extension(format!("{}.tmp", MESH_CACHE_EXT));
+        fs::write(&temp_path, self.to_bytes())
+            .and_then(|_| fs::rename(&temp_path, &disk_path))
+            .map_err(|err| AssetError::new(format!("Could not write mesh cache {}: {:?}", disk_path.display(), err)))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        write_u32(&mut bytes, MESH_CACHE_VERSION);
+
+        let attributes = Vertex::layout().attribute_descriptions();
+        write_u32(&mut bytes, size_of::<Vertex>() as u32);
+        write_u32(&mut bytes, attributes.len() as u32);
+        for attribute in attributes.iter() {
+            write_u32(&mut bytes, attribute.location);
+            write_u32(&mut bytes, attribute.element.format as u32);
+            write_u32(&mut bytes, attribute.element.offset);
+        }
+
+        let (vertices, indices) = (self.mesh.vertices(), self.mesh.indices());
+        write_u32(&mut bytes, vertices.len() as u32);
+        write_u32(&mut bytes, indices.len() as u32);
+
+        for corner in [self.bounds.min, self.bounds.max].iter() {
+            for value in [corner.x, corner.y, corner.z].iter() {
+                write_u32(&mut bytes, value.to_bits());
+            }
+        }
+
+        write_u32(&mut bytes, self.submeshes.len() as u32);
+        for submesh in self.submeshes.iter() {
+            write_u32(&mut bytes, submesh.indices.start);
+            write_u32(&mut bytes, submesh.indices.end);
+            write_u32(&mut bytes, submesh.material.unwrap_or(NO_MATERIAL));
+            write_u32(&mut bytes, submesh.lod);
+        }
+
+        write_u32(&mut bytes, self.material_libraries.len() as u32);
+        for library in self.material_libraries.iter() {
+            write_u32(&mut bytes, library.len() as u32);
+            bytes.extend_from_slice(library.as_bytes());
+        }
+
+        bytes.resize(align(bytes.len()), 0);
+
+        //NOTE: The vertices are written as they are in memory, like the vertex buffer
+        unsafe {
+            bytes.extend_from_slice(slice::from_raw_parts(
+                vertices.as_ptr() as *const u8,
+                size_of_val(vertices)
+            ));
+        }
+
+        for index in indices.iter() {
+            write_u32(&mut bytes, *index);
+        }
+
+        bytes
+    }
+
+    /// Copies the mesh out of the bytes. Fails if the cache was written by another version or for another vertex layout.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AssetError> {
+        let (cache, vertex_bytes, index_bytes) = Self::parse(bytes)?;
+
+        let vertex_count = vertex_bytes.len() / size_of::<Vertex>();
+        let mut vertices = Vec::with_capacity(vertex_count);
+        unsafe {
+            ptr::copy_nonoverlapping(bytes[vertex_bytes].as_ptr(), vertices.as_mut_ptr() as *mut u8, vertex_count * size_of::<Vertex>());
+            vertices.set_len(vertex_count);
+        }
+
+        let indices = bytes[index_bytes]
+            .chunks_exact(size_of::<u32>())
+            .map(|index| u32::from_le_bytes(index.try_into().unwrap()))
+            .collect();
+
+        Ok(MeshCache {
+            mesh: MeshData::Owned { vertices, indices },
+            ..cache
+        })
+    }
+
+    /// Keeps the mesh in the mapped file. It's copied out only if it can't be read in place,
+    /// when the data is not aligned for the vertices or the host is not little endian.
+    pub fn from_map(map: Mmap) -> Result<Self, AssetError> {
+        let (cache, vertices, indices) = Self::parse(&map)?;
+
+        let aligned = |offset: usize, align: usize| (map.as_ptr() as usize + offset).is_multiple_of(align);
+        if !cfg!(target_endian = "little") || !aligned(vertices.start, align_of::<Vertex>()) || !aligned(indices.start, align_of::<u32>()) {
+            return Self::from_bytes(&map);
+        }
+
+        Ok(MeshCache {
+            mesh: MeshData::Mapped { map, vertices, indices },
+            ..cache
+        })
+    }
+
+    /// Checks the header and the indices, returns the cache without a mesh and the byte ranges of the vertices and indices.
+    fn parse(bytes: &[u8]) -> Result<(Self, Range<usize>, Range<usize>), AssetError> {
+        let mut reader = Reader {
+            bytes,
+            offset: 0,
+        };
+
+        if reader.read_bytes(MAGIC.len())? != MAGIC {
+            return Err(AssetError::new("Not a mesh cache".to_string()));
+        }
+
+        let version = reader.read_u32()?;
+        if version != MESH_CACHE_VERSION {
+            return Err(AssetError::new(format!("Mesh cache version {} is not {}", version, MESH_CACHE_VERSION)));
+        }
+
+        let attributes = Vertex::layout().attribute_descriptions();
+        let stride = reader.read_u32()?;
+        let attribute_count = reader.read_u32()?;
+
+        let mut layout_matches = stride == size_of::<Vertex>() as u32 && attribute_count == attributes.len() as u32;
+        for _ in 0..attribute_count {
+            let (location, format, offset) = (reader.read_u32()?, reader.read_u32()?, reader.read_u32()?);
+
+            layout_matches &= attributes.iter().any(|attribute| attribute.location == location
+                && attribute.element.format as u32 == format
+                && attribute.element.offset == offset);
+        }
+
+        if !layout_matches {
+            return Err(AssetError::new("Mesh cache has a different vertex layout".to_string()));
+        }
+
+        let vertex_count = reader.read_u32()? as usize;
+        let index_count = reader.read_u32()? as usize;
+
+        let mut bounds = [0.0; 6];
+        for value in bounds.iter_mut() {
+            *value = reader.read_f32()?;
+        }
+
+        let submesh_count = reader.read_u32()?;
+        let submeshes = (0..submesh_count)
+            .map(|_| {
+                let indices = reader.read_u32()?..reader.read_u32()?;
+                let material = Some(reader.read_u32()?).filter(|material| *material != NO_MATERIAL);
+                let lod = reader.read_u32()?;
+
+                if indices.start > indices.end || indices.end as usize > index_count {
+                    return Err(AssetError::new("Mesh cache has a submesh out of range".to_string()));
+                }
+
+                Ok(CachedSubMesh {
+                    indices,
+                    material,
+                    lod,
+                })
+            })
+            .collect::<Result<Vec<_>, AssetError>>()?;
+
+        let library_count = reader.read_u32()?;
+        let material_libraries = (0..library_count)
+            .map(|_| {
+                let len = reader.read_u32()? as usize;
+                String::from_utf8(reader.read_bytes(len)?.to_vec())
+                    .map_err(|_| AssetError::new("Mesh cache has an invalid material library".to_string()))
+            })
+            .collect::<Result<Vec<_>, AssetError>>()?;
+
+        reader.offset = align(reader.offset);
+
+        let vertex_start = reader.offset;
+        reader.read_bytes(vertex_count * size_of::<Vertex>())?;
+        let index_start = reader.offset;
+
+        let out_of_range = reader.read_bytes(index_count * size_of::<u32>())?
+            .chunks_exact(size_of::<u32>())
+            .any(|index| u32::from_le_bytes(index.try_into().unwrap()) as usize >= vertex_count);
+
+        if out_of_range {
+            return Err(AssetError::new("Mesh cache has an index out of range".to_string()));
+        }
+
+        let cache = MeshCache {
+            mesh: MeshData::Owned {
+                vertices: Vec::new(),
+                indices: Vec::new(),
+            },
+            bounds: Aabb::new(
+                Vector3::new(bounds[0], bounds[1], bounds[2]),
+                Vector3::new(bounds[3], bounds[4], bounds[5]),
+            ),
+            submeshes,
+            material_libraries,
+        };
+
+        Ok((cache, vertex_start..index_start, index_start..reader.offset))
+    }
+}
+
+pub fn cache_path(model_path: &str) -> String {
+    format!("{}.{}", model_path, MESH_CACHE_EXT)
+}
+
+/// The caller has to make sure the bytes are aligned for `T` and hold whole values.
+unsafe fn cast_slice<T>(bytes: &[u8]) -> &[T] {
+    slice::from_raw_parts(bytes.as_ptr() as *const T, bytes.len() / size_of::<T>())
+}
+
+fn align(offset: usize) -> usize {
+    (offset + DATA_ALIGNMENT - 1) & !(DATA_ALIGNMENT - 1)
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], AssetError> {
+        let bytes = self.bytes.get(self.offset..self.offset + len)
+            .ok_or_else(|| AssetError::new("Mesh cache is truncated".to_string()))?;
+
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, AssetError> {
+        self.read_bytes(size_of::<u32>())
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_f32(&mut self) -> Result<f32, AssetError> {
+        self.read_u32()
+            .map(f32::from_bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CachedSubMesh, MeshCache, MeshData
+    };
+
+    use crate::model::Vertex;
+
+    use memmap2::Mmap;
+
+    use zeus_core::math::{
+        Aabb, Vector2, Vector3, Vector4
+    };
+
+    use std::{
+        env, fs::{
+            self, File
+        }
+    };
+
+    fn mesh() -> MeshCache {
+        let vertices: Vec<Vertex> = (0..4)
+            .map(|i| Vertex {
+                a_pos: Vector3::new(i as f32, (i % 2) as f32, -1.0),
+                a_color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+                a_uv: Vector2::new(0.5, i as f32),
+                a_normal: Vector3::new(0.0, 0.0, 1.0),
+                a_tangent: Vector4::new(1.0, 0.0, 0.0, -1.0),
+                a_joints: Vector4::new(0.0, 1.0, 0.0, 0.0),
+                a_weights: Vector4::new(0.25, 0.75, 0.0, 0.0),
+            })
+            .collect();
+
+        MeshCache {
+            bounds: Aabb::from_points(vertices.iter().map(|vertex| vertex.a_pos)),
+            mesh: MeshData::Owned {
+                vertices,
+                indices: vec![0, 1, 2, 2, 3, 0],
+            },
+            submeshes: vec![
+                CachedSubMesh {
+                    indices: 0..3,
+                    material: Some(1),
+                    lod: 0,
+                },
+                CachedSubMesh {
+                    indices: 3..6,
+                    material: None,
+                    lod: 1,
+                },
+            ],
+            material_libraries: vec!["room.mtl".to_string()],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mesh = mesh();
+
+        assert_eq!(MeshCache::from_bytes(&mesh.to_bytes()).unwrap(), mesh);
+    }
+
+    #[test]
+    fn reads_mapped_mesh_in_place() {
+        let mesh = mesh();
+        let path = env::temp_dir().join(format!("zeus_mesh_cache_{}.zmesh", std::process::id()));
+        fs::write(&path, mesh.to_bytes()).unwrap();
+
+        let map = unsafe { Mmap::map(&File::open(&path).unwrap()) }.unwrap();
+        let mapped = MeshCache::from_map(map).unwrap();
+
+        assert!(matches!(mapped.mesh, MeshData::Mapped { .. }));
+        assert_eq!(mapped, mesh);
+
+        drop(mapped);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reject_other_caches() {
+        let bytes = mesh().to_bytes();
+
+        let mut version = bytes.clone();
+        version[4] += 1;
+        assert!(MeshCache::from_bytes(&version).is_err());
+
+        //The stride of the vertices
+        let mut layout = bytes.clone();
+        layout[8] += 4;
+        assert!(MeshCache::from_bytes(&layout).is_err());
+
+        assert!(MeshCache::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(MeshCache::from_bytes(b"ZMS").is_err());
+    }
+}
@@ -2,21 +2,22 @@ use super::{
     buffer::BufferState,
     desc::{DescSet, DescSetWrite},
     device::DeviceState,
+    vertex_layout::{
+        VertexLayout, VertexSemantic, VertexStream
+    },
 };
 use gfx_hal::{
-    adapter::MemoryType,
     buffer::{ SubRange, Usage},
     format::Format,
     memory::Properties,
     pso::{
-        AttributeDesc, Descriptor, Element, VertexBufferDesc, VertexInputRate,
+        Descriptor, VertexInputRate,
     },
     Backend,
 };
 use std::{
     cell::RefCell,
     cmp::Ordering,
-    mem::size_of,
     rc::Rc
 };
 
@@ -40,17 +41,16 @@ pub enum Color {
     Alpha,
 }
 
+/// A uniform buffer written to a binding of a descriptor set, the set is owned by the caller.
 pub struct Uniform<B: Backend> {
     pub buffer: Option<BufferState<B>>,
-    pub desc: Option<DescSet<B>>,
 }
 
 impl<B: Backend> Uniform<B> {
     pub fn new<T>(
         device: Rc<RefCell<DeviceState<B>>>,
-        memory_types: &[MemoryType],
         data: &[T],
-        mut desc: DescSet<B>,
+        desc: &mut DescSet<B>,
         binding: u32,
     ) -> Self
     where
@@ -60,7 +60,6 @@ impl<B: Backend> Uniform<B> {
             Rc::clone(&device),
             &data,
             Usage::UNIFORM,
-            memory_types,
             Properties::CPU_VISIBLE | Properties::COHERENT,
         );
         let buffer = Some(buffer);
@@ -82,63 +81,102 @@ impl<B: Backend> Uniform<B> {
 
         Uniform {
             buffer,
-            desc: Some(desc),
         }
     }
-
-    pub fn get_layout(&self) -> &B::DescriptorSetLayout {
-        self.desc.as_ref()
-            .unwrap().get_layout()
-    }
 }
 
 //TODO: make a_uv a vector3. why? 3d models?
 //TODO: add texCoord
+//NOTE: The attribute offsets depend on the field order
+#[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct Vertex {
     pub a_pos: Vector3,
     pub a_color: Vector4,
     pub a_uv: Vector2,
+    pub a_normal: Vector3,
+    /// w is the handedness of the bitangent, see tangent.rs
+    pub a_tangent: Vector4,
+    /// Indices into the joint palette of the object, as floats
+    pub a_joints: Vector4,
+    /// All zero for vertices that are not skinned
+    pub a_weights: Vector4,
 }
 
 impl Vertex {
-    pub fn get_vertex_buffer_description() -> [VertexBufferDesc; 1] {
-        [
-            VertexBufferDesc {
-                binding: 0,
-                stride: size_of::<Self>() as u32,
-                rate: VertexInputRate::Vertex,
-            }
-        ]
+    /// Every attribute of the vertex, interleaved in one buffer as it is in memory.
+    pub fn layout() -> VertexLayout {
+        VertexLayout::new(vec![
+            VertexStream::packed(VertexInputRate::Vertex, &[
+                (VertexSemantic::Position, Format::Rgb32Sfloat),
+                (VertexSemantic::Color, Format::Rgba32Sfloat),
+                (VertexSemantic::TexCoord, Format::Rg32Sfloat),
+                (VertexSemantic::Normal, Format::Rgb32Sfloat),
+                (VertexSemantic::Tangent, Format::Rgba32Sfloat),
+                (VertexSemantic::Joints, Format::Rgba32Sfloat),
+                (VertexSemantic::Weights, Format::Rgba32Sfloat),
+            ])
+        ]).expect("The vertex layout is invalid")
     }
 
-    pub fn get_attribute_description() -> [AttributeDesc; 3] {
-        [
-            AttributeDesc {
-                binding: 0,
-                location: 0,
-                element: Element {
-                    format: Format::Rgb32Sfloat,
-                    offset: 0,
-                }
-            },
-            AttributeDesc {
-                binding: 0,
-                location: 1,
-                element: Element {
-                    format: Format::Rgba32Sfloat,
-                    offset: 12,
-                }
-            },
-            AttributeDesc {
-                binding: 0,
-                location: 2,
-                element: Element {
-                    format: Format::Rg32Sfloat,
-                    offset: 28,
-                }
+    /// The attribute of the semantic, padded to 4 components. Vertices have no instance data.
+    pub fn attribute(&self, semantic: VertexSemantic, row: u32) -> [f32; 4] {
+        match semantic {
+            VertexSemantic::Position => [self.a_pos.x, self.a_pos.y, self.a_pos.z, 1.0],
+            VertexSemantic::Color => [self.a_color.x, self.a_color.y, self.a_color.z, self.a_color.w],
+            VertexSemantic::TexCoord => [self.a_uv.x, self.a_uv.y, 0.0, 0.0],
+            VertexSemantic::Normal => [self.a_normal.x, self.a_normal.y, self.a_normal.z, 0.0],
+            VertexSemantic::Tangent => [self.a_tangent.x, self.a_tangent.y, self.a_tangent.z, self.a_tangent.w],
+            VertexSemantic::Joints => [self.a_joints.x, self.a_joints.y, self.a_joints.z, self.a_joints.w],
+            VertexSemantic::Weights => [self.a_weights.x, self.a_weights.y, self.a_weights.z, self.a_weights.w],
+            _ => semantic.default_value(row)
+        }
+    }
+}
+
+/// One copy of a mesh, the instance stream of an object holds them as they are in memory.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Instance {
+    /// Applied to the vertices before the camera, the lights are in the same space
+    pub transform: Matrix4,
+    /// Multiplies the color of the vertices
+    pub color: Vector4,
+}
+
+impl Instance {
+    pub fn new(transform: Matrix4, color: Vector4) -> Self {
+        Instance {
+            transform,
+            color,
+        }
+    }
+
+    /// The stream objects draw their instances from, after the streams of the mesh.
+    pub fn stream() -> VertexStream {
+        VertexStream::packed(VertexInputRate::Instance(1), &[
+            (VertexSemantic::InstanceTransform, Format::Rgba32Sfloat),
+            (VertexSemantic::InstanceColor, Format::Rgba32Sfloat),
+        ])
+    }
+
+    /// The rows of the transform are the locations of the matrix, like the camera matrices shaders multiply from the left.
+    #[allow(dead_code)]
+    pub fn attribute(&self, semantic: VertexSemantic, row: u32) -> [f32; 4] {
+        match semantic {
+            VertexSemantic::InstanceTransform => {
+                let row = row as usize * 4;
+                [self.transform[row], self.transform[row + 1], self.transform[row + 2], self.transform[row + 3]]
             },
-        ]
+            VertexSemantic::InstanceColor => [self.color.x, self.color.y, self.color.z, self.color.w],
+            _ => semantic.default_value(row)
+        }
+    }
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Instance::new(Matrix4::new(), Vector4::new(1.0, 1.0, 1.0, 1.0))
     }
 }
 
@@ -166,7 +204,8 @@ impl Eq for Vertex {}
 
 impl PartialEq for Vertex {
     fn eq(&self, other: &Self) -> bool {
-        self.a_pos == other.a_pos && self.a_color == other.a_color && self.a_uv == other.a_uv
+        self.a_pos == other.a_pos && self.a_color == other.a_color && self.a_uv == other.a_uv && self.a_normal == other.a_normal && self.a_tangent == other.a_tangent
+            && self.a_joints == other.a_joints && self.a_weights == other.a_weights
     }
 }
 
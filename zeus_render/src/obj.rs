@@ -1,150 +1,156 @@
 use gfx_hal::{
     buffer::{
-        IndexBufferView, Usage, SubRange
+        IndexBufferView, SubRange
     },
     command::CommandBuffer,
     device::Device,
     pool::CommandPoolCreateFlags,
-    pso::{
-        ColorValue, DescriptorPoolCreateFlags, DescriptorRangeDesc, DescriptorSetLayoutBinding,DescriptorType, ShaderStageFlags, ImageDescriptorType, BufferDescriptorType, BufferDescriptorFormat
-    },
     Backend, IndexType,
 };
 
 use zeus_core::{
     math::{
+        Aabb,
+        BoundingSphere,
+        Matrix4,
         Vector2,
         Vector3,
         Vector4
     }, 
-    time::Stopwatch
+    time::Stopwatch,
+    vfs
 };
 
 use super::{
-    adapter::AdapterState,
+    animation::{
+        AnimationClip, AnimationPlayer, Skeleton
+    },
     buffer::BufferState,
-    desc::DescSetLayout,
+    constants::{
+        LOD_COUNT, LOD_ERROR, LOD_REDUCTION, WELD_EPSILON
+    },
     device::DeviceState,
-    image::ImageState,
+    error::AssetError,
+    gltf_import::GltfModel,
+    material::MaterialDesc,
+    mesh_cache::{
+        CachedSubMesh, MeshCache, MeshData
+    },
+    mesh::{
+        self, generate_smooth_normals, weld_vertices
+    },
     model::{
-        Color,
-        Uniform,
-        Vertex
+        Instance, Vertex
     },
+    tangent::generate_tangents,
+    vertex_layout::VertexLayout,
 };
 
 use tobj;
 
 use std::{
     cell::RefCell,
-    collections::BTreeMap,
+    io::Cursor,
+    ops::Range,
+    path::Path,
     rc::Rc
 };
 
-//TODO: Should separate to Geometry, Material, Texture
-//TODO: Should create pipeline as well
+/// A range of the indices drawn with one material, `None` uses the material of the object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubMesh {
+    pub indices: Range<u32>,
+    pub material: Option<String>,
+    /// 0 is the full detail mesh, see mesh::generate_lods
+    pub lod: usize,
+}
+
+/// A run of the instances of an object that is culled and picks its LOD on its own, drawn from the same buffers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceBatch {
+    pub instances: Range<u32>,
+    /// The bounds of the instances, in the space of the lights
+    pub bounds: Aabb,
+    pub sphere: BoundingSphere,
+    /// 0 is the full detail mesh, see mesh::generate_lods
+    pub lod: usize,
+}
+
+/// The geometry of a model file, with the indices grouped by material, and its materials.
+pub struct ModelData {
+    pub mesh: MeshData,
+    pub bounds: Aabb,
+    pub submeshes: Vec<SubMesh>,
+    pub materials: Vec<MaterialDesc>,
+    /// The skin the joints of the vertices point into, with the clips that animate it
+    pub skeleton: Option<Skeleton>,
+    pub clips: Vec<AnimationClip>,
+}
+
+//TODO: Should separate to Geometry and Transform
 pub struct RenderObject<B: Backend> {
     device: Rc<RefCell<DeviceState<B>>>,
-    color_desc_pool: Option<B::DescriptorPool>,
-    texture_desc_pool: Option<B::DescriptorPool>,
-    //
-    
-    pub vertices: Vec<Vertex>,
-    pub indices: Vec<u32>,
-    color: ColorValue,
+    /// Kept on the CPU for the occluders and layout changes, mapped when it comes from a mesh cache
+    mesh: MeshData,
+    bounds: Aabb,
+    bounding_sphere: BoundingSphere,
+    material: String,
+    submeshes: Vec<SubMesh>,
+    model_materials: Vec<MaterialDesc>,
+    model_path: Option<String>,
+    skeleton: Option<Skeleton>,
+    clips: Vec<AnimationClip>,
+    player: AnimationPlayer,
+    joint_offset: Option<u32>,
+    /// The streams of the mesh, with the instance stream last
+    vertex_layout: VertexLayout,
+    instances: Vec<Instance>,
+    instances_bounds: Aabb,
+    instances_sphere: BoundingSphere,
+    instances_changed: bool,
+    batches: Vec<InstanceBatch>,
+    occluder: bool,
     //
-    image: Option<ImageState<B>>,
-    color_uniform: Uniform<B>,
-    vertex_buffer: BufferState<B>,
+    vertex_buffers: Vec<BufferState<B>>,
+    instance_buffer: Option<BufferState<B>>,
     index_buffer: Option<BufferState<B>>,
 }
 
-//TODO: add Some(Texture)
-//Texture = image path and type
 impl<B: Backend> RenderObject<B> {
     pub fn new_from_vertices(
         device: Rc<RefCell<DeviceState<B>>>,
-        adapter: &AdapterState<B>,
-        texture_path: &str,
+        material: &str,
         vertices: &[Vertex],
         indices: &[u32],
     ) -> Self {
-        let texture_desc = DescSetLayout::new(
-            Rc::clone(&device),
-            vec![
-                DescriptorSetLayoutBinding {
-                    binding: 0,
-                    ty: DescriptorType::Image {
-                        ty: ImageDescriptorType::Sampled {
-                            with_sampler: false
-                        }
-                    },
-                    count: 1,
-                    stage_flags: ShaderStageFlags::FRAGMENT,
-                    immutable_samplers: false,
-                },
-                DescriptorSetLayoutBinding {
-                    binding: 1,
-                    ty: DescriptorType::Sampler,
-                    count: 1,
-                    stage_flags: ShaderStageFlags::FRAGMENT,
-                    immutable_samplers: false,
-                },
-            ],
-        );
+        Self::new_with_layout(device, material, vertices, indices, Vertex::layout())
+    }
 
-        let color_desc = DescSetLayout::new(
-            Rc::clone(&device),
-            vec![DescriptorSetLayoutBinding {
-                binding: 0,
-                ty: DescriptorType::Buffer {
-                    ty: BufferDescriptorType::Uniform,
-                    format: BufferDescriptorFormat::Structured {
-                        dynamic_offset: false
-                    }
-                },
-                count: 1,
-                stage_flags: ShaderStageFlags::FRAGMENT,
-                immutable_samplers: false,
-            }],
-        );
+    /// Uploads only the attributes of the layout, in its formats. The instance stream is added to the layout,
+    /// the object starts with one instance that doesn't move it.
+    pub fn new_with_layout(
+        device: Rc<RefCell<DeviceState<B>>>,
+        material: &str,
+        vertices: &[Vertex],
+        indices: &[u32],
+        vertex_layout: VertexLayout,
+    ) -> Self {
+        let mesh = MeshData::Owned {
+            vertices: vertices.to_vec(),
+            indices: indices.to_vec(),
+        };
 
-        let mut texture_desc_pool = unsafe {
-            device.borrow().device.create_descriptor_pool(
-                1, //Number of sets
-                &[
-                    DescriptorRangeDesc {
-                        ty: DescriptorType::Image {
-                            ty: ImageDescriptorType::Sampled {
-                                with_sampler: false
-                            }
-                        },
-                        count: 1,
-                    },
-                    DescriptorRangeDesc {
-                        ty: DescriptorType::Sampler,
-                        count: 1,
-                    },
-                ],
-                DescriptorPoolCreateFlags::empty(),
-            )
-        }.ok();
-
-        let mut color_desc_pool = unsafe {
-            device.borrow().device.create_descriptor_pool(
-                1,
-                &[DescriptorRangeDesc {
-                    ty: DescriptorType::Buffer {
-                        ty: BufferDescriptorType::Uniform,
-                        format: BufferDescriptorFormat::Structured {
-                            dynamic_offset: false
-                        }
-                    },
-                    count: 1,
-                }],
-                DescriptorPoolCreateFlags::empty(),
-            )
-        }.ok();
+        Self::new_with_mesh(device, material, mesh, vertex_layout)
+    }
+
+    /// The mesh is uploaded as it is, a mapped mesh cache is not copied.
+    fn new_with_mesh(
+        device: Rc<RefCell<DeviceState<B>>>,
+        material: &str,
+        mesh: MeshData,
+        vertex_layout: VertexLayout,
+    ) -> Self {
+        let (vertices, indices) = (mesh.vertices(), mesh.indices());
 
         let mut staging_pool = unsafe {
             device.borrow().device.create_command_pool(
@@ -153,98 +159,259 @@ impl<B: Backend> RenderObject<B> {
             )
         }.expect("Can't create Command Pool");
 
-        let texture_desc = texture_desc.create_desc_set(texture_desc_pool.as_mut().unwrap());
-        let color_desc = color_desc.create_desc_set(color_desc_pool.as_mut().unwrap());
-
-        let image = ImageState::new_texture(
-            texture_desc,
-            texture_path,
-            &adapter,
-            Usage::TRANSFER_SRC,
-            &mut device.borrow_mut(),
-            &mut staging_pool,
-        );
+        let vertex_layout = instanced_layout(vertex_layout);
+        let vertex_buffers = create_vertex_buffers(&device, &vertex_layout, vertices, &mut staging_pool);
 
-        let vertex_buffer = BufferState::new_vertex_buffer(
-            Rc::clone(&device),
-            &vertices,
-            &adapter.memory_types,
-            &mut staging_pool,
-        );
+        let instances = vec![Instance::default()];
+        let instance_buffer = create_instance_buffer(&device, &instances, &mut staging_pool);
 
         let index_buffer = if !indices.is_empty() {
             Some(BufferState::new_index_buffer(
                 Rc::clone(&device),
-                &indices,
-                &adapter.memory_types,
+                indices,
                 &mut staging_pool,
             ))
         } else {
             None
         };
 
-        let color_uniform = Uniform::new(
-            Rc::clone(&device),
-            &adapter.memory_types,
-            &[1.0_f32, 1.0_f32, 1.0_f32, 1.0_f32],
-            color_desc,
-            0,
-        );
-
-        //TODO: could save time by making it async
-        //Should save the cmd_pool and destroy once done.
-        image.wait_for_transfer_completion();
-
         unsafe {
             device.borrow().device
                 .destroy_command_pool(staging_pool);
         }
 
+        let (bounds, bounding_sphere) = mesh::bounding_volumes(vertices);
+        let index_count = indices.len() as u32;
+
         RenderObject {
             device,
-            color_desc_pool,
-            texture_desc_pool,
-            //
-            vertices: vertices.to_vec(),
-            indices: if !indices.is_empty() {
-                indices.to_vec()
-            } else {
-                Vec::new()
-            },
-            color: [1.0, 0.1, 0.1, 1.0],
+            mesh,
+            bounds,
+            bounding_sphere,
+            material: material.to_string(),
+            submeshes: vec![SubMesh {
+                indices: 0..index_count,
+                material: None,
+                lod: 0,
+            }],
+            model_materials: Vec::new(),
+            model_path: None,
+            skeleton: None,
+            clips: Vec::new(),
+            player: AnimationPlayer::new(),
+            joint_offset: None,
+            vertex_layout,
+            instances,
+            instances_bounds: bounds,
+            instances_sphere: bounding_sphere,
+            instances_changed: false,
+            batches: vec![InstanceBatch {
+                instances: 0..1,
+                bounds,
+                sphere: bounding_sphere,
+                lod: 0,
+            }],
+            occluder: false,
             //
-            image: Some(image),
-            color_uniform,
-            vertex_buffer,
+            vertex_buffers,
+            instance_buffer,
             index_buffer,
         }
     }
 
+    /// The parts of the model with a material of its own are drawn with it, `material` is used for the rest.
     pub fn new_from_model(
         device: Rc<RefCell<DeviceState<B>>>,
-        adapter: &AdapterState<B>,
         model_path: &str,
-        texture_path: &str
+        material: &str
     ) -> Self {
+        let model = Self::load_model(model_path)
+            .expect("Could not load model");
+
+        let mut object = Self::new_with_mesh(
+            device,
+            material,
+            model.mesh,
+            Vertex::layout()
+        );
+
+        object.submeshes = model.submeshes;
+        object.model_materials = model.materials;
+        object.model_path = Some(model_path.to_string());
+        object.set_skeleton(model.skeleton, model.clips);
+
+        object
+    }
+
+    /// Loads an OBJ or glTF file, picked by the extension.
+    pub fn load_model(model_path: &str) -> Result<ModelData, AssetError> {
+        let extension = Path::new(model_path).extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+
+        match extension.as_deref() {
+            Some("gltf") | Some("glb") => Self::load_gltf(model_path),
+            _ => Self::load_obj(model_path),
+        }
+    }
+
+    /// The scene of a glTF file flattened into one mesh, the nodes are baked into the vertices.
+    fn load_gltf(model_path: &str) -> Result<ModelData, AssetError> {
         let mut timer = Stopwatch::new();
 
-        let obj = tobj::load_obj(model_path, false);
+        let model = GltfModel::load(model_path)?;
+        let (mut vertices, groups) = model.flatten();
 
-        debug!("Loaded file in {} ms", timer.get_current_delta());
+        if vertices.is_empty() {
+            return Err(AssetError::new(format!("Model {} has no vertices", model_path)));
+        }
+
+        //NOTE: The joints of the vertices can only point into one skin
+        let mut skins = model.nodes.iter().filter_map(|node| node.mesh.and(node.skin));
+        let skeleton = skins.next().map(|skin| {
+            if skins.any(|other| other != skin) {
+                warn!("Model {} has more than one skin, only the first one is animated", model_path);
+            }
+
+            Skeleton::from_gltf(&model, skin)
+        });
+
+        let clips = match &skeleton {
+            Some(skeleton) => model.animations.iter()
+                .map(|animation| AnimationClip::from_gltf(animation, skeleton))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let (indices, ranges) = group_by_material(groups);
+        let (indices, ranges) = optimize_ranges(&mut vertices, &indices, ranges);
+        let submeshes = ranges.into_iter()
+            .map(|(lod, material_id, indices)| SubMesh {
+                indices,
+                material: material_id.map(|id| model_material_name(model_path, &model.materials[id].name)),
+                lod,
+            })
+            .collect();
 
-        //TODO: return an error
-        if let Err(err) = obj {
-            info!("{:?}", err);
+        let materials = model.materials.into_iter()
+            .map(|mut desc| {
+                desc.name = model_material_name(model_path, &desc.name);
+                desc
+            })
+            .collect();
+
+        timer.update_time();
+
+        info!("Loaded Model with {} vertices and {} indices in {} ms", vertices.len(), indices.len(), timer.get_delta());
+
+        Ok(ModelData {
+            bounds: Aabb::from_points(vertices.iter().map(|vertex| vertex.a_pos)),
+            mesh: MeshData::Owned { vertices, indices },
+            submeshes,
+            materials,
+            skeleton,
+            clips,
+        })
+    }
+
+    /// Loads an OBJ file from its mesh cache, the file is imported again when the cache is missing or older.
+    /// The materials are always read from the material libraries.
+    fn load_obj(model_path: &str) -> Result<ModelData, AssetError> {
+        let mut timer = Stopwatch::new();
+
+        let mesh = match MeshCache::load(model_path) {
+            Some(Ok(mesh)) => mesh,
+            cache => {
+                if let Some(Err(err)) = cache {
+                    warn!("Could not load the mesh cache, importing the model again: {}", err.message);
+                }
+
+                let mesh = Self::import_obj(model_path)?;
+                if let Err(err) = mesh.save(model_path) {
+                    warn!("{}", err.message);
+                }
+
+                mesh
+            }
+        };
+
+        let model_dir = Path::new(model_path).parent().unwrap_or_else(|| Path::new(""));
+        let mut mtl_materials = Vec::new();
+        for library in mesh.material_libraries.iter() {
+            let library_path = model_dir.join(library).to_string_lossy().to_string();
+            let mtl_bytes = vfs::read(&library_path)
+                .map_err(|err| AssetError::new(format!("Could not read material library {}: {:?}", library_path, err)))?;
+
+            let (materials, _) = tobj::load_mtl_buf(&mut Cursor::new(mtl_bytes))
+                .map_err(|err| AssetError::new(format!("Could not load material library {}: {:?}", library_path, err)))?;
+            mtl_materials.extend(materials);
         }
 
-        let (models, _materials) = obj.unwrap();
+        //NOTE: Materials removed from the libraries since the import fall back to the material of the object
+        let submeshes = mesh.submeshes.into_iter()
+            .map(|submesh| SubMesh {
+                indices: submesh.indices,
+                lod: submesh.lod as usize,
+                material: submesh.material
+                    .and_then(|id| mtl_materials.get(id as usize))
+                    .map(|material| model_material_name(model_path, &material.name)),
+            })
+            .collect();
+
+        let model_dir = model_dir.to_string_lossy();
+        let materials = mtl_materials.iter()
+            .map(|material| {
+                let mut desc = MaterialDesc::from_mtl(material, &model_dir);
+                desc.name = model_material_name(model_path, &material.name);
+                desc
+            })
+            .collect();
+
+        timer.update_time();
+
+        info!("Loaded Model with {} vertices and {} indices in {} ms", mesh.mesh.vertices().len(), mesh.mesh.indices().len(), timer.get_delta());
+
+        Ok(ModelData {
+            mesh: mesh.mesh,
+            bounds: mesh.bounds,
+            submeshes,
+            materials,
+            skeleton: None,
+            clips: Vec::new(),
+        })
+    }
+
+    /// Parses an OBJ file into deduplicated vertices and indices, grouped by material.
+    fn import_obj(model_path: &str) -> Result<MeshCache, AssetError> {
+        let mut timer = Stopwatch::new();
+
+        let model_bytes = vfs::read(model_path)
+            .map_err(|err| AssetError::new(format!("Could not read model {}: {:?}", model_path, err)))?;
 
-        let mut vertices: Vec<Vertex> = vec![];
-        let mut indices: Vec<u32> = vec![];
-        let mut unique_vertex_map: BTreeMap<Vertex, u32> = BTreeMap::new();
+        //NOTE: Material libraries are resolved relative to the model, through the vfs as well
+        let model_dir = Path::new(model_path).parent().unwrap_or_else(|| Path::new(""));
+        let material_libraries = RefCell::new(Vec::new());
+        let (models, mtl_materials) = tobj::load_obj_buf(&mut Cursor::new(model_bytes), false, |mtl_path| {
+            material_libraries.borrow_mut().push(mtl_path.to_string_lossy().to_string());
+
+            let mtl_bytes = vfs::read(&model_dir.join(mtl_path).to_string_lossy())
+                .map_err(|_| tobj::LoadError::OpenFileFailed)?;
+
+            tobj::load_mtl_buf(&mut Cursor::new(mtl_bytes))
+        })
+            .map_err(|err| AssetError::new(format!("Could not load model {}: {:?}", model_path, err)))?;
+
+        debug!("Loaded file in {} ms", timer.get_current_delta());
+
+        //NOTE: Every corner gets a vertex, the identical ones are welded afterwards
+        let mut corners: Vec<Vertex> = vec![];
+        let mut groups: Vec<MaterialGroup> = vec![];
 
         for (_i, m) in models.iter().enumerate() {
             let mesh = &m.mesh;
+
+            //Ids without a material in the library fall back to the material of the object
+            let material_id = mesh.material_id.filter(|id| *id < mtl_materials.len());
+            let mut indices = Vec::with_capacity(mesh.indices.len());
             
             for (_j, idx) in mesh.indices.iter().enumerate() {
                 let index = *idx as usize;
@@ -261,111 +428,465 @@ impl<B: Backend> RenderObject<B> {
                         z: 1.0,
                         w: 1.0
                     },
-                    a_uv: Vector2 {
-                        x: mesh.texcoords[index * 2],
-                        y: 1.0 - mesh.texcoords[index * 2 + 1]
+                    a_uv: if mesh.texcoords.is_empty() {
+                        Vector2 {
+                            x: 0.0,
+                            y: 0.0
+                        }
+                    } else {
+                        Vector2 {
+                            x: mesh.texcoords[index * 2],
+                            y: 1.0 - mesh.texcoords[index * 2 + 1]
+                        }
+                    },
+                    //Missing normals are generated once the mesh is deduplicated
+                    a_normal: if mesh.normals.is_empty() {
+                        Vector3 {
+                            x: 0.0,
+                            y: 0.0,
+                            z: 0.0
+                        }
+                    } else {
+                        Vector3 {
+                            x: mesh.normals[index * 3],
+                            y: mesh.normals[index * 3 + 1],
+                            z: mesh.normals[index * 3 + 2]
+                        }
+                    },
+                    //OBJ files have no tangents, they are generated after the normals
+                    a_tangent: Vector4 {
+                        x: 0.0,
+                        y: 0.0,
+                        z: 0.0,
+                        w: 0.0
+                    },
+                    //OBJ files have no skins
+                    a_joints: Vector4 {
+                        x: 0.0,
+                        y: 0.0,
+                        z: 0.0,
+                        w: 0.0
+                    },
+                    a_weights: Vector4 {
+                        x: 0.0,
+                        y: 0.0,
+                        z: 0.0,
+                        w: 0.0
                     }
                 };
 
-                if unique_vertex_map.get(&vertex).is_none() {
-                    unique_vertex_map.insert(vertex, vertices.len() as u32);
-                    vertices.push(vertex);
-                }
-
-                indices.push(*unique_vertex_map.get(&vertex).unwrap());
+                indices.push(corners.len() as u32);
+                corners.push(vertex);
             }
+
+            groups.push((material_id, indices));
         }
-        
+
+        let (mut indices, ranges) = group_by_material(groups);
+        let (mut vertices, welded_indices) = weld_vertices(&corners, &indices, WELD_EPSILON);
+        indices = welded_indices;
+
+        if vertices.is_empty() {
+            return Err(AssetError::new(format!("Model {} has no vertices", model_path)));
+        }
+
+        if models.iter().any(|model| model.mesh.normals.is_empty()) {
+            debug!("Model {} has no normals, generating them", model_path);
+            generate_smooth_normals(&mut vertices, &indices);
+        }
+
+        generate_tangents(&mut vertices, &mut indices);
+
+        let (indices, ranges) = optimize_ranges(&mut vertices, &indices, ranges);
+        let submeshes = ranges.into_iter()
+            .map(|(lod, material_id, indices)| CachedSubMesh {
+                indices,
+                material: material_id.map(|id| id as u32),
+                lod: lod as u32,
+            })
+            .collect();
+
         timer.update_time();
 
-        info!("Loaded Model with {} vertices and {} indices in {} ms", vertices.len(), indices.len(), timer.get_delta());
+        info!("Imported Model with {} vertices and {} indices in {} ms", vertices.len(), indices.len(), timer.get_delta());
 
-        Self::new_from_vertices(
-            device,
-            &adapter,
-            texture_path,
-            &vertices,
-            &indices
-        )
+        Ok(MeshCache {
+            bounds: Aabb::from_points(vertices.iter().map(|vertex| vertex.a_pos)),
+            mesh: MeshData::Owned { vertices, indices },
+            submeshes,
+            material_libraries: material_libraries.into_inner(),
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn get_material(&self) -> &str {
+        &self.material
+    }
+
+    #[allow(dead_code)]
+    pub fn set_material(&mut self, material: &str) {
+        self.material = material.to_string();
+    }
+
+    pub fn vertices(&self) -> &[Vertex] {
+        self.mesh.vertices()
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        self.mesh.indices()
     }
 
+    /// The bounds of the vertices, in the space of the model.
     #[allow(dead_code)]
-    pub fn get_color(&self) -> ColorValue {
-        self.color
-    }
-
-    pub fn update_color(
-        &mut self,
-        color: &Color,
-        value: f32,
-    ) {
-        //Update color state
-        match color {
-            Color::Red => self.color[0] = value / 255.0,
-            Color::Green => self.color[1] = value / 255.0,
-            Color::Blue => self.color[2] = value / 255.0,
-            Color::Alpha => self.color[3] = value / 255.0,
+    pub fn get_bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// The bounding sphere of the vertices, in the space of the model.
+    #[allow(dead_code)]
+    pub fn get_bounding_sphere(&self) -> BoundingSphere {
+        self.bounding_sphere
+    }
+
+    /// The bounding sphere of every instance, in the space of the lights.
+    pub fn get_instances_bounding_sphere(&self) -> BoundingSphere {
+        self.instances_sphere
+    }
+
+    /// The bounds of every instance, in the space of the lights. Empty when the object has no instances.
+    #[allow(dead_code)]
+    pub fn get_instances_bounds(&self) -> Aabb {
+        self.instances_bounds
+    }
+
+    pub fn is_occluder(&self) -> bool {
+        self.occluder
+    }
+
+    /// Occluders are drawn to the depth buffer of occlusion culling, with their first LOD. Meant for big, simple objects like walls.
+    pub fn set_occluder(&mut self, occluder: bool) {
+        self.occluder = occluder;
+    }
+
+    pub fn get_lod_count(&self) -> usize {
+        self.submeshes.iter()
+            .map(|submesh| submesh.lod + 1)
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// Clamped to the LODs of the model.
+    pub fn set_lod(&mut self, batch: usize, lod: usize) {
+        let lod = lod.min(self.get_lod_count() - 1);
+        self.batches[batch].lod = lod;
+    }
+
+    pub fn get_model_path(&self) -> Option<&str> {
+        self.model_path.as_deref()
+    }
+
+    /// The materials of the MTL library of the model.
+    pub fn get_model_materials(&self) -> &[MaterialDesc] {
+        &self.model_materials
+    }
+
+    /// Every material the object is drawn with.
+    pub fn materials(&self) -> impl Iterator<Item = &str> {
+        self.submeshes.iter()
+            .map(move |submesh| submesh.material.as_deref().unwrap_or(&self.material))
+    }
+
+    /// The index ranges of the LOD drawn with the material.
+    pub fn material_ranges<'a>(&'a self, material: &'a str, lod: usize) -> impl Iterator<Item = Range<u32>> + 'a {
+        self.submeshes.iter()
+            .filter(move |submesh| submesh.lod == lod)
+            .filter(move |submesh| submesh.material.as_deref().unwrap_or(&self.material) == material)
+            .map(|submesh| submesh.indices.clone())
+    }
+
+    /// Every index range of the LOD, whatever the material.
+    pub fn lod_ranges(&self, lod: usize) -> impl Iterator<Item = Range<u32>> + '_ {
+        self.submeshes.iter()
+            .filter(move |submesh| submesh.lod == lod)
+            .map(|submesh| submesh.indices.clone())
+    }
+
+    /// The index ranges of the first LOD, the one occlusion culling draws.
+    /// The simplified LODs can shrink the silhouette past the object and hide what is visible.
+    pub fn occluder_ranges(&self) -> impl Iterator<Item = Range<u32>> + '_ {
+        self.submeshes.iter()
+            .filter(|submesh| submesh.lod == 0)
+            .map(|submesh| submesh.indices.clone())
+    }
+
+    /// Reloads the model from disk. The current geometry is kept if the new one can't be parsed.
+    /// The caller has to make sure the device is idle.
+    pub fn reload_model(&mut self) -> Result<(), AssetError> {
+        let model_path = match &self.model_path {
+            Some(model_path) => model_path.clone(),
+            None => return Err(AssetError::new("Render object was not loaded from a model".to_string()))
+        };
+
+        let model = Self::load_model(&model_path)?;
+
+        let mut staging_pool = unsafe {
+            self.device.borrow().device.create_command_pool(
+                self.device.borrow().queues.family,
+                CommandPoolCreateFlags::empty(),
+            )
+        }.expect("Can't create Command Pool");
+
+        self.vertex_buffers = create_vertex_buffers(&self.device, &self.vertex_layout, model.mesh.vertices(), &mut staging_pool);
+
+        self.index_buffer = if !model.mesh.indices().is_empty() {
+            Some(BufferState::new_index_buffer(
+                Rc::clone(&self.device),
+                model.mesh.indices(),
+                &mut staging_pool,
+            ))
+        } else {
+            None
+        };
+
+        unsafe {
+            self.device.borrow().device
+                .destroy_command_pool(staging_pool);
+        }
+
+        self.mesh = model.mesh;
+        self.bounds = model.bounds;
+        self.bounding_sphere = mesh::bounding_volumes(self.mesh.vertices()).1;
+        self.update_instances_bounds();
+        self.submeshes = model.submeshes;
+        for batch in self.batches.iter_mut() {
+            batch.lod = 0;
+        }
+        self.model_materials = model.materials;
+        self.set_skeleton(model.skeleton, model.clips);
+
+        info!("Reloaded model {}", model_path);
+
+        Ok(())
+    }
+
+    pub fn get_vertex_layout(&self) -> &VertexLayout {
+        &self.vertex_layout
+    }
+
+    /// Uploads the vertices again with the new layout. The caller has to make sure the device is idle,
+    /// objects that are already drawn need the pipelines of the layout, see `RendererState::add_object`.
+    #[allow(dead_code)]
+    pub fn set_vertex_layout(&mut self, vertex_layout: VertexLayout) {
+        let mut staging_pool = unsafe {
+            self.device.borrow().device.create_command_pool(
+                self.device.borrow().queues.family,
+                CommandPoolCreateFlags::empty(),
+            )
+        }.expect("Can't create Command Pool");
+
+        let vertex_layout = instanced_layout(vertex_layout);
+        self.vertex_buffers = create_vertex_buffers(&self.device, &vertex_layout, self.mesh.vertices(), &mut staging_pool);
+        self.vertex_layout = vertex_layout;
+
+        unsafe {
+            self.device.borrow().device
+                .destroy_command_pool(staging_pool);
+        }
+    }
+
+    pub fn get_instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn get_instance_count(&self) -> u32 {
+        self.instances.len() as u32
+    }
+
+    /// The instances are uploaded before the next frame, see `upload_instances`. An object without instances is not drawn.
+    /// The instances are one batch, culled as a whole.
+    pub fn set_instances(&mut self, instances: Vec<Instance>) {
+        let batch_size = instances.len();
+        self.set_batched_instances(instances, batch_size);
+    }
+
+    /// Splits the instances in batches of `batch_size`, each culled and picking its LOD on its own.
+    pub fn set_batched_instances(&mut self, instances: Vec<Instance>, batch_size: usize) {
+        self.batches = instance_batches(0..instances.len(), batch_size);
+        self.instances = instances;
+        self.instances_changed = true;
+        self.update_instances_bounds();
+    }
+
+    /// The added instances are a batch of their own.
+    pub fn add_instances(&mut self, instances: &[Instance]) {
+        let start = self.instances.len();
+        self.instances.extend_from_slice(instances);
+        self.batches.extend(instance_batches(start..self.instances.len(), instances.len()));
+        self.instances_changed = true;
+        self.update_instances_bounds();
+    }
+
+    pub fn get_batches(&self) -> &[InstanceBatch] {
+        &self.batches
+    }
+
+    pub fn has_instance_changes(&self) -> bool {
+        self.instances_changed
+    }
+
+    /// The caller has to make sure the device is idle.
+    pub fn upload_instances(&mut self) {
+        let mut staging_pool = unsafe {
+            self.device.borrow().device.create_command_pool(
+                self.device.borrow().queues.family,
+                CommandPoolCreateFlags::empty(),
+            )
+        }.expect("Can't create Command Pool");
+
+        self.instance_buffer = create_instance_buffer(&self.device, &self.instances, &mut staging_pool);
+        self.instances_changed = false;
+
+        unsafe {
+            self.device.borrow().device
+                .destroy_command_pool(staging_pool);
+        }
+    }
+
+    fn update_instances_bounds(&mut self) {
+        let bounds = self.bounds;
+        let bounding_sphere = self.bounding_sphere;
+
+        for batch in self.batches.iter_mut() {
+            let instances = &self.instances[batch.instances.start as usize..batch.instances.end as usize];
+            batch.bounds = instances.iter()
+                .fold(Aabb::empty(), |batch_bounds, instance| batch_bounds.union(&bounds.transform(&instance.transform)));
+            batch.sphere = instances.iter()
+                .map(|instance| bounding_sphere.transform(&instance.transform))
+                .reduce(|sphere, other| sphere.union(&other))
+                .unwrap_or(bounding_sphere);
+        }
+
+        self.instances_bounds = self.batches.iter()
+            .fold(Aabb::empty(), |instances_bounds, batch| instances_bounds.union(&batch.bounds));
+        self.instances_sphere = self.batches.iter()
+            .map(|batch| batch.sphere)
+            .reduce(|sphere, other| sphere.union(&other))
+            .unwrap_or(bounding_sphere);
+    }
+
+    /// Objects with the same mesh, material and vertex layout can be drawn as instances of one of them.
+    /// Skinned objects are never batched, each one has a pose of its own.
+    pub fn can_batch(&self, other: &RenderObject<B>) -> bool {
+        if self.skeleton.is_some() || other.skeleton.is_some() {
+            return false;
+        }
+
+        if self.material != other.material || self.vertex_layout != other.vertex_layout || self.submeshes != other.submeshes {
+            return false;
+        }
+
+        match (&self.model_path, &other.model_path) {
+            (Some(model_path), Some(other_path)) => model_path == other_path,
+            (None, None) => self.mesh == other.mesh,
+            _ => false
+        }
+    }
+
+    /// The first clip is played on a loop.
+    fn set_skeleton(&mut self, skeleton: Option<Skeleton>, clips: Vec<AnimationClip>) {
+        self.player = AnimationPlayer::new();
+        if skeleton.is_some() && !clips.is_empty() {
+            self.player.play(0, true);
         }
 
-        //Update Buffer
-        self.color_uniform
-            .buffer.as_mut().unwrap()
-            .update_data(0, &self.color);
+        self.skeleton = skeleton;
+        self.clips = clips;
+    }
+
+    pub fn get_clips(&self) -> &[AnimationClip] {
+        &self.clips
+    }
+
+    fn find_clip(&self, clip: &str) -> Result<usize, AssetError> {
+        self.clips.iter()
+            .position(|other| other.name == clip)
+            .ok_or_else(|| AssetError::new(format!("Render object has no animation clip {}", clip)))
     }
 
     #[allow(dead_code)]
-    pub fn update_buffers(&mut self) {}
+    pub fn play(&mut self, clip: &str, looping: bool) -> Result<(), AssetError> {
+        let clip = self.find_clip(clip)?;
+        self.player.play(clip, looping);
+
+        Ok(())
+    }
+
+    /// Fades from the playing clips to `clip` over `duration` seconds.
+    pub fn crossfade(&mut self, clip: &str, duration: f32, looping: bool) -> Result<(), AssetError> {
+        let clip = self.find_clip(clip)?;
+        self.player.crossfade(clip, duration, looping);
+
+        Ok(())
+    }
+
+    pub fn get_player(&self) -> &AnimationPlayer {
+        &self.player
+    }
 
     #[allow(dead_code)]
-    pub fn get_desc_set(&self) -> Vec<&B::DescriptorSet> {
-        vec![
-            self.image.as_ref().unwrap()
-                .desc.set.as_ref().unwrap(),
-            self.color_uniform
-                .desc.as_ref().unwrap()
-                .set.as_ref().unwrap(),
-        ]
-    }
-
-    pub fn append_desc_set<'a>(
-        &'a self,
-        vec: &mut Vec<&'a B::DescriptorSet>,
-    ) {
-        vec.push(self.image.as_ref().unwrap().desc.set.as_ref().unwrap());
-        vec.push(
-            self.color_uniform
-                .desc.as_ref().unwrap()
-                .set.as_ref().unwrap(),
-        );
+    pub fn get_player_mut(&mut self) -> &mut AnimationPlayer {
+        &mut self.player
+    }
+
+    /// `delta` is in seconds.
+    pub fn update_animation(&mut self, delta: f32) {
+        if self.skeleton.is_some() {
+            self.player.update(delta, &self.clips);
+        }
+    }
+
+    /// The joint palette of the current pose, empty when the object is not skinned.
+    pub fn joint_matrices(&self) -> Vec<Matrix4> {
+        match &self.skeleton {
+            Some(skeleton) => skeleton.joint_matrices(&self.player.pose(skeleton, &self.clips)),
+            None => Vec::new(),
+        }
+    }
+
+    /// Where the joint palette of the object starts in the joint buffer of the frame.
+    pub fn set_joint_offset(&mut self, joint_offset: Option<u32>) {
+        self.joint_offset = joint_offset;
     }
 
     #[allow(dead_code)]
-    pub fn get_layout(&self) -> Vec<&B::DescriptorSetLayout> {
-        vec![
-            self.image.as_ref().unwrap()
-                .get_layout(),
-            self.color_uniform.get_layout(),
-        ]
+    pub fn get_joint_offset(&self) -> Option<u32> {
+        self.joint_offset
     }
 
-    pub fn append_layout<'a>(
-        &'a self,
-        vec: &mut Vec<&'a B::DescriptorSetLayout>,
-    ) {
-        vec.push(self.image.as_ref().unwrap().get_layout());
-        vec.push(self.color_uniform.get_layout());
+    /// The joint offset as the shaders read it, -1 when the object is not skinned.
+    pub fn get_joint_offset_constant(&self) -> u32 {
+        self.joint_offset
+            .map(|offset| offset as i32)
+            .unwrap_or(-1) as u32
     }
 
+    /// The streams of the mesh are bound from `offset`, then the instance stream.
     pub unsafe fn bind_buffers(
         &self,
         cmd: &mut B::CommandBuffer,
         offset: u32,
     ) -> u32 {
+        let buffers: Vec<&BufferState<B>> = self.vertex_buffers.iter()
+            .chain(self.instance_buffer.iter())
+            .collect();
+
         cmd.bind_vertex_buffers(
-            offset, 
-            Some((self.vertex_buffer.get_buffer(), SubRange::WHOLE))
+            offset,
+            buffers.iter()
+                .map(|buffer| (buffer.get_buffer(), SubRange::WHOLE))
         );
-        
+
+
         if let Some(index_buffer) = &self.index_buffer {
             cmd.bind_index_buffer(IndexBufferView {
                 buffer: index_buffer.get_buffer(),
@@ -377,18 +898,171 @@ impl<B: Backend> RenderObject<B> {
             })
         }
 
-        offset + 1
+        offset + self.vertex_layout.get_streams().len() as u32
     }
 }
 
-impl<B: Backend> Drop for RenderObject<B> {
-    fn drop(&mut self) {
-        self.device.borrow().device.wait_idle().unwrap();
-        unsafe {
-            self.device.borrow()
-                .device.destroy_descriptor_pool(self.texture_desc_pool.take().unwrap());
-            self.device.borrow()
-                .device.destroy_descriptor_pool(self.color_desc_pool.take().unwrap());
+/// The layout of the mesh with the instance stream at the end.
+fn instanced_layout(vertex_layout: VertexLayout) -> VertexLayout {
+    vertex_layout.with_stream(Instance::stream())
+        .unwrap_or_else(|err| panic!("Can't add the instance stream to the vertex layout: {}", err.message))
+}
+
+/// One vertex buffer per stream of the mesh, every stream but the instance stream.
+fn create_vertex_buffers<B: Backend>(
+    device: &Rc<RefCell<DeviceState<B>>>,
+    vertex_layout: &VertexLayout,
+    vertices: &[Vertex],
+    staging_pool: &mut B::CommandPool,
+) -> Vec<BufferState<B>> {
+    let mesh_streams = vertex_layout.get_streams().len() - 1;
+
+    //The vertices are already in the full layout
+    if vertex_layout.get_streams()[..mesh_streams] == *Vertex::layout().get_streams() {
+        return vec![BufferState::new_vertex_buffer(Rc::clone(device), vertices, staging_pool)];
+    }
+
+    (0..mesh_streams)
+        .map(|stream| {
+            let bytes = vertex_layout.pack(stream, vertices.len(), |index, semantic, row| vertices[index].attribute(semantic, row));
+            BufferState::new_vertex_buffer(Rc::clone(device), &bytes, staging_pool)
+        })
+        .collect()
+}
+
+/// The instances split in runs of `batch_size`, the bounds are set by `update_instances_bounds`.
+fn instance_batches(instances: Range<usize>, batch_size: usize) -> Vec<InstanceBatch> {
+    instances.clone()
+        .step_by(batch_size.max(1))
+        .map(|start| InstanceBatch {
+            instances: start as u32..(start + batch_size.max(1)).min(instances.end) as u32,
+            bounds: Aabb::empty(),
+            sphere: BoundingSphere::new(Vector3::default(), 0.0),
+            lod: 0,
+        })
+        .collect()
+}
+
+/// The instances as they are in memory, `None` when there are none to draw.
+fn create_instance_buffer<B: Backend>(
+    device: &Rc<RefCell<DeviceState<B>>>,
+    instances: &[Instance],
+    staging_pool: &mut B::CommandPool,
+) -> Option<BufferState<B>> {
+    if instances.is_empty() {
+        return None;
+    }
+
+    Some(BufferState::new_vertex_buffer(Rc::clone(device), instances, staging_pool))
+}
+
+/// Materials of different models can share a name, so they are named after the model as well.
+fn model_material_name(model_path: &str, material: &str) -> String {
+    format!("{}:{}", model_path, material)
+}
+
+/// A material range of one LOD.
+type LodRange = (usize, Option<usize>, Range<u32>);
+
+/// Orders the triangles of every material range for the vertex cache and overdraw, then appends their LODs.
+/// Ranges with fewer LODs than the others keep drawing their last one. The vertices end up in the order they are used.
+fn optimize_ranges(vertices: &mut Vec<Vertex>, indices: &[u32], ranges: Vec<MaterialRange>) -> (Vec<u32>, Vec<LodRange>) {
+    let levels: Vec<(Option<usize>, Vec<Vec<u32>>)> = ranges.into_iter()
+        .map(|(material_id, range)| {
+            let group = &indices[range.start as usize..range.end as usize];
+            let optimized = mesh::optimize_overdraw(&mesh::optimize_vertex_cache(group, vertices.len()), vertices);
+
+            let mut levels = mesh::generate_lods(vertices, &optimized, LOD_COUNT, LOD_REDUCTION, LOD_ERROR);
+            levels.insert(0, optimized);
+
+            (material_id, levels)
+        })
+        .collect();
+
+    let mut optimized = Vec::new();
+    let mut level_ranges: Vec<Vec<Range<u32>>> = Vec::new();
+    for (_, group_levels) in levels.iter() {
+        let ranges = group_levels.iter()
+            .map(|level| {
+                let start = optimized.len() as u32;
+                optimized.extend_from_slice(level);
+                start..optimized.len() as u32
+            })
+            .collect();
+
+        level_ranges.push(ranges);
+    }
+
+    let lod_count = level_ranges.iter().map(|ranges| ranges.len()).max().unwrap_or(0);
+    let lod_ranges = (0..lod_count)
+        .flat_map(|lod| levels.iter()
+            .zip(level_ranges.iter())
+            .map(move |((material_id, _), ranges)| (lod, *material_id, ranges[lod.min(ranges.len() - 1)].clone())))
+        .collect();
+
+    mesh::optimize_vertex_fetch(vertices, &mut optimized);
+
+    (optimized, lod_ranges)
+}
+
+/// The indices of a material id, `None` is the material of the object.
+type MaterialRange = (Option<usize>, Range<u32>);
+
+/// A list of indices drawn with a material id, before they are merged by `group_by_material`.
+pub type MaterialGroup = (Option<usize>, Vec<u32>);
+
+/// Joins the index lists of the meshes so every material gets one contiguous range, in order of first use.
+fn group_by_material(groups: Vec<MaterialGroup>) -> (Vec<u32>, Vec<MaterialRange>) {
+    let mut merged: Vec<MaterialGroup> = Vec::new();
+    for (material_id, indices) in groups {
+        match merged.iter_mut().find(|(id, _)| *id == material_id) {
+            Some((_, merged_indices)) => merged_indices.extend(indices),
+            None => merged.push((material_id, indices)),
         }
     }
+
+    let mut indices = Vec::new();
+    let mut ranges = Vec::new();
+    for (material_id, group) in merged {
+        if group.is_empty() {
+            continue;
+        }
+
+        let start = indices.len() as u32;
+        indices.extend(group);
+        ranges.push((material_id, start..indices.len() as u32));
+    }
+
+    (indices, ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{group_by_material, instance_batches};
+
+    #[test]
+    fn groups_indices_by_material() {
+        let groups = vec![
+            (Some(1), vec![0, 1, 2]),
+            (None, vec![3, 4, 5]),
+            (Some(1), vec![6, 7, 8]),
+            (Some(0), vec![]),
+        ];
+
+        let (indices, ranges) = group_by_material(groups);
+
+        assert_eq!(indices, vec![0, 1, 2, 6, 7, 8, 3, 4, 5]);
+        assert_eq!(ranges, vec![(Some(1), 0..6), (None, 6..9)]);
+    }
+
+    #[test]
+    fn splits_instances_in_batches() {
+        let ranges = |instances, batch_size| instance_batches(instances, batch_size).into_iter()
+            .map(|batch| batch.instances)
+            .collect::<Vec<_>>();
+
+        assert_eq!(ranges(0..5, 2), vec![0..2, 2..4, 4..5]);
+        assert_eq!(ranges(3..6, 3), vec![3..6]);
+        assert!(ranges(0..0, 0).is_empty());
+    }
 }
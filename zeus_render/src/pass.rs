@@ -1,18 +1,14 @@
-use super::{
-    device::DeviceState,
-    swapchain::SwapchainState
-};
+use super::device::DeviceState;
 
 use gfx_hal::{
     Backend,
     device::Device,
-    format::Format,
     image::{
         Access, Layout,
     },
     memory::Dependencies,
     pass::{
-        Attachment,AttachmentLoadOp, AttachmentOps, AttachmentStoreOp, SubpassDependency, SubpassDesc
+        Attachment, SubpassDependency, SubpassDesc
     },
     pso::PipelineStage
 };
@@ -28,45 +24,45 @@ pub struct RenderPassState<B: Backend> {
 }
 
 impl<B: Backend> RenderPassState<B> {
+    /// A render pass with a single subpass. The attachments are the colors, followed by
+    /// one resolve per color if `has_resolves` and the depth if `has_depth`.
     pub fn new(
-        swapchain: &SwapchainState<B>,
         device: Rc<RefCell<DeviceState<B>>>,
+        attachments: Vec<Attachment>,
+        color_count: usize,
+        has_resolves: bool,
+        has_depth: bool,
     ) -> Self {
         let render_pass = {
-            let color_attachment = Attachment {
-                format: Some(swapchain.format),
-                samples: 1,
-                ops: AttachmentOps::new(AttachmentLoadOp::Clear, AttachmentStoreOp::Store),
-                stencil_ops: AttachmentOps::DONT_CARE,
-                layouts: Layout::Undefined..Layout::Present,
-            };
+            let resolve_count = if has_resolves { color_count } else { 0 };
 
-            let depth_attachment = Attachment {
-                format: Some(Format::D32SfloatS8Uint),
-                samples: 1,
-                ops: AttachmentOps::new(AttachmentLoadOp::Clear, AttachmentStoreOp::DontCare),
-                stencil_ops: AttachmentOps::new(AttachmentLoadOp::DontCare, AttachmentStoreOp::DontCare),
-                layouts: Layout::Undefined..Layout::DepthStencilAttachmentOptimal
-            };
+            let colors: Vec<_> = (0..color_count)
+                .map(|index| (index, Layout::ColorAttachmentOptimal))
+                .collect();
+            let resolves: Vec<_> = (color_count..color_count + resolve_count)
+                .map(|index| (index, Layout::ColorAttachmentOptimal))
+                .collect();
+            let depth = (color_count + resolve_count, Layout::DepthStencilAttachmentOptimal);
 
             let subpass = SubpassDesc {
-                colors: &[(0, Layout::ColorAttachmentOptimal)],
-                depth_stencil: Some(&(1, Layout::DepthStencilAttachmentOptimal)),
+                colors: &colors,
+                depth_stencil: if has_depth { Some(&depth) } else { None },
                 inputs: &[],
-                resolves: &[],
+                resolves: &resolves,
                 preserves: &[],
             };
 
+            //NOTE: Waits for the previous users of the attachments, earlier passes may have drawn to or sampled them
             let dependency = SubpassDependency {
-                passes: None .. None,
-                stages: (PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::EARLY_FRAGMENT_TESTS) .. (PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::EARLY_FRAGMENT_TESTS),
-                accesses: Access::empty() .. (Access::COLOR_ATTACHMENT_WRITE | Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
+                passes: None .. Some(0),
+                stages: (PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::EARLY_FRAGMENT_TESTS | PipelineStage::LATE_FRAGMENT_TESTS | PipelineStage::FRAGMENT_SHADER) .. (PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::EARLY_FRAGMENT_TESTS),
+                accesses: (Access::COLOR_ATTACHMENT_WRITE | Access::DEPTH_STENCIL_ATTACHMENT_WRITE) .. (Access::COLOR_ATTACHMENT_READ | Access::COLOR_ATTACHMENT_WRITE | Access::DEPTH_STENCIL_ATTACHMENT_READ | Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
                 flags: Dependencies::empty()
             };
 
             unsafe {
                 device.borrow()
-                    .device.create_render_pass(&[color_attachment, depth_attachment], &[subpass], &[dependency])
+                    .device.create_render_pass(attachments, &[subpass], &[dependency])
             }.ok()
         };
 
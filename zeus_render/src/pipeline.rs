@@ -1,26 +1,143 @@
 use gfx_hal::{
+    adapter::PhysicalDevice,
     device::Device,
+    format::Format,
+    image::NumSamples,
     pass::Subpass,
     pso::{
-        self, BlendState, ColorBlendDesc, ColorMask, Comparison, DepthStencilDesc, DepthTest, EntryPoint, Face, FrontFace, GraphicsPipelineDesc, InputAssemblerDesc, PolygonMode, Primitive, Rasterizer, ShaderStageFlags, Specialization, State
+        self, AttributeDesc, ColorBlendDesc, ColorMask, Comparison, DepthBias, DepthStencilDesc, DepthTest, DescriptorSetLayoutBinding, DescriptorType, Element, EntryPoint, Face, FrontFace, GraphicsPipelineDesc, InputAssemblerDesc, Multisampling, PolygonMode, Primitive, Rasterizer, ShaderStageFlags, Specialization, State, VertexBufferDesc, VertexInputRate
     },
     Backend,
 };
 
 use std::{
-    borrow,
     cell::RefCell,
-    fs,
+    collections::BTreeMap,
+    mem::size_of,
     rc::Rc
 };
 
+use zeus_core::math::Matrix4;
+
 use super::{
+    constants::DEFAULT_ATTRIBUTE_BINDING,
+    desc::DescSetLayout,
     device::DeviceState,
-    model::Vertex
+    error::AssetError,
+    material::{
+        MaterialDesc, RenderState
+    },
+    shader::{
+        Shader, ShaderCache
+    },
+    vertex_layout::VertexLayout,
 };
 
 const ENTRY_NAME: &str = "main";
 
+/// Everything a graphics pipeline depends on, apart from the render pass.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PipelineKey {
+    pub vertex_shader: String,
+    pub fragment_shader: String,
+    pub render_state: RenderState,
+    /// The sample count of the pass the pipeline is used in
+    pub samples: NumSamples,
+    /// The vertex buffers of the meshes drawn with the pipeline
+    pub vertex_layout: VertexLayout,
+    layout: Vec<Vec<(u32, DescriptorType, usize, u32)>>,
+}
+
+impl PipelineKey {
+    pub fn new<B: Backend>(
+        material: &MaterialDesc,
+        desc_layouts: &[&DescSetLayout<B>],
+        samples: NumSamples,
+        vertex_layout: &VertexLayout,
+    ) -> Self {
+        let layout = desc_layouts.iter()
+            .map(|layout| layout.bindings.iter()
+                .map(|binding| (binding.binding, binding.ty, binding.count, binding.stage_flags.bits()))
+                .collect())
+            .collect();
+
+        PipelineKey {
+            vertex_shader: material.vertex_shader.clone(),
+            fragment_shader: material.fragment_shader.clone(),
+            render_state: material.render_state,
+            samples,
+            vertex_layout: vertex_layout.clone(),
+            layout,
+        }
+    }
+}
+
+/// Keeps one pipeline per material setup, so materials that share shaders and state share the pipeline.
+/// Pipelines are built for the current render pass, the cache has to be cleared when it is recreated.
+pub struct PipelineCache<B: Backend> {
+    device: Rc<RefCell<DeviceState<B>>>,
+    shader_cache: ShaderCache,
+    pipelines: BTreeMap<PipelineKey, PipelineState<B>>,
+}
+
+impl<B: Backend> PipelineCache<B> {
+    pub fn new(device_ptr: Rc<RefCell<DeviceState<B>>>) -> Self {
+        PipelineCache {
+            device: device_ptr,
+            shader_cache: ShaderCache::new(),
+            pipelines: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: &PipelineKey) -> Option<&PipelineState<B>> {
+        self.pipelines.get(key)
+    }
+
+    /// Returns the pipeline of the key, building it the first time it is requested.
+    pub fn get_or_create(
+        &mut self,
+        key: &PipelineKey,
+        desc_layouts: &[&DescSetLayout<B>],
+        render_pass: &B::RenderPass,
+    ) -> Result<&PipelineState<B>, AssetError> {
+        if !self.pipelines.contains_key(key) {
+            let mut pipeline = PipelineState::empty(Rc::clone(&self.device));
+            pipeline.new_pipeline(&mut self.shader_cache, key, desc_layouts, render_pass)?;
+
+            debug!("Created pipeline for {} and {}", key.vertex_shader, key.fragment_shader);
+
+            self.pipelines.insert(key.clone(), pipeline);
+        }
+
+        Ok(&self.pipelines[key])
+    }
+
+    /// Rebuilds the pipeline of the key, e.g. after its shaders changed.
+    /// If the new pipeline can't be built the previous one is kept.
+    /// The caller has to make sure the device is idle.
+    pub fn rebuild(
+        &mut self,
+        key: &PipelineKey,
+        desc_layouts: &[&DescSetLayout<B>],
+        render_pass: &B::RenderPass,
+    ) -> Result<(), AssetError> {
+        match self.pipelines.get_mut(key) {
+            Some(pipeline) => pipeline.new_pipeline(&mut self.shader_cache, key, desc_layouts, render_pass),
+            None => self.get_or_create(key, desc_layouts, render_pass).map(|_| ())
+        }
+    }
+
+    /// Destroys every pipeline. The caller has to make sure the device is idle.
+    pub fn clear(&mut self) {
+        self.pipelines.clear();
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+}
+
 pub struct PipelineState<B: Backend> {
     pub pipeline: Option<B::GraphicsPipeline>,
     pub pipeline_layout: Option<B::PipelineLayout>,
@@ -29,17 +146,17 @@ pub struct PipelineState<B: Backend> {
 
 impl<B: Backend> PipelineState<B> {
     #[allow(dead_code)]
-    pub fn new<IS>(
-        desc_layouts: IS,
+    pub fn new(
+        shader_cache: &mut ShaderCache,
+        key: &PipelineKey,
+        desc_layouts: &[&DescSetLayout<B>],
         render_pass: &B::RenderPass,
         device_ptr: Rc<RefCell<DeviceState<B>>>,
-    ) -> Self
-    where
-        IS: IntoIterator,
-        IS::Item: borrow::Borrow<B::DescriptorSetLayout>, <IS as IntoIterator>::IntoIter: ExactSizeIterator
-    {
+    ) -> Self {
         let mut pipeline = Self::empty(Rc::clone(&device_ptr));
-        pipeline.new_pipeline(desc_layouts, &render_pass);
+        if let Err(err) = pipeline.new_pipeline(shader_cache, key, desc_layouts, render_pass) {
+            error!("{}", err.message);
+        }
 
         pipeline
     }
@@ -52,33 +169,60 @@ impl<B: Backend> PipelineState<B> {
         }
     }
 
-    pub fn new_pipeline<IS>(
+    /// Builds a new pipeline and replaces the current one.
+    /// If the shaders fail to load or don't match the vertex and descriptor layouts the current pipeline is kept.
+    pub fn new_pipeline(
         &mut self,
-        desc_layouts: IS,
+        shader_cache: &mut ShaderCache,
+        key: &PipelineKey,
+        desc_layouts: &[&DescSetLayout<B>],
         render_pass: &B::RenderPass,
-    ) where
-        IS: IntoIterator,
-        IS::Item: borrow::Borrow<B::DescriptorSetLayout>, <IS as IntoIterator>::IntoIter: ExactSizeIterator
-    {
+    ) -> Result<(), AssetError> {
+        let vs = shader_cache.load(&key.vertex_shader)?;
+        let fs = shader_cache.load(&key.fragment_shader)?;
+
+        let layout_bindings: Vec<_> = desc_layouts.iter()
+            .map(|layout| &layout.bindings[..])
+            .collect();
+
+        let (buffers, attributes) = vertex_inputs(&key.vertex_layout, &vs);
+
+        validate_shader(&vs, &attributes, &layout_bindings)?;
+        validate_shader(&fs, &attributes, &layout_bindings)?;
+
+        //NOTE: Only 4 sets are guaranteed, the camera, lights, shadows and material use all of them
+        let max_sets = self.device.borrow().physical_device.limits().max_bound_descriptor_sets;
+        if desc_layouts.len() as u64 > max_sets as u64 {
+            return Err(AssetError::new(format!(
+                "Pipeline of {} needs {} descriptor sets, the device supports {}",
+                key.fragment_shader,
+                desc_layouts.len(),
+                max_sets
+            )));
+        }
+
         let device = &self.device.borrow().device;
+
+        let vs_module = create_shader_module::<B>(device, &vs)?;
+        let fs_module = match create_shader_module::<B>(device, &fs) {
+            Ok(module) => module,
+            Err(err) => {
+                unsafe {
+                    device.destroy_shader_module(vs_module);
+                }
+                return Err(err);
+            }
+        };
+
         let pipeline_layout = unsafe {
             device.create_pipeline_layout(
-                desc_layouts,
+                desc_layouts.iter()
+                    .map(|layout| layout.layout.as_ref().unwrap()),
                 &[(ShaderStageFlags::VERTEX, 0..8)]
             )
         }.expect("Could not create pipeline layout");
 
         let pipeline = {
-            let vs_module = create_shader_module::<B>(
-                &device,
-                "data/shaders/quad.vert",
-                glsl_to_spirv::ShaderType::Vertex,
-            );
-            let fs_module = create_shader_module::<B>(
-                &device,
-                "data/shaders/quad.frag",
-                glsl_to_spirv::ShaderType::Fragment,
-            );
 
             let pipeline = {
                 let (vs_entry, fs_entry) = (
@@ -101,7 +245,7 @@ impl<B: Backend> PipelineState<B> {
 
                 let rasterizer = Rasterizer {
                     polygon_mode: PolygonMode::Fill,
-                    cull_face: Face::empty(),
+                    cull_face: key.render_state.cull.face(),
                     front_face: FrontFace::CounterClockwise,
                     depth_clamping: false,
                     depth_bias: Option::None,
@@ -109,13 +253,10 @@ impl<B: Backend> PipelineState<B> {
                     line_width: State::Static(1.0)
                 };
 
-                let buffers = &Vertex::get_vertex_buffer_description();
-                let attributes = &Vertex::get_attribute_description();
-
                 let mut pipeline_desc = GraphicsPipelineDesc::new(
                     pso::PrimitiveAssemblerDesc::Vertex{
-                        buffers,
-                        attributes,
+                        buffers: &buffers,
+                        attributes: &attributes,
                         input_assembler: InputAssemblerDesc {
                             primitive: Primitive::TriangleList,
                             with_adjacency: false,
@@ -133,20 +274,33 @@ impl<B: Backend> PipelineState<B> {
 
                 pipeline_desc.blender.targets.push(ColorBlendDesc {
                     mask: ColorMask::ALL,
-                    blend: Some(BlendState::ALPHA),
+                    blend: key.render_state.blend.blend_state(),
                 });
 
                 pipeline_desc.depth_stencil = DepthStencilDesc {
-                    depth: Some(DepthTest {
-                        fun: Comparison::Less,
-                        write: true
-                    }),
+                    depth: if key.render_state.depth_test {
+                        Some(DepthTest {
+                            fun: Comparison::Less,
+                            write: key.render_state.depth_write
+                        })
+                    } else {
+                        None
+                    },
                     depth_bounds: false,
                     stencil: None
                 };
 
+                if key.samples > 1 {
+                    pipeline_desc.multisampling = Some(Multisampling {
+                        rasterization_samples: key.samples,
+                        sample_shading: None,
+                        sample_mask: !0,
+                        alpha_coverage: false,
+                        alpha_to_one: false,
+                    });
+                }
+
                 unsafe { device.create_graphics_pipeline(&pipeline_desc, None) }
-                    .expect("Could not create graphics pipeline")
             };
 
             unsafe {
@@ -157,8 +311,273 @@ impl<B: Backend> PipelineState<B> {
             pipeline
         };
 
-        self.pipeline = Some(pipeline);
-        self.pipeline_layout = Some(pipeline_layout);
+        let pipeline = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err(err) => {
+                unsafe {
+                    device.destroy_pipeline_layout(pipeline_layout);
+                }
+                return Err(AssetError::new(format!("Could not create graphics pipeline: {:?}", err)));
+            }
+        };
+
+        //Destroy the previous pipeline, the caller makes sure the device is idle.
+        unsafe {
+            if let Some(old_pipeline) = self.pipeline.replace(pipeline) {
+                device.destroy_graphics_pipeline(old_pipeline);
+            }
+
+            if let Some(old_layout) = self.pipeline_layout.replace(pipeline_layout) {
+                device.destroy_pipeline_layout(old_layout);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds a depth only pipeline for the shadow pass and replaces the current one.
+    /// The vertex shader gets the matrix of the light as a push constant, there is no fragment shader.
+    pub fn new_shadow_pipeline(
+        &mut self,
+        shader_cache: &mut ShaderCache,
+        vertex_shader: &str,
+        vertex_layout: &VertexLayout,
+        depth_bias: DepthBias,
+        desc_layouts: &[&DescSetLayout<B>],
+        render_pass: &B::RenderPass,
+    ) -> Result<(), AssetError> {
+        let vs = shader_cache.load(vertex_shader)?;
+
+        let layout_bindings: Vec<_> = desc_layouts.iter()
+            .map(|layout| &layout.bindings[..])
+            .collect();
+
+        let (buffers, attributes) = vertex_inputs(vertex_layout, &vs);
+
+        validate_shader(&vs, &attributes, &layout_bindings)?;
+
+        let device = &self.device.borrow().device;
+
+        let vs_module = create_shader_module::<B>(device, &vs)?;
+
+        //The matrix of the tile and the joint offset of the object
+        let pipeline_layout = unsafe {
+            device.create_pipeline_layout(
+                desc_layouts.iter()
+                    .map(|layout| layout.layout.as_ref().unwrap()),
+                &[(ShaderStageFlags::VERTEX, 0..(size_of::<Matrix4>() + size_of::<i32>()) as u32)]
+            )
+        }.expect("Could not create pipeline layout");
+
+        let pipeline = {
+            let vs_entry = EntryPoint::<B> {
+                entry: ENTRY_NAME,
+                module: &vs_module,
+                specialization: Specialization::default(),
+            };
+
+            let subpass = Subpass {
+                index: 0,
+                main_pass: render_pass,
+            };
+
+            //NOTE: Both faces are drawn, the models are not guaranteed to be closed
+            let rasterizer = Rasterizer {
+                polygon_mode: PolygonMode::Fill,
+                cull_face: Face::NONE,
+                front_face: FrontFace::CounterClockwise,
+                depth_clamping: false,
+                depth_bias: Some(State::Static(depth_bias)),
+                conservative: false,
+                line_width: State::Static(1.0)
+            };
+
+            let mut pipeline_desc = GraphicsPipelineDesc::new(
+                pso::PrimitiveAssemblerDesc::Vertex{
+                    buffers: &buffers,
+                    attributes: &attributes,
+                    input_assembler: InputAssemblerDesc {
+                        primitive: Primitive::TriangleList,
+                        with_adjacency: false,
+                        restart_index: None
+                    },
+                    vertex: vs_entry,
+                    geometry: None,
+                    tessellation: None
+                },
+                rasterizer,
+                None,
+                &pipeline_layout,
+                subpass,
+            );
+
+            pipeline_desc.depth_stencil = DepthStencilDesc {
+                depth: Some(DepthTest {
+                    fun: Comparison::LessEqual,
+                    write: true
+                }),
+                depth_bounds: false,
+                stencil: None
+            };
+
+            let pipeline = unsafe { device.create_graphics_pipeline(&pipeline_desc, None) };
+
+            unsafe {
+                device.destroy_shader_module(vs_module);
+            }
+
+            pipeline
+        };
+
+        let pipeline = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err(err) => {
+                unsafe {
+                    device.destroy_pipeline_layout(pipeline_layout);
+                }
+                return Err(AssetError::new(format!("Could not create shadow pipeline: {:?}", err)));
+            }
+        };
+
+        unsafe {
+            if let Some(old_pipeline) = self.pipeline.replace(pipeline) {
+                device.destroy_graphics_pipeline(old_pipeline);
+            }
+
+            if let Some(old_layout) = self.pipeline_layout.replace(pipeline_layout) {
+                device.destroy_pipeline_layout(old_layout);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds a pipeline that draws one triangle over the whole pass and replaces the current one.
+    /// The vertex shader makes the corners from the vertex index, the fragment shader gets `constants` bytes of push constants.
+    pub fn new_fullscreen_pipeline(
+        &mut self,
+        shader_cache: &mut ShaderCache,
+        vertex_shader: &str,
+        fragment_shader: &str,
+        constants: u32,
+        desc_layouts: &[&DescSetLayout<B>],
+        render_pass: &B::RenderPass,
+    ) -> Result<(), AssetError> {
+        let vs = shader_cache.load(vertex_shader)?;
+        let fs = shader_cache.load(fragment_shader)?;
+
+        let layout_bindings: Vec<_> = desc_layouts.iter()
+            .map(|layout| &layout.bindings[..])
+            .collect();
+
+        validate_shader(&vs, &[], &layout_bindings)?;
+        validate_shader(&fs, &[], &layout_bindings)?;
+
+        let device = &self.device.borrow().device;
+
+        let vs_module = create_shader_module::<B>(device, &vs)?;
+        let fs_module = match create_shader_module::<B>(device, &fs) {
+            Ok(module) => module,
+            Err(err) => {
+                unsafe {
+                    device.destroy_shader_module(vs_module);
+                }
+                return Err(err);
+            }
+        };
+
+        let pipeline_layout = unsafe {
+            device.create_pipeline_layout(
+                desc_layouts.iter()
+                    .map(|layout| layout.layout.as_ref().unwrap()),
+                &[(ShaderStageFlags::FRAGMENT, 0..constants)]
+            )
+        }.expect("Could not create pipeline layout");
+
+        let pipeline = {
+            let (vs_entry, fs_entry) = (
+                EntryPoint::<B> {
+                    entry: ENTRY_NAME,
+                    module: &vs_module,
+                    specialization: Specialization::default(),
+                },
+                EntryPoint::<B> {
+                    entry: ENTRY_NAME,
+                    module: &fs_module,
+                    specialization: Specialization::default(),
+                },
+            );
+
+            let subpass = Subpass {
+                index: 0,
+                main_pass: render_pass,
+            };
+
+            let rasterizer = Rasterizer {
+                polygon_mode: PolygonMode::Fill,
+                cull_face: Face::NONE,
+                front_face: FrontFace::CounterClockwise,
+                depth_clamping: false,
+                depth_bias: None,
+                conservative: false,
+                line_width: State::Static(1.0)
+            };
+
+            let mut pipeline_desc = GraphicsPipelineDesc::new(
+                pso::PrimitiveAssemblerDesc::Vertex{
+                    buffers: &[],
+                    attributes: &[],
+                    input_assembler: InputAssemblerDesc {
+                        primitive: Primitive::TriangleList,
+                        with_adjacency: false,
+                        restart_index: None
+                    },
+                    vertex: vs_entry,
+                    geometry: None,
+                    tessellation: None
+                },
+                rasterizer,
+                Some(fs_entry),
+                &pipeline_layout,
+                subpass,
+            );
+
+            pipeline_desc.blender.targets.push(ColorBlendDesc {
+                mask: ColorMask::ALL,
+                blend: None,
+            });
+
+            let pipeline = unsafe { device.create_graphics_pipeline(&pipeline_desc, None) };
+
+            unsafe {
+                device.destroy_shader_module(vs_module);
+                device.destroy_shader_module(fs_module);
+            }
+
+            pipeline
+        };
+
+        let pipeline = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err(err) => {
+                unsafe {
+                    device.destroy_pipeline_layout(pipeline_layout);
+                }
+                return Err(AssetError::new(format!("Could not create fullscreen pipeline: {:?}", err)));
+            }
+        };
+
+        unsafe {
+            if let Some(old_pipeline) = self.pipeline.replace(pipeline) {
+                device.destroy_graphics_pipeline(old_pipeline);
+            }
+
+            if let Some(old_layout) = self.pipeline_layout.replace(pipeline_layout) {
+                device.destroy_pipeline_layout(old_layout);
+            }
+        }
+
+        Ok(())
     }
 
     pub fn is_empty(&self) -> bool {
@@ -181,22 +600,55 @@ impl<B: Backend> Drop for PipelineState<B> {
     }
 }
 
+/// The vertex buffers and attributes of the layout. The inputs of the vertex shader the layout doesn't have
+/// read the default value of their location, from a buffer with a stride of 0.
+fn vertex_inputs(vertex_layout: &VertexLayout, vertex_shader: &Shader) -> (Vec<VertexBufferDesc>, Vec<AttributeDesc>) {
+    let mut buffers = vertex_layout.buffer_descriptions();
+    let mut attributes = vertex_layout.attribute_descriptions();
+
+    let missing: Vec<AttributeDesc> = vertex_shader.reflection.inputs.iter()
+        .filter(|input| !attributes.iter().any(|attribute| attribute.location == input.location))
+        .map(|input| AttributeDesc {
+            location: input.location,
+            binding: DEFAULT_ATTRIBUTE_BINDING,
+            element: Element {
+                format: input.format.unwrap_or(Format::Rgba32Sfloat),
+                offset: input.location * size_of::<[f32; 4]>() as u32,
+            }
+        })
+        .collect();
+
+    if !missing.is_empty() {
+        buffers.push(VertexBufferDesc {
+            binding: DEFAULT_ATTRIBUTE_BINDING,
+            stride: 0,
+            rate: VertexInputRate::Vertex,
+        });
+        attributes.extend(missing);
+    }
+
+    (buffers, attributes)
+}
+
+fn validate_shader(
+    shader: &Shader,
+    attributes: &[AttributeDesc],
+    layout_bindings: &[&[DescriptorSetLayoutBinding]],
+) -> Result<(), AssetError> {
+    if shader.reflection.stage.contains(ShaderStageFlags::VERTEX) {
+        shader.reflection.validate_vertex_inputs(attributes)
+            .map_err(|err| AssetError::new(format!("{}: {}", shader.path, err.message)))?;
+    }
+
+    shader.reflection.validate_descriptors(layout_bindings)
+        .map_err(|err| AssetError::new(format!("{}: {}", shader.path, err.message)))
+}
+
 fn create_shader_module<B: Backend>(
     device: &B::Device,
-    path: &str,
-    shader_type: glsl_to_spirv::ShaderType,
-) -> B::ShaderModule {
-    //Read a shader file and compile it to SPIR-V
-    let glsl = fs::read_to_string(path)
-        .unwrap();
-    let file = glsl_to_spirv::compile(&glsl, shader_type)
-        .unwrap();
-    //Read SPIR-V and create shader module
-    //TOOD: part of auxil crate now
-    let spirv: Vec<u32> = gfx_auxil::read_spirv(file)
-        .unwrap();
-
+    shader: &Shader,
+) -> Result<B::ShaderModule, AssetError> {
     unsafe { 
-        device.create_shader_module(&spirv)
-    }.unwrap()
+        device.create_shader_module(&shader.spirv)
+    }.map_err(|err| AssetError::new(format!("Could not create shader module {}: {:?}", shader.path, err)))
 }
@@ -0,0 +1,641 @@
+use gfx_hal::{
+    command::CommandBuffer,
+    device::Device,
+    format::{
+        ChannelType, Format
+    },
+    image::{
+        Filter, Layout, Lod, PackedColor, SamplerDesc, WrapMode
+    },
+    pso::{
+        Descriptor, DescriptorPoolCreateFlags, DescriptorRangeDesc, DescriptorSetLayoutBinding, DescriptorType, ImageDescriptorType, ShaderStageFlags
+    },
+    Backend,
+};
+
+use super::{
+    constants::{
+        BACKBUFFER_ATTACHMENT, BLOOM_ATTACHMENT, BLOOM_BLUR_ATTACHMENT, BLOOM_EXTRACT_ATTACHMENT, BLOOM_EXTRACT_PASS, BLOOM_EXTRACT_SHADER_PATH, BLOOM_HORIZONTAL_PASS, BLOOM_SCALE, BLOOM_VERTICAL_PASS, BLUR_SHADER_PATH, COLOR_LUT_SIZE, FULLSCREEN_VERTEX_SHADER_PATH, FXAA_EDGE_THRESHOLD, FXAA_EDGE_THRESHOLD_MIN, FXAA_PASS, FXAA_SHADER_PATH, FXAA_SUBPIXEL, HDR_ATTACHMENT, HDR_FORMAT, LDR_ATTACHMENT, LDR_FORMAT, LINEAR_IMAGE_FORMAT, TONEMAP_PASS, TONEMAP_SHADER_PATH
+    },
+    desc::{
+        DescSet, DescSetLayout, DescSetWrite
+    },
+    device::DeviceState,
+    error::AssetError,
+    graph::{
+        AttachmentDesc, AttachmentSize, PassDesc, RenderGraph, RenderGraphDesc
+    },
+    image::{
+        DataImage, ImageState
+    },
+    pipeline::PipelineState,
+    shader::ShaderCache,
+};
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    mem::size_of,
+    rc::Rc
+};
+
+//The image a pass reads, the bloom and the LUT are only read by the tonemapping
+const SOURCE_BINDING: u32 = 0;
+const SAMPLER_BINDING: u32 = 1;
+const BLOOM_BINDING: u32 = 2;
+const LUT_BINDING: u32 = 3;
+
+/// Which effects are applied after the scene is drawn, and how strong they are.
+/// Toggling bloom or FXAA changes the passes of the render graph, the rest applies from the next frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PostSettings {
+    /// Scales the HDR colors before they are tonemapped
+    pub exposure: f32,
+    /// Filmic tonemapping, the colors are clamped without it
+    pub tonemapping: bool,
+    pub bloom: bool,
+    /// The brightness the bloom starts from
+    pub bloom_threshold: f32,
+    /// 0 cuts off at the threshold, 1 fades in from black
+    pub bloom_knee: f32,
+    pub bloom_intensity: f32,
+    pub fxaa: bool,
+    /// Grades the colors with the LUT of `PostState::set_color_lut`
+    pub color_grading: bool,
+    pub vignette: bool,
+    /// How much the corners are darkened
+    pub vignette_strength: f32,
+    pub gamma_correction: bool,
+    pub gamma: f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        PostSettings {
+            exposure: 1.0,
+            tonemapping: true,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_knee: 0.5,
+            bloom_intensity: 0.3,
+            fxaa: true,
+            color_grading: false,
+            vignette: true,
+            vignette_strength: 0.8,
+            gamma_correction: true,
+            gamma: 2.2,
+        }
+    }
+}
+
+impl PostSettings {
+    /// Whether going from these settings to the other ones needs the render graph to be rebuilt.
+    pub fn changes_passes(&self, other: &PostSettings) -> bool {
+        self.bloom != other.bloom || self.fxaa != other.fxaa
+    }
+
+    /// Turns the effect on or off, returns whether it is on.
+    pub fn toggle(&mut self, effect: PostEffect) -> bool {
+        let enabled = match effect {
+            PostEffect::Tonemapping => &mut self.tonemapping,
+            PostEffect::Bloom => &mut self.bloom,
+            PostEffect::Fxaa => &mut self.fxaa,
+            PostEffect::ColorGrading => &mut self.color_grading,
+            PostEffect::Vignette => &mut self.vignette,
+            PostEffect::GammaCorrection => &mut self.gamma_correction,
+        };
+
+        *enabled = !*enabled;
+        *enabled
+    }
+}
+
+/// The effects of `PostSettings` that can be toggled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PostEffect {
+    Tonemapping,
+    Bloom,
+    Fxaa,
+    ColorGrading,
+    Vignette,
+    GammaCorrection,
+}
+
+/// The passes after the main pass, each draws one fullscreen triangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PostPass {
+    BloomExtract,
+    BloomHorizontal,
+    BloomVertical,
+    Tonemap,
+    Fxaa,
+}
+
+impl PostPass {
+    pub const ALL: [PostPass; 5] = [
+        PostPass::BloomExtract,
+        PostPass::BloomHorizontal,
+        PostPass::BloomVertical,
+        PostPass::Tonemap,
+        PostPass::Fxaa,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PostPass::BloomExtract => BLOOM_EXTRACT_PASS,
+            PostPass::BloomHorizontal => BLOOM_HORIZONTAL_PASS,
+            PostPass::BloomVertical => BLOOM_VERTICAL_PASS,
+            PostPass::Tonemap => TONEMAP_PASS,
+            PostPass::Fxaa => FXAA_PASS,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        PostPass::ALL.iter()
+            .copied()
+            .find(|pass| pass.name() == name)
+    }
+
+    pub fn fragment_shader(self) -> &'static str {
+        match self {
+            PostPass::BloomExtract => BLOOM_EXTRACT_SHADER_PATH,
+            PostPass::BloomHorizontal | PostPass::BloomVertical => BLUR_SHADER_PATH,
+            PostPass::Tonemap => TONEMAP_SHADER_PATH,
+            PostPass::Fxaa => FXAA_SHADER_PATH,
+        }
+    }
+
+    /// The attachment the pass samples.
+    fn source(self) -> &'static str {
+        match self {
+            PostPass::BloomExtract | PostPass::Tonemap => HDR_ATTACHMENT,
+            PostPass::BloomHorizontal => BLOOM_EXTRACT_ATTACHMENT,
+            PostPass::BloomVertical => BLOOM_BLUR_ATTACHMENT,
+            PostPass::Fxaa => LDR_ATTACHMENT,
+        }
+    }
+}
+
+/// Every shader of the post-processing passes, for hot reloading.
+pub fn shader_paths() -> impl Iterator<Item = &'static str> {
+    std::iter::once(FULLSCREEN_VERTEX_SHADER_PATH)
+        .chain(PostPass::ALL.iter().map(|pass| pass.fragment_shader()))
+}
+
+/// The push constants of a pass, three vec4s: the parameters of the pass, the effects that are on and how the colors are displayed.
+/// `srgb_backbuffer` is whether the swapchain encodes the colors it is given.
+pub fn post_constants(pass: PostPass, settings: &PostSettings, srgb_backbuffer: bool) -> [f32; 12] {
+    let flag = |enabled: bool| if enabled { 1.0 } else { 0.0 };
+
+    let params = match pass {
+        PostPass::BloomExtract => [settings.bloom_threshold, settings.bloom_knee, 0.0, 0.0],
+        PostPass::BloomHorizontal => [1.0, 0.0, 0.0, 0.0],
+        PostPass::BloomVertical => [0.0, 1.0, 0.0, 0.0],
+        PostPass::Tonemap => [settings.exposure, settings.bloom_intensity, settings.vignette_strength, 0.0],
+        PostPass::Fxaa => [FXAA_SUBPIXEL, FXAA_EDGE_THRESHOLD, FXAA_EDGE_THRESHOLD_MIN, 0.0],
+    };
+
+    //NOTE: With FXAA the tonemapping writes to the LDR attachment, only the last pass writes to the backbuffer
+    let srgb_target = srgb_backbuffer && match pass {
+        PostPass::Tonemap => !settings.fxaa,
+        PostPass::Fxaa => true,
+        _ => false
+    };
+
+    [
+        params[0], params[1], params[2], params[3],
+        flag(settings.tonemapping), flag(settings.bloom), flag(settings.color_grading), flag(settings.vignette),
+        flag(settings.gamma_correction), settings.gamma, flag(srgb_target), 0.0,
+    ]
+}
+
+/// The texels per side of the LUT cube in a square image of `side` texels, None if the size doesn't fit one.
+/// The slices of the cube go left to right and top to bottom, with blue increasing.
+pub fn lut_size(side: u32) -> Option<u32> {
+    let texels = side as u64 * side as u64;
+    let size = (texels as f64).cbrt().round() as u64;
+    let tiles = (size as f64).sqrt().round() as u64;
+
+    if size > 1 && size * size * size == texels && tiles * tiles == size && tiles * size == side as u64 {
+        Some(size as u32)
+    } else {
+        None
+    }
+}
+
+/// A LUT that leaves the colors as they are. `size` has to be a square number.
+pub fn identity_lut(size: u32) -> Vec<[u8; 4]> {
+    let tiles = (size as f32).sqrt().round() as u32;
+    let side = size * tiles;
+    let scale = |value: u32| (value * 255 / (size - 1)) as u8;
+
+    (0..side * side)
+        .map(|texel| {
+            let (x, y) = (texel % side, texel / side);
+            let slice = (y / size) * tiles + x / size;
+
+            [scale(x % size), scale(y % size), scale(slice), 255]
+        })
+        .collect()
+}
+
+/// Brings the HDR image of the main pass to the backbuffer: bloom, exposure and filmic tonemapping,
+/// color grading, vignette, gamma correction and FXAA. Each pass samples the images of the render graph
+/// through a descriptor set of its own, the settings are pushed as constants when it is recorded.
+pub struct PostState<B: Backend> {
+    settings: PostSettings,
+    srgb_backbuffer: bool,
+    descs: BTreeMap<PostPass, DescSet<B>>,
+    /// Only the passes that are in the render graph have one
+    pipelines: BTreeMap<PostPass, PipelineState<B>>,
+    shader_cache: ShaderCache,
+    lut: Option<DataImage<B>>,
+    /// Sampled in place of the bloom when it is off
+    black: Option<DataImage<B>>,
+    device: Rc<RefCell<DeviceState<B>>>,
+    sampler: Option<B::Sampler>,
+    post_desc_pool: Option<B::DescriptorPool>,
+}
+
+impl<B: Backend> PostState<B> {
+    pub fn new(
+        settings: PostSettings,
+        device: Rc<RefCell<DeviceState<B>>>,
+    ) -> Self {
+        let set_count = PostPass::ALL.len();
+
+        let mut post_desc_pool = unsafe {
+            device.borrow().device.create_descriptor_pool(
+                set_count,
+                [
+                    DescriptorRangeDesc {
+                        ty: DescriptorType::Image {
+                            ty: ImageDescriptorType::Sampled {
+                                with_sampler: false
+                            }
+                        },
+                        count: set_count * 3,
+                    },
+                    DescriptorRangeDesc {
+                        ty: DescriptorType::Sampler,
+                        count: set_count,
+                    },
+                ],
+                DescriptorPoolCreateFlags::empty(),
+            )
+        }.ok();
+
+        let sampler = unsafe {
+            device.borrow().device.create_sampler(&SamplerDesc {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                mip_filter: Filter::Nearest,
+                wrap_mode: (WrapMode::Clamp, WrapMode::Clamp, WrapMode::Clamp),
+                lod_bias: Lod(0.0),
+                lod_range: Lod(0.0) .. Lod(0.0),
+                comparison: None,
+                border: PackedColor(0_u32),
+                normalized: true,
+                anisotropy_clamp: None,
+            })
+        }.expect("Can't create post-processing sampler");
+
+        let image_binding = |binding| DescriptorSetLayoutBinding {
+            binding,
+            ty: DescriptorType::Image {
+                ty: ImageDescriptorType::Sampled {
+                    with_sampler: false
+                }
+            },
+            count: 1,
+            stage_flags: ShaderStageFlags::FRAGMENT,
+            immutable_samplers: false,
+        };
+
+        let mut descs = BTreeMap::new();
+        for &pass in PostPass::ALL.iter() {
+            let post_desc = DescSetLayout::new(
+                Rc::clone(&device),
+                vec![
+                    image_binding(SOURCE_BINDING),
+                    DescriptorSetLayoutBinding {
+                        binding: SAMPLER_BINDING,
+                        ty: DescriptorType::Sampler,
+                        count: 1,
+                        stage_flags: ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    },
+                    image_binding(BLOOM_BINDING),
+                    image_binding(LUT_BINDING),
+                ],
+            );
+
+            let mut post_desc = post_desc.create_desc_set(
+                post_desc_pool.as_mut().unwrap()
+            );
+
+            //NOTE: The images are written by `set_inputs`, they change with the render graph
+            post_desc.write_to_state(
+                vec![DescSetWrite {
+                    binding: SAMPLER_BINDING,
+                    array_offset: 0,
+                    descriptors: Some(Descriptor::Sampler(&sampler)),
+                }],
+                &mut device.borrow_mut().device
+            );
+
+            descs.insert(pass, post_desc);
+        }
+
+        let black = DataImage::new(
+            Rc::clone(&device),
+            LINEAR_IMAGE_FORMAT,
+            1,
+            1,
+            &[vec![[0_u8, 0, 0, 255]]],
+        );
+
+        let mut post_state = PostState {
+            settings,
+            srgb_backbuffer: false,
+            descs,
+            pipelines: BTreeMap::new(),
+            shader_cache: ShaderCache::new(),
+            lut: None,
+            black: Some(black),
+            device,
+            sampler: Some(sampler),
+            post_desc_pool,
+        };
+
+        let lut = DataImage::new(
+            Rc::clone(&post_state.device),
+            LINEAR_IMAGE_FORMAT,
+            COLOR_LUT_SIZE * (COLOR_LUT_SIZE as f32).sqrt() as u32,
+            1,
+            &[identity_lut(COLOR_LUT_SIZE)],
+        );
+        post_state.bind_lut(lut);
+
+        post_state
+    }
+
+    pub fn get_settings(&self) -> PostSettings {
+        self.settings
+    }
+
+    /// The caller has to rebuild the render graph if the passes change, see `PostSettings::changes_passes`.
+    pub fn set_settings(&mut self, settings: PostSettings) {
+        self.settings = settings;
+    }
+
+    /// Loads a color grading LUT, a square image with the slices of the color cube, e.g. 64x64 for a 16³ cube.
+    /// The caller has to make sure the device is idle.
+    pub fn set_color_lut(&mut self, path: &str) -> Result<(), AssetError> {
+        let image = ImageState::<B>::load_image(path)?;
+
+        let size = match lut_size(image.width()) {
+            Some(size) if image.width() == image.height() => size,
+            _ => return Err(AssetError::new(format!(
+                "{} is {}x{}, a color LUT has to be square with the slices of a cube",
+                path,
+                image.width(),
+                image.height()
+            )))
+        };
+
+        let texels: Vec<[u8; 4]> = image.pixels()
+            .map(|texel| [texel[0], texel[1], texel[2], texel[3]])
+            .collect();
+
+        let lut = DataImage::new(
+            Rc::clone(&self.device),
+            LINEAR_IMAGE_FORMAT,
+            image.width(),
+            1,
+            &[texels],
+        );
+        self.bind_lut(lut);
+
+        info!("Color LUT {}: {}³", path, size);
+
+        Ok(())
+    }
+
+    fn bind_lut(&mut self, lut: DataImage<B>) {
+        self.descs.get_mut(&PostPass::Tonemap).unwrap().write_to_state(
+            vec![DescSetWrite {
+                binding: LUT_BINDING,
+                array_offset: 0,
+                descriptors: Some(Descriptor::Image(
+                    lut.get_image_view(),
+                    Layout::ShaderReadOnlyOptimal
+                )),
+            }],
+            &mut self.device.borrow_mut().device
+        );
+
+        //The old LUT is only freed after the descriptor stops pointing at it
+        self.lut = Some(lut);
+    }
+
+    /// Adds the passes from the HDR attachment to the backbuffer. The bloom is drawn at a lower resolution,
+    /// with FXAA the tonemapping writes to an LDR attachment first.
+    pub fn add_passes(&self, graph: &mut RenderGraphDesc) {
+        let mut tonemap = PassDesc::new(TONEMAP_PASS);
+        tonemap.add_input(HDR_ATTACHMENT);
+
+        if self.settings.bloom {
+            for name in [BLOOM_EXTRACT_ATTACHMENT, BLOOM_BLUR_ATTACHMENT, BLOOM_ATTACHMENT].iter() {
+                let mut attachment = AttachmentDesc::new(name, HDR_FORMAT);
+                attachment.size = AttachmentSize::Relative(BLOOM_SCALE);
+                graph.add_attachment(attachment);
+            }
+
+            for &pass in [PostPass::BloomExtract, PostPass::BloomHorizontal, PostPass::BloomVertical].iter() {
+                let output = match pass {
+                    PostPass::BloomExtract => BLOOM_EXTRACT_ATTACHMENT,
+                    PostPass::BloomHorizontal => BLOOM_BLUR_ATTACHMENT,
+                    _ => BLOOM_ATTACHMENT
+                };
+
+                let mut bloom_pass = PassDesc::new(pass.name());
+                bloom_pass.add_input(pass.source());
+                bloom_pass.add_color(output);
+                graph.add_pass(bloom_pass);
+            }
+
+            tonemap.add_input(BLOOM_ATTACHMENT);
+        }
+
+        if self.settings.fxaa {
+            graph.add_attachment(AttachmentDesc::new(LDR_ATTACHMENT, LDR_FORMAT));
+            tonemap.add_color(LDR_ATTACHMENT);
+            graph.add_pass(tonemap);
+
+            let mut fxaa = PassDesc::new(FXAA_PASS);
+            fxaa.add_input(LDR_ATTACHMENT);
+            fxaa.add_color(BACKBUFFER_ATTACHMENT);
+            graph.add_pass(fxaa);
+        } else {
+            tonemap.add_color(BACKBUFFER_ATTACHMENT);
+            graph.add_pass(tonemap);
+        }
+    }
+
+    /// Points the descriptor sets to the images of the graph. The caller has to make sure the device is idle.
+    pub fn set_inputs(&mut self, graph: &RenderGraph<B>, backbuffer_format: Format) {
+        self.srgb_backbuffer = backbuffer_format.base_format().1 == ChannelType::Srgb;
+
+        let black = self.black.as_ref().unwrap().get_image_view();
+        let device_state = &mut *self.device.borrow_mut();
+        let device = &mut device_state.device;
+
+        for (pass, desc) in self.descs.iter_mut() {
+            let source = match graph.get_image_view(pass.source()) {
+                Some(source) => source,
+                None => continue
+            };
+
+            desc.write_to_state(
+                vec![DescSetWrite {
+                    binding: SOURCE_BINDING,
+                    array_offset: 0,
+                    descriptors: Some(Descriptor::Image(source, Layout::ShaderReadOnlyOptimal)),
+                }],
+                device
+            );
+
+            if *pass == PostPass::Tonemap {
+                let bloom = graph.get_image_view(BLOOM_ATTACHMENT).unwrap_or(black);
+
+                desc.write_to_state(
+                    vec![DescSetWrite {
+                        binding: BLOOM_BINDING,
+                        array_offset: 0,
+                        descriptors: Some(Descriptor::Image(bloom, Layout::ShaderReadOnlyOptimal)),
+                    }],
+                    device
+                );
+            }
+        }
+    }
+
+    /// Builds the pipelines of the passes in the graph, the previous ones are kept on failure.
+    /// The caller has to make sure the device is idle.
+    pub fn create_pipelines(&mut self, graph: &RenderGraph<B>) -> Result<(), AssetError> {
+        self.pipelines.retain(|pass, _| graph.get_render_pass(pass.name()).is_some());
+
+        //The other passes are still built when one fails
+        let mut result = Ok(());
+        for &pass in PostPass::ALL.iter() {
+            let render_pass = match graph.get_render_pass(pass.name()) {
+                Some(render_pass) => render_pass,
+                None => continue
+            };
+
+            let layouts = vec![&self.descs[&pass].layout];
+            let device = Rc::clone(&self.device);
+            let built = self.pipelines.entry(pass)
+                .or_insert_with(|| PipelineState::empty(device))
+                .new_fullscreen_pipeline(
+                    &mut self.shader_cache,
+                    FULLSCREEN_VERTEX_SHADER_PATH,
+                    pass.fragment_shader(),
+                    size_of::<[f32; 12]>() as u32,
+                    &layouts,
+                    render_pass
+                );
+
+            if let Err(err) = built {
+                result = Err(err);
+            }
+        }
+
+        result
+    }
+
+    /// Draws the pass, inside its render pass.
+    pub unsafe fn record(
+        &self,
+        cmd_buffer: &mut B::CommandBuffer,
+        pass: PostPass,
+    ) {
+        let (pipeline, pipeline_layout) = match self.pipelines.get(&pass) {
+            Some(PipelineState { pipeline: Some(pipeline), pipeline_layout: Some(pipeline_layout), .. }) => (pipeline, pipeline_layout),
+            _ => return
+        };
+
+        let constants: Vec<u32> = post_constants(pass, &self.settings, self.srgb_backbuffer).iter()
+            .map(|value| value.to_bits())
+            .collect();
+
+        cmd_buffer.bind_graphics_pipeline(pipeline);
+        cmd_buffer.bind_graphics_descriptor_sets(
+            pipeline_layout,
+            0,
+            self.descs[&pass].set.as_ref(),
+            &[]
+        );
+        cmd_buffer.push_graphics_constants(pipeline_layout, ShaderStageFlags::FRAGMENT, 0, &constants);
+        cmd_buffer.draw(0..3, 0..1);
+    }
+}
+
+impl<B: Backend> Drop for PostState<B> {
+    fn drop(&mut self) {
+        let device = &self.device.borrow().device;
+        device.wait_idle().unwrap();
+        unsafe {
+            device.destroy_sampler(self.sampler.take().unwrap());
+            device.destroy_descriptor_pool(self.post_desc_pool.take().unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        identity_lut, lut_size, post_constants, PostEffect, PostPass, PostSettings
+    };
+
+    #[test]
+    fn identity_lut_layout() {
+        assert_eq!(lut_size(64), Some(16));
+        assert_eq!(lut_size(512), Some(64));
+        assert_eq!(lut_size(100), None);
+
+        //A 16³ cube in 4x4 slices of 16x16 texels
+        let lut = identity_lut(16);
+        assert_eq!(lut.len(), 64 * 64);
+        assert_eq!(lut[0], [0, 0, 0, 255]);
+        assert_eq!(lut[15], [255, 0, 0, 255]);
+        assert_eq!(lut[16], [0, 0, 17, 255]);
+        assert_eq!(lut[63 * 64 + 63], [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn toggles_rebuild_only_for_passes() {
+        let settings = PostSettings::default();
+
+        let mut vignette = settings;
+        assert!(!vignette.toggle(PostEffect::Vignette));
+        assert!(!settings.changes_passes(&vignette));
+
+        let mut fxaa = settings;
+        assert!(!fxaa.toggle(PostEffect::Fxaa));
+        assert!(settings.changes_passes(&fxaa));
+    }
+
+    #[test]
+    fn only_the_last_pass_writes_srgb() {
+        let mut settings = PostSettings::default();
+
+        assert_eq!(post_constants(PostPass::Tonemap, &settings, true)[10], 0.0);
+        assert_eq!(post_constants(PostPass::Fxaa, &settings, true)[10], 1.0);
+
+        settings.fxaa = false;
+        assert_eq!(post_constants(PostPass::Tonemap, &settings, true)[10], 1.0);
+        assert_eq!(post_constants(PostPass::Tonemap, &settings, false)[10], 0.0);
+    }
+}
@@ -0,0 +1,558 @@
+use super::{
+    error::AssetError,
+    mesh::generate_flat_normals,
+    model::Vertex,
+    tangent::generate_tangents,
+};
+
+use zeus_core::{
+    math::{
+        Vector2,
+        Vector3,
+        Vector4
+    },
+    vfs
+};
+
+use std::{
+    collections::HashMap,
+    f32::consts::PI
+};
+
+/// How the vertices of the primitives are finished. The shapes are centered on the origin with +y up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrimitiveOptions {
+    /// The UVs go from 0 to 1 across the shape, or across each face of a cube. Scaled to repeat the texture
+    pub uv_scale: Vector2,
+    /// Every face gets the normal of its plane, otherwise the normals follow the surface
+    pub flat_normals: bool,
+    /// Needed for normal and parallax mapping
+    pub tangents: bool,
+    pub color: Vector4,
+}
+
+impl Default for PrimitiveOptions {
+    fn default() -> Self {
+        PrimitiveOptions {
+            uv_scale: Vector2::new(1.0, 1.0),
+            flat_normals: false,
+            tangents: true,
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// A plane facing +y, `size` is along x and z. Each side is split in `subdivisions` quads.
+pub fn plane(size: Vector2, subdivisions: u32, options: &PrimitiveOptions) -> (Vec<Vertex>, Vec<u32>) {
+    let mut builder = MeshBuilder::default();
+    let quads = subdivisions.max(1) as usize;
+
+    builder.grid(quads, quads, |column, row| {
+        let (u, v) = (column as f32 / quads as f32, row as f32 / quads as f32);
+
+        (
+            Vector3::new((u - 0.5) * size.x, 0.0, (v - 0.5) * size.y),
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector2::new(u, v)
+        )
+    });
+
+    builder.finish(options)
+}
+
+/// Each face is split in `subdivisions` x `subdivisions` quads and has UVs of its own.
+pub fn cube(size: f32, subdivisions: u32, options: &PrimitiveOptions) -> (Vec<Vertex>, Vec<u32>) {
+    let mut builder = MeshBuilder::default();
+    let quads = subdivisions.max(1) as usize;
+    let half = size * 0.5;
+
+    let normals = [
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(-1.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector3::new(0.0, -1.0, 0.0),
+        Vector3::new(0.0, 0.0, 1.0),
+        Vector3::new(0.0, 0.0, -1.0),
+    ];
+
+    for normal in normals.iter().cloned() {
+        let (right, up) = face_axes(normal);
+
+        builder.grid(quads, quads, |column, row| {
+            let (u, v) = (column as f32 / quads as f32, row as f32 / quads as f32);
+
+            (
+                (normal + right * (u * 2.0 - 1.0) + up * (1.0 - v * 2.0)) * half,
+                normal,
+                Vector2::new(u, v)
+            )
+        });
+    }
+
+    builder.finish(options)
+}
+
+/// A sphere split in `segments` around y and `rings` from pole to pole.
+#[allow(dead_code)]
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32, options: &PrimitiveOptions) -> (Vec<Vertex>, Vec<u32>) {
+    let mut builder = MeshBuilder::default();
+    let (segments, rings) = (segments.max(3) as usize, rings.max(2) as usize);
+
+    builder.grid(segments, rings, |column, row| {
+        let (u, v) = (column as f32 / segments as f32, row as f32 / rings as f32);
+        let normal = sphere_direction(u, v * PI);
+
+        (normal * radius, normal, Vector2::new(u, v))
+    });
+
+    builder.finish(options)
+}
+
+/// An icosahedron with every face split in 4 `subdivisions` times, the triangles are close to the same size.
+#[allow(dead_code)]
+pub fn icosphere(radius: f32, subdivisions: u32, options: &PrimitiveOptions) -> (Vec<Vertex>, Vec<u32>) {
+    let t = (1.0 + 5.0_f32.sqrt()) * 0.5;
+    let mut positions: Vec<Vector3> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+    ].iter()
+        .map(|&(x, y, z)| Vector3::new(x, y, z).normalize())
+        .collect();
+
+    let mut faces: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32, positions: &mut Vec<Vector3>| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push(((positions[a as usize] + positions[b as usize]) * 0.5).normalize());
+                positions.len() as u32 - 1
+            })
+        };
+
+        faces = faces.iter()
+            .flat_map(|&[a, b, c]| {
+                let ab = midpoint(a, b, &mut positions);
+                let bc = midpoint(b, c, &mut positions);
+                let ca = midpoint(c, a, &mut positions);
+
+                vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    //NOTE: The UVs are spherical like the UV sphere, faces across the seam get vertices of their own
+    let mut builder = MeshBuilder::default();
+    let mut vertices: HashMap<(u32, u32, u32), u32> = HashMap::new();
+    for face in faces.iter() {
+        let normals = [positions[face[0] as usize], positions[face[1] as usize], positions[face[2] as usize]];
+        let mut uvs = [sphere_uv(normals[0]), sphere_uv(normals[1]), sphere_uv(normals[2])];
+
+        let (min_u, max_u) = uvs.iter().fold((1.0_f32, 0.0_f32), |(min, max), uv| (min.min(uv.x), max.max(uv.x)));
+        if max_u - min_u > 0.5 {
+            for uv in uvs.iter_mut().filter(|uv| uv.x < 0.5) {
+                uv.x += 1.0;
+            }
+        }
+
+        let mut corners = [0; 3];
+        for corner in 0..3 {
+            let key = (face[corner], uvs[corner].x.to_bits(), uvs[corner].y.to_bits());
+            corners[corner] = *vertices.entry(key)
+                .or_insert_with(|| builder.vertex(normals[corner] * radius, normals[corner], uvs[corner]));
+        }
+
+        builder.triangle(corners[0], corners[1], corners[2]);
+    }
+
+    builder.finish(options)
+}
+
+/// A closed cylinder along y, split in `segments` around and `height_segments` along the side.
+#[allow(dead_code)]
+pub fn cylinder(radius: f32, height: f32, segments: u32, height_segments: u32, options: &PrimitiveOptions) -> (Vec<Vertex>, Vec<u32>) {
+    let mut builder = MeshBuilder::default();
+    let (segments, height_segments) = (segments.max(3) as usize, height_segments.max(1) as usize);
+
+    builder.grid(segments, height_segments, |column, row| {
+        let (u, v) = (column as f32 / segments as f32, row as f32 / height_segments as f32);
+        let normal = sphere_direction(u, PI * 0.5);
+
+        (
+            normal * radius + Vector3::new(0.0, height * (0.5 - v), 0.0),
+            normal,
+            Vector2::new(u, v)
+        )
+    });
+
+    builder.cap(Vector3::new(0.0, height * 0.5, 0.0), Vector3::new(0.0, 1.0, 0.0), radius, segments);
+    builder.cap(Vector3::new(0.0, -height * 0.5, 0.0), Vector3::new(0.0, -1.0, 0.0), radius, segments);
+
+    builder.finish(options)
+}
+
+/// A cone along y with the tip at the top and a closed base.
+#[allow(dead_code)]
+pub fn cone(radius: f32, height: f32, segments: u32, height_segments: u32, options: &PrimitiveOptions) -> (Vec<Vertex>, Vec<u32>) {
+    let mut builder = MeshBuilder::default();
+    let (segments, height_segments) = (segments.max(3) as usize, height_segments.max(1) as usize);
+
+    builder.grid(segments, height_segments, |column, row| {
+        let (u, v) = (column as f32 / segments as f32, row as f32 / height_segments as f32);
+        let direction = sphere_direction(u, PI * 0.5);
+
+        //The normal is perpendicular to the slope, the tip gets one per segment
+        let normal = (direction * height + Vector3::new(0.0, radius, 0.0)).normalize();
+
+        (
+            direction * (radius * v) + Vector3::new(0.0, height * (0.5 - v), 0.0),
+            normal,
+            Vector2::new(u, v)
+        )
+    });
+
+    builder.cap(Vector3::new(0.0, -height * 0.5, 0.0), Vector3::new(0.0, -1.0, 0.0), radius, segments);
+
+    builder.finish(options)
+}
+
+/// A torus around y, `radius` is to the center of the tube.
+#[allow(dead_code)]
+pub fn torus(radius: f32, tube_radius: f32, segments: u32, tube_segments: u32, options: &PrimitiveOptions) -> (Vec<Vertex>, Vec<u32>) {
+    let mut builder = MeshBuilder::default();
+    let (segments, tube_segments) = (segments.max(3) as usize, tube_segments.max(3) as usize);
+
+    builder.grid(segments, tube_segments, |column, row| {
+        let (u, v) = (column as f32 / segments as f32, row as f32 / tube_segments as f32);
+        let direction = sphere_direction(u, PI * 0.5);
+
+        //The tube starts at the top and goes around outwards
+        let angle = v * 2.0 * PI;
+        let normal = Vector3::new(0.0, angle.cos(), 0.0) + direction * angle.sin();
+
+        (direction * radius + normal * tube_radius, normal, Vector2::new(u, v))
+    });
+
+    builder.finish(options)
+}
+
+/// A cylinder along y with hemispheres for caps, `height` is the length of the cylinder between them.
+/// Each hemisphere is split in `rings`, the V coordinate follows the length of the surface.
+#[allow(dead_code)]
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32, options: &PrimitiveOptions) -> (Vec<Vertex>, Vec<u32>) {
+    let mut builder = MeshBuilder::default();
+    let (segments, rings) = (segments.max(3) as usize, rings.max(1) as usize);
+    let length = PI * radius + height;
+
+    //The row between the hemispheres is the cylinder
+    builder.grid(segments, rings * 2 + 1, |column, row| {
+        let u = column as f32 / segments as f32;
+        let (angle, offset) = if row <= rings {
+            (row as f32 / rings as f32 * PI * 0.5, height * 0.5)
+        } else {
+            ((1.0 + (row - rings - 1) as f32 / rings as f32) * PI * 0.5, -height * 0.5)
+        };
+
+        let normal = sphere_direction(u, angle);
+        let distance = angle * radius + if row <= rings { 0.0 } else { height };
+
+        (
+            normal * radius + Vector3::new(0.0, offset, 0.0),
+            normal,
+            Vector2::new(u, distance / length)
+        )
+    });
+
+    builder.finish(options)
+}
+
+/// Heights from 0 to 1 on a grid, sampled with bilinear filtering.
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightmap {
+    pub columns: usize,
+    pub rows: usize,
+    /// Row by row, starting at -z
+    pub heights: Vec<f32>,
+}
+
+#[allow(dead_code)]
+impl Heightmap {
+    pub fn new(columns: usize, rows: usize, heights: Vec<f32>) -> Result<Self, AssetError> {
+        if columns == 0 || rows == 0 || heights.len() != columns * rows {
+            return Err(AssetError::new(format!("A {}x{} heightmap can't have {} heights", columns, rows, heights.len())));
+        }
+
+        Ok(Heightmap {
+            columns,
+            rows,
+            heights,
+        })
+    }
+
+    /// The brightness of an image, the top of the image is at -z.
+    pub fn load(path: &str) -> Result<Self, AssetError> {
+        let bytes = vfs::read(path)
+            .map_err(|err| AssetError::new(format!("Could not read heightmap {}: {:?}", path, err)))?;
+
+        let img = image::load_from_memory(&bytes)
+            .map_err(|err| AssetError::new(format!("Could not decode heightmap {}: {:?}", path, err)))?
+            .to_luma();
+
+        let heights = img.pixels()
+            .map(|texel| texel[0] as f32 / 255.0)
+            .collect();
+
+        Self::new(img.width() as usize, img.height() as usize, heights)
+    }
+
+    /// `u` and `v` go from 0 to 1 across the map and are clamped to it.
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = u.clamp(0.0, 1.0) * (self.columns - 1) as f32;
+        let y = v.clamp(0.0, 1.0) * (self.rows - 1) as f32;
+
+        let (column, row) = (x.floor() as usize, y.floor() as usize);
+        let (next_column, next_row) = ((column + 1).min(self.columns - 1), (row + 1).min(self.rows - 1));
+        let (tx, ty) = (x - column as f32, y - row as f32);
+
+        let height = |column: usize, row: usize| self.heights[row * self.columns + column];
+        let top = height(column, row) * (1.0 - tx) + height(next_column, row) * tx;
+        let bottom = height(column, next_row) * (1.0 - tx) + height(next_column, next_row) * tx;
+
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+/// A plane displaced by the heightmap, `size` is the extent along x and z and the height of a full sample along y.
+/// Each side is split in `subdivisions` quads, independent of the size of the heightmap.
+#[allow(dead_code)]
+pub fn terrain(heightmap: &Heightmap, size: Vector3, subdivisions: u32, options: &PrimitiveOptions) -> (Vec<Vertex>, Vec<u32>) {
+    let mut builder = MeshBuilder::default();
+    let quads = subdivisions.max(1) as usize;
+    let step = 1.0 / quads as f32;
+
+    builder.grid(quads, quads, |column, row| {
+        let (u, v) = (column as f32 * step, row as f32 * step);
+
+        //Central differences, one quad to each side
+        let slope_x = (heightmap.sample(u + step, v) - heightmap.sample(u - step, v)) * size.y / (2.0 * step * size.x);
+        let slope_z = (heightmap.sample(u, v + step) - heightmap.sample(u, v - step)) * size.y / (2.0 * step * size.z);
+
+        (
+            Vector3::new((u - 0.5) * size.x, heightmap.sample(u, v) * size.y, (v - 0.5) * size.z),
+            Vector3::new(-slope_x, 1.0, -slope_z).normalize(),
+            Vector2::new(u, v)
+        )
+    });
+
+    builder.finish(options)
+}
+
+/// Points around y: `u` goes from +x towards -z, `angle` from +y down to -y.
+fn sphere_direction(u: f32, angle: f32) -> Vector3 {
+    let around = u * 2.0 * PI;
+
+    Vector3::new(angle.sin() * around.cos(), angle.cos(), -angle.sin() * around.sin())
+}
+
+/// The inverse of `sphere_direction`, U is in [0, 1).
+fn sphere_uv(direction: Vector3) -> Vector2 {
+    let u = (-direction.z).atan2(direction.x) / (2.0 * PI);
+
+    Vector2::new(if u < 0.0 { u + 1.0 } else { u }, direction.y.clamp(-1.0, 1.0).acos() / PI)
+}
+
+/// The right and up directions of a face seen from the side its normal points to, for planar UVs.
+fn face_axes(normal: Vector3) -> (Vector3, Vector3) {
+    let up = if normal.y.abs() > 0.5 {
+        Vector3::new(0.0, 0.0, -normal.y.signum())
+    } else {
+        Vector3::new(0.0, 1.0, 0.0)
+    };
+
+    (up.cross(&normal), up)
+}
+
+#[derive(Default)]
+struct MeshBuilder {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    fn vertex(&mut self, pos: Vector3, normal: Vector3, uv: Vector2) -> u32 {
+        self.vertices.push(Vertex {
+            a_pos: pos,
+            a_color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            a_uv: uv,
+            a_normal: normal,
+            a_tangent: Vector4::default(),
+            a_joints: Vector4::default(),
+            a_weights: Vector4::default(),
+        });
+
+        self.vertices.len() as u32 - 1
+    }
+
+    /// Wound to face the side the normals of its vertices point to. Degenerate triangles, like the ones on the poles, are dropped.
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        let [va, vb, vc] = [self.vertices[a as usize], self.vertices[b as usize], self.vertices[c as usize]];
+        let face_normal = (vb.a_pos - va.a_pos).cross(&(vc.a_pos - va.a_pos));
+        if face_normal.magn() < 1e-12 {
+            return;
+        }
+
+        if face_normal.dot(&(va.a_normal + vb.a_normal + vc.a_normal)) < 0.0 {
+            self.indices.extend_from_slice(&[a, c, b]);
+        } else {
+            self.indices.extend_from_slice(&[a, b, c]);
+        }
+    }
+
+    /// `columns` x `rows` quads, `vertex` gets the column and row of every corner
+    /// and returns its position, normal and UV.
+    fn grid(&mut self, columns: usize, rows: usize, vertex: impl Fn(usize, usize) -> (Vector3, Vector3, Vector2)) {
+        let first = self.vertices.len() as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (pos, normal, uv) = vertex(column, row);
+                self.vertex(pos, normal, uv);
+            }
+        }
+
+        let stride = columns as u32 + 1;
+        for row in 0..rows as u32 {
+            for column in 0..columns as u32 {
+                let corner = first + row * stride + column;
+
+                self.triangle(corner, corner + 1, corner + stride + 1);
+                self.triangle(corner + stride + 1, corner + stride, corner);
+            }
+        }
+    }
+
+    /// A disc facing up or down, with planar UVs.
+    fn cap(&mut self, center: Vector3, normal: Vector3, radius: f32, segments: usize) {
+        let (right, up) = face_axes(normal);
+        let middle = self.vertex(center, normal, Vector2::new(0.5, 0.5));
+
+        let first = self.vertices.len() as u32;
+        for segment in 0..=segments {
+            let offset = sphere_direction(segment as f32 / segments as f32, PI * 0.5) * radius;
+            let uv = Vector2::new(
+                0.5 + offset.dot(&right) / (2.0 * radius),
+                0.5 - offset.dot(&up) / (2.0 * radius)
+            );
+
+            self.vertex(center + offset, normal, uv);
+        }
+
+        for segment in 0..segments as u32 {
+            self.triangle(middle, first + segment, first + segment + 1);
+        }
+    }
+
+    fn finish(self, options: &PrimitiveOptions) -> (Vec<Vertex>, Vec<u32>) {
+        let MeshBuilder { mut vertices, mut indices } = self;
+
+        for vertex in vertices.iter_mut() {
+            vertex.a_uv = Vector2::new(vertex.a_uv.x * options.uv_scale.x, vertex.a_uv.y * options.uv_scale.y);
+            vertex.a_color = options.color;
+        }
+
+        if options.flat_normals {
+            let (flat_vertices, flat_indices) = generate_flat_normals(&vertices, &indices);
+            vertices = flat_vertices;
+            indices = flat_indices;
+        }
+
+        if options.tangents {
+            generate_tangents(&mut vertices, &mut indices);
+        }
+
+        (vertices, indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        capsule, cone, cube, cylinder, icosphere, plane, terrain, torus, uv_sphere, Heightmap, PrimitiveOptions
+    };
+
+    use crate::model::Vertex;
+
+    use zeus_core::math::{
+        Vector2,
+        Vector3
+    };
+
+    use std::f32::consts::PI;
+
+    /// Positive when the faces point outwards.
+    fn volume((vertices, indices): &(Vec<Vertex>, Vec<u32>)) -> f32 {
+        indices.chunks_exact(3)
+            .map(|face| {
+                let [a, b, c] = [vertices[face[0] as usize].a_pos, vertices[face[1] as usize].a_pos, vertices[face[2] as usize].a_pos];
+                a.dot(&b.cross(&c)) / 6.0
+            })
+            .sum()
+    }
+
+    #[test]
+    fn closed_shapes_face_outwards() {
+        let options = PrimitiveOptions::default();
+        let close = |volume: f32, expected: f32| (volume - expected).abs() < expected * 0.02;
+
+        assert!(close(volume(&cube(2.0, 3, &options)), 8.0));
+        assert!(close(volume(&uv_sphere(1.0, 64, 32, &options)), 4.0 / 3.0 * PI));
+        assert!(close(volume(&icosphere(1.0, 3, &options)), 4.0 / 3.0 * PI));
+        assert!(close(volume(&cylinder(1.0, 2.0, 64, 2, &options)), 2.0 * PI));
+        assert!(close(volume(&cone(1.0, 3.0, 64, 2, &options)), PI));
+        assert!(close(volume(&torus(2.0, 0.5, 64, 32, &options)), 2.0 * PI * PI * 2.0 * 0.25));
+        assert!(close(volume(&capsule(1.0, 2.0, 64, 16, &options)), 4.0 / 3.0 * PI + 2.0 * PI));
+
+        let flat = PrimitiveOptions {
+            flat_normals: true,
+            ..options
+        };
+        assert!(close(volume(&uv_sphere(1.0, 64, 32, &flat)), 4.0 / 3.0 * PI));
+    }
+
+    #[test]
+    fn plane_grid() {
+        let options = PrimitiveOptions {
+            uv_scale: Vector2::new(4.0, 2.0),
+            ..PrimitiveOptions::default()
+        };
+        let (vertices, indices) = plane(Vector2::new(2.0, 4.0), 2, &options);
+
+        assert_eq!(vertices.len(), 9);
+        assert_eq!(indices.len(), 24);
+        assert!(vertices.iter().all(|vertex| vertex.a_normal == Vector3::new(0.0, 1.0, 0.0)));
+        assert_eq!(vertices[8].a_pos, Vector3::new(1.0, 0.0, 2.0));
+        assert_eq!(vertices[8].a_uv, Vector2::new(4.0, 2.0));
+    }
+
+    #[test]
+    fn terrain_follows_heightmap() {
+        assert!(Heightmap::new(2, 2, vec![0.0; 3]).is_err());
+
+        //A slope rising towards +x
+        let heightmap = Heightmap::new(2, 2, vec![0.0, 1.0, 0.0, 1.0]).unwrap();
+        assert_eq!(heightmap.sample(0.25, 0.5), 0.25);
+
+        let (vertices, _) = terrain(&heightmap, Vector3::new(2.0, 2.0, 2.0), 4, &PrimitiveOptions::default());
+        assert_eq!(vertices.len(), 25);
+        assert_eq!(vertices[24].a_pos, Vector3::new(1.0, 2.0, 1.0));
+
+        let normal = vertices[12].a_normal;
+        assert!(normal.x < 0.0 && normal.y > 0.0 && normal.z.abs() < 1e-6);
+    }
+}
@@ -0,0 +1,593 @@
+use gfx_hal::{
+    format::Format,
+    pso::{
+        AttributeDesc, BufferDescriptorType, DescriptorSetLayoutBinding, DescriptorType, ImageDescriptorType, ShaderStageFlags
+    },
+};
+
+use std::collections::BTreeMap;
+
+use super::{
+    error::AssetError,
+    vertex_layout::format_components,
+};
+
+//region SPIR-V constants
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const SPIRV_HEADER_LEN: usize = 5;
+
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_INPUT: u32 = 1;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+const EXECUTION_MODEL_VERTEX: u32 = 0;
+const EXECUTION_MODEL_TESSELLATION_CONTROL: u32 = 1;
+const EXECUTION_MODEL_TESSELLATION_EVALUATION: u32 = 2;
+const EXECUTION_MODEL_GEOMETRY: u32 = 3;
+const EXECUTION_MODEL_FRAGMENT: u32 = 4;
+const EXECUTION_MODEL_GL_COMPUTE: u32 = 5;
+
+const IMAGE_SAMPLED_STORAGE: u32 = 2;
+
+//endregion
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorKind {
+    UniformBuffer,
+    StorageBuffer,
+    SampledImage,
+    StorageImage,
+    Sampler,
+    CombinedImageSampler,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShaderInput {
+    pub name: String,
+    pub location: u32,
+    pub format: Option<Format>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShaderDescriptor {
+    pub name: String,
+    pub set: u32,
+    pub binding: u32,
+    pub kind: DescriptorKind,
+    pub count: u32,
+}
+
+/// The interface of a shader module, read from its SPIR-V.
+#[derive(Debug, Clone)]
+pub struct ShaderReflection {
+    pub stage: ShaderStageFlags,
+    pub inputs: Vec<ShaderInput>,
+    pub descriptors: Vec<ShaderDescriptor>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SpirvType {
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct,
+    Pointer { ty: u32 },
+}
+
+#[derive(Default)]
+struct Decorations {
+    location: Option<u32>,
+    binding: Option<u32>,
+    set: Option<u32>,
+    block: bool,
+    buffer_block: bool,
+}
+
+impl ShaderReflection {
+    pub fn new(spirv: &[u32]) -> Result<Self, AssetError> {
+        if spirv.len() < SPIRV_HEADER_LEN || spirv[0] != SPIRV_MAGIC {
+            return Err(AssetError::new("Invalid SPIR-V header".to_string()));
+        }
+
+        let mut stage = ShaderStageFlags::empty();
+        let mut names: BTreeMap<u32, String> = BTreeMap::new();
+        let mut types: BTreeMap<u32, SpirvType> = BTreeMap::new();
+        let mut constants: BTreeMap<u32, u32> = BTreeMap::new();
+        let mut decorations: BTreeMap<u32, Decorations> = BTreeMap::new();
+        let mut variables: Vec<(u32, u32, u32)> = Vec::new();
+
+        let mut offset = SPIRV_HEADER_LEN;
+        while offset < spirv.len() {
+            let word_count = (spirv[offset] >> 16) as usize;
+            let opcode = spirv[offset] & 0xffff;
+
+            if word_count == 0 || offset + word_count > spirv.len() {
+                return Err(AssetError::new("Malformed SPIR-V instruction".to_string()));
+            }
+
+            let operands = &spirv[offset + 1..offset + word_count];
+
+            match opcode {
+                OP_NAME if operands.len() > 1 => {
+                    names.insert(operands[0], read_string(&operands[1..]));
+                },
+                OP_ENTRY_POINT if !operands.is_empty() => {
+                    stage |= match operands[0] {
+                        EXECUTION_MODEL_VERTEX => ShaderStageFlags::VERTEX,
+                        EXECUTION_MODEL_TESSELLATION_CONTROL => ShaderStageFlags::HULL,
+                        EXECUTION_MODEL_TESSELLATION_EVALUATION => ShaderStageFlags::DOMAIN,
+                        EXECUTION_MODEL_GEOMETRY => ShaderStageFlags::GEOMETRY,
+                        EXECUTION_MODEL_FRAGMENT => ShaderStageFlags::FRAGMENT,
+                        EXECUTION_MODEL_GL_COMPUTE => ShaderStageFlags::COMPUTE,
+                        _ => ShaderStageFlags::empty(),
+                    };
+                },
+                OP_TYPE_INT if operands.len() >= 3 => {
+                    types.insert(operands[0], SpirvType::Int {
+                        width: operands[1],
+                        signed: operands[2] != 0,
+                    });
+                },
+                OP_TYPE_FLOAT if operands.len() >= 2 => {
+                    types.insert(operands[0], SpirvType::Float { width: operands[1] });
+                },
+                OP_TYPE_VECTOR if operands.len() >= 3 => {
+                    types.insert(operands[0], SpirvType::Vector {
+                        component: operands[1],
+                        count: operands[2],
+                    });
+                },
+                OP_TYPE_MATRIX if operands.len() >= 3 => {
+                    types.insert(operands[0], SpirvType::Matrix {
+                        column: operands[1],
+                        count: operands[2],
+                    });
+                },
+                OP_TYPE_IMAGE if operands.len() >= 7 => {
+                    types.insert(operands[0], SpirvType::Image { sampled: operands[6] });
+                },
+                OP_TYPE_SAMPLER if !operands.is_empty() => {
+                    types.insert(operands[0], SpirvType::Sampler);
+                },
+                OP_TYPE_SAMPLED_IMAGE if !operands.is_empty() => {
+                    types.insert(operands[0], SpirvType::SampledImage);
+                },
+                OP_TYPE_ARRAY if operands.len() >= 3 => {
+                    types.insert(operands[0], SpirvType::Array {
+                        element: operands[1],
+                        length: operands[2],
+                    });
+                },
+                OP_TYPE_RUNTIME_ARRAY if operands.len() >= 2 => {
+                    types.insert(operands[0], SpirvType::RuntimeArray { element: operands[1] });
+                },
+                OP_TYPE_STRUCT if !operands.is_empty() => {
+                    types.insert(operands[0], SpirvType::Struct);
+                },
+                OP_TYPE_POINTER if operands.len() >= 3 => {
+                    types.insert(operands[0], SpirvType::Pointer { ty: operands[2] });
+                },
+                OP_CONSTANT if operands.len() >= 3 => {
+                    constants.insert(operands[1], operands[2]);
+                },
+                OP_VARIABLE if operands.len() >= 3 => {
+                    variables.push((operands[0], operands[1], operands[2]));
+                },
+                OP_DECORATE if operands.len() >= 2 => {
+                    let decoration = decorations.entry(operands[0]).or_default();
+                    match (operands[1], operands.get(2)) {
+                        (DECORATION_BLOCK, _) => decoration.block = true,
+                        (DECORATION_BUFFER_BLOCK, _) => decoration.buffer_block = true,
+                        (DECORATION_LOCATION, Some(value)) => decoration.location = Some(*value),
+                        (DECORATION_BINDING, Some(value)) => decoration.binding = Some(*value),
+                        (DECORATION_DESCRIPTOR_SET, Some(value)) => decoration.set = Some(*value),
+                        _ => {}
+                    }
+                },
+                _ => {}
+            }
+
+            offset += word_count;
+        }
+
+        let mut inputs = Vec::new();
+        let mut descriptors = Vec::new();
+
+        for (pointer_type, id, storage_class) in variables {
+            let name = names.get(&id).cloned().unwrap_or_default();
+            let decoration = decorations.get(&id);
+
+            let ty = match types.get(&pointer_type) {
+                Some(SpirvType::Pointer { ty }) => *ty,
+                _ => continue,
+            };
+
+            match storage_class {
+                STORAGE_CLASS_INPUT => {
+                    //NOTE: Built-ins such as gl_VertexIndex have no location
+                    //Matrices take one location per column
+                    if let Some(location) = decoration.and_then(|d| d.location) {
+                        let (ty, columns) = match types.get(&ty) {
+                            Some(SpirvType::Matrix { column, count }) => (*column, *count),
+                            _ => (ty, 1),
+                        };
+
+                        for column in 0..columns {
+                            inputs.push(ShaderInput {
+                                name: name.clone(),
+                                location: location + column,
+                                format: vertex_format(&types, ty),
+                            });
+                        }
+                    }
+                },
+                STORAGE_CLASS_UNIFORM | STORAGE_CLASS_UNIFORM_CONSTANT | STORAGE_CLASS_STORAGE_BUFFER => {
+                    let (element, count) = array_element(&types, &constants, ty);
+
+                    let kind = match (storage_class, types.get(&element)) {
+                        (STORAGE_CLASS_STORAGE_BUFFER, _) => DescriptorKind::StorageBuffer,
+                        (STORAGE_CLASS_UNIFORM, _) => {
+                            if decorations.get(&element).is_some_and(|d| d.buffer_block) {
+                                DescriptorKind::StorageBuffer
+                            } else {
+                                DescriptorKind::UniformBuffer
+                            }
+                        },
+                        (_, Some(SpirvType::Sampler)) => DescriptorKind::Sampler,
+                        (_, Some(SpirvType::SampledImage)) => DescriptorKind::CombinedImageSampler,
+                        (_, Some(SpirvType::Image { sampled })) if *sampled == IMAGE_SAMPLED_STORAGE => {
+                            DescriptorKind::StorageImage
+                        },
+                        (_, Some(SpirvType::Image { .. })) => DescriptorKind::SampledImage,
+                        _ => continue,
+                    };
+
+                    descriptors.push(ShaderDescriptor {
+                        name,
+                        set: decoration.and_then(|d| d.set).unwrap_or(0),
+                        binding: decoration.and_then(|d| d.binding).unwrap_or(0),
+                        kind,
+                        count,
+                    });
+                },
+                _ => {}
+            }
+        }
+
+        inputs.sort_by_key(|input| input.location);
+        descriptors.sort_by_key(|desc| (desc.set, desc.binding));
+
+        Ok(ShaderReflection {
+            stage,
+            inputs,
+            descriptors,
+        })
+    }
+
+    /// Checks that every vertex input of the shader is provided by the vertex attributes.
+    pub fn validate_vertex_inputs(&self, attributes: &[AttributeDesc]) -> Result<(), AssetError> {
+        for input in self.inputs.iter() {
+            let attribute = attributes.iter()
+                .find(|attribute| attribute.location == input.location)
+                .ok_or_else(|| AssetError::new(format!(
+                    "Vertex input '{}' at location {} has no matching vertex attribute",
+                    input.name,
+                    input.location
+                )))?;
+
+            if let Some(format) = input.format {
+                if !is_compatible_format(format, attribute.element.format) {
+                    return Err(AssetError::new(format!(
+                        "Vertex input '{}' at location {} expects {:?} but the vertex attribute is {:?}",
+                        input.name,
+                        input.location,
+                        format,
+                        attribute.element.format
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every descriptor used by the shader exists in the descriptor set layouts,
+    /// with a compatible type and visible to the shader stage.
+    pub fn validate_descriptors(&self, layouts: &[&[DescriptorSetLayoutBinding]]) -> Result<(), AssetError> {
+        for desc in self.descriptors.iter() {
+            let bindings = layouts.get(desc.set as usize)
+                .ok_or_else(|| AssetError::new(format!(
+                    "Descriptor '{}' uses set {} but the pipeline only has {} sets",
+                    desc.name,
+                    desc.set,
+                    layouts.len()
+                )))?;
+
+            let binding = bindings.iter()
+                .find(|binding| binding.binding == desc.binding)
+                .ok_or_else(|| AssetError::new(format!(
+                    "Descriptor '{}' (set = {}, binding = {}) is missing from the descriptor set layout",
+                    desc.name,
+                    desc.set,
+                    desc.binding
+                )))?;
+
+            if !desc.kind.is_compatible(binding.ty) {
+                return Err(AssetError::new(format!(
+                    "Descriptor '{}' (set = {}, binding = {}) is {:?} but the layout declares {:?}",
+                    desc.name,
+                    desc.set,
+                    desc.binding,
+                    desc.kind,
+                    binding.ty
+                )));
+            }
+
+            if !binding.stage_flags.contains(self.stage) {
+                return Err(AssetError::new(format!(
+                    "Descriptor '{}' (set = {}, binding = {}) is not visible to the {:?} stage",
+                    desc.name,
+                    desc.set,
+                    desc.binding,
+                    self.stage
+                )));
+            }
+
+            if (binding.count as u32) < desc.count {
+                return Err(AssetError::new(format!(
+                    "Descriptor '{}' (set = {}, binding = {}) needs {} descriptors but the layout has {}",
+                    desc.name,
+                    desc.set,
+                    desc.binding,
+                    desc.count,
+                    binding.count
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl DescriptorKind {
+    pub fn is_compatible(self, ty: DescriptorType) -> bool {
+        matches!(
+            (self, ty),
+            (DescriptorKind::UniformBuffer, DescriptorType::Buffer {
+                ty: BufferDescriptorType::Uniform, ..
+            })
+            | (DescriptorKind::StorageBuffer, DescriptorType::Buffer {
+                ty: BufferDescriptorType::Storage { .. }, ..
+            })
+            | (DescriptorKind::SampledImage, DescriptorType::Image {
+                ty: ImageDescriptorType::Sampled { with_sampler: false }
+            })
+            | (DescriptorKind::CombinedImageSampler, DescriptorType::Image {
+                ty: ImageDescriptorType::Sampled { with_sampler: true }
+            })
+            | (DescriptorKind::StorageImage, DescriptorType::Image {
+                ty: ImageDescriptorType::Storage { .. }
+            })
+            | (DescriptorKind::Sampler, DescriptorType::Sampler)
+        )
+    }
+}
+
+fn read_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words.iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .take_while(|byte| *byte != 0)
+        .collect();
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn array_element(
+    types: &BTreeMap<u32, SpirvType>,
+    constants: &BTreeMap<u32, u32>,
+    ty: u32,
+) -> (u32, u32) {
+    match types.get(&ty) {
+        Some(SpirvType::Array { element, length }) => {
+            (*element, constants.get(length).copied().unwrap_or(1))
+        },
+        Some(SpirvType::RuntimeArray { element }) => (*element, 1),
+        _ => (ty, 1),
+    }
+}
+
+/// The attribute has at least the components the shader reads and is read as the same numeric type,
+/// normalized and half float attributes are read as floats.
+fn is_compatible_format(input: Format, attribute: Format) -> bool {
+    match (format_components(input), format_components(attribute)) {
+        (Some((input_components, input_type)), Some((components, numeric_type))) => components >= input_components && numeric_type == input_type,
+        _ => input == attribute
+    }
+}
+
+fn vertex_format(types: &BTreeMap<u32, SpirvType>, ty: u32) -> Option<Format> {
+    let (component, count) = match types.get(&ty)? {
+        SpirvType::Vector { component, count } => (*component, *count),
+        _ => (ty, 1),
+    };
+
+    match (types.get(&component)?, count) {
+        (SpirvType::Float { width: 32 }, 1) => Some(Format::R32Sfloat),
+        (SpirvType::Float { width: 32 }, 2) => Some(Format::Rg32Sfloat),
+        (SpirvType::Float { width: 32 }, 3) => Some(Format::Rgb32Sfloat),
+        (SpirvType::Float { width: 32 }, 4) => Some(Format::Rgba32Sfloat),
+        (SpirvType::Int { width: 32, signed: true }, 1) => Some(Format::R32Sint),
+        (SpirvType::Int { width: 32, signed: true }, 2) => Some(Format::Rg32Sint),
+        (SpirvType::Int { width: 32, signed: true }, 3) => Some(Format::Rgb32Sint),
+        (SpirvType::Int { width: 32, signed: true }, 4) => Some(Format::Rgba32Sint),
+        (SpirvType::Int { width: 32, signed: false }, 1) => Some(Format::R32Uint),
+        (SpirvType::Int { width: 32, signed: false }, 2) => Some(Format::Rg32Uint),
+        (SpirvType::Int { width: 32, signed: false }, 3) => Some(Format::Rgb32Uint),
+        (SpirvType::Int { width: 32, signed: false }, 4) => Some(Format::Rgba32Uint),
+        _ => None,
+    }
+}
+
+//region Tests
+
+#[cfg(test)]
+mod tests {
+    use super::{DescriptorKind, ShaderReflection};
+
+    use gfx_hal::{
+        format::Format,
+        pso::{
+            AttributeDesc, BufferDescriptorFormat, BufferDescriptorType, DescriptorSetLayoutBinding, DescriptorType, Element, ShaderStageFlags
+        },
+    };
+
+    fn instruction(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    // A vertex shader with `layout(location = 0) in vec3 a_pos;`
+    // and `layout(set = 0, binding = 0) uniform UBO { ... };`
+    fn vertex_module() -> Vec<u32> {
+        let mut words = vec![super::SPIRV_MAGIC, 0x0001_0000, 0, 20, 0];
+        words.extend(instruction(15, &[0, 1, 0x6e69_616d, 0]));
+        words.extend(instruction(71, &[2, 30, 0]));
+        words.extend(instruction(71, &[6, 2]));
+        words.extend(instruction(71, &[8, 33, 0]));
+        words.extend(instruction(71, &[8, 34, 0]));
+        words.extend(instruction(22, &[3, 32]));
+        words.extend(instruction(23, &[4, 3, 3]));
+        words.extend(instruction(32, &[5, 1, 4]));
+        words.extend(instruction(59, &[5, 2, 1]));
+        words.extend(instruction(30, &[6, 4]));
+        words.extend(instruction(32, &[7, 2, 6]));
+        words.extend(instruction(59, &[7, 8, 2]));
+        words
+    }
+
+    #[test]
+    fn reflect_vertex_module() {
+        let reflection = ShaderReflection::new(&vertex_module()).unwrap();
+
+        assert_eq!(reflection.stage, ShaderStageFlags::VERTEX);
+        assert_eq!(reflection.inputs.len(), 1);
+        assert_eq!(reflection.inputs[0].location, 0);
+        assert_eq!(reflection.inputs[0].format, Some(Format::Rgb32Sfloat));
+        assert_eq!(reflection.descriptors.len(), 1);
+        assert_eq!(reflection.descriptors[0].kind, DescriptorKind::UniformBuffer);
+    }
+
+    // `layout(location = 7) in mat4 a_instance_model;`
+    #[test]
+    fn reflect_matrix_input() {
+        let mut words = vec![super::SPIRV_MAGIC, 0x0001_0000, 0, 20, 0];
+        words.extend(instruction(15, &[0, 1, 0x6e69_616d, 0]));
+        words.extend(instruction(71, &[2, 30, 7]));
+        words.extend(instruction(22, &[3, 32]));
+        words.extend(instruction(23, &[4, 3, 4]));
+        words.extend(instruction(24, &[5, 4, 4]));
+        words.extend(instruction(32, &[6, 1, 5]));
+        words.extend(instruction(59, &[6, 2, 1]));
+
+        let reflection = ShaderReflection::new(&words).unwrap();
+
+        assert_eq!(reflection.inputs.iter().map(|input| input.location).collect::<Vec<_>>(), vec![7, 8, 9, 10]);
+        assert!(reflection.inputs.iter().all(|input| input.format == Some(Format::Rgba32Sfloat)));
+    }
+
+    #[test]
+    fn validate_mismatched_vertex_input() {
+        let reflection = ShaderReflection::new(&vertex_module()).unwrap();
+
+        let matching = [AttributeDesc {
+            binding: 0,
+            location: 0,
+            element: Element { format: Format::Rgb32Sfloat, offset: 0 },
+        }];
+        let mismatched = [AttributeDesc {
+            binding: 0,
+            location: 0,
+            element: Element { format: Format::Rg32Sfloat, offset: 0 },
+        }];
+
+        assert!(reflection.validate_vertex_inputs(&matching).is_ok());
+        assert!(reflection.validate_vertex_inputs(&mismatched).is_err());
+    }
+
+    #[test]
+    fn validate_packed_vertex_input() {
+        let reflection = ShaderReflection::new(&vertex_module()).unwrap();
+
+        let attribute = |format| [AttributeDesc {
+            binding: 0,
+            location: 0,
+            element: Element { format, offset: 0 },
+        }];
+
+        //Normalized and half floats are read as floats, extra components are dropped
+        assert!(reflection.validate_vertex_inputs(&attribute(Format::Rgba8Snorm)).is_ok());
+        assert!(reflection.validate_vertex_inputs(&attribute(Format::Rgba16Sfloat)).is_ok());
+        assert!(reflection.validate_vertex_inputs(&attribute(Format::Rgba8Uint)).is_err());
+        assert!(reflection.validate_vertex_inputs(&attribute(Format::Rg16Sfloat)).is_err());
+    }
+
+    #[test]
+    fn validate_descriptors() {
+        let reflection = ShaderReflection::new(&vertex_module()).unwrap();
+
+        let bindings = vec![DescriptorSetLayoutBinding {
+            binding: 0,
+            ty: DescriptorType::Buffer {
+                ty: BufferDescriptorType::Uniform,
+                format: BufferDescriptorFormat::Structured {
+                    dynamic_offset: false
+                }
+            },
+            count: 1,
+            stage_flags: ShaderStageFlags::VERTEX,
+            immutable_samplers: false,
+        }];
+
+        assert!(reflection.validate_descriptors(&[&bindings]).is_ok());
+        assert!(reflection.validate_descriptors(&[]).is_err());
+    }
+
+    #[test]
+    fn invalid_header() {
+        assert!(ShaderReflection::new(&[0, 1, 2, 3, 4]).is_err());
+    }
+}
+
+//endregion
@@ -1,13 +1,17 @@
 extern crate gfx_backend_vulkan as back;
 
 use gfx_hal::{
+    buffer::SubRange,
     command::{
-        ClearColor, ClearValue, CommandBuffer, CommandBufferFlags, ClearDepthStencil, Level, SubpassContents
+        self, CommandBuffer, CommandBufferFlags, Level
     },
     device::Device,
-    pool::CommandPool,
+    image::NumSamples,
+    pool::{
+        CommandPool, CommandPoolCreateFlags
+    },
     pso::{
-        ColorValue, Rect, Viewport
+        ClearRect, ColorValue, Rect, ShaderStageFlags, Viewport
     },
     queue::{
         CommandQueue, Submission
@@ -20,31 +24,75 @@ use gfx_hal::{
 
 use super::{
     backend::BackendState,
-    buffer::DepthBuffer,
-    camera::CameraState,
+    buffer::BufferState,
+    camera::{
+        Camera, CameraState
+    },
+    controller::{
+        CameraController, ControllerInput, ControllerKind
+    },
+    culling::{
+        CullingSettings, CullingStats
+    },
     constants::{
-        DEPTH_IMAGE_FORMAT, DIMS
+        ANIMATION_CROSSFADE, BENCHMARK_GRID_SIZE, BENCHMARK_MATERIAL, BENCHMARK_SPACING, BENCHMARK_WALL_HEIGHT, COLOR_ATTACHMENT, DEFAULT_ATTRIBUTE_BINDING, DEPTH_ATTACHMENT, DEPTH_IMAGE_FORMAT, DIMS, ENVIRONMENT_SIZE, FRAMES_IN_FLIGHT, HDR_ATTACHMENT, HDR_FORMAT, IBL_SAMPLES, IRRADIANCE_SIZE, LOD_SCREEN_SIZE, MAIN_PASS, MAX_JOINT_MATRICES, MAX_LIGHTS, MSAA_SAMPLES, PREFILTERED_LEVELS, PREFILTERED_SIZE, SHADOW_ATTACHMENT, SHADOW_PASS, SHADOW_VERTEX_SHADER_PATH
     },
     device::DeviceState,
-    error::NoLevelLoadedError,
+    desc::DescSetLayout,
+    error::{
+        AssetError, NoLevelLoadedError, RenderGraphError
+    },
     framebuffer::FramebufferState,
+    graph::{
+        AttachmentClear, AttachmentDesc, AttachmentSize, PassDesc, RenderGraph, RenderGraphDesc
+    },
+    ibl::{
+        load_environment, EnvironmentMaps
+    },
+    light::{
+        Light, LightState
+    },
+    material::{
+        BlendMode, Material, MaterialDesc, MaterialParam
+    },
+    mesh,
     model::{
-        Color, Dimensions
+        Color, Instance
     },
     obj::RenderObject,
-    pass::RenderPassState,
-    pipeline::PipelineState,
+    pipeline::{
+        PipelineCache, PipelineKey
+    },
+    post::{
+        self, PostEffect, PostPass, PostSettings, PostState
+    },
+    primitives::{
+        self, PrimitiveOptions
+    },
+    shadow::{
+        ShadowSettings, ShadowState
+    },
     swapchain::SwapchainState,
+    vertex_layout::VertexLayout,
+    view::{
+        self, CameraView, ViewRect, ViewTarget
+    },
+    watcher::{
+        AssetKind, AssetWatcher
+    },
 };
 
 use crate::zeus_core::{
     input,
-    math::Matrix4,
+    math::{
+        Matrix4, Vector2, Vector3, Vector4
+    },
     time::Stopwatch,
 };
 
 use std::{
     cell::RefCell,
+    collections::BTreeMap,
     iter,
     rc::Rc
 };
@@ -53,21 +101,38 @@ use winit::event::VirtualKeyCode;
 
 pub struct RendererState<B: Backend> {
     swapchain: SwapchainState<B>,
-    object: Option<RenderObject<B>>,
+    objects: Vec<RenderObject<B>>,
+    materials: BTreeMap<String, Material<B>>,
+    selected_material: Option<String>,
     device: Rc<RefCell<DeviceState<B>>>,
     pub backend: BackendState<B>,
-    render_pass: RenderPassState<B>,
-    pipeline: PipelineState<B>,
+    graph: RenderGraph<B>,
+    pipelines: PipelineCache<B>,
     framebuffer: FramebufferState<B>,
+    samples: NumSamples,
     viewport: Viewport,
     timer: Stopwatch,
-    camera: CameraState<B>,
+    /// The first view is the main camera, it is moved by the controller and can't be removed
+    views: Vec<CameraView<B>>,
+    /// The top-down picture-in-picture view, see `toggle_overview`
+    overview: Option<usize>,
+    controller: Box<dyn CameraController>,
+    controller_kind: ControllerKind,
+    /// The object the orbit and follow controllers turn around, the origin when `None`
+    camera_target: Option<usize>,
+    mouse_delta: Vector2,
+    zoom_delta: f32,
+    lights: LightState<B>,
+    shadows: ShadowState<B>,
+    post: PostState<B>,
+    /// Read by the shader inputs the vertex layout of an object doesn't have
+    default_attributes: BufferState<B>,
     window_dimensions: Extent2D,
     pub recreate_swapchain: bool,
     bg_color: ColorValue,
     cur_color: Color,
     cur_value: u32,
-    depth_buffer: Option<DepthBuffer<B>>
+    watcher: AssetWatcher,
 }
 
 impl<B: Backend> RendererState<B> {
@@ -90,104 +155,722 @@ impl<B: Backend> RendererState<B> {
             window_dimensions
         );
 
-        let mut camera = CameraState::new(
-            swapchain.size as usize,
+        let viewport = RendererState::create_viewport(&swapchain);
+
+        let mut main_camera = Camera::perspective(
+            90.0,
+            swapchain.extent.width as f32 / swapchain.extent.height.max(1) as f32,
+            0.1,
+            1000.0,
+        );
+        main_camera.position = Vector3::new(1.0, 0.5, 0.0);
+        main_camera.look_at(Vector3::new(0.0, 0.5, 0.0));
+
+        let controller_kind = ControllerKind::FreeFly;
+        let controller = controller_kind.create(&main_camera, Vector3::default());
+
+        let main_view = CameraView::new(
+            main_camera,
+            ViewTarget::Window(ViewRect::full()),
+            CullingSettings::default(),
             Rc::clone(&device),
-            &backend.adapter.memory_types,
         );
+        let views = vec![main_view];
 
-        let viewport = RendererState::create_viewport(&swapchain);
+        let lights = LightState::new(
+            FRAMES_IN_FLIGHT,
+            MAX_LIGHTS,
+            Rc::clone(&device),
+        );
 
-        let mut camera_model = Matrix4::new_rotation_y(90.0);
-        camera_model.translate(0.0, -0.5, -1.0);
-
-        camera.update_ubo(
-            camera_model,
-            Matrix4::new(),
-            Matrix4::perspective(
-                90.0_f32.to_radians(),
-                (viewport.rect.w / viewport.rect.h) as f32,
-                0.1,
-                1000.0,
-            ),
+        let shadows = ShadowState::new(
+            FRAMES_IN_FLIGHT,
+            ShadowSettings::default(),
+            Rc::clone(&device),
         );
 
-        camera.update_all_buffers();
+        let post = PostState::new(
+            PostSettings::default(),
+            Rc::clone(&device),
+        );
 
-        let render_pass = RenderPassState::new(&swapchain, Rc::clone(&device));
+        let samples = device.borrow().clamp_samples(MSAA_SAMPLES);
+        info!("MSAA: {}x", samples);
 
-        let depth_buffer = if DepthBuffer::stencil_support(Rc::clone(&device), DEPTH_IMAGE_FORMAT) {
-            Some(DepthBuffer::new(
-                Rc::clone(&device),
-                &backend.adapter,
-                Dimensions {
-                    width: window_dimensions.width,
-                    height: window_dimensions.height
-                }
-            ))
-        } else {
-            None
-        };
+        let graph_desc = RendererState::create_graph_desc(&device.borrow(), samples, &shadows, &post, &views);
+        let graph = RenderGraph::new(
+            Rc::clone(&device),
+            graph_desc,
+            swapchain.format,
+            swapchain.extent
+        ).unwrap_or_else(|err| panic!("Could not build the render graph: {}", err.message));
 
         let framebuffer = unsafe {
             FramebufferState::new(
                 Rc::clone(&device),
-                swapchain.size,
+                FRAMES_IN_FLIGHT,
             )
         };
 
-        let pipeline = PipelineState::empty(Rc::clone(&device));
+        let pipelines = PipelineCache::new(Rc::clone(&device));
+
+        let default_attributes = {
+            let mut staging_pool = unsafe {
+                device.borrow().device.create_command_pool(
+                    device.borrow().queues.family,
+                    CommandPoolCreateFlags::empty(),
+                )
+            }.expect("Can't create Command Pool");
+
+            let buffer = BufferState::new_vertex_buffer(
+                Rc::clone(&device),
+                &VertexLayout::default_values(),
+                &mut staging_pool
+            );
+
+            unsafe {
+                device.borrow().device
+                    .destroy_command_pool(staging_pool);
+            }
+
+            buffer
+        };
+
+        let mut watcher = AssetWatcher::new();
+        watcher.watch(SHADOW_VERTEX_SHADER_PATH, AssetKind::Shader);
+        for path in post::shader_paths() {
+            watcher.watch(path, AssetKind::Shader);
+        }
 
         RendererState {
             backend,
-            pipeline,
+            pipelines,
             device,
-            object: None,
-            render_pass,
+            objects: Vec::new(),
+            materials: BTreeMap::new(),
+            selected_material: None,
+            graph,
             swapchain,
             framebuffer,
+            samples,
             viewport,
             timer: Stopwatch::new(),
-            camera,
+            views,
+            overview: None,
+            controller,
+            controller_kind,
+            camera_target: None,
+            mouse_delta: Vector2::default(),
+            zoom_delta: 0.0,
+            lights,
+            shadows,
+            post,
+            default_attributes,
             window_dimensions,
             recreate_swapchain: true,
             bg_color: [0.0, 0.0, 0.0, 1.0],
             cur_color: Color::Red,
             cur_value: 0,
-            depth_buffer
+            watcher,
         }
     }
 
     pub fn load_level(&mut self) {
         info!("Load new level");
 
+        // let (vertices, indices) = primitives::plane(Vector2::new(10.0, 10.0), 10, &PrimitiveOptions::default());
         // let object = RenderObject::new_from_vertices(
         //     Rc::clone(&self.device),
-        //     &self.backend.adapter,
-        //     "./data/textures/viking_room.png",
-        //     &VERTICES,
-        //     &INDICES,
+        //     "viking_room",
+        //     &vertices,
+        //     &indices,
         // );
 
-        let object = RenderObject::new_from_model(
+        self.add_light(Light::directional(
+            Vector3::new(-0.5, -1.0, 0.5),
+            Vector3::new(1.0, 0.95, 0.9),
+            0.8
+        ));
+        self.add_light(Light::point(
+            Vector3::new(0.0, 1.0, 1.0),
+            Vector3::new(1.0, 0.6, 0.3),
+            1.5,
+            4.0
+        ));
+
+        //NOTE: The materials come from the MTL library of the model
+        let mut object = RenderObject::new_from_model(
             Rc::clone(&self.device),
-            &self.backend.adapter,
             "./data/models/viking_room.obj",
-            "./data/textures/viking_room.png"
+            "viking_room"
         );
+        //The walls of the room hide what is behind them
+        object.set_occluder(true);
+        self.add_object(object);
 
-        let mut layouts = Vec::new();
-        self.camera.append_layout(&mut layouts);
-        object.append_layout(&mut layouts);
+        self.recreate_swapchain();
+
+        info!("GPU memory: {}", self.device.borrow().allocator.stats());
+    }
 
-        self.pipeline.new_pipeline(
-            layouts,
-            self.render_pass.render_pass.as_ref().unwrap()
+    /// A grid of tens of thousands of cubes to measure instanced drawing, all instances of one object.
+    /// Every row is a batch of instances, culled and picking its LOD on its own. The wall across the grid is an occluder, for occlusion culling.
+    pub fn load_benchmark_level(&mut self) {
+        info!("Load instancing benchmark");
+
+        self.add_light(Light::directional(
+            Vector3::new(-0.5, -1.0, 0.5),
+            Vector3::new(1.0, 0.95, 0.9),
+            1.0
+        ));
+
+        let mut desc = MaterialDesc::new_pbr(BENCHMARK_MATERIAL);
+        desc.set_param("roughness", MaterialParam::Float(0.6));
+        self.add_material(desc);
+
+        let (vertices, indices) = primitives::cube(BENCHMARK_SPACING * 0.5, 1, &PrimitiveOptions::default());
+        let extent = BENCHMARK_GRID_SIZE as f32 * BENCHMARK_SPACING;
+
+        let instances = (0..BENCHMARK_GRID_SIZE)
+            .flat_map(|row| (0..BENCHMARK_GRID_SIZE).map(move |column| (row, column)))
+            .map(|(row, column)| {
+                let x = column as f32 * BENCHMARK_SPACING - extent * 0.5;
+                let z = row as f32 * BENCHMARK_SPACING - extent * 0.5;
+                //Waves, so the cubes shadow each other
+                let wave = (x * 0.5).sin() * (z * 0.5).cos();
+
+                Instance::new(
+                    Matrix4::new_traslation(x, wave * BENCHMARK_SPACING - 1.0, z),
+                    Vector4::new(column as f32 / BENCHMARK_GRID_SIZE as f32, 0.5 + wave * 0.5, row as f32 / BENCHMARK_GRID_SIZE as f32, 1.0)
+                )
+            })
+            .collect();
+
+        let mut object = RenderObject::new_from_vertices(
+            Rc::clone(&self.device),
+            BENCHMARK_MATERIAL,
+            &vertices,
+            &indices,
         );
+        object.set_batched_instances(instances, BENCHMARK_GRID_SIZE);
+        self.add_object(object);
 
-        self.object = Some(object);
+        let (vertices, indices) = primitives::plane(Vector2::new(extent, BENCHMARK_WALL_HEIGHT), 1, &PrimitiveOptions::default());
+        let mut wall = RenderObject::new_from_vertices(
+            Rc::clone(&self.device),
+            BENCHMARK_MATERIAL,
+            &vertices,
+            &indices,
+        );
+        wall.set_instances(vec![Instance::new(
+            Matrix4::new_traslation(0.0, BENCHMARK_WALL_HEIGHT * 0.5 - 1.0, 0.0) * Matrix4::new_rotation_x(std::f32::consts::FRAC_PI_2),
+            Vector4::new(0.8, 0.8, 0.8, 1.0)
+        )]);
+        wall.set_occluder(true);
+        self.add_object(wall);
 
         self.recreate_swapchain();
+
+        let instance_count: u32 = self.objects.iter().map(|object| object.get_instance_count()).sum();
+        info!("Drawing {} instances with {} objects", instance_count, self.objects.len());
+    }
+
+    /// Creates the GPU resources of the material. A material with the same name is replaced.
+    pub fn add_material(&mut self, desc: MaterialDesc) {
+        self.watcher.watch(&desc.vertex_shader, AssetKind::Shader);
+        self.watcher.watch(&desc.fragment_shader, AssetKind::Shader);
+        for (_, path) in desc.textures() {
+            self.watcher.watch(path, AssetKind::Texture);
+        }
+
+        let material = Material::new(
+            Rc::clone(&self.device),
+            &self.backend.adapter,
+            desc
+        );
+
+        if self.selected_material.is_none() {
+            self.selected_material = Some(material.get_name().to_string());
+        }
+
+        //Pipelines are created lazily, on the next swapchain recreation
+        self.recreate_swapchain = true;
+
+        if let Some(old_material) = self.materials.insert(material.get_name().to_string(), material) {
+            self.device.borrow().device.wait_idle()
+                .expect("Device is empty!");
+            drop(old_material);
+        }
+    }
+
+    /// The MTL materials of the object's model are loaded as well, unless a material with that name already is.
+    /// An object with the mesh and material of one that is already added becomes instances of it, see `RenderObject::can_batch`.
+    pub fn add_object(&mut self, object: RenderObject<B>) {
+        if let Some(batch) = self.objects.iter_mut().find(|other| other.can_batch(&object)) {
+            batch.add_instances(object.get_instances());
+            return;
+        }
+
+        self.add_model_materials(&object);
+
+        for material in object.materials() {
+            if !self.materials.contains_key(material) {
+                warn!("Material {} is not loaded, parts of the object will not be drawn", material);
+            }
+        }
+
+        if let Some(model_path) = object.get_model_path() {
+            self.watcher.watch(model_path, AssetKind::Model);
+        }
+
+        //Pipelines for a new vertex layout are created on the next swapchain recreation
+        if !self.vertex_layouts().contains(object.get_vertex_layout()) {
+            self.recreate_swapchain = true;
+        }
+
+        self.objects.push(object);
+    }
+
+    /// The vertex layouts of the objects, each one once.
+    fn vertex_layouts(&self) -> Vec<VertexLayout> {
+        let mut vertex_layouts: Vec<VertexLayout> = Vec::new();
+        for object in self.objects.iter() {
+            if !vertex_layouts.contains(object.get_vertex_layout()) {
+                vertex_layouts.push(object.get_vertex_layout().clone());
+            }
+        }
+
+        vertex_layouts
+    }
+
+    /// The vertex layouts of the objects drawn with the material.
+    fn material_vertex_layouts(objects: &[RenderObject<B>], material: &str) -> Vec<VertexLayout> {
+        let mut vertex_layouts: Vec<VertexLayout> = Vec::new();
+        for object in objects.iter().filter(|object| object.materials().any(|other| other == material)) {
+            if !vertex_layouts.contains(object.get_vertex_layout()) {
+                vertex_layouts.push(object.get_vertex_layout().clone());
+            }
+        }
+
+        vertex_layouts
+    }
+
+    fn add_model_materials(&mut self, object: &RenderObject<B>) {
+        let missing: Vec<MaterialDesc> = object.get_model_materials().iter()
+            .filter(|desc| !self.materials.contains_key(&desc.name))
+            .cloned()
+            .collect();
+
+        for desc in missing {
+            self.add_material(desc);
+        }
+    }
+
+    /// The passes of a frame: the shadow maps are drawn first, then the render targets of the views,
+    /// then the scene is drawn to the HDR attachment, or to a multisampled color that is resolved into it.
+    /// The post-processing passes bring it to the backbuffer.
+    fn create_graph_desc(
+        device: &DeviceState<B>,
+        samples: NumSamples,
+        shadows: &ShadowState<B>,
+        post: &PostState<B>,
+        views: &[CameraView<B>],
+    ) -> RenderGraphDesc {
+        let mut graph = RenderGraphDesc::new();
+        let mut main_pass = PassDesc::new(MAIN_PASS);
+
+        graph.add_attachment(AttachmentDesc::new(HDR_ATTACHMENT, HDR_FORMAT));
+
+        if samples > 1 {
+            let mut color = AttachmentDesc::new(COLOR_ATTACHMENT, HDR_FORMAT);
+            color.samples = samples;
+            graph.add_attachment(color);
+
+            main_pass.add_color(COLOR_ATTACHMENT);
+            main_pass.add_resolve(HDR_ATTACHMENT);
+        } else {
+            main_pass.add_color(HDR_ATTACHMENT);
+        }
+
+        graph.set_clear(Self::scene_color(samples), AttachmentClear::Color([0.0, 0.0, 0.0, 1.0]));
+
+        let has_depth = device.supports_depth_attachment(DEPTH_IMAGE_FORMAT);
+        if has_depth {
+            let mut depth = AttachmentDesc::new(DEPTH_ATTACHMENT, DEPTH_IMAGE_FORMAT);
+            depth.samples = samples;
+            depth.clear = Some(AttachmentClear::DepthStencil(1.0, 0));
+            graph.add_attachment(depth);
+
+            main_pass.set_depth(DEPTH_ATTACHMENT);
+        } else {
+            warn!("{:?} can not be used as a depth attachment, drawing without depth", DEPTH_IMAGE_FORMAT);
+        }
+
+        shadows.add_passes(&mut graph, &mut main_pass);
+
+        //NOTE: The render targets have the formats and sample count of the main pass, so they share its pipelines
+        for view in views.iter() {
+            let (name, width, height) = match view.get_target() {
+                ViewTarget::Texture { name, width, height } => (name.as_str(), *width, *height),
+                ViewTarget::Window(_) => continue
+            };
+
+            let size = AttachmentSize::Absolute(width, height);
+            let mut pass = PassDesc::new(&view::render_target_pass(name));
+
+            let mut target = AttachmentDesc::new(name, HDR_FORMAT);
+            target.size = size;
+            target.clear = Some(AttachmentClear::Color([0.0, 0.0, 0.0, 1.0]));
+            graph.add_attachment(target);
+
+            if samples > 1 {
+                let mut color = AttachmentDesc::new(&view::render_target_color(name), HDR_FORMAT);
+                color.size = size;
+                color.samples = samples;
+                color.clear = Some(AttachmentClear::Color([0.0, 0.0, 0.0, 1.0]));
+                graph.add_attachment(color);
+
+                pass.add_color(&view::render_target_color(name));
+                pass.add_resolve(name);
+            } else {
+                pass.add_color(name);
+            }
+
+            if has_depth {
+                let mut depth = AttachmentDesc::new(&view::render_target_depth(name), DEPTH_IMAGE_FORMAT);
+                depth.size = size;
+                depth.samples = samples;
+                depth.clear = Some(AttachmentClear::DepthStencil(1.0, 0));
+                graph.add_attachment(depth);
+
+                pass.set_depth(&view::render_target_depth(name));
+            }
+
+            pass.add_input(SHADOW_ATTACHMENT);
+            graph.add_pass(pass);
+
+            main_pass.add_input(name);
+        }
+
+        graph.add_pass(main_pass);
+        post.add_passes(&mut graph);
+
+        graph
+    }
+
+    /// The attachment the scene is drawn to.
+    fn scene_color(samples: NumSamples) -> &'static str {
+        if samples > 1 {
+            COLOR_ATTACHMENT
+        } else {
+            HDR_ATTACHMENT
+        }
+    }
+
+    /// Switches the MSAA sample count, clamped to what the device supports.
+    /// The attachments and pipelines are recreated before the next frame.
+    pub fn set_msaa(&mut self, samples: NumSamples) {
+        let samples = self.device.borrow().clamp_samples(samples);
+
+        if samples != self.samples {
+            info!("MSAA: {}x", samples);
+
+            self.samples = samples;
+            self.recreate_swapchain = true;
+        }
+    }
+
+    /// Goes through 1, 2, 4 and 8 samples, skipping the unsupported ones.
+    pub fn cycle_msaa(&mut self) {
+        let max_samples = self.device.borrow().clamp_samples(8);
+
+        if self.samples >= max_samples {
+            self.set_msaa(1);
+        } else {
+            self.set_msaa(self.samples * 2);
+        }
+    }
+
+    /// Returns the index of the light.
+    pub fn add_light(&mut self, light: Light) -> usize {
+        self.lights.add_light(light)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_light_mut(&mut self, index: usize) -> Option<&mut Light> {
+        self.lights.get_light_mut(index)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_shadow_settings(&self) -> ShadowSettings {
+        self.shadows.get_settings()
+    }
+
+    /// The shadow map and its pipeline are recreated before the next frame.
+    #[allow(dead_code)]
+    pub fn set_shadow_settings(&mut self, settings: ShadowSettings) {
+        self.shadows.set_settings(settings);
+        self.recreate_swapchain = true;
+    }
+
+    pub fn get_post_settings(&self) -> PostSettings {
+        self.post.get_settings()
+    }
+
+    /// Turning bloom or FXAA on or off rebuilds the render graph before the next frame.
+    pub fn set_post_settings(&mut self, settings: PostSettings) {
+        if self.post.get_settings().changes_passes(&settings) {
+            self.recreate_swapchain = true;
+        }
+
+        self.post.set_settings(settings);
+    }
+
+    pub fn toggle_post_effect(&mut self, effect: PostEffect) {
+        let mut settings = self.get_post_settings();
+        let enabled = settings.toggle(effect);
+
+        info!("{:?}: {}", effect, if enabled { "on" } else { "off" });
+        self.set_post_settings(settings);
+    }
+
+    /// Multiplies the exposure by 2^stops.
+    pub fn adjust_exposure(&mut self, stops: f32) {
+        let mut settings = self.get_post_settings();
+        settings.exposure *= stops.exp2();
+
+        info!("Exposure: {:.2}", settings.exposure);
+        self.set_post_settings(settings);
+    }
+
+    /// Loads the color grading LUT, it is used while color grading is on. The old LUT is kept on failure.
+    #[allow(dead_code)]
+    pub fn set_color_lut(&mut self, path: &str) -> Result<(), AssetError> {
+        self.device.borrow().device.wait_idle()
+            .expect("Device is empty!");
+
+        self.post.set_color_lut(path)
+    }
+
+    pub fn get_culling_settings(&self) -> CullingSettings {
+        self.views[0].get_culling().get_settings()
+    }
+
+    /// Applies to every view.
+    pub fn set_culling_settings(&mut self, settings: CullingSettings) {
+        for view in self.views.iter_mut() {
+            view.get_culling_mut().set_settings(settings);
+        }
+    }
+
+    pub fn toggle_occlusion_culling(&mut self) {
+        let mut settings = self.get_culling_settings();
+        settings.occlusion = !settings.occlusion;
+
+        info!("Occlusion culling: {}", if settings.occlusion { "on" } else { "off" });
+        self.set_culling_settings(settings);
+    }
+
+    /// What the culling of the main view did in the last frame, for profiling.
+    pub fn get_culling_stats(&self) -> CullingStats {
+        self.views[0].get_culling().get_stats()
+    }
+
+    /// Adds a camera drawn every frame after the main one, to a part of the window or to a render target.
+    /// The view is not added if its render target can't be built, e.g. when the name is already used.
+    #[allow(dead_code)]
+    pub fn add_view(&mut self, camera: Camera, target: ViewTarget) -> Result<usize, RenderGraphError> {
+        let mut view = CameraView::new(
+            camera,
+            target,
+            self.get_culling_settings(),
+            Rc::clone(&self.device),
+        );
+        view.resize(self.swapchain.extent);
+
+        let has_render_target = view.get_render_target().is_some();
+        self.views.push(view);
+
+        if has_render_target {
+            if let Err(err) = self.validate_views() {
+                self.views.pop();
+                return Err(err);
+            }
+
+            self.recreate_swapchain = true;
+        }
+
+        Ok(self.views.len() - 1)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_view(&self, index: usize) -> Option<&CameraView<B>> {
+        self.views.get(index)
+    }
+
+    /// Moving the camera of a view is picked up by the next frame, its target has to change with `set_view_target`.
+    #[allow(dead_code)]
+    pub fn get_view_mut(&mut self, index: usize) -> Option<&mut CameraView<B>> {
+        self.views.get_mut(index)
+    }
+
+    /// Moves a view to another part of the window or to a render target, the old target is kept on failure.
+    #[allow(dead_code)]
+    pub fn set_view_target(&mut self, index: usize, target: ViewTarget) -> Result<(), RenderGraphError> {
+        let has_render_target = target.get_render_target().is_some();
+        let old_target = match self.views.get_mut(index) {
+            Some(view) => {
+                let old_target = view.get_target().clone();
+                view.set_target(target);
+                old_target
+            },
+            None => return Err(RenderGraphError::new(format!("There is no view {}", index)))
+        };
+
+        if has_render_target || old_target.get_render_target().is_some() {
+            if let Err(err) = self.validate_views() {
+                self.views[index].set_target(old_target);
+                return Err(err);
+            }
+
+            self.recreate_swapchain = true;
+        }
+
+        self.views[index].resize(self.swapchain.extent);
+
+        Ok(())
+    }
+
+    /// The main view can't be removed. Materials that sample the render target of the view turn white.
+    #[allow(dead_code)]
+    pub fn remove_view(&mut self, index: usize) {
+        if index == 0 || index >= self.views.len() {
+            warn!("View {} can not be removed", index);
+            return;
+        }
+
+        let view = self.views.remove(index);
+        self.overview = match self.overview {
+            Some(overview) if overview == index => None,
+            Some(overview) if overview > index => Some(overview - 1),
+            overview => overview
+        };
+
+        if view.get_render_target().is_some() {
+            self.recreate_swapchain = true;
+        }
+    }
+
+    /// Shows or hides a top-down view of the level in a corner of the window.
+    pub fn toggle_overview(&mut self) {
+        if let Some(index) = self.overview.take() {
+            self.remove_view(index);
+            return;
+        }
+
+        let mut camera = Camera::orthographic(4.0, 1.0, 0.1, 100.0);
+        camera.position = Vector3::new(0.0, 10.0, 0.0);
+        camera.look_at(Vector3::new(0.0, 0.0, 0.0));
+
+        match self.add_view(camera, ViewTarget::Window(ViewRect::new(0.7, 0.7, 0.28, 0.28))) {
+            Ok(index) => self.overview = Some(index),
+            Err(err) => error!("Could not add the overview: {}", err.message)
+        }
+    }
+
+    /// Checks that the render graph can be built with the render targets of the views.
+    fn validate_views(&self) -> Result<(), RenderGraphError> {
+        let graph_desc = Self::create_graph_desc(&self.device.borrow(), self.samples, &self.shadows, &self.post, &self.views);
+        graph_desc.compile()?;
+
+        Ok(())
+    }
+
+    /// Points the render textures of the materials to the images of the graph.
+    /// Textures of targets no view draws to are white. The caller has to make sure the device is idle.
+    fn bind_render_targets(&mut self) {
+        for material in self.materials.values_mut() {
+            let targets: Vec<String> = material.get_desc().render_targets()
+                .map(str::to_string)
+                .collect();
+
+            for name in targets {
+                let image_view = if self.views.iter().any(|view| view.get_render_target() == Some(name.as_str())) {
+                    self.graph.get_image_view(&name)
+                } else {
+                    None
+                };
+
+                material.set_render_target(&name, image_view);
+            }
+        }
+    }
+
+    /// Lights the scene with an equirectangular panorama, .hdr files keep their full range.
+    /// The maps are generated on the CPU, which takes a moment.
+    #[allow(dead_code)]
+    pub fn set_environment(&mut self, path: &str) -> Result<(), AssetError> {
+        let environment = load_environment(path, ENVIRONMENT_SIZE)?;
+        let maps = EnvironmentMaps::new(
+            &environment,
+            IRRADIANCE_SIZE,
+            PREFILTERED_SIZE,
+            PREFILTERED_LEVELS,
+            IBL_SAMPLES
+        );
+
+        self.device.borrow().device.wait_idle()
+            .expect("Device is empty!");
+        self.lights.set_environment(&maps);
+
+        Ok(())
+    }
+
+    /// The sets are the camera, the lights, the shadows and then the sets of the material.
+    fn material_layouts<'a>(
+        camera: &'a CameraState<B>,
+        lights: &'a LightState<B>,
+        shadows: &'a ShadowState<B>,
+        material: &'a Material<B>,
+    ) -> Vec<&'a DescSetLayout<B>> {
+        let mut layouts = Vec::new();
+        camera.append_layout(&mut layouts);
+        lights.append_layout(&mut layouts);
+        shadows.append_layout(&mut layouts);
+        material.append_layout(&mut layouts);
+
+        layouts
+    }
+
+    /// Builds the pipelines of the materials that are not in the cache yet, for the vertex layouts of the objects drawn with them.
+    fn create_pipelines(&mut self) {
+        let render_pass = match self.graph.get_render_pass(MAIN_PASS) {
+            Some(render_pass) => render_pass,
+            None => return
+        };
+        let samples = self.graph.get_samples(MAIN_PASS);
+
+        let mut material_keys = Vec::new();
+        for material in self.materials.values() {
+            let layouts = Self::material_layouts(self.views[0].get_state(), &self.lights, &self.shadows, material);
+
+            let keys: Vec<PipelineKey> = Self::material_vertex_layouts(&self.objects, material.get_name()).iter()
+                .map(|vertex_layout| PipelineKey::new(material.get_desc(), &layouts, samples, vertex_layout))
+                .collect();
+
+            for key in keys.iter() {
+                if let Err(err) = self.pipelines.get_or_create(key, &layouts, render_pass) {
+                    error!("Could not create the pipeline of material {}: {}", material.get_name(), err.message);
+                }
+            }
+
+            material_keys.push((material.get_name().to_string(), keys));
+        }
+
+        //Drawing looks the pipelines up with these keys
+        for (name, keys) in material_keys {
+            self.materials.get_mut(&name).unwrap().set_pipeline_keys(keys);
+        }
     }
 
     fn recreate_swapchain(&mut self) {
@@ -201,50 +884,152 @@ impl<B: Backend> RendererState<B> {
             self.window_dimensions
         );
 
-        self.render_pass = RenderPassState::new(
-            &self.swapchain,
-            Rc::clone(&self.device)
-        );
+        let graph_desc = Self::create_graph_desc(&self.device.borrow(), self.samples, &self.shadows, &self.post, &self.views);
+        if let Err(err) = self.graph.rebuild(graph_desc, self.swapchain.format, self.swapchain.extent) {
+            panic!("Could not rebuild the render graph: {}", err.message);
+        }
+        self.bind_render_targets();
 
-        self.depth_buffer = if DepthBuffer::stencil_support(
-            Rc::clone(&self.device),
-            DEPTH_IMAGE_FORMAT
-        ) {
-            Some(DepthBuffer::new(
-                Rc::clone(&self.device),
-                &self.backend.adapter,
-                Dimensions {
-                    width: self.window_dimensions.width,
-                    height: self.window_dimensions.height
-                }
-            ))
-        } else {
-            None
-        };
+        if let Some(shadow_map) = self.graph.get_image_view(SHADOW_ATTACHMENT) {
+            self.shadows.set_shadow_map(shadow_map);
+        }
+
+        if let Some(render_pass) = self.graph.get_render_pass(SHADOW_PASS) {
+            if let Err(err) = self.shadows.create_pipelines(render_pass, self.views[0].get_state(), &self.vertex_layouts()) {
+                error!("Could not create the shadow pipelines: {}", err.message);
+            }
+        }
+
+        self.post.set_inputs(&self.graph, self.swapchain.format);
+        if let Err(err) = self.post.create_pipelines(&self.graph) {
+            error!("Could not create the post-processing pipelines: {}", err.message);
+        }
 
         self.framebuffer = unsafe {
             FramebufferState::new(
                 Rc::clone(&self.device),
-                self.swapchain.size
+                FRAMES_IN_FLIGHT
             )
         };
 
-        let mut layouts = Vec::new();
-        self.camera.append_layout(&mut layouts);
-        //NOTE: recreate swapchain is called only from draw after our check.
-        self.object.as_ref()
-            .expect("Render Object is empty!")
-            .append_layout(&mut layouts);
-
-        self.pipeline.new_pipeline(
-            layouts,
-            self.render_pass.render_pass.as_ref()
-                .expect("Render Pass is empty!")
-        );
+        //The pipelines were built for the old render passes
+        self.pipelines.clear();
+        self.create_pipelines();
 
         self.viewport = RendererState::create_viewport(
             &self.swapchain
         );
+        for view in self.views.iter_mut() {
+            view.resize(self.swapchain.extent);
+        }
+    }
+
+    /// Rebuilds the pipelines, textures and meshes whose files changed on disk.
+    /// On failure the old version is kept and the error is logged.
+    fn reload_changed_assets(&mut self) {
+        let changed_assets = self.watcher.changed_assets();
+
+        if changed_assets.is_empty() || self.objects.is_empty() {
+            return;
+        }
+
+        self.device.borrow().device.wait_idle()
+            .expect("Device is empty!");
+
+        let mut changed_shaders = Vec::new();
+
+        for (kind, path) in changed_assets {
+            info!("Asset changed: {}", path);
+
+            match kind {
+                AssetKind::Shader => changed_shaders.push(path),
+                AssetKind::Texture => {
+                    for material in self.materials.values_mut() {
+                        if let Err(err) = material.reload_texture(&path, &self.backend.adapter) {
+                            error!("Could not reload {}: {}", path, err.message);
+                        }
+                    }
+                },
+                AssetKind::Model => {
+                    for object in self.objects.iter_mut() {
+                        if object.get_model_path() != Some(path.as_str()) {
+                            continue;
+                        }
+
+                        if let Err(err) = object.reload_model() {
+                            error!("Could not reload {}: {}", path, err.message);
+                        }
+                    }
+
+                    //Materials added to the MTL library since the model was loaded
+                    let reloaded: Vec<MaterialDesc> = self.objects.iter()
+                        .filter(|object| object.get_model_path() == Some(path.as_str()))
+                        .flat_map(|object| object.get_model_materials().iter().cloned())
+                        .filter(|desc| !self.materials.contains_key(&desc.name))
+                        .collect();
+
+                    for desc in reloaded {
+                        self.add_material(desc);
+                    }
+                },
+            }
+        }
+
+        if changed_shaders.is_empty() {
+            return;
+        }
+
+        if changed_shaders.iter().any(|path| path == SHADOW_VERTEX_SHADER_PATH) {
+            if let Some(render_pass) = self.graph.get_render_pass(SHADOW_PASS) {
+                match self.shadows.create_pipelines(render_pass, self.views[0].get_state(), &self.vertex_layouts()) {
+                    Ok(_) => info!("Reloaded the shadow shader"),
+                    Err(err) => error!("Could not reload the shadow shader, keeping the old pipeline: {}", err.message)
+                }
+            }
+        }
+
+        if changed_shaders.iter().any(|path| post::shader_paths().any(|post_path| post_path == path)) {
+            match self.post.create_pipelines(&self.graph) {
+                Ok(_) => info!("Reloaded the post-processing shaders"),
+                Err(err) => error!("Could not reload the post-processing shaders, keeping the old pipelines: {}", err.message)
+            }
+        }
+
+        let render_pass = match self.graph.get_render_pass(MAIN_PASS) {
+            Some(render_pass) => render_pass,
+            None => return
+        };
+
+        for material in self.materials.values() {
+            let desc = material.get_desc();
+            if !changed_shaders.iter().any(|path| desc.uses_shader(path)) {
+                continue;
+            }
+
+            let layouts = Self::material_layouts(self.views[0].get_state(), &self.lights, &self.shadows, material);
+
+            for key in material.get_pipeline_keys() {
+                match self.pipelines.rebuild(key, &layouts, render_pass) {
+                    Ok(_) => info!("Reloaded shaders of material {}", material.get_name()),
+                    Err(err) => error!("Could not reload shaders, keeping the old pipeline: {}", err.message)
+                }
+            }
+        }
+    }
+
+    /// Uploads the instances that changed since the last frame.
+    //TODO: Instance buffers per frame, so moving instances don't have to wait for the GPU
+    fn upload_instances(&mut self) {
+        if !self.objects.iter().any(|object| object.has_instance_changes()) {
+            return;
+        }
+
+        self.device.borrow().device.wait_idle()
+            .expect("Device is empty!");
+
+        for object in self.objects.iter_mut().filter(|object| object.has_instance_changes()) {
+            object.upload_instances();
+        }
     }
 
     fn create_viewport(swapchain: &SwapchainState<B>) -> Viewport {
@@ -260,7 +1045,7 @@ impl<B: Backend> RendererState<B> {
     }
 
     pub fn draw(&mut self) -> Result<(), NoLevelLoadedError> {
-        if self.pipeline.is_empty() {
+        if self.objects.is_empty() {
             return Err(NoLevelLoadedError{
                 message: "No level is loaded!!/nPlease a level before you try to draw the scene".to_string()
             })
@@ -280,9 +1065,17 @@ impl<B: Backend> RendererState<B> {
             self.recreate_swapchain = false;
         }
 
+        self.reload_changed_assets();
+        self.upload_instances();
+
         //Get Delta
         self.timer.update_time();
 
+        let frame_idx = (self.swapchain.frame_index as usize) % self.framebuffer.num_frames();
+
+        //Wait until the GPU is done with the frame before reusing its resources
+        self.framebuffer.wait_for_frame(frame_idx);
+
         let surface_image = unsafe {
             match self.backend.surface.acquire_image(!0) {
                 Ok((i, _)) => i,
@@ -292,29 +1085,18 @@ impl<B: Backend> RendererState<B> {
                 }
             }
         };
-        
-        let attachments = vec![
-            std::borrow::Borrow::borrow(&surface_image),
-            self.depth_buffer.as_ref().unwrap()
-                .depth_buffer.get_image_view().as_ref().unwrap()
-        ];
-
-        let framebuffer = unsafe {
-            self.device.borrow()
-                .device.create_framebuffer(
-                    self.render_pass.render_pass.as_ref().unwrap(),
-                    attachments,
-                    self.swapchain.extent
-                ).expect("Could not create framebuffer")
-        };
 
-        let frame_idx = (self.swapchain.frame_index % self.swapchain.size) as usize;
         self.swapchain.frame_index += 1;
 
         //Updates
         self.update_camera();
+        self.update_lods();
+        self.update_views(frame_idx);
         self.update_colors();
-        self.camera.update_buffer(frame_idx);
+        self.update_animations(frame_idx);
+        let shadow_tiles = self.shadows.update(frame_idx, self.lights.get_lights(), self.views[0].get_state(), self.views[0].camera.get_planes());
+        self.lights.update_buffer(frame_idx, &shadow_tiles);
+        self.graph.set_clear(Self::scene_color(self.samples), AttachmentClear::Color(self.bg_color));
 
         let framedata = self.framebuffer.get_frame_data(frame_idx);
 
@@ -327,56 +1109,155 @@ impl<B: Backend> RendererState<B> {
             };
 
             cmd_buffer.begin_primary(CommandBufferFlags::ONE_TIME_SUBMIT);
-            if cfg!(debug_assertions) {
-                cmd_buffer.begin_debug_marker("setup", 0);
-            }
-            cmd_buffer.set_viewports(0, &[self.viewport.clone()]);
-            cmd_buffer.set_scissors(0, &[self.viewport.rect]);
-            cmd_buffer.bind_graphics_pipeline(
-                self.pipeline.pipeline.as_ref()
-                    .expect("Pipeline is empty!")
+
+            //Stays bound for every pass, the streams of the objects are bound below it
+            cmd_buffer.bind_vertex_buffers(
+                DEFAULT_ATTRIBUTE_BINDING,
+                iter::once((self.default_attributes.get_buffer(), SubRange::WHOLE))
             );
-            if cfg!(debug_assertions) {
-                cmd_buffer.end_debug_marker();
-            }
-            
-
-            cmd_buffer.begin_render_pass(
-                self.render_pass.render_pass.as_ref()
-                    .expect("Render Pass is empty!"),
-                &framebuffer,
-                self.viewport.rect,
-                &[ClearValue {
-                    color: ClearColor {
-                        float32: self.bg_color,
-                    },
-                }, ClearValue {
-                    depth_stencil: ClearDepthStencil {
-                        depth: 1.0_f32,
-                        stencil: 0_u32
+
+            let objects = &self.objects;
+            let pipelines = &self.pipelines;
+            let lights = &self.lights;
+            let shadows = &self.shadows;
+            let post = &self.post;
+            let views = &self.views;
+            let extent = self.swapchain.extent;
+            let has_depth = self.device.borrow().supports_depth_attachment(DEPTH_IMAGE_FORMAT);
+
+            //NOTE: Opaque materials go first so the blended ones are drawn over them
+            let mut materials: Vec<&Material<B>> = self.materials.values().collect();
+            materials.sort_by_key(|material| material.get_desc().render_state.blend != BlendMode::Opaque);
+
+            //Draws the objects visible from the view in the pass being recorded.
+            //Materials that sample render targets are skipped when drawing into one, it could be the target itself
+            let record_view = |cmd_buffer: &mut B::CommandBuffer, view: &CameraView<B>| {
+                let is_render_target = view.get_render_target().is_some();
+
+                for material in materials.iter() {
+                    if is_render_target && material.samples_render_target() {
+                        continue;
                     }
-                }],
-                SubpassContents::Inline,
-            );
 
-            self.object.as_ref().unwrap()
-                .bind_buffers(&mut cmd_buffer, 0);
+                    let mut bound_layout: Option<&VertexLayout> = None;
+
+                    for (index, object) in objects.iter().enumerate() {
+                        if !object.materials().any(|name| name == material.get_name()) {
+                            continue;
+                        }
+
+                        let mut batches = object.get_batches().iter()
+                            .enumerate()
+                            .filter(|(batch, _)| view.get_culling().is_visible(index, *batch))
+                            .map(|(_, batch)| batch)
+                            .peekable();
+                        if batches.peek().is_none() {
+                            continue;
+                        }
+
+                        //Objects with the same vertex layout share the pipeline
+                        let pipeline = match material.get_pipeline_key(object.get_vertex_layout()).and_then(|key| pipelines.get(key)) {
+                            Some(pipeline) if !pipeline.is_empty() => pipeline,
+                            _ => continue
+                        };
+
+                        if bound_layout != Some(object.get_vertex_layout()) {
+                            bound_layout = Some(object.get_vertex_layout());
+
+                            cmd_buffer.bind_graphics_pipeline(
+                                pipeline.pipeline.as_ref()
+                                    .expect("Pipeline is empty!")
+                            );
+
+                            //TODO: Possible improvement, should save this item and update when needed.
+                            let mut desc_sets = Vec::new();
+                            view.get_state().append_desc_set(frame_idx, &mut desc_sets);
+                            lights.append_desc_set(frame_idx, &mut desc_sets);
+                            shadows.append_desc_set(frame_idx, &mut desc_sets);
+                            material.append_desc_set(&mut desc_sets);
+
+                            cmd_buffer.bind_graphics_descriptor_sets(
+                                pipeline.pipeline_layout.as_ref().expect("Pipeline Layout is empty!"),
+                                0,
+                                desc_sets,
+                                &[],
+                            );
+                        }
+
+                        cmd_buffer.push_graphics_constants(
+                            pipeline.pipeline_layout.as_ref().expect("Pipeline Layout is empty!"),
+                            ShaderStageFlags::VERTEX,
+                            0,
+                            &[object.get_joint_offset_constant()]
+                        );
+
+                        object.bind_buffers(cmd_buffer, 0);
+                        for batch in batches {
+                            for range in object.material_ranges(material.get_name(), batch.lod) {
+                                cmd_buffer.draw_indexed(range, 0, batch.instances.clone());
+                            }
+                        }
+                    }
+                }
+            };
 
-            //TODO: Possible improvement, should save this item and update when needed.
-            let mut desc_sets = Vec::new();
-            self.camera.append_desc_set(&mut desc_sets);
-            self.object.as_ref().unwrap()
-                .append_desc_set(&mut desc_sets);
+            self.graph.execute(
+                &mut cmd_buffer,
+                std::borrow::Borrow::borrow(&surface_image),
+                framedata.framebuffers,
+                |pass, _, cmd_buffer| {
+                    if pass == SHADOW_PASS {
+                        shadows.record(cmd_buffer, objects, views[0].get_state(), frame_idx);
+                        return;
+                    }
+
+                    if let Some(post_pass) = PostPass::from_name(pass) {
+                        post.record(cmd_buffer, post_pass);
+                        return;
+                    }
+
+                    if pass != MAIN_PASS {
+                        let view = views.iter()
+                            .find(|view| view.get_render_target().map(view::render_target_pass).as_deref() == Some(pass));
+
+                        if let Some(view) = view.filter(|view| view.enabled) {
+                            record_view(cmd_buffer, view);
+                        }
+                        return;
+                    }
 
-            cmd_buffer.bind_graphics_descriptor_sets(
-                self.pipeline.pipeline_layout.as_ref().expect("Pipeline Layout is empty!"),
-                0,
-                desc_sets,
-                &[],
+                    //Window views are drawn in order, each over the ones before it
+                    let window_views = views.iter()
+                        .filter(|view| view.enabled && view.get_render_target().is_none());
+
+                    for (position, view) in window_views.enumerate() {
+                        let rect = view.get_rect(extent);
+
+                        cmd_buffer.set_viewports(0, &[Viewport {
+                            rect,
+                            depth: 0.0 .. 1.0,
+                        }]);
+                        cmd_buffer.set_scissors(0, [rect]);
+
+                        //The pass cleared the depth of the first view, the others could overlap it
+                        if position > 0 && has_depth {
+                            cmd_buffer.clear_attachments(
+                                iter::once(command::AttachmentClear::DepthStencil {
+                                    depth: Some(1.0),
+                                    stencil: None,
+                                }),
+                                iter::once(ClearRect {
+                                    rect,
+                                    layers: 0..1,
+                                })
+                            );
+                        }
+
+                        record_view(cmd_buffer, view);
+                    }
+                }
             );
 
-            cmd_buffer.draw_indexed(0..self.object.as_ref().unwrap().indices.len() as u32, 0, 0..1);
-            cmd_buffer.end_render_pass();
             if cfg!(debug_assertions) {
                 cmd_buffer.insert_debug_marker("done", 0);
             }
@@ -385,22 +1266,21 @@ impl<B: Backend> RendererState<B> {
             let submission = Submission {
                 command_buffers: iter::once(&cmd_buffer),
                 wait_semaphores: None,
-                signal_semaphores: iter::once(&*framedata.present_sem),
+                signal_semaphores: iter::once(&*framedata.render_finished_sem),
             };
 
-            self.device.borrow_mut().queues.queues[0].submit(submission, None);
+            self.device.borrow().device.reset_fence(&*framedata.fence).unwrap();
+            self.device.borrow_mut().queues.queues[0].submit(submission, Some(&*framedata.fence));
             framedata.cmd_buffers.push(cmd_buffer);
 
             //present frame
-            if let Err(_) = self.device.borrow_mut().queues.queues[0].present(
+            if self.device.borrow_mut().queues.queues[0].present(
                 &mut *self.backend.surface,
                 surface_image,
-                Some(framedata.present_sem)
-            ) {
+                Some(framedata.render_finished_sem)
+            ).is_err() {
                 self.recreate_swapchain = true;
             }
-
-            self.device.borrow().device.destroy_framebuffer(framebuffer);
         }
 
         Ok(())
@@ -416,104 +1296,153 @@ impl<B: Backend> RendererState<B> {
     }
 
     pub fn update_camera_rotation(&mut self, mouse_x: f64, mouse_y: f64) {
-        let step = -0.5_f32;
+        self.mouse_delta += Vector2::new(mouse_x as f32, mouse_y as f32);
+    }
+
+    /// `lines` of the mouse wheel, positive zooms in.
+    pub fn update_camera_zoom(&mut self, lines: f32) {
+        self.zoom_delta += lines;
+    }
 
-        self.camera
-            .update_model(Matrix4::new_rotation_y(mouse_x as f32 * step * self.timer.get_delta_f32()));
+    #[allow(dead_code)]
+    pub fn get_camera(&self) -> &Camera {
+        &self.views[0].camera
+    }
+
+    #[allow(dead_code)]
+    pub fn get_camera_mut(&mut self) -> &mut Camera {
+        &mut self.views[0].camera
+    }
 
-        self.camera
-            .update_model(Matrix4::new_rotation_x(mouse_y as f32 * step * self.timer.get_delta_f32()));
+    #[allow(dead_code)]
+    pub fn set_camera_controller(&mut self, controller: Box<dyn CameraController>) {
+        self.controller = controller;
     }
 
-    //TODO: Need to update the 
-    // pub fn update_fov(&mut self, delta: f32) {
-    //     let step = 0.05_f32;
+    #[allow(dead_code)]
+    pub fn set_camera_target(&mut self, target: Option<usize>) {
+        self.camera_target = target;
+    }
+
+    /// Switches between the free-fly, orbit and follow controllers.
+    pub fn cycle_camera_controller(&mut self) {
+        self.controller_kind = self.controller_kind.next();
+        self.controller = self.controller_kind.create(&self.views[0].camera, self.camera_target_position());
 
-    //     // self.camera.update_proj(Matrix4::perspective(
-    //     //     i * step * self.timer.get_delta_f64(),
-    //     //     (self.viewport.rect.w / self.viewport.rect.h) as f32,
-    //     //     0.1,
-    //     //     1000.0,
-    //     // ));
-    // }
+        info!("Camera controller: {:?}", self.controller_kind);
+    }
+
+    fn camera_target_position(&self) -> Vector3 {
+        self.camera_target
+            .and_then(|index| self.objects.get(index))
+            .map(|object| object.get_instances_bounding_sphere().center)
+            .unwrap_or_default()
+    }
 
     fn update_camera(&mut self) {
-        let step = 0.005_f32;
+        let axis = |positive: VirtualKeyCode, negative: VirtualKeyCode| {
+            input::is_btn_down(positive) as i32 as f32 - input::is_btn_down(negative) as i32 as f32
+        };
 
-        if input::is_btn_down(VirtualKeyCode::W) {
-            self.camera.update_model(Matrix4::new_traslation(
-                0.0,
-                0.0,
-                step * self.timer.get_delta_f32(),
-            ));
-        }
+        let controller_input = ControllerInput {
+            movement: Vector3::new(
+                axis(VirtualKeyCode::D, VirtualKeyCode::A),
+                axis(VirtualKeyCode::R, VirtualKeyCode::F),
+                axis(VirtualKeyCode::W, VirtualKeyCode::S),
+            ),
+            look: self.mouse_delta,
+            zoom: self.zoom_delta,
+        };
+        self.mouse_delta = Vector2::default();
+        self.zoom_delta = 0.0;
 
-        if input::is_btn_down(VirtualKeyCode::S) {
-            self.camera.update_model(Matrix4::new_traslation(
-                0.0,
-                0.0,
-                -step * self.timer.get_delta_f32(),
-            ));
-        }
+        let target = self.camera_target_position();
+        self.controller.set_target(target);
+        self.controller.update(&mut self.views[0].camera, &controller_input, self.timer.get_delta_seconds_f32());
 
-        if input::is_btn_down(VirtualKeyCode::A) {
-            self.camera.update_model(Matrix4::new_traslation(
-                step * self.timer.get_delta_f32(),
-                0.0,
-                0.0,
-            ));
+        if input::is_btn_down(VirtualKeyCode::J) {
+            self.views[0].camera.set_fov(90.0);
         }
 
-        if input::is_btn_down(VirtualKeyCode::D) {
-            self.camera.update_model(Matrix4::new_traslation(
-                -step * self.timer.get_delta_f32(),
-                0.0,
-                0.0,
-            ));
+        if input::is_btn_down(VirtualKeyCode::K) {
+            self.views[0].camera.set_fov(120.0);
         }
+    }
 
-        if input::is_btn_down(VirtualKeyCode::R) {
-            self.camera.update_model(Matrix4::new_traslation(
-                0.0,
-                -step * self.timer.get_delta_f32(),
-                0.0,
-            ));
-        }
+    /// Picks the LOD of every instance batch by how much of the screen its instances cover.
+    fn update_lods(&mut self) {
+        let model_view = self.views[0].camera.view();
+        let proj = self.views[0].camera.proj();
+
+        for object in self.objects.iter_mut() {
+            let lods: Vec<usize> = object.get_batches().iter()
+                .map(|batch| mesh::select_lod(mesh::screen_size(&batch.sphere, &model_view, &proj), object.get_lod_count(), LOD_SCREEN_SIZE))
+                .collect();
 
-        if input::is_btn_down(VirtualKeyCode::F) {
-            self.camera.update_model(Matrix4::new_traslation(
-                0.0,
-                step * self.timer.get_delta_f32(),
-                0.0,
-            ));
+            for (batch, lod) in lods.into_iter().enumerate() {
+                object.set_lod(batch, lod);
+            }
         }
+    }
 
-        if input::is_btn_down(VirtualKeyCode::Q) {
-            self.camera
-                .update_model(Matrix4::new_rotation_z(0.1 * self.timer.get_delta_f32()));
+    /// Uploads the cameras of the views and culls the objects for each of them.
+    /// The shadow pass is not culled, objects outside the views can still cast shadows into them.
+    fn update_views(&mut self, frame_idx: usize) {
+        for view in self.views.iter_mut().filter(|view| view.enabled) {
+            view.update(frame_idx, &self.objects);
         }
 
-        if input::is_btn_down(VirtualKeyCode::E) {
-            self.camera
-                .update_model(Matrix4::new_rotation_z(-0.1 * self.timer.get_delta_f32()));
+        debug!("Culling: {}", self.views[0].get_culling().get_stats());
+    }
+
+    /// Advances the clips of the skinned objects and uploads their joint palettes.
+    fn update_animations(&mut self, frame_idx: usize) {
+        let delta = self.timer.get_delta_seconds_f32();
+        let mut palette = Vec::new();
+
+        for object in self.objects.iter_mut() {
+            object.update_animation(delta);
+
+            let joints = object.joint_matrices();
+            if joints.is_empty() {
+                object.set_joint_offset(None);
+                continue;
+            }
+
+            //NOTE: Objects past the end of the joint buffer are drawn in their bind pose
+            if palette.len() + joints.len() > MAX_JOINT_MATRICES {
+                debug!("More than {} joint matrices, the object is not animated", MAX_JOINT_MATRICES);
+                object.set_joint_offset(None);
+                continue;
+            }
+
+            object.set_joint_offset(Some(palette.len() as u32));
+            palette.extend(joints);
         }
 
-        if input::is_btn_down(VirtualKeyCode::J) {
-            self.camera.set_proj(Matrix4::perspective(
-                90.0_f32.to_radians(),
-                (self.viewport.rect.w / self.viewport.rect.h) as f32,
-                0.1,
-                1000.0,
-            ));
+        //NOTE: Every view has its own camera set, each holds a copy of the palettes
+        for view in self.views.iter_mut() {
+            view.get_state_mut().update_joints(frame_idx, &palette);
         }
+    }
 
-        if input::is_btn_down(VirtualKeyCode::K) {
-            self.camera.set_proj(Matrix4::perspective(
-                120.0_f32.to_radians(),
-                (self.viewport.rect.w / self.viewport.rect.h) as f32,
-                0.1,
-                1000.0,
-            ));
+    /// Crossfades every animated object to its next clip.
+    pub fn next_animation(&mut self) {
+        for object in self.objects.iter_mut() {
+            let clips = object.get_clips();
+            if clips.is_empty() {
+                continue;
+            }
+
+            let next = object.get_player().current_clip()
+                .map(|clip| (clip + 1) % clips.len())
+                .unwrap_or(0);
+            let name = clips[next].name.clone();
+
+            info!("Playing animation {}", name);
+            if let Err(err) = object.crossfade(&name, ANIMATION_CROSSFADE, true) {
+                error!("{}", err.message);
+            }
         }
     }
 
@@ -624,8 +1553,14 @@ impl<B: Backend> RendererState<B> {
     }
 
     fn update_uniform_buffer(&mut self, value: f32) {
-        self.object.as_mut().unwrap()
-            .update_color(&self.cur_color, value);
+        let material = match &self.selected_material {
+            Some(name) => self.materials.get_mut(name),
+            None => None
+        };
+
+        if let Some(material) = material {
+            material.update_color(&self.cur_color, value, &self.backend.adapter);
+        }
     }
 
     fn update_bg(&mut self, value: f32) {
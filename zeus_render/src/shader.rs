@@ -0,0 +1,110 @@
+use std::{
+    collections::BTreeMap,
+    io::Cursor,
+    path::Path,
+    rc::Rc,
+    time::SystemTime
+};
+
+use zeus_core::vfs;
+
+use super::{
+    constants::SHADER_BINARY_EXT,
+    error::AssetError,
+    reflect::ShaderReflection
+};
+
+/// SPIR-V code of a shader together with its reflected interface.
+#[derive(Debug)]
+pub struct Shader {
+    pub path: String,
+    pub spirv: Vec<u32>,
+    pub reflection: ShaderReflection,
+    modified: Option<SystemTime>,
+}
+
+/// Keeps the loaded shaders around so pipeline recreation doesn't read and compile them again.
+/// A shader is reloaded only when its source or binary changed on disk.
+#[derive(Debug, Default)]
+pub struct ShaderCache {
+    shaders: BTreeMap<String, Rc<Shader>>,
+}
+
+impl ShaderCache {
+    pub fn new() -> Self {
+        ShaderCache {
+            shaders: BTreeMap::new(),
+        }
+    }
+
+    pub fn load(&mut self, path: &str) -> Result<Rc<Shader>, AssetError> {
+        let modified = last_modified(path);
+
+        if let Some(shader) = self.shaders.get(path) {
+            if shader.modified.is_some() && shader.modified == modified {
+                return Ok(Rc::clone(shader));
+            }
+        }
+
+        let spirv = load_spirv(path)?;
+        let reflection = ShaderReflection::new(&spirv)
+            .map_err(|err| AssetError::new(format!("Could not reflect shader {}: {}", path, err.message)))?;
+
+        let shader = Rc::new(Shader {
+            path: path.to_string(),
+            spirv,
+            reflection,
+            modified,
+        });
+
+        self.shaders.insert(path.to_string(), Rc::clone(&shader));
+
+        Ok(shader)
+    }
+}
+
+/// Loads the SPIR-V of a shader. The precompiled `.spv` file built by `build.rs` is preferred,
+/// the GLSL source is compiled only if the binary is missing or older than the source.
+pub fn load_spirv(path: &str) -> Result<Vec<u32>, AssetError> {
+    let binary_path = format!("{}.{}", path, SHADER_BINARY_EXT);
+
+    if vfs::is_up_to_date(path, &binary_path) {
+        debug!("Loading precompiled shader {}", binary_path);
+
+        let bytes = vfs::read(&binary_path)
+            .map_err(|err| AssetError::new(format!("Could not read shader {}: {:?}", binary_path, err)))?;
+
+        return gfx_auxil::read_spirv(Cursor::new(bytes))
+            .map_err(|err| AssetError::new(format!("Could not read SPIR-V of {}: {:?}", binary_path, err)));
+    }
+
+    debug!("Compiling shader {}", path);
+
+    let shader_type = shader_type(path)?;
+
+    let glsl = vfs::read_to_string(path)
+        .map_err(|err| AssetError::new(format!("Could not read shader {}: {:?}", path, err)))?;
+    let file = glsl_to_spirv::compile(&glsl, shader_type)
+        .map_err(|err| AssetError::new(format!("Could not compile shader {}: {}", path, err)))?;
+
+    gfx_auxil::read_spirv(file)
+        .map_err(|err| AssetError::new(format!("Could not read SPIR-V of {}: {:?}", path, err)))
+}
+
+pub fn shader_type(path: &str) -> Result<glsl_to_spirv::ShaderType, AssetError> {
+    match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("vert") => Ok(glsl_to_spirv::ShaderType::Vertex),
+        Some("frag") => Ok(glsl_to_spirv::ShaderType::Fragment),
+        Some("geom") => Ok(glsl_to_spirv::ShaderType::Geometry),
+        Some("tesc") => Ok(glsl_to_spirv::ShaderType::TessellationControl),
+        Some("tese") => Ok(glsl_to_spirv::ShaderType::TessellationEvaluation),
+        Some("comp") => Ok(glsl_to_spirv::ShaderType::Compute),
+        _ => Err(AssetError::new(format!("Unknown shader type for {}", path)))
+    }
+}
+
+fn last_modified(path: &str) -> Option<SystemTime> {
+    let binary_path = format!("{}.{}", path, SHADER_BINARY_EXT);
+
+    vfs::modified(path).max(vfs::modified(&binary_path))
+}
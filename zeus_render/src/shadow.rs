@@ -0,0 +1,704 @@
+use gfx_hal::{
+    buffer::SubRange,
+    command::CommandBuffer,
+    device::Device,
+    format::Format,
+    image::{
+        Filter, Layout, Lod, PackedColor, SamplerDesc, WrapMode
+    },
+    pso::{
+        BufferDescriptorFormat, BufferDescriptorType, Comparison, DepthBias, Descriptor, DescriptorPoolCreateFlags, DescriptorRangeDesc, DescriptorSetLayoutBinding, DescriptorType, ImageDescriptorType, Rect, ShaderStageFlags, Viewport
+    },
+    Backend,
+};
+
+use super::{
+    buffer::BufferState,
+    camera::CameraState,
+    constants::{
+        MAX_SHADOW_CASCADES, MAX_SHADOW_TILES, SHADOW_ATLAS_GRID, SHADOW_ATLAS_SIZE, SHADOW_ATTACHMENT, SHADOW_DISTANCE, SHADOW_PASS, SHADOW_VERTEX_SHADER_PATH
+    },
+    desc::{
+        DescSet, DescSetLayout, DescSetWrite
+    },
+    device::DeviceState,
+    error::AssetError,
+    graph::{
+        AttachmentClear, AttachmentDesc, AttachmentSize, PassDesc, RenderGraphDesc
+    },
+    light::{
+        Light, LightKind
+    },
+    obj::RenderObject,
+    pipeline::PipelineState,
+    shader::ShaderCache,
+    vertex_layout::VertexLayout,
+};
+
+use zeus_core::math::{
+    Matrix4, Vector3, Vector4
+};
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    mem::size_of,
+    ops::Range,
+    rc::Rc
+};
+
+/// How the shadows are rendered. The shadow map and its pipelines have to be rebuilt when they change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    /// Width and height of the shadow map atlas in texels
+    pub atlas_size: u32,
+    /// The atlas is split in `atlas_grid` x `atlas_grid` tiles
+    pub atlas_grid: u32,
+    /// Directional lights take one tile per cascade
+    pub cascades: usize,
+    /// How far from the camera directional lights cast shadows
+    pub distance: f32,
+    /// 0 splits the cascades evenly, 1 logarithmically
+    pub split_lambda: f32,
+    pub depth_bias: f32,
+    pub slope_bias: f32,
+    /// The PCF kernel is (2 * pcf_radius + 1)² texels, 0 only uses the hardware filter
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            atlas_size: SHADOW_ATLAS_SIZE,
+            atlas_grid: SHADOW_ATLAS_GRID,
+            cascades: MAX_SHADOW_CASCADES,
+            distance: SHADOW_DISTANCE,
+            split_lambda: 0.75,
+            depth_bias: 1.25,
+            slope_bias: 1.75,
+            pcf_radius: 1,
+        }
+    }
+}
+
+impl ShadowSettings {
+    fn tile_count(&self) -> usize {
+        ((self.atlas_grid * self.atlas_grid) as usize).min(MAX_SHADOW_TILES)
+    }
+
+    fn tile_size(&self) -> u32 {
+        self.atlas_size / self.atlas_grid.max(1)
+    }
+
+    fn cascade_count(&self) -> usize {
+        self.cascades.clamp(1, MAX_SHADOW_CASCADES)
+    }
+}
+
+/// The std140 layout of a tile in the shadow buffer.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ShadowTileData {
+    matrix: Matrix4,
+    //xy: offset in the atlas, zw: scale
+    rect: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ShadowData {
+    //The far distance of each cascade in view space
+    splits: [f32; 4],
+    //x: pcf radius, y: size of a texel of the atlas
+    params: [f32; 4],
+    tiles: [ShadowTileData; MAX_SHADOW_TILES],
+}
+
+/// Renders the shadow maps of the directional and spot lights into the tiles of one depth atlas.
+/// The matrices of the tiles are uploaded to a uniform buffer per frame in flight, next to the atlas and a comparison sampler.
+pub struct ShadowState<B: Backend> {
+    settings: ShadowSettings,
+    format: Format,
+    /// The light matrix of each tile used this frame
+    tiles: Vec<Matrix4>,
+    buffers: Vec<Option<BufferState<B>>>,
+    descs: Vec<DescSet<B>>,
+    /// One pipeline per vertex layout of the objects
+    pipelines: BTreeMap<VertexLayout, PipelineState<B>>,
+    shader_cache: ShaderCache,
+    device: Rc<RefCell<DeviceState<B>>>,
+    sampler: Option<B::Sampler>,
+    shadow_desc_pool: Option<B::DescriptorPool>,
+}
+
+impl<B: Backend> ShadowState<B> {
+    pub fn new(
+        size: usize,
+        settings: ShadowSettings,
+        device: Rc<RefCell<DeviceState<B>>>,
+    ) -> Self {
+        //NOTE: D16 depth attachments are supported everywhere
+        let format = if device.borrow().supports_depth_attachment(Format::D32Sfloat) {
+            Format::D32Sfloat
+        } else {
+            Format::D16Unorm
+        };
+
+        let mut shadow_desc_pool = unsafe {
+            device.borrow().device.create_descriptor_pool(
+                size,
+                [
+                    DescriptorRangeDesc {
+                        ty: DescriptorType::Buffer {
+                            ty: BufferDescriptorType::Uniform,
+                            format: BufferDescriptorFormat::Structured {
+                                dynamic_offset: false
+                            }
+                        },
+                        count: size,
+                    },
+                    DescriptorRangeDesc {
+                        ty: DescriptorType::Image {
+                            ty: ImageDescriptorType::Sampled {
+                                with_sampler: false
+                            }
+                        },
+                        count: size,
+                    },
+                    DescriptorRangeDesc {
+                        ty: DescriptorType::Sampler,
+                        count: size,
+                    },
+                ],
+                DescriptorPoolCreateFlags::empty(),
+            )
+        }.ok();
+
+        //The hardware compares the depth and filters the 2x2 texels around it
+        let sampler = unsafe {
+            device.borrow().device.create_sampler(&SamplerDesc {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                mip_filter: Filter::Nearest,
+                wrap_mode: (WrapMode::Clamp, WrapMode::Clamp, WrapMode::Clamp),
+                lod_bias: Lod(0.0),
+                lod_range: Lod(0.0) .. Lod(0.0),
+                comparison: Some(Comparison::LessEqual),
+                border: PackedColor(!0),
+                normalized: true,
+                anisotropy_clamp: None,
+            })
+        }.expect("Can't create shadow sampler");
+
+        let mut buffers = Vec::default();
+        let mut descs = Vec::default();
+        for _i in 0..size {
+            let buffer = BufferState::new_uniform_buffer::<ShadowData>(
+                Rc::clone(&device),
+                1,
+            );
+
+            let shadow_desc = DescSetLayout::new(
+                Rc::clone(&device),
+                vec![
+                    DescriptorSetLayoutBinding {
+                        binding: 0,
+                        ty: DescriptorType::Buffer {
+                            ty: BufferDescriptorType::Uniform,
+                            format: BufferDescriptorFormat::Structured {
+                                dynamic_offset: false
+                            }
+                        },
+                        count: 1,
+                        stage_flags: ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    },
+                    DescriptorSetLayoutBinding {
+                        binding: 1,
+                        ty: DescriptorType::Image {
+                            ty: ImageDescriptorType::Sampled {
+                                with_sampler: false
+                            }
+                        },
+                        count: 1,
+                        stage_flags: ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    },
+                    DescriptorSetLayoutBinding {
+                        binding: 2,
+                        ty: DescriptorType::Sampler,
+                        count: 1,
+                        stage_flags: ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    },
+                ],
+            );
+
+            let mut shadow_desc = shadow_desc.create_desc_set(
+                shadow_desc_pool.as_mut().unwrap()
+            );
+
+            //NOTE: The atlas is written by `set_shadow_map`, it changes with the render graph
+            shadow_desc.write_to_state(
+                vec![
+                    DescSetWrite {
+                        binding: 0,
+                        array_offset: 0,
+                        descriptors: Some(Descriptor::Buffer(
+                            buffer.get_buffer(),
+                            SubRange {
+                                offset: 0,
+                                size: Some(size_of::<ShadowData>() as u64)
+                            }
+                        )),
+                    },
+                    DescSetWrite {
+                        binding: 2,
+                        array_offset: 0,
+                        descriptors: Some(Descriptor::Sampler(&sampler)),
+                    },
+                ],
+                &mut device.borrow_mut().device
+            );
+
+            buffers.push(Some(buffer));
+            descs.push(shadow_desc);
+        }
+
+        ShadowState {
+            settings,
+            format,
+            tiles: Vec::new(),
+            buffers,
+            descs,
+            pipelines: BTreeMap::new(),
+            shader_cache: ShaderCache::new(),
+            device,
+            sampler: Some(sampler),
+            shadow_desc_pool,
+        }
+    }
+
+    pub fn get_settings(&self) -> ShadowSettings {
+        self.settings
+    }
+
+    /// The caller has to rebuild the render graph and the pipelines.
+    pub fn set_settings(&mut self, settings: ShadowSettings) {
+        self.settings = settings;
+    }
+
+    /// Adds the atlas and the shadow pass, the main pass samples the atlas.
+    pub fn add_passes(&self, graph: &mut RenderGraphDesc, main_pass: &mut PassDesc) {
+        let mut atlas = AttachmentDesc::new(SHADOW_ATTACHMENT, self.format);
+        atlas.size = AttachmentSize::Absolute(self.settings.atlas_size, self.settings.atlas_size);
+        atlas.clear = Some(AttachmentClear::DepthStencil(1.0, 0));
+        graph.add_attachment(atlas);
+
+        let mut shadow_pass = PassDesc::new(SHADOW_PASS);
+        shadow_pass.set_depth(SHADOW_ATTACHMENT);
+        graph.add_pass(shadow_pass);
+
+        main_pass.add_input(SHADOW_ATTACHMENT);
+    }
+
+    /// Points the descriptor sets to the atlas. The caller has to make sure the device is idle.
+    pub fn set_shadow_map(&mut self, image_view: &B::ImageView) {
+        for desc in self.descs.iter_mut() {
+            desc.write_to_state(
+                vec![DescSetWrite {
+                    binding: 1,
+                    array_offset: 0,
+                    descriptors: Some(Descriptor::Image(
+                        image_view,
+                        Layout::ShaderReadOnlyOptimal
+                    )),
+                }],
+                &mut self.device.borrow_mut().device
+            );
+        }
+    }
+
+    /// Builds the pipelines of the shadow pass for the vertex layouts, the previous ones are kept on failure.
+    /// The caller has to make sure the device is idle.
+    //NOTE: The camera set holds the joint palettes of the skinned objects
+    pub fn create_pipelines(
+        &mut self,
+        render_pass: &B::RenderPass,
+        camera: &CameraState<B>,
+        vertex_layouts: &[VertexLayout],
+    ) -> Result<(), AssetError> {
+        let depth_bias = DepthBias {
+            const_factor: self.settings.depth_bias,
+            clamp: 0.0,
+            slope_factor: self.settings.slope_bias,
+        };
+
+        let mut layouts = Vec::new();
+        camera.append_layout(&mut layouts);
+
+        self.pipelines.retain(|vertex_layout, _| vertex_layouts.contains(vertex_layout));
+
+        //The other layouts are still built when one fails
+        let mut result = Ok(());
+        for vertex_layout in vertex_layouts.iter() {
+            let device = Rc::clone(&self.device);
+            let built = self.pipelines.entry(vertex_layout.clone())
+                .or_insert_with(|| PipelineState::empty(device))
+                .new_shadow_pipeline(
+                    &mut self.shader_cache,
+                    SHADOW_VERTEX_SHADER_PATH,
+                    vertex_layout,
+                    depth_bias,
+                    &layouts,
+                    render_pass
+                );
+
+            if let Err(err) = built {
+                result = Err(err);
+            }
+        }
+
+        result
+    }
+
+    /// Fits the shadow maps to the lights and the camera and uploads them to the buffer of the frame.
+    /// `planes` are the near and far planes of the camera, see `Camera::get_planes`. Returns the atlas tiles of each light.
+    pub fn update(
+        &mut self,
+        idx: usize,
+        lights: &[Light],
+        camera: &CameraState<B>,
+        planes: (f32, f32),
+    ) -> Vec<Option<Range<usize>>> {
+        let settings = self.settings;
+        let cascades = settings.cascade_count();
+        let light_tiles = assign_tiles(lights, cascades, settings.tile_count());
+
+        let (near, far) = planes;
+        let splits = cascade_splits(near, far.min(settings.distance), cascades, settings.split_lambda);
+
+        //NOTE: The camera model is applied to the scene, the lights are in the same space as the vertices
+        let view_proj = camera.get_proj() * camera.get_view() * camera.get_model();
+        //NOTE: Falls back to the identity, the default matrix is all zeros
+        #[allow(clippy::unwrap_or_default)]
+        let inv_view_proj = view_proj.inverse().unwrap_or_else(Matrix4::new);
+
+        self.tiles.clear();
+        for (light, tiles) in lights.iter().zip(light_tiles.iter()) {
+            if tiles.is_none() {
+                continue;
+            }
+
+            match light.kind {
+                LightKind::Directional => {
+                    for cascade in 0..cascades {
+                        let start = if cascade == 0 { near } else { splits[cascade - 1] };
+                        let corners = frustum_slice(&inv_view_proj, near, far, start, splits[cascade]);
+
+                        self.tiles.push(directional_matrix(light.direction, &corners, settings.tile_size()));
+                    }
+                },
+                _ => self.tiles.push(spot_matrix(light))
+            }
+        }
+
+        let mut data = ShadowData {
+            splits: [far; 4],
+            params: [settings.pcf_radius as f32, 1.0 / settings.atlas_size as f32, 0.0, 0.0],
+            tiles: [ShadowTileData {
+                matrix: Matrix4::new(),
+                rect: [0.0; 4]
+            }; MAX_SHADOW_TILES],
+        };
+
+        data.splits[..splits.len()].copy_from_slice(&splits);
+
+        let scale = 1.0 / settings.atlas_grid as f32;
+        for (index, matrix) in self.tiles.iter().enumerate() {
+            let (column, row) = tile_position(index, settings.atlas_grid);
+
+            data.tiles[index] = ShadowTileData {
+                matrix: *matrix,
+                rect: [column as f32 * scale, row as f32 * scale, scale, scale],
+            };
+        }
+
+        self.buffers[idx].as_mut().unwrap()
+            .update_data(0, &[data]);
+
+        light_tiles
+    }
+
+    /// Draws the objects into every tile used this frame, inside the shadow pass.
+    pub unsafe fn record(
+        &self,
+        cmd_buffer: &mut B::CommandBuffer,
+        objects: &[RenderObject<B>],
+        camera: &CameraState<B>,
+        frame_idx: usize,
+    ) {
+        let tile_size = self.settings.tile_size();
+
+        for (index, matrix) in self.tiles.iter().enumerate() {
+            let (column, row) = tile_position(index, self.settings.atlas_grid);
+
+            let rect = Rect {
+                x: (column * tile_size) as i16,
+                y: (row * tile_size) as i16,
+                w: tile_size as i16,
+                h: tile_size as i16,
+            };
+
+            cmd_buffer.set_viewports(0, &[Viewport {
+                rect,
+                depth: 0.0 .. 1.0,
+            }]);
+            cmd_buffer.set_scissors(0, [rect]);
+
+            let constants: Vec<u32> = (0..16)
+                .map(|i| matrix[i].to_bits())
+                .collect();
+
+            for (vertex_layout, pipeline) in self.pipelines.iter() {
+                let (pipeline, pipeline_layout) = match (&pipeline.pipeline, &pipeline.pipeline_layout) {
+                    (Some(pipeline), Some(pipeline_layout)) => (pipeline, pipeline_layout),
+                    _ => continue
+                };
+
+                cmd_buffer.bind_graphics_pipeline(pipeline);
+
+                let mut desc_sets = Vec::new();
+                camera.append_desc_set(frame_idx, &mut desc_sets);
+                cmd_buffer.bind_graphics_descriptor_sets(pipeline_layout, 0, desc_sets, &[]);
+                cmd_buffer.push_graphics_constants(pipeline_layout, ShaderStageFlags::VERTEX, 0, &constants);
+
+                for object in objects.iter().filter(|object| object.get_vertex_layout() == vertex_layout && object.get_instance_count() > 0) {
+                    cmd_buffer.push_graphics_constants(
+                        pipeline_layout,
+                        ShaderStageFlags::VERTEX,
+                        size_of::<Matrix4>() as u32,
+                        &[object.get_joint_offset_constant()]
+                    );
+
+                    object.bind_buffers(cmd_buffer, 0);
+                    for batch in object.get_batches() {
+                        for range in object.lod_ranges(batch.lod) {
+                            cmd_buffer.draw_indexed(range, 0, batch.instances.clone());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn append_desc_set<'a>(
+        &'a self,
+        idx: usize,
+        vec: &mut Vec<&'a B::DescriptorSet>,
+    ) {
+        vec.push(self.descs[idx].set.as_ref().unwrap())
+    }
+
+    //NOTE: The layouts of all the frames are identical
+    pub fn append_layout<'a>(
+        &'a self,
+        vec: &mut Vec<&'a DescSetLayout<B>>,
+    ) {
+        vec.push(&self.descs[0].layout)
+    }
+}
+
+impl<B: Backend> Drop for ShadowState<B> {
+    fn drop(&mut self) {
+        let device = &self.device.borrow().device;
+        device.wait_idle().unwrap();
+        unsafe {
+            device.destroy_sampler(self.sampler.take().unwrap());
+            device.destroy_descriptor_pool(self.shadow_desc_pool.take().unwrap());
+        }
+    }
+}
+
+/// The atlas tiles of each light, in order. Directional lights take one tile per cascade and spot lights one.
+/// Lights without shadows and the ones that don't fit in the atlas get None.
+pub fn assign_tiles(
+    lights: &[Light],
+    cascades: usize,
+    tile_count: usize,
+) -> Vec<Option<Range<usize>>> {
+    let mut next_tile = 0;
+
+    lights.iter()
+        .map(|light| {
+            if !light.has_shadows() {
+                return None;
+            }
+
+            let count = if light.kind == LightKind::Directional { cascades } else { 1 };
+            if next_tile + count > tile_count {
+                warn!("The shadow atlas is full, a {:?} light will not cast shadows", light.kind);
+                return None;
+            }
+
+            next_tile += count;
+            Some(next_tile - count .. next_tile)
+        })
+        .collect()
+}
+
+/// The far distance of each cascade, a blend of the even and the logarithmic split.
+pub fn cascade_splits(
+    near: f32,
+    far: f32,
+    count: usize,
+    lambda: f32,
+) -> Vec<f32> {
+    (1..=count)
+        .map(|cascade| {
+            let ratio = cascade as f32 / count as f32;
+            let log = near * (far / near).powf(ratio);
+            let even = near + (far - near) * ratio;
+
+            lambda * log + (1.0 - lambda) * even
+        })
+        .collect()
+}
+
+fn tile_position(index: usize, grid: u32) -> (u32, u32) {
+    (index as u32 % grid, index as u32 / grid)
+}
+
+/// The corners of the part of the camera frustum between the `start` and `end` distances.
+fn frustum_slice(
+    inv_view_proj: &Matrix4,
+    near: f32,
+    far: f32,
+    start: f32,
+    end: f32,
+) -> [Vector3; 8] {
+    let unproject = |x: f32, y: f32, z: f32| {
+        let point = *inv_view_proj * Vector4::new(x, y, z, 1.0);
+        Vector3::new(point.x, point.y, point.z) / point.w
+    };
+
+    let mut corners = [Vector3::default(); 8];
+    let corner_signs = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+
+    //NOTE: The distance grows linearly along the edges of the frustum
+    for (index, &(x, y)) in corner_signs.iter().enumerate() {
+        let near_corner = unproject(x, y, -1.0);
+        let edge = unproject(x, y, 1.0) - near_corner;
+
+        corners[index] = near_corner + edge * ((start - near) / (far - near));
+        corners[index + 4] = near_corner + edge * ((end - near) / (far - near));
+    }
+
+    corners
+}
+
+/// Maps the depth of the projections from -1..1 to the 0..1 of the shadow pass.
+fn depth_zero_to_one() -> Matrix4 {
+    let mut mat = Matrix4::new();
+    mat[10] = 0.5;
+    mat[11] = 0.5;
+
+    mat
+}
+
+fn light_view(position: Vector3, direction: Vector3) -> Matrix4 {
+    let up = if direction.y.abs() > 0.99 { Vector3::Z } else { Vector3::Y };
+
+    Matrix4::look_at(position, position + direction, up) * Matrix4::new_traslation(-position.x, -position.y, -position.z)
+}
+
+/// An orthographic projection around a slice of the camera frustum.
+fn directional_matrix(
+    direction: Vector3,
+    corners: &[Vector3; 8],
+    tile_size: u32,
+) -> Matrix4 {
+    let center = corners.iter().fold(Vector3::default(), |sum, &corner| sum + corner) / 8.0;
+
+    //NOTE: A sphere around the slice keeps the size of the projection the same when the camera rotates
+    let radius = corners.iter().map(|&corner| (corner - center).magn()).fold(0.0, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    //Casters between the light and the slice still cast shadows
+    let direction = direction.normalize();
+    let view = light_view(center - direction * (radius * 2.0), direction);
+    let mut proj = Matrix4::orthographic(-radius, radius, -radius, radius, 0.0, radius * 3.0);
+
+    //Move in whole texels, so the edges of the shadows don't shimmer when the camera moves
+    let origin = (proj * view) * Vector4::new(0.0, 0.0, 0.0, 1.0);
+    let texels = tile_size as f32 / 2.0;
+    proj[3] += ((origin.x * texels).round() - origin.x * texels) / texels;
+    proj[7] += ((origin.y * texels).round() - origin.y * texels) / texels;
+
+    depth_zero_to_one() * proj * view
+}
+
+fn spot_matrix(light: &Light) -> Matrix4 {
+    let fov = light.outer_cone.max(light.inner_cone).clamp(1.0, 179.0);
+    let range = light.range.max(0.1);
+    let proj = Matrix4::perspective(fov.to_radians(), 1.0, range * 0.01, range);
+
+    depth_zero_to_one() * proj * light_view(light.position, light.direction.normalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        assign_tiles, cascade_splits, directional_matrix, frustum_slice
+    };
+
+    use crate::light::Light;
+
+    use zeus_core::math::{
+        Matrix4, Vector3, Vector4
+    };
+
+    #[test]
+    fn assigns_atlas_tiles() {
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        let lights = [
+            Light::point(Vector3::new(0.0, 0.0, 0.0), white, 1.0, 5.0),
+            Light::directional(Vector3::new(0.0, -1.0, 0.0), white, 1.0),
+            Light::spot(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0), white, 1.0, 5.0, 30.0, 45.0),
+            Light::spot(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0), white, 1.0, 5.0, 30.0, 45.0),
+        ];
+
+        let tiles = assign_tiles(&lights, 4, 5);
+
+        assert_eq!(tiles, vec![None, Some(0..4), Some(4..5), None]);
+    }
+
+    #[test]
+    fn splits_cascades() {
+        let even = cascade_splits(1.0, 9.0, 4, 0.0);
+        assert_eq!(even, vec![3.0, 5.0, 7.0, 9.0]);
+
+        let log = cascade_splits(1.0, 16.0, 4, 1.0);
+        for (split, expected) in log.iter().zip([2.0, 4.0, 8.0, 16.0].iter()) {
+            assert!((split - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn cascade_covers_frustum_slice() {
+        let proj = Matrix4::perspective(90.0_f32.to_radians(), 1.0, 0.1, 100.0);
+        let corners = frustum_slice(&proj.inverse().unwrap(), 0.1, 100.0, 1.0, 5.0);
+
+        //With a 90 degree fov the slice is as wide as it is far
+        assert!((corners[0].z + 1.0).abs() < 1e-3);
+        assert!((corners[6].x - 5.0).abs() < 1e-2);
+
+        let matrix = directional_matrix(Vector3::new(-0.5, -1.0, 0.5), &corners, 1024);
+        for corner in corners.iter() {
+            let point = matrix * Vector4::new(corner.x, corner.y, corner.z, 1.0);
+
+            assert!(point.x.abs() <= 1.0 && point.y.abs() <= 1.0);
+            assert!(point.z >= 0.0 && point.z <= 1.0);
+        }
+    }
+}
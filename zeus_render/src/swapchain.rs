@@ -14,6 +14,7 @@ pub struct SwapchainState<B: Backend> {
     device: Rc<RefCell<DeviceState<B>>>,
     pub extent: Extent,
     pub format: Format,
+    #[allow(dead_code)]
     pub size: u32,
     pub frame_index: u32
 }
@@ -0,0 +1,176 @@
+use super::model::Vertex;
+
+use zeus_core::math::{
+    Vector3,
+    Vector4
+};
+
+/// Generates tangents in the MikkTSpace convention: the tangent follows +u, `w` is the handedness and
+/// the bitangent is `cross(normal, tangent) * w`. The normals have to be generated first.
+///
+/// Vertices shared by faces with mirrored UVs are split, so `vertices` can grow and `indices` are remapped.
+//NOTE: The UVs are flipped on import, the tangent space is built with v pointing up the image like OpenGL
+pub fn generate_tangents(vertices: &mut Vec<Vertex>, indices: &mut [u32]) {
+    //Accumulated tangents of every vertex, for each handedness
+    let mut tangents = vec![[Vector3::default(); 2]; vertices.len()];
+    let mut used = vec![[false; 2]; vertices.len()];
+    let mut corner_signs = vec![0; indices.len()];
+
+    for (face_index, face) in indices.chunks_exact(3).enumerate() {
+        let corners = [face[0] as usize, face[1] as usize, face[2] as usize];
+        let [p0, p1, p2] = [vertices[corners[0]].a_pos, vertices[corners[1]].a_pos, vertices[corners[2]].a_pos];
+        let [uv0, uv1, uv2] = [vertices[corners[0]].a_uv, vertices[corners[1]].a_uv, vertices[corners[2]].a_uv];
+
+        let (edge_1, edge_2) = (p1 - p0, p2 - p0);
+        let (du_1, dv_1) = (uv1.x - uv0.x, uv0.y - uv1.y);
+        let (du_2, dv_2) = (uv2.x - uv0.x, uv0.y - uv2.y);
+
+        let det = du_1 * dv_2 - du_2 * dv_1;
+        if det.abs() < 1e-12 {
+            //No UVs to follow, the tangent is picked when the vertex is finished
+            continue;
+        }
+
+        let tangent = (edge_1 * dv_2 - edge_2 * dv_1) / det;
+        let bitangent = (edge_2 * du_1 - edge_1 * du_2) / det;
+
+        for (corner, &vertex) in corners.iter().enumerate() {
+            let normal = vertices[vertex].a_normal;
+            let sign = if normal.cross(&tangent).dot(&bitangent) < 0.0 { 1 } else { 0 };
+
+            //Weighted by the angle of the corner, like MikkTSpace
+            let to_next = vertices[corners[(corner + 1) % 3]].a_pos - vertices[vertex].a_pos;
+            let to_prev = vertices[corners[(corner + 2) % 3]].a_pos - vertices[vertex].a_pos;
+            let angle = corner_angle(to_next, to_prev);
+
+            tangents[vertex][sign] += tangent * angle;
+            used[vertex][sign] = true;
+            corner_signs[face_index * 3 + corner] = sign;
+        }
+    }
+
+    //Vertices on a mirror seam get a copy for the faces with the other handedness
+    let mut split = vec![None; vertices.len()];
+    for vertex in 0..vertices.len() {
+        if used[vertex][0] && used[vertex][1] {
+            split[vertex] = Some(vertices.len() as u32);
+            vertices.push(vertices[vertex]);
+            tangents.push([Vector3::default(), tangents[vertex][1]]);
+            used.push([false, true]);
+        }
+    }
+
+    for (index, sign) in indices.iter_mut().zip(corner_signs) {
+        if sign == 1 {
+            if let Some(copy) = split[*index as usize] {
+                *index = copy;
+            }
+        }
+    }
+
+    for (vertex, (tangent, used)) in vertices.iter_mut().zip(tangents.iter().zip(used)) {
+        let sign = if used[0] || !used[1] { 0 } else { 1 };
+        let tangent = orthogonal_tangent(vertex.a_normal, tangent[sign]);
+
+        vertex.a_tangent = Vector4::new(tangent.x, tangent.y, tangent.z, if sign == 0 { 1.0 } else { -1.0 });
+    }
+}
+
+fn corner_angle(a: Vector3, b: Vector3) -> f32 {
+    let lengths = a.magn() * b.magn();
+    if lengths == 0.0 {
+        return 0.0;
+    }
+
+    (a.dot(&b) / lengths).clamp(-1.0, 1.0).acos()
+}
+
+/// Gram-Schmidt against the normal, any perpendicular direction when the tangent is missing.
+fn orthogonal_tangent(normal: Vector3, tangent: Vector3) -> Vector3 {
+    let tangent = tangent - normal * normal.dot(&tangent);
+    if tangent.magn() > 1e-6 {
+        return tangent.normalize();
+    }
+
+    let axis = if normal.x.abs() < 0.9 {
+        Vector3::new(1.0, 0.0, 0.0)
+    } else {
+        Vector3::new(0.0, 1.0, 0.0)
+    };
+
+    let tangent = axis - normal * normal.dot(&axis);
+    if tangent.magn() > 1e-6 {
+        tangent.normalize()
+    } else {
+        axis
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate_tangents;
+
+    use crate::model::Vertex;
+
+    use zeus_core::math::{
+        Vector2,
+        Vector3,
+        Vector4
+    };
+
+    fn vertex(x: f32, y: f32, u: f32, v: f32) -> Vertex {
+        Vertex {
+            a_pos: Vector3::new(x, y, 0.0),
+            a_color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            a_uv: Vector2::new(u, v),
+            a_normal: Vector3::new(0.0, 0.0, 1.0),
+            a_tangent: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            a_joints: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            a_weights: Vector4::new(0.0, 0.0, 0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn tangents_follow_u() {
+        //Flipped UVs, v grows down the quad
+        let mut vertices = vec![
+            vertex(0.0, 0.0, 0.0, 1.0),
+            vertex(1.0, 0.0, 1.0, 1.0),
+            vertex(1.0, 1.0, 1.0, 0.0),
+            vertex(0.0, 1.0, 0.0, 0.0),
+        ];
+        let mut indices = vec![0, 1, 2, 2, 3, 0];
+
+        generate_tangents(&mut vertices, &mut indices);
+
+        assert_eq!(vertices.len(), 4);
+        for vertex in vertices.iter() {
+            assert_eq!(vertex.a_tangent, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_split_vertices() {
+        //The right quad mirrors the UVs of the left one, the middle edge is shared
+        let mut vertices = vec![
+            vertex(0.0, 0.0, 0.0, 1.0),
+            vertex(1.0, 0.0, 1.0, 1.0),
+            vertex(1.0, 1.0, 1.0, 0.0),
+            vertex(0.0, 1.0, 0.0, 0.0),
+            vertex(2.0, 0.0, 0.0, 1.0),
+            vertex(2.0, 1.0, 0.0, 0.0),
+        ];
+        let mut indices = vec![0, 1, 2, 2, 3, 0, 1, 4, 5, 5, 2, 1];
+
+        generate_tangents(&mut vertices, &mut indices);
+
+        //Both vertices of the middle edge are split
+        assert_eq!(vertices.len(), 8);
+        assert_eq!(indices[..6], [0, 1, 2, 2, 3, 0]);
+        assert_eq!(indices[6..], [6, 4, 5, 5, 7, 6]);
+
+        assert_eq!(vertices[4].a_tangent, Vector4::new(-1.0, 0.0, 0.0, -1.0));
+        assert_eq!(vertices[6].a_tangent, Vector4::new(-1.0, 0.0, 0.0, -1.0));
+        assert_eq!(vertices[1].a_tangent, Vector4::new(1.0, 0.0, 0.0, 1.0));
+    }
+}
@@ -0,0 +1,511 @@
+use gfx_hal::{
+    format::Format,
+    pso::{
+        AttributeDesc, Element, VertexBufferDesc, VertexInputRate
+    },
+};
+
+use super::{
+    constants::DEFAULT_ATTRIBUTE_BINDING,
+    error::AssetError,
+};
+
+/// What an attribute holds. Every semantic has a fixed location in the shaders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VertexSemantic {
+    Position,
+    Color,
+    TexCoord,
+    Normal,
+    Tangent,
+    Joints,
+    Weights,
+    /// The model matrix of an instance, one row per location
+    InstanceTransform,
+    InstanceColor,
+}
+
+impl VertexSemantic {
+    pub const ALL: [VertexSemantic; 9] = [
+        VertexSemantic::Position,
+        VertexSemantic::Color,
+        VertexSemantic::TexCoord,
+        VertexSemantic::Normal,
+        VertexSemantic::Tangent,
+        VertexSemantic::Joints,
+        VertexSemantic::Weights,
+        VertexSemantic::InstanceTransform,
+        VertexSemantic::InstanceColor,
+    ];
+
+    pub fn location(self) -> u32 {
+        match self {
+            VertexSemantic::Position => 0,
+            VertexSemantic::Color => 1,
+            VertexSemantic::TexCoord => 2,
+            VertexSemantic::Normal => 3,
+            VertexSemantic::Tangent => 4,
+            VertexSemantic::Joints => 5,
+            VertexSemantic::Weights => 6,
+            VertexSemantic::InstanceTransform => 7,
+            VertexSemantic::InstanceColor => 11,
+        }
+    }
+
+    /// How many locations the attribute takes, each one with the format of the attribute.
+    pub fn rows(self) -> u32 {
+        match self {
+            VertexSemantic::InstanceTransform => 4,
+            _ => 1
+        }
+    }
+
+    /// The value shaders read when the mesh doesn't have the attribute.
+    pub fn default_value(self, row: u32) -> [f32; 4] {
+        match self {
+            VertexSemantic::Color | VertexSemantic::InstanceColor => [1.0, 1.0, 1.0, 1.0],
+            VertexSemantic::Normal => [0.0, 0.0, 1.0, 0.0],
+            VertexSemantic::Tangent => [1.0, 0.0, 0.0, 1.0],
+            VertexSemantic::InstanceTransform => {
+                let mut value = [0.0; 4];
+                value[row as usize] = 1.0;
+                value
+            },
+            _ => [0.0; 4]
+        }
+    }
+}
+
+/// How the components of a format are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Float,
+    Half,
+    Unorm,
+    Snorm,
+    /// Integers that the shader reads as floats
+    Uscaled,
+    Uint,
+    Sint,
+}
+
+/// How shaders read a format, the attribute and the input of the shader have to agree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumericType {
+    Float,
+    Uint,
+    Sint,
+}
+
+/// The encoding, the size of a component in bytes and the number of components of the vertex formats we can write.
+fn format_encoding(format: Format) -> Option<(Encoding, u32, u32)> {
+    let encoding = match format {
+        Format::R32Sfloat => (Encoding::Float, 4, 1),
+        Format::Rg32Sfloat => (Encoding::Float, 4, 2),
+        Format::Rgb32Sfloat => (Encoding::Float, 4, 3),
+        Format::Rgba32Sfloat => (Encoding::Float, 4, 4),
+        Format::Rg16Sfloat => (Encoding::Half, 2, 2),
+        Format::Rgba16Sfloat => (Encoding::Half, 2, 4),
+        Format::Rgba8Unorm => (Encoding::Unorm, 1, 4),
+        Format::Rg16Unorm => (Encoding::Unorm, 2, 2),
+        Format::Rgba16Unorm => (Encoding::Unorm, 2, 4),
+        Format::Rgba8Snorm => (Encoding::Snorm, 1, 4),
+        Format::Rg16Snorm => (Encoding::Snorm, 2, 2),
+        Format::Rgba16Snorm => (Encoding::Snorm, 2, 4),
+        Format::Rgba8Uscaled => (Encoding::Uscaled, 1, 4),
+        Format::Rgba16Uscaled => (Encoding::Uscaled, 2, 4),
+        Format::Rgba8Uint => (Encoding::Uint, 1, 4),
+        Format::Rgba16Uint => (Encoding::Uint, 2, 4),
+        Format::R32Uint => (Encoding::Uint, 4, 1),
+        Format::Rg32Uint => (Encoding::Uint, 4, 2),
+        Format::Rgb32Uint => (Encoding::Uint, 4, 3),
+        Format::Rgba32Uint => (Encoding::Uint, 4, 4),
+        Format::R32Sint => (Encoding::Sint, 4, 1),
+        Format::Rg32Sint => (Encoding::Sint, 4, 2),
+        Format::Rgb32Sint => (Encoding::Sint, 4, 3),
+        Format::Rgba32Sint => (Encoding::Sint, 4, 4),
+        _ => return None
+    };
+
+    Some(encoding)
+}
+
+/// The number of components of a vertex format and how shaders read it.
+pub fn format_components(format: Format) -> Option<(u32, NumericType)> {
+    format_encoding(format)
+        .map(|(encoding, _, components)| {
+            let numeric_type = match encoding {
+                Encoding::Uint => NumericType::Uint,
+                Encoding::Sint => NumericType::Sint,
+                _ => NumericType::Float
+            };
+
+            (components, numeric_type)
+        })
+}
+
+/// The size in bytes of one location of the format.
+fn format_size(format: Format) -> Option<u32> {
+    format_encoding(format)
+        .map(|(_, component_size, components)| component_size * components)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VertexAttribute {
+    pub semantic: VertexSemantic,
+    pub format: Format,
+    /// From the start of the element in the stream
+    pub offset: u32,
+}
+
+/// One vertex buffer, with its attributes interleaved.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VertexStream {
+    pub rate: VertexInputRate,
+    pub stride: u32,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+impl VertexStream {
+    /// The attributes one after the other, in order and without padding.
+    /// Formats that can't be written take no space and are rejected by the layout.
+    pub fn packed(rate: VertexInputRate, attributes: &[(VertexSemantic, Format)]) -> Self {
+        let mut stride = 0;
+        let attributes = attributes.iter()
+            .map(|&(semantic, format)| {
+                let attribute = VertexAttribute {
+                    semantic,
+                    format,
+                    offset: stride,
+                };
+
+                stride += format_size(format).unwrap_or(0) * semantic.rows();
+                attribute
+            })
+            .collect();
+
+        VertexStream {
+            rate,
+            stride,
+            attributes,
+        }
+    }
+}
+
+/// The vertex buffers of a mesh and the attributes in each of them. The pipelines are built from it,
+/// so meshes only have to carry the attributes they need.
+/// Shader inputs the layout doesn't have read the default value of their semantic.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VertexLayout {
+    streams: Vec<VertexStream>,
+}
+
+impl VertexLayout {
+    pub fn new(streams: Vec<VertexStream>) -> Result<Self, AssetError> {
+        if streams.len() > DEFAULT_ATTRIBUTE_BINDING as usize {
+            return Err(AssetError::new(format!("A vertex layout can't have more than {} streams", DEFAULT_ATTRIBUTE_BINDING)));
+        }
+
+        let mut semantics = Vec::new();
+        for attribute in streams.iter().flat_map(|stream| stream.attributes.iter()) {
+            if semantics.contains(&attribute.semantic) {
+                return Err(AssetError::new(format!("Vertex layout has {:?} more than once", attribute.semantic)));
+            }
+            semantics.push(attribute.semantic);
+
+            if format_encoding(attribute.format).is_none() {
+                return Err(AssetError::new(format!("{:?} is not supported for the vertex attribute {:?}", attribute.format, attribute.semantic)));
+            }
+        }
+
+        for stream in streams.iter() {
+            let out_of_range = stream.attributes.iter()
+                .any(|attribute| attribute.offset + format_size(attribute.format).unwrap_or(0) * attribute.semantic.rows() > stream.stride);
+
+            if out_of_range {
+                return Err(AssetError::new(format!("Vertex stream of stride {} has an attribute past its end", stream.stride)));
+            }
+        }
+
+        Ok(VertexLayout {
+            streams,
+        })
+    }
+
+    pub fn get_streams(&self) -> &[VertexStream] {
+        &self.streams
+    }
+
+    /// The layout with one more stream at the end.
+    pub fn with_stream(&self, stream: VertexStream) -> Result<Self, AssetError> {
+        let mut streams = self.streams.clone();
+        streams.push(stream);
+
+        VertexLayout::new(streams)
+    }
+
+    /// The stream and the attribute of the semantic.
+    #[allow(dead_code)]
+    pub fn find(&self, semantic: VertexSemantic) -> Option<(usize, VertexAttribute)> {
+        self.streams.iter()
+            .enumerate()
+            .find_map(|(index, stream)| stream.attributes.iter()
+                .find(|attribute| attribute.semantic == semantic)
+                .map(|attribute| (index, *attribute)))
+    }
+
+    /// Every stream is bound at its index.
+    pub fn buffer_descriptions(&self) -> Vec<VertexBufferDesc> {
+        self.streams.iter()
+            .enumerate()
+            .map(|(binding, stream)| VertexBufferDesc {
+                binding: binding as u32,
+                stride: stream.stride,
+                rate: stream.rate,
+            })
+            .collect()
+    }
+
+    pub fn attribute_descriptions(&self) -> Vec<AttributeDesc> {
+        let mut descriptions = Vec::new();
+
+        for (binding, stream) in self.streams.iter().enumerate() {
+            for attribute in stream.attributes.iter() {
+                let row_size = format_size(attribute.format).unwrap_or(0);
+
+                for row in 0..attribute.semantic.rows() {
+                    descriptions.push(AttributeDesc {
+                        location: attribute.semantic.location() + row,
+                        binding: binding as u32,
+                        element: Element {
+                            format: attribute.format,
+                            offset: attribute.offset + row * row_size,
+                        }
+                    });
+                }
+            }
+        }
+
+        descriptions
+    }
+
+    /// Writes `count` elements of the stream, `value` gets the index of the element, the semantic and the row
+    /// of each attribute and returns it as floats. They are converted to the format of the attribute.
+    pub fn pack(&self, stream: usize, count: usize, value: impl Fn(usize, VertexSemantic, u32) -> [f32; 4]) -> Vec<u8> {
+        let stream = &self.streams[stream];
+        let mut bytes = vec![0; stream.stride as usize * count];
+
+        for index in 0..count {
+            let element = &mut bytes[index * stream.stride as usize..(index + 1) * stream.stride as usize];
+
+            for attribute in stream.attributes.iter() {
+                let (encoding, component_size, components) = format_encoding(attribute.format)
+                    .expect("Vertex layout has an unsupported format");
+
+                for row in 0..attribute.semantic.rows() {
+                    let values = value(index, attribute.semantic, row);
+                    let offset = (attribute.offset + row * component_size * components) as usize;
+
+                    for (component, value) in values.iter().take(components as usize).enumerate() {
+                        let start = offset + component * component_size as usize;
+                        write_component(&mut element[start..start + component_size as usize], encoding, *value);
+                    }
+                }
+            }
+        }
+
+        bytes
+    }
+
+    /// The default value of every location, for the buffer bound at `DEFAULT_ATTRIBUTE_BINDING`.
+    pub fn default_values() -> Vec<[f32; 4]> {
+        let mut values = Vec::new();
+
+        for semantic in VertexSemantic::ALL.iter() {
+            for row in 0..semantic.rows() {
+                let location = (semantic.location() + row) as usize;
+                if values.len() <= location {
+                    values.resize(location + 1, [0.0; 4]);
+                }
+
+                values[location] = semantic.default_value(row);
+            }
+        }
+
+        values
+    }
+}
+
+/// Little endian, like the GPUs we run on.
+fn write_component(bytes: &mut [u8], encoding: Encoding, value: f32) {
+    match (encoding, bytes.len()) {
+        (Encoding::Float, _) => bytes.copy_from_slice(&value.to_le_bytes()),
+        (Encoding::Half, _) => bytes.copy_from_slice(&half_bits(value).to_le_bytes()),
+        (Encoding::Unorm, 1) => bytes[0] = (value.clamp(0.0, 1.0) * 255.0).round() as u8,
+        (Encoding::Unorm, _) => bytes.copy_from_slice(&((value.clamp(0.0, 1.0) * 65535.0).round() as u16).to_le_bytes()),
+        (Encoding::Snorm, 1) => bytes[0] = (value.clamp(-1.0, 1.0) * 127.0).round() as i8 as u8,
+        (Encoding::Snorm, _) => bytes.copy_from_slice(&((value.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes()),
+        (Encoding::Uscaled, _) | (Encoding::Uint, _) => bytes.copy_from_slice(&(value.round() as u32).to_le_bytes()[..bytes.len()]),
+        (Encoding::Sint, _) => bytes.copy_from_slice(&(value.round() as i32).to_le_bytes()[..bytes.len()]),
+    }
+}
+
+/// Rounds to the nearest half float, values past its range become infinite.
+fn half_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        //Infinity stays infinity and NaN stays NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+
+        //Subnormal, the implicit bit becomes part of the mantissa
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let round = (mantissa >> (shift - 1)) & 1;
+
+        return sign | ((mantissa >> shift) + round) as u16;
+    }
+
+    //A carry out of the mantissa rounds up to the next exponent
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    let round = (mantissa >> 12) & 1;
+
+    sign | (half + round) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        half_bits, VertexLayout, VertexSemantic, VertexStream
+    };
+
+    use crate::model::{
+        Instance, Vertex
+    };
+
+    use gfx_hal::{
+        format::Format,
+        pso::VertexInputRate
+    };
+
+    use std::{
+        mem::size_of,
+        slice
+    };
+
+    use zeus_core::math::{
+        Matrix4, Vector4
+    };
+
+    #[test]
+    fn vertex_layout_matches_memory() {
+        let layout = Vertex::layout();
+        assert_eq!(layout.buffer_descriptions()[0].stride, size_of::<Vertex>() as u32);
+        assert_eq!(layout.find(VertexSemantic::Weights).map(|(_, attribute)| attribute.offset), Some(80));
+    }
+
+    #[test]
+    fn instance_stream_matches_memory() {
+        let mut transform = Matrix4::new_scale(2.0, 2.0, 2.0);
+        transform.translate(1.0, 2.0, 3.0);
+        let instance = Instance::new(transform, Vector4::new(0.5, 0.25, 1.0, 1.0));
+
+        let layout = Vertex::layout().with_stream(Instance::stream()).unwrap();
+        assert_eq!(layout.buffer_descriptions()[1].stride, size_of::<Instance>() as u32);
+        assert!(Vertex::layout().with_stream(Vertex::layout().get_streams()[0].clone()).is_err());
+
+        let packed = layout.pack(1, 1, |_, semantic, row| instance.attribute(semantic, row));
+        let memory = unsafe {
+            slice::from_raw_parts(&instance as *const Instance as *const u8, size_of::<Instance>())
+        };
+        assert_eq!(packed, memory);
+    }
+
+    #[test]
+    fn split_streams() {
+        let layout = VertexLayout::new(vec![
+            VertexStream::packed(VertexInputRate::Vertex, &[(VertexSemantic::Position, Format::Rgb32Sfloat)]),
+            VertexStream::packed(VertexInputRate::Vertex, &[
+                (VertexSemantic::Normal, Format::Rgba8Snorm),
+                (VertexSemantic::TexCoord, Format::Rg16Sfloat),
+            ]),
+            VertexStream::packed(VertexInputRate::Instance(1), &[(VertexSemantic::InstanceTransform, Format::Rgba32Sfloat)]),
+        ]).unwrap();
+
+        let buffers = layout.buffer_descriptions();
+        assert_eq!(buffers.iter().map(|buffer| buffer.stride).collect::<Vec<_>>(), vec![12, 8, 64]);
+        assert_eq!(buffers[2].rate, VertexInputRate::Instance(1));
+
+        let attributes = layout.attribute_descriptions();
+        assert_eq!(attributes.len(), 7);
+        assert_eq!((attributes[2].location, attributes[2].binding, attributes[2].element.offset), (2, 1, 4));
+        assert_eq!((attributes[6].location, attributes[6].element.offset), (10, 48));
+
+        assert_eq!(layout.find(VertexSemantic::TexCoord).map(|(stream, _)| stream), Some(1));
+        assert!(layout.find(VertexSemantic::Color).is_none());
+    }
+
+    #[test]
+    fn reject_invalid_layouts() {
+        let repeated = VertexLayout::new(vec![
+            VertexStream::packed(VertexInputRate::Vertex, &[(VertexSemantic::Position, Format::Rgb32Sfloat)]),
+            VertexStream::packed(VertexInputRate::Vertex, &[(VertexSemantic::Position, Format::Rgb32Sfloat)]),
+        ]);
+        assert!(repeated.is_err());
+
+        let unsupported = VertexLayout::new(vec![
+            VertexStream::packed(VertexInputRate::Vertex, &[(VertexSemantic::Position, Format::Bc1RgbUnorm)]),
+        ]);
+        assert!(unsupported.is_err());
+    }
+
+    #[test]
+    fn pack_formats() {
+        let layout = VertexLayout::new(vec![
+            VertexStream::packed(VertexInputRate::Vertex, &[
+                (VertexSemantic::Normal, Format::Rgba8Snorm),
+                (VertexSemantic::Color, Format::Rgba8Unorm),
+                (VertexSemantic::TexCoord, Format::Rg16Sfloat),
+                (VertexSemantic::Joints, Format::Rgba8Uscaled),
+            ]),
+        ]).unwrap();
+
+        let bytes = layout.pack(0, 2, |index, semantic, _| match semantic {
+            VertexSemantic::Normal => [0.0, -1.0, 1.0, 0.0],
+            VertexSemantic::Color => [1.0, 0.5, 0.0, 2.0],
+            VertexSemantic::TexCoord => [0.5, index as f32, 0.0, 0.0],
+            _ => [3.0, 0.0, 1.0, 255.0]
+        });
+
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(bytes[..4], [0, 0x81, 0x7f, 0]);
+        assert_eq!(bytes[4..8], [255, 128, 0, 255]);
+        assert_eq!(bytes[8..12], [0x00, 0x38, 0x00, 0x00]);
+        assert_eq!(bytes[12..16], [3, 0, 1, 255]);
+        assert_eq!(bytes[26..28], [0x00, 0x3c]);
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(half_bits(1.0), 0x3c00);
+        assert_eq!(half_bits(-2.0), 0xc000);
+        assert_eq!(half_bits(65504.0), 0x7bff);
+        assert_eq!(half_bits(1e6), 0x7c00);
+        assert_eq!(half_bits(5.960_464_5e-8), 0x0001);
+        assert_eq!(half_bits(0.0), 0);
+    }
+}
@@ -0,0 +1,234 @@
+use gfx_hal::{
+    image::Extent,
+    pso::Rect,
+    Backend,
+};
+
+use super::{
+    camera::{
+        Camera, CameraState
+    },
+    constants::{
+        FRAMES_IN_FLIGHT, RENDER_TARGET_PASS_PREFIX
+    },
+    culling::{
+        CullingSettings, CullingState
+    },
+    device::DeviceState,
+    obj::RenderObject,
+};
+
+use std::{
+    cell::RefCell,
+    rc::Rc
+};
+
+/// A rectangle of the window, in fractions of its size. The origin is the top left corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewRect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        ViewRect {
+            x,
+            y,
+            width,
+            height
+        }
+    }
+
+    pub fn full() -> Self {
+        ViewRect::new(0.0, 0.0, 1.0, 1.0)
+    }
+
+    /// The pixels of the rectangle in a window of the extent, clamped to it and at least one pixel wide.
+    pub fn resolve(&self, extent: Extent) -> Rect {
+        let (width, height) = (extent.width as f32, extent.height as f32);
+
+        let left = (self.x.clamp(0.0, 1.0) * width).round();
+        let top = (self.y.clamp(0.0, 1.0) * height).round();
+        let right = ((self.x + self.width).clamp(0.0, 1.0) * width).round();
+        let bottom = ((self.y + self.height).clamp(0.0, 1.0) * height).round();
+
+        Rect {
+            x: left as i16,
+            y: top as i16,
+            w: (right - left).max(1.0) as i16,
+            h: (bottom - top).max(1.0) as i16,
+        }
+    }
+}
+
+impl Default for ViewRect {
+    fn default() -> Self {
+        ViewRect::full()
+    }
+}
+
+/// Where a camera view is drawn.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub enum ViewTarget {
+    /// A part of the window, for split-screen or picture-in-picture
+    Window(ViewRect),
+    /// An offscreen image, sampled by the materials with a `MaterialParam::RenderTexture` of the same name
+    Texture {
+        name: String,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl ViewTarget {
+    #[allow(dead_code)]
+    pub fn texture(name: &str, width: u32, height: u32) -> Self {
+        ViewTarget::Texture {
+            name: name.to_string(),
+            width: width.max(1),
+            height: height.max(1),
+        }
+    }
+
+    /// The name of the render target, None for the window.
+    pub fn get_render_target(&self) -> Option<&str> {
+        match self {
+            ViewTarget::Window(_) => None,
+            ViewTarget::Texture { name, .. } => Some(name),
+        }
+    }
+}
+
+/// The graph pass that draws into the render target.
+pub fn render_target_pass(name: &str) -> String {
+    format!("{}{}", RENDER_TARGET_PASS_PREFIX, name)
+}
+
+/// The multisampled color that is resolved into the render target.
+pub fn render_target_color(name: &str) -> String {
+    format!("{}:color", name)
+}
+
+pub fn render_target_depth(name: &str) -> String {
+    format!("{}:depth", name)
+}
+
+/// A camera and the target it is drawn to. Every view has its own camera buffers and culling,
+/// so any number of them can be drawn in the same frame.
+pub struct CameraView<B: Backend> {
+    pub camera: Camera,
+    /// Disabled views are not drawn, their render targets are only cleared
+    pub enabled: bool,
+    target: ViewTarget,
+    state: CameraState<B>,
+    culling: CullingState,
+}
+
+impl<B: Backend> CameraView<B> {
+    pub fn new(
+        camera: Camera,
+        target: ViewTarget,
+        culling: CullingSettings,
+        device: Rc<RefCell<DeviceState<B>>>,
+    ) -> Self {
+        let mut state = CameraState::new(
+            FRAMES_IN_FLIGHT,
+            device,
+        );
+
+        state.set_camera(&camera);
+        state.update_all_buffers();
+
+        CameraView {
+            camera,
+            enabled: true,
+            target,
+            state,
+            culling: CullingState::new(culling),
+        }
+    }
+
+    pub fn get_target(&self) -> &ViewTarget {
+        &self.target
+    }
+
+    /// Changing to or from a render target rebuilds the render graph, see `RendererState::set_view_target`.
+    pub fn set_target(&mut self, target: ViewTarget) {
+        self.target = target;
+    }
+
+    pub fn get_render_target(&self) -> Option<&str> {
+        self.target.get_render_target()
+    }
+
+    pub fn get_state(&self) -> &CameraState<B> {
+        &self.state
+    }
+
+    pub fn get_state_mut(&mut self) -> &mut CameraState<B> {
+        &mut self.state
+    }
+
+    pub fn get_culling(&self) -> &CullingState {
+        &self.culling
+    }
+
+    pub fn get_culling_mut(&mut self) -> &mut CullingState {
+        &mut self.culling
+    }
+
+    /// The pixels the view is drawn to, in the window or in its render target.
+    pub fn get_rect(&self, extent: Extent) -> Rect {
+        match &self.target {
+            ViewTarget::Window(rect) => rect.resolve(extent),
+            ViewTarget::Texture { width, height, .. } => Rect {
+                x: 0,
+                y: 0,
+                w: *width as i16,
+                h: *height as i16,
+            },
+        }
+    }
+
+    /// Matches the aspect of the camera to the target, window views follow the window.
+    pub fn resize(&mut self, extent: Extent) {
+        let rect = self.get_rect(extent);
+        self.camera.set_viewport(rect.w as u32, rect.h as u32);
+    }
+
+    /// Uploads the matrices of the camera and culls the objects for it.
+    pub fn update(&mut self, frame_idx: usize, objects: &[RenderObject<B>]) {
+        self.state.set_camera(&self.camera);
+        self.state.update_buffer(frame_idx);
+
+        let view_proj = self.camera.proj() * self.camera.view();
+        self.culling.update(objects, &view_proj);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ViewRect;
+
+    use gfx_hal::image::Extent;
+
+    #[test]
+    fn rect_resolves_to_pixels() {
+        let extent = Extent {
+            width: 800,
+            height: 600,
+            depth: 1
+        };
+
+        let right = ViewRect::new(0.5, 0.0, 0.5, 1.0).resolve(extent);
+        assert_eq!((right.x, right.y, right.w, right.h), (400, 0, 400, 600));
+
+        //Clamped to the window and never empty
+        let outside = ViewRect::new(0.9, 0.75, 0.5, 0.0).resolve(extent);
+        assert_eq!((outside.x, outside.y, outside.w, outside.h), (720, 450, 80, 1));
+    }
+}
@@ -0,0 +1,162 @@
+use notify::{
+    watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher
+};
+
+use std::{
+    collections::{
+        BTreeMap, BTreeSet
+    },
+    fs,
+    path::{
+        Path, PathBuf
+    },
+    sync::mpsc::{
+        channel, Receiver
+    },
+    time::Duration
+};
+
+use zeus_core::vfs;
+
+const DEBOUNCE_DELAY_MS: u64 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AssetKind {
+    Shader,
+    Texture,
+    Model,
+}
+
+struct WatchedAsset {
+    kind: AssetKind,
+    path: String,
+}
+
+/// Watches the asset files used by the renderer and reports the ones that changed on disk.
+pub struct AssetWatcher {
+    watcher: Option<RecommendedWatcher>,
+    receiver: Receiver<DebouncedEvent>,
+    assets: BTreeMap<PathBuf, WatchedAsset>,
+    watched_dirs: BTreeSet<PathBuf>,
+}
+
+impl AssetWatcher {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+
+        let watcher = match watcher(sender, Duration::from_millis(DEBOUNCE_DELAY_MS)) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                error!("Could not create asset watcher, hot reloading is disabled: {:?}", err);
+                None
+            }
+        };
+
+        AssetWatcher {
+            watcher,
+            receiver,
+            assets: BTreeMap::new(),
+            watched_dirs: BTreeSet::new(),
+        }
+    }
+
+    pub fn watch(&mut self, path: &str, kind: AssetKind) {
+        let watcher = match self.watcher.as_mut() {
+            Some(watcher) => watcher,
+            None => return
+        };
+
+        //NOTE: Only loose files can change, packed assets are not watched
+        let disk_path = match vfs::disk_path(path) {
+            Some(disk_path) => disk_path,
+            None => {
+                debug!("Asset {} is not a loose file, not watching it", path);
+                return;
+            }
+        };
+
+        let full_path = match fs::canonicalize(&disk_path) {
+            Ok(full_path) => full_path,
+            Err(err) => {
+                warn!("Could not watch asset {}: {:?}", path, err);
+                return;
+            }
+        };
+
+        //NOTE: Editors usually save by replacing the file, so we watch the parent directory
+        //instead of the file itself.
+        if let Some(dir) = full_path.parent() {
+            if !self.watched_dirs.contains(dir) {
+                match watcher.watch(dir, RecursiveMode::NonRecursive) {
+                    Ok(_) => {
+                        self.watched_dirs.insert(dir.to_path_buf());
+                    },
+                    Err(err) => {
+                        warn!("Could not watch directory {:?}: {:?}", dir, err);
+                        return;
+                    }
+                }
+            }
+        }
+
+        debug!("Watching {:?} asset {}", kind, path);
+
+        self.assets.insert(full_path, WatchedAsset {
+            kind,
+            path: path.to_string(),
+        });
+    }
+
+    #[allow(dead_code)]
+    pub fn unwatch_all(&mut self) {
+        if let Some(watcher) = self.watcher.as_mut() {
+            for dir in self.watched_dirs.iter() {
+                if let Err(err) = watcher.unwatch(dir) {
+                    warn!("Could not unwatch directory {:?}: {:?}", dir, err);
+                }
+            }
+        }
+
+        self.watched_dirs.clear();
+        self.assets.clear();
+    }
+
+    /// Drains the pending file events and returns every watched asset that changed since the last call.
+    pub fn changed_assets(&mut self) -> Vec<(AssetKind, String)> {
+        let mut changed: BTreeMap<PathBuf, (AssetKind, String)> = BTreeMap::new();
+
+        while let Ok(event) = self.receiver.try_recv() {
+            let path = match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Rename(_, path) => path,
+                DebouncedEvent::Error(err, path) => {
+                    warn!("Asset watcher error on {:?}: {:?}", path, err);
+                    continue;
+                },
+                _ => continue,
+            };
+
+            if let Some(asset) = self.find_asset(&path) {
+                changed.insert(path, (asset.kind, asset.path.clone()));
+            }
+        }
+
+        changed.into_values().collect()
+    }
+
+    fn find_asset(&self, path: &Path) -> Option<&WatchedAsset> {
+        if let Some(asset) = self.assets.get(path) {
+            return Some(asset);
+        }
+
+        fs::canonicalize(path).ok()
+            .and_then(|full_path| self.assets.get(&full_path))
+    }
+}
+
+impl Default for AssetWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}